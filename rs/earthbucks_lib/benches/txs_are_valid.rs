@@ -0,0 +1,116 @@
+// times BlockVerifier::txs_are_valid on a block with thousands of pkh inputs,
+// on one thread and on every available thread, against the serial
+// txs_are_valid_in_order that verifies the txs one by one. run with
+//
+//   cargo bench --bench txs_are_valid
+
+use earthbucks_lib::block::Block;
+use earthbucks_lib::block_verifier::BlockVerifier;
+use earthbucks_lib::header_chain::HeaderChain;
+use earthbucks_lib::key_pair::KeyPair;
+use earthbucks_lib::merkle_txs::MerkleTxs;
+use earthbucks_lib::pkh::Pkh;
+use earthbucks_lib::pkh_key_map::PkhKeyMap;
+use earthbucks_lib::script::Script;
+use earthbucks_lib::tx::Tx;
+use earthbucks_lib::tx_in::TxIn;
+use earthbucks_lib::tx_out::TxOut;
+use earthbucks_lib::tx_out_bn_map::TxOutBnMap;
+use earthbucks_lib::tx_signer::TxSigner;
+use earthbucks_lib::verify_error::VerifyError;
+use std::time::{Duration, Instant};
+
+const N_TXS: u32 = 1000;
+const N_INPUTS_PER_TX: u32 = 4;
+const N_RUNS: usize = 5;
+
+// a block at height 1 whose txs each spend N_INPUTS_PER_TX pkh outputs of a
+// funding tx, each paid to its own key
+fn block() -> (HeaderChain, TxOutBnMap, Block) {
+    let mut pkh_key_map = PkhKeyMap::new();
+    let mut outputs = Vec::new();
+    for _ in 0..N_TXS * N_INPUTS_PER_TX {
+        let key = KeyPair::from_random();
+        let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
+        pkh_key_map.add(key, &pkh.buf);
+        outputs.push(TxOut::new(100, Script::from_pkh_output(&pkh.buf)));
+    }
+    let funding_tx = Tx::new(1, vec![], outputs, 0);
+    let mut tx_out_bn_map = TxOutBnMap::new();
+    tx_out_bn_map.add_tx_outputs(&funding_tx, 0);
+
+    let pkh = Pkh::from_pub_key_buffer(KeyPair::from_random().pub_key.buf.to_vec());
+    let domain = "example.com".to_string();
+    let mut lch = HeaderChain::new();
+    let coinbase_tx_0 = lch.get_next_coinbase_tx(&pkh, &domain);
+    let merkle_root = MerkleTxs::new(vec![coinbase_tx_0]).root;
+    lch.add(lch.get_next_header(merkle_root, 1).unwrap());
+
+    let mut txs = vec![lch.get_next_coinbase_tx(&pkh, &domain)];
+    for n_tx in 0..N_TXS {
+        let inputs = (0..N_INPUTS_PER_TX)
+            .map(|n_in| {
+                let tx_out_num = n_tx * N_INPUTS_PER_TX + n_in;
+                TxIn::new(
+                    funding_tx.id(),
+                    tx_out_num,
+                    Script::from_pkh_input_placeholder(),
+                    0,
+                )
+            })
+            .collect();
+        let value = 100 * N_INPUTS_PER_TX as u64;
+        let tx = Tx::new(1, inputs, vec![TxOut::new(value, Script::from_empty())], 1);
+        let mut tx_signer = TxSigner::new(tx, &tx_out_bn_map, &pkh_key_map, 1);
+        txs.push(tx_signer.sign().unwrap());
+    }
+    let merkle_root = MerkleTxs::new(txs.clone()).root;
+    let header = lch.get_next_header(merkle_root, 2).unwrap();
+    (lch, tx_out_bn_map, Block::new(header, txs))
+}
+
+// the fastest of N_RUNS runs of verify on a fresh verifier
+fn time(
+    lch: &HeaderChain,
+    tx_out_bn_map: &TxOutBnMap,
+    block: &Block,
+    n_threads: usize,
+    verify: impl Fn(&mut BlockVerifier) -> Result<(), VerifyError>,
+) -> Duration {
+    (0..N_RUNS)
+        .map(|_| {
+            let mut block_verifier = BlockVerifier::new(block.clone(), tx_out_bn_map, lch);
+            block_verifier.n_threads = n_threads;
+            let start = Instant::now();
+            verify(&mut block_verifier).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let (lch, tx_out_bn_map, block) = block();
+    let n_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let in_order = time(&lch, &tx_out_bn_map, &block, 1, |v| {
+        v.txs_are_valid_in_order()
+    });
+    let one_thread = time(&lch, &tx_out_bn_map, &block, 1, |v| v.txs_are_valid());
+    let all_threads = time(&lch, &tx_out_bn_map, &block, n_threads, |v| {
+        v.txs_are_valid()
+    });
+    let speedup = |duration: Duration| in_order.as_secs_f64() / duration.as_secs_f64();
+    println!("{} txs with {} pkh inputs:", N_TXS, N_TXS * N_INPUTS_PER_TX);
+    println!("  txs_are_valid_in_order: {:?}", in_order);
+    println!(
+        "  txs_are_valid, 1 thread: {:?} ({:.2}x)",
+        one_thread,
+        speedup(one_thread)
+    );
+    println!(
+        "  txs_are_valid, {} threads: {:?} ({:.2}x)",
+        n_threads,
+        all_threads,
+        speedup(all_threads)
+    );
+}
@@ -4,7 +4,9 @@ use crate::error::EbxError;
 use crate::header::Header;
 use crate::tx::Tx;
 use crate::var_int::VarInt;
+use serde::{Deserialize, Serialize};
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Block {
     pub header: Header,
     pub txs: Vec<Tx>,
@@ -99,6 +101,16 @@ mod tests {
         assert_eq!(block1.txs[0].version, block2.txs[0].version);
     }
 
+    #[test]
+    fn test_json_round_trip() {
+        let header = Header::from_buf([0; Header::SIZE]).unwrap();
+        let tx = Tx::new(0, vec![], vec![], 1);
+        let block1 = Block::new(header, vec![tx]);
+        let json = serde_json::to_string(&block1).unwrap();
+        let block2: Block = serde_json::from_str(&json).unwrap();
+        assert_eq!(block2.to_buf(), block1.to_buf());
+    }
+
     #[test]
     fn test_from_buf_reader() {
         let header = Header {
@@ -1,10 +1,17 @@
 use crate::block::Block;
+use crate::domain::Domain;
+use crate::error::EbxError;
 use crate::header::Header;
+use crate::header_chain::HeaderChain;
+use crate::mempool::Mempool;
 use crate::merkle_txs::MerkleTxs;
+use crate::pkh::Pkh;
 use crate::script::Script;
 use crate::tx::Tx;
 use crate::tx_in::TxIn;
 use crate::tx_out::TxOut;
+use crate::var_int::VarInt;
+use std::collections::HashSet;
 
 pub struct BlockBuilder {
     pub header: Header,
@@ -39,6 +46,69 @@ impl BlockBuilder {
         header.merkle_root = root;
         Self::new(header, txs, merkle_txs)
     }
+
+    // build the next block on top of lch. the coinbase pays the mine's pkh
+    // and the candidate txs are added parents first for as long as they fit
+    // in max_size bytes and max_tx_count txs (both including the coinbase). a
+    // tx whose parent does not fit is left out too. the header is valid except
+    // for the proof of work.
+    pub fn from_template(
+        lch: &HeaderChain,
+        pkh: &Pkh,
+        domain: &Domain,
+        txs: Vec<Tx>,
+        new_timestamp: u64,
+        max_size: usize,
+        max_tx_count: usize,
+    ) -> Result<Self, EbxError> {
+        if !domain.is_valid() {
+            return Err(EbxError::GenericError {
+                source: None,
+                message: "invalid domain".to_string(),
+            });
+        }
+        let coinbase_tx = lch.get_next_coinbase_tx(pkh, &domain.to_strict_str());
+        let mut size = Header::SIZE
+            + VarInt::from_u64(max_tx_count as u64).to_buf().len()
+            + coinbase_tx.to_buf().len();
+        if size > max_size || max_tx_count == 0 {
+            return Err(EbxError::GenericError {
+                source: None,
+                message: "block limits too small for coinbase".to_string(),
+            });
+        }
+
+        let candidate_ids: HashSet<[u8; 32]> = txs.iter().map(|tx| tx.id()).collect();
+        let mut included_ids: HashSet<[u8; 32]> = HashSet::new();
+        let mut block_txs = vec![coinbase_tx];
+        for tx in Mempool::sort_txs(txs) {
+            if block_txs.len() >= max_tx_count {
+                break;
+            }
+            let has_missing_parent = tx.inputs.iter().any(|tx_in| {
+                candidate_ids.contains(&tx_in.input_tx_id)
+                    && !included_ids.contains(&tx_in.input_tx_id)
+            });
+            if has_missing_parent {
+                continue;
+            }
+            let tx_size = tx.to_buf().len();
+            if size + tx_size > max_size {
+                continue;
+            }
+            size += tx_size;
+            included_ids.insert(tx.id());
+            block_txs.push(tx);
+        }
+
+        let merkle_txs = MerkleTxs::new(block_txs.clone());
+        let header = lch.get_next_header(merkle_txs.root, new_timestamp)?;
+        Ok(Self::new(header, block_txs, merkle_txs))
+    }
+
+    pub fn to_block(&self) -> Block {
+        Block::new(self.header.clone(), self.txs.clone())
+    }
 }
 
 #[cfg(test)]
@@ -73,4 +143,88 @@ mod tests {
         assert_eq!(bb.header.timestamp, bh.timestamp);
         assert_eq!(bb.header.target, bh.target);
     }
+
+    fn txs_for_template() -> (Tx, Tx, Tx) {
+        let parent = Tx::new(
+            0,
+            vec![TxIn::new([1; 32], 0, Script::from_empty(), 0)],
+            vec![TxOut::new(100, Script::from_empty())],
+            0,
+        );
+        let child = Tx::new(
+            0,
+            vec![TxIn::new(parent.id(), 0, Script::from_empty(), 0)],
+            vec![TxOut::new(100, Script::from_empty())],
+            0,
+        );
+        let other = Tx::new(
+            0,
+            vec![TxIn::new([2; 32], 0, Script::from_empty(), 0)],
+            vec![TxOut::new(100, Script::from_empty())],
+            0,
+        );
+        (parent, child, other)
+    }
+
+    #[test]
+    fn test_from_template() {
+        use crate::block_verifier::BlockVerifier;
+        use crate::tx_out_bn_map::TxOutBnMap;
+
+        let lch = HeaderChain::new();
+        let pkh = Pkh::from_pub_key_buffer(vec![2; 33]);
+        let domain = Domain::from_strict_str("example.com".to_string());
+        let (parent, child, other) = txs_for_template();
+        let txs = vec![child.clone(), other.clone(), parent.clone()];
+        let bb = BlockBuilder::from_template(&lch, &pkh, &domain, txs, 1, 1_000_000, 100).unwrap();
+        assert_eq!(bb.header.block_num, 0);
+        let ids: Vec<[u8; 32]> = bb.txs[1..].iter().map(|tx| tx.id()).collect();
+        assert_eq!(ids, vec![other.id(), parent.id(), child.id()]);
+
+        let tx_out_bn_map = TxOutBnMap::new();
+        let block_verifier = BlockVerifier::new(bb.to_block(), &tx_out_bn_map, &lch);
+        assert!(block_verifier.merkle_root_is_valid().is_ok());
+        assert!(block_verifier.has_valid_coinbase().is_ok());
+    }
+
+    #[test]
+    fn test_from_template_max_tx_count() {
+        let lch = HeaderChain::new();
+        let pkh = Pkh::from_pub_key_buffer(vec![2; 33]);
+        let domain = Domain::from_strict_str("example.com".to_string());
+        let (parent, child, other) = txs_for_template();
+        let txs = vec![parent.clone(), child, other];
+        let bb = BlockBuilder::from_template(&lch, &pkh, &domain, txs, 1, 1_000_000, 2).unwrap();
+        assert_eq!(bb.txs.len(), 2);
+        assert_eq!(bb.txs[1].id(), parent.id());
+    }
+
+    #[test]
+    fn test_from_template_max_size() {
+        let lch = HeaderChain::new();
+        let pkh = Pkh::from_pub_key_buffer(vec![2; 33]);
+        let domain = Domain::from_strict_str("example.com".to_string());
+        let (parent, child, other) = txs_for_template();
+        let coinbase_tx = lch.get_next_coinbase_tx(&pkh, &domain.to_strict_str());
+        let max_size = Header::SIZE + 1 + coinbase_tx.to_buf().len() + other.to_buf().len();
+
+        // the parent comes first but does not fit once other is in, so the
+        // child is left out as well
+        let txs = vec![other.clone(), parent, child];
+        let bb = BlockBuilder::from_template(&lch, &pkh, &domain, txs, 1, max_size, 100).unwrap();
+        assert_eq!(bb.txs.len(), 2);
+        assert_eq!(bb.txs[1].id(), other.id());
+
+        assert!(BlockBuilder::from_template(&lch, &pkh, &domain, vec![], 1, 10, 100).is_err());
+    }
+
+    #[test]
+    fn test_from_template_invalid_domain() {
+        let lch = HeaderChain::new();
+        let pkh = Pkh::from_pub_key_buffer(vec![2; 33]);
+        let domain = Domain::from_strict_str("invalid".to_string());
+        assert!(
+            BlockBuilder::from_template(&lch, &pkh, &domain, vec![], 1, 1_000_000, 100).is_err()
+        );
+    }
 }
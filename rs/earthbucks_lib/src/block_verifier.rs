@@ -1,142 +1,666 @@
 use crate::block::Block;
-use crate::domain::Domain;
 use crate::header::Header;
 use crate::header_chain::HeaderChain;
 use crate::merkle_txs::MerkleTxs;
-use crate::tx_out_bn_map::TxOutBnMap;
+use crate::sig_cache::SigCache;
+use crate::sig_check::SigCheck;
+use crate::spend_graph::SpendGraph;
+use crate::tx_out_bn_overlay::TxOutBnOverlay;
+use crate::tx_out_bn_store::TxOutBnStore;
 use crate::tx_verifier::TxVerifier;
+use crate::utxo_delta::UtxoDelta;
+use crate::verify_error::VerifyError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 
 pub struct BlockVerifier<'a> {
     pub block: Block,
-    pub tx_out_bn_map: TxOutBnMap, // from earlier blocks
-    pub lch: &'a HeaderChain,      // longest chain
+    pub tx_out_bn_map: &'a dyn TxOutBnStore, // from earlier blocks
+    pub lch: &'a HeaderChain,                // longest chain
+    pub utxo_delta: UtxoDelta,               // set by txs_are_valid
+    pub n_threads: usize,                    // for signature checks
+    pub sig_cache: Option<Arc<SigCache>>,    // shared with the mempool
 }
 
 impl<'a> BlockVerifier<'a> {
-    pub fn new(block: Block, tx_out_bn_map: TxOutBnMap, lch: &'a HeaderChain) -> Self {
+    pub fn new(block: Block, tx_out_bn_map: &'a dyn TxOutBnStore, lch: &'a HeaderChain) -> Self {
+        let utxo_delta = UtxoDelta::new(block.header.block_num);
+        let n_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
         Self {
             block,
             tx_out_bn_map,
             lch,
+            utxo_delta,
+            n_threads,
+            sig_cache: None,
         }
     }
 
-    pub fn header_is_valid_at(&mut self, timestamp: u64) -> bool {
+    pub fn header_is_valid_at(&mut self, timestamp: u64) -> Result<(), VerifyError> {
         let header = &self.block.header;
         let lch = &self.lch;
-        lch.new_header_is_valid_at(header, timestamp)
+        if !lch.new_header_is_valid_at(header, timestamp) {
+            return Err(VerifyError::InvalidHeader);
+        }
+        Ok(())
     }
 
-    pub fn merkle_root_is_valid(&self) -> bool {
+    pub fn merkle_root_is_valid(&self) -> Result<(), VerifyError> {
         let txs = &self.block.txs;
         let merkle_root = self.block.header.merkle_root;
         // TODO: Eliminate clone of txs
         let merkle_txs = MerkleTxs::new(txs.clone());
-        merkle_txs.root == merkle_root
+        if merkle_txs.root != merkle_root {
+            return Err(VerifyError::InvalidMerkleRoot);
+        }
+        Ok(())
     }
 
-    pub fn has_valid_coinbase(&self) -> bool {
+    pub fn has_valid_coinbase(&self) -> Result<(), VerifyError> {
+        let invalid = |rule| Err(VerifyError::InvalidCoinbase { rule });
         // 1. coinbase tx is first tx
         let txs = &self.block.txs;
         if txs.is_empty() {
-            return false;
+            return invalid("block has no txs");
         }
         let coinbase_tx = &txs[0];
         if !coinbase_tx.is_coinbase() {
-            return false;
+            return invalid("first tx is not a coinbase");
         }
         // 2. lockNum equals block number
         if coinbase_tx.lock_abs != self.block.header.block_num {
-            return false;
+            return invalid("lock_abs does not equal block number");
         }
         // 3. version is 1
         if coinbase_tx.version != 1 {
-            return false;
+            return invalid("version is not 1");
         }
         // 4. all outputs are pkh
         for tx_output in &coinbase_tx.outputs {
             if !tx_output.script.is_pkh_output() {
-                return false;
+                return invalid("output is not pkh");
             }
         }
         // 5. output amount is correct
         let total_output_value: u64 = coinbase_tx.outputs.iter().map(|output| output.value).sum();
         let expected_coinbase_amount = Header::coinbase_amount(self.block.header.block_num);
         if total_output_value != expected_coinbase_amount {
-            return false;
+            return invalid("output amount is not the coinbase amount");
         }
         // 5. coinbase script is valid (push only)
         let coinbase_input = &coinbase_tx.inputs[0];
         let coinbase_script = &coinbase_input.script;
         if !coinbase_script.is_push_only() {
-            return false;
+            return invalid("input script is not push only");
         }
         // 6. domain name, top of the stack, is valid
-        let mut script_chunks = coinbase_script.chunks.clone();
-        if script_chunks.is_empty() {
-            return false;
-        }
-        let domain_chunk = script_chunks.pop().unwrap();
-        let domain_buf = domain_chunk.buffer.clone().unwrap();
-        let res_domain_str = String::from_utf8(domain_buf);
-        if res_domain_str.is_err() {
-            return false;
-        }
-        let domain_str = res_domain_str.unwrap();
-        if !Domain::is_valid_domain(&domain_str) {
-            return false;
+        if coinbase_tx.get_coinbase_domain().is_none() {
+            return invalid("input script has no valid domain");
         }
         // note that we do not verify whether domain is actually responsive and
         // delivers this block. that would require pinging the domain name,
-        // which is done elsewhere.
-        true
+        // which is done with SignedMineDescriptor::fetch_verified_now.
+        Ok(())
     }
 
-    pub fn txs_are_valid(&mut self) -> bool {
-        if !self.has_valid_coinbase() {
-            return false;
+    // the scripts of all txs are first run together across threads, each tx
+    // against the outputs it spends as recorded in the spend graph, and the
+    // signatures of OP_CHECKSIG are collected and then checked together across
+    // threads too. if anything fails, the txs are verified again one by one in
+    // order with every check run in place, so that a block is rejected for
+    // exactly the same reason as it would be without either.
+    pub fn txs_are_valid(&mut self) -> Result<(), VerifyError> {
+        self.has_valid_coinbase()?;
+        let block_num = self.block.header.block_num;
+        let spend_graph = SpendGraph::new(&self.block.txs, self.tx_out_bn_map, block_num)?;
+        if let Some(sig_checks) = self.run_scripts(&spend_graph) {
+            if SigCheck::verify_in_parallel(&sig_checks, self.n_threads) {
+                if let Some(sig_cache) = &self.sig_cache {
+                    for sig_check in &sig_checks {
+                        sig_cache.insert(sig_check);
+                    }
+                }
+                self.utxo_delta = spend_graph.utxo_delta;
+                return Ok(());
+            }
         }
-        let txs = &self.block.txs[1..];
+        self.utxo_delta = self.verify_txs_in_order()?;
+        Ok(())
+    }
+
+    // runs the scripts of every tx but the coinbase with signature checks
+    // deferred. each tx needs nothing but its own inputs from the spend graph,
+    // so the txs are split evenly across n_threads threads whether or not they
+    // spend each other's outputs. none if any tx fails.
+    fn run_scripts(&self, spend_graph: &SpendGraph) -> Option<Vec<SigCheck>> {
+        let txs = &self.block.txs;
+        let block_num = self.block.header.block_num;
+        let sig_cache = self.sig_cache.as_ref();
+        let n_txs = txs.len().saturating_sub(1);
+        let n_threads = self.n_threads.clamp(1, n_txs.max(1));
+        let chunk_size = n_txs.div_ceil(n_threads).max(1);
+        let failed = AtomicBool::new(false);
+        let chunks: Vec<Option<Vec<SigCheck>>> = thread::scope(|scope| {
+            let handles: Vec<_> = (1..txs.len())
+                .step_by(chunk_size)
+                .map(|start| {
+                    let end = (start + chunk_size).min(txs.len());
+                    let failed = &failed;
+                    scope.spawn(move || {
+                        let mut sig_checks = Vec::new();
+                        for (tx, inputs) in
+                            txs[start..end].iter().zip(&spend_graph.inputs[start..end])
+                        {
+                            if failed.load(Ordering::Relaxed) {
+                                return None;
+                            }
+                            let mut tx_verifier = TxVerifier::new(tx.clone(), inputs, block_num);
+                            tx_verifier.enable_deferred_sigs();
+                            if let Some(sig_cache) = sig_cache {
+                                tx_verifier.set_sig_cache(sig_cache.clone());
+                            }
+                            if tx_verifier.verify().is_err() {
+                                failed.store(true, Ordering::Relaxed);
+                                return None;
+                            }
+                            sig_checks.extend(tx_verifier.take_deferred_sigs());
+                        }
+                        Some(sig_checks)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        let chunks: Vec<Vec<SigCheck>> = chunks.into_iter().collect::<Option<_>>()?;
+        Some(chunks.into_iter().flatten().collect())
+    }
+
+    // verifies txs that do not spend each other's outputs at the same time on
+    // n_threads threads. the result and the utxo delta are the same as those
+    // of txs_are_valid.
+    pub fn txs_are_valid_in_parallel(&mut self) -> Result<(), VerifyError> {
+        self.has_valid_coinbase()?;
+        let block_num = self.block.header.block_num;
+        let spend_graph = SpendGraph::new(&self.block.txs, self.tx_out_bn_map, block_num)?;
+        spend_graph.verify_txs(
+            &self.block.txs,
+            block_num,
+            self.n_threads,
+            self.sig_cache.as_ref(),
+        )?;
+        self.utxo_delta = spend_graph.utxo_delta;
+        Ok(())
+    }
+
+    // verifies the txs one by one in order on this thread with every check run
+    // in place, as was done before txs_are_valid used threads. the result and
+    // the utxo delta are the same as those of txs_are_valid.
+    pub fn txs_are_valid_in_order(&mut self) -> Result<(), VerifyError> {
+        self.has_valid_coinbase()?;
+        self.utxo_delta = self.verify_txs_in_order()?;
+        Ok(())
+    }
+
+    fn verify_txs_in_order(&self) -> Result<UtxoDelta, VerifyError> {
+        let block_num = self.block.header.block_num;
+        let mut utxo_delta = UtxoDelta::new(block_num);
+        let mut tx_out_bn_overlay = TxOutBnOverlay::new(self.tx_out_bn_map);
+        // the coinbase outputs are created by the block but cannot be spent
+        // in it
+        utxo_delta.add_tx_outputs(&self.block.txs[0]);
         // iterate through all transactions except the first (coinbase tx)
         // verify with verifier
-        // if invalid, return false
+        // if invalid, return the reason along with the position of the tx
         // if valid, add outputs to tx_output_map and remove used outputs
-        for tx in txs {
-            let mut tx_verifier =
-                TxVerifier::new(tx.clone(), &self.tx_out_bn_map, self.block.header.block_num);
-            if !tx_verifier.verify() {
-                return false;
+        for (n_tx, tx) in self.block.txs.iter().enumerate().skip(1) {
+            let mut tx_verifier = TxVerifier::new(tx.clone(), &tx_out_bn_overlay, block_num);
+            if let Some(sig_cache) = &self.sig_cache {
+                tx_verifier.set_sig_cache(sig_cache.clone());
             }
-            let header = &self.block.header;
-            let block_num = header.block_num;
-            self.tx_out_bn_map.add_tx_outputs(tx, block_num);
+            if let Err(err) = tx_verifier.verify() {
+                // the tx is not known to be invalid if its inputs can not be
+                // read
+                if let VerifyError::StoreError { .. } = err {
+                    return Err(err);
+                }
+                return Err(VerifyError::InvalidTx {
+                    n_tx,
+                    tx_id: tx.id(),
+                    err: Box::new(err),
+                });
+            }
+            utxo_delta.add_tx_outputs(tx);
+            tx_out_bn_overlay
+                .add_tx_outputs(tx, block_num)
+                .map_err(VerifyError::store)?;
             // remove used outputs to prevent double spending
             for tx_input in &tx.inputs {
-                self.tx_out_bn_map
-                    .remove(&tx_input.input_tx_id.clone(), tx_input.input_tx_out_num);
+                let tx_id = &tx_input.input_tx_id;
+                let tx_out_num = tx_input.input_tx_out_num;
+                let tx_out_bn = tx_out_bn_overlay
+                    .get(tx_id, tx_out_num)
+                    .map_err(VerifyError::store)?
+                    .unwrap();
+                utxo_delta.spend(tx_id, tx_out_num, &tx_out_bn);
+                tx_out_bn_overlay
+                    .remove(tx_id, tx_out_num)
+                    .map_err(VerifyError::store)?;
             }
         }
-        true
+        Ok(utxo_delta)
     }
 
-    pub fn is_valid_at(&mut self, timestamp: u64) -> bool {
+    pub fn is_valid_at(&mut self, timestamp: u64) -> Result<(), VerifyError> {
         if timestamp < self.block.header.timestamp {
-            return false;
+            return Err(VerifyError::TimestampInFuture {
+                timestamp,
+                header_timestamp: self.block.header.timestamp,
+            });
         }
-        if !self.header_is_valid_at(timestamp) {
-            return false;
+        self.header_is_valid_at(timestamp)?;
+        self.merkle_root_is_valid()?;
+        self.txs_are_valid()?;
+        Ok(())
+    }
+
+    pub fn is_valid_now(&mut self) -> Result<(), VerifyError> {
+        let timestamp = Header::get_new_timestamp();
+        self.is_valid_at(timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::EbxError;
+    use crate::key_pair::KeyPair;
+    use crate::mempool::Mempool;
+    use crate::pkh::Pkh;
+    use crate::pkh_key_map::PkhKeyMap;
+    use crate::script::Script;
+    use crate::tx::Tx;
+    use crate::tx_builder::TxBuilder;
+    use crate::tx_in::TxIn;
+    use crate::tx_out::TxOut;
+    use crate::tx_out_bn::TxOutBn;
+    use crate::tx_out_bn_batch::TxOutBnBatch;
+    use crate::tx_out_bn_map::TxOutBnMap;
+    use crate::tx_signer::TxSigner;
+
+    fn block_from_txs(lch: &HeaderChain, txs: Vec<Tx>) -> Block {
+        let merkle_root = MerkleTxs::new(txs.clone()).root;
+        let timestamp = lch.headers.len() as u64 + 1;
+        let header = lch.get_next_header(merkle_root, timestamp).unwrap();
+        Block::new(header, txs)
+    }
+
+    #[test]
+    fn test_utxo_delta_apply_and_rollback() {
+        let key = KeyPair::from_random();
+        let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
+        let mut pkh_key_map = PkhKeyMap::new();
+        pkh_key_map.add(key, &pkh.buf);
+        let domain = "example.com".to_string();
+        let mut lch = HeaderChain::new();
+        let mut tx_out_bn_map = TxOutBnMap::new();
+
+        // block 0 only has a coinbase tx, whose output becomes spendable
+        let coinbase_tx_0 = lch.get_next_coinbase_tx(&pkh, &domain);
+        let block_0 = block_from_txs(&lch, vec![coinbase_tx_0.clone()]);
+        let header_0 = block_0.header.clone();
+        let mut block_verifier = BlockVerifier::new(block_0, &tx_out_bn_map, &lch);
+        assert!(block_verifier.txs_are_valid().is_ok());
+        let utxo_delta_0 = block_verifier.utxo_delta;
+        assert_eq!(utxo_delta_0.created.map.len(), 1);
+        assert!(utxo_delta_0.spent.map.is_empty());
+        utxo_delta_0.apply(&mut tx_out_bn_map).unwrap();
+        lch.add(header_0);
+        assert!(tx_out_bn_map.get(&coinbase_tx_0.id(), 0).is_some());
+
+        // block 1 spends the coinbase output of block 0
+        let coinbase_tx_1 = lch.get_next_coinbase_tx(&pkh, &domain);
+        let mut tx_builder = TxBuilder::new(&tx_out_bn_map, Script::from_pkh_output(&pkh.buf), 1);
+        let amount = Header::coinbase_amount(0);
+        tx_builder.add_output(TxOut::new(amount, Script::from_pkh_output(&pkh.buf)));
+        let tx = tx_builder.build().unwrap();
+        let mut tx_signer = TxSigner::new(tx, &tx_out_bn_map, &pkh_key_map, 1);
+        let tx = tx_signer.sign().unwrap();
+        let block_1 = block_from_txs(&lch, vec![coinbase_tx_1.clone(), tx.clone()]);
+        let mut block_verifier = BlockVerifier::new(block_1, &tx_out_bn_map, &lch);
+        assert!(block_verifier.txs_are_valid().is_ok());
+        let utxo_delta_1 = block_verifier.utxo_delta;
+        assert_eq!(utxo_delta_1.created.map.len(), 2);
+        assert_eq!(utxo_delta_1.spent.map.len(), 1);
+        assert_eq!(
+            utxo_delta_1
+                .spent
+                .get(&coinbase_tx_0.id(), 0)
+                .unwrap()
+                .block_num,
+            0
+        );
+
+        let before = tx_out_bn_map.clone();
+        utxo_delta_1.apply(&mut tx_out_bn_map).unwrap();
+        assert!(tx_out_bn_map.get(&coinbase_tx_0.id(), 0).is_none());
+        assert!(tx_out_bn_map.get(&coinbase_tx_1.id(), 0).is_some());
+        assert_eq!(tx_out_bn_map.get(&tx.id(), 0).unwrap().block_num, 1);
+
+        utxo_delta_1.rollback(&mut tx_out_bn_map).unwrap();
+        assert_eq!(tx_out_bn_map.map, before.map);
+        utxo_delta_0.rollback(&mut tx_out_bn_map).unwrap();
+        assert!(tx_out_bn_map.map.is_empty());
+    }
+
+    #[test]
+    fn test_double_spend_in_block_is_invalid() {
+        let key = KeyPair::from_random();
+        let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
+        let mut pkh_key_map = PkhKeyMap::new();
+        pkh_key_map.add(key, &pkh.buf);
+        let mut lch = HeaderChain::new();
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        let coinbase_tx_0 = lch.get_next_coinbase_tx(&pkh, &"example.com".to_string());
+        tx_out_bn_map.add_tx_outputs(&coinbase_tx_0, 0);
+        lch.add(block_from_txs(&lch, vec![coinbase_tx_0]).header);
+
+        let coinbase_tx_1 = lch.get_next_coinbase_tx(&pkh, &"example.com".to_string());
+        let mut tx_builder = TxBuilder::new(&tx_out_bn_map, Script::from_pkh_output(&pkh.buf), 1);
+        let amount = Header::coinbase_amount(0);
+        tx_builder.add_output(TxOut::new(amount, Script::from_pkh_output(&pkh.buf)));
+        let tx = tx_builder.build().unwrap();
+        let mut tx_signer = TxSigner::new(tx, &tx_out_bn_map, &pkh_key_map, 1);
+        let tx = tx_signer.sign().unwrap();
+        let block_1 = block_from_txs(&lch, vec![coinbase_tx_1, tx.clone(), tx.clone()]);
+        let mut block_verifier = BlockVerifier::new(block_1, &tx_out_bn_map, &lch);
+        match block_verifier.txs_are_valid() {
+            Err(VerifyError::InvalidTx { n_tx, tx_id, err }) => {
+                assert_eq!(n_tx, 2);
+                assert_eq!(tx_id, tx.id());
+                assert!(matches!(*err, VerifyError::InputNotFound { n_in: 0, .. }));
+            }
+            _ => panic!("expected invalid tx"),
         }
-        if !self.merkle_root_is_valid() {
-            return false;
+    }
+
+    // a block at height 1 with a tx that spends the block's own coinbase
+    fn block_spending_own_coinbase() -> (HeaderChain, TxOutBnMap, Block) {
+        let key = KeyPair::from_random();
+        let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
+        let mut pkh_key_map = PkhKeyMap::new();
+        pkh_key_map.add(key, &pkh.buf);
+        let mut lch = HeaderChain::new();
+        let tx_out_bn_map = TxOutBnMap::new();
+        let coinbase_tx_0 = lch.get_next_coinbase_tx(&pkh, &"example.com".to_string());
+        lch.add(block_from_txs(&lch, vec![coinbase_tx_0]).header);
+
+        let coinbase_tx_1 = lch.get_next_coinbase_tx(&pkh, &"example.com".to_string());
+        let mut signing_map = TxOutBnMap::new();
+        signing_map.add_tx_outputs(&coinbase_tx_1, 1);
+        let tx_in = TxIn::new(
+            coinbase_tx_1.id(),
+            0,
+            Script::from_pkh_input_placeholder(),
+            0,
+        );
+        let tx_out = TxOut::new(100, Script::from_pkh_output(&pkh.buf));
+        let tx = Tx::new(1, vec![tx_in], vec![tx_out], 1);
+        let mut tx_signer = TxSigner::new(tx, &signing_map, &pkh_key_map, 1);
+        let tx = tx_signer.sign().unwrap();
+        let block = block_from_txs(&lch, vec![coinbase_tx_1, tx]);
+        (lch, tx_out_bn_map, block)
+    }
+
+    #[test]
+    fn test_spending_own_coinbase_is_invalid_in_order() {
+        let (lch, tx_out_bn_map, block) = block_spending_own_coinbase();
+        let block_verifier = BlockVerifier::new(block, &tx_out_bn_map, &lch);
+        match block_verifier.verify_txs_in_order() {
+            Err(VerifyError::InvalidTx { n_tx, err, .. }) => {
+                assert_eq!(n_tx, 1);
+                assert!(matches!(*err, VerifyError::InputNotFound { n_in: 0, .. }));
+            }
+            _ => panic!("expected invalid tx"),
         }
-        if !self.txs_are_valid() {
-            return false;
+    }
+
+    #[test]
+    fn test_spending_own_coinbase_is_invalid() {
+        let (lch, tx_out_bn_map, block) = block_spending_own_coinbase();
+        let mut block_verifier = BlockVerifier::new(block, &tx_out_bn_map, &lch);
+        let res = block_verifier.txs_are_valid();
+        match &res {
+            Err(VerifyError::InvalidTx { n_tx, err, .. }) => {
+                assert_eq!(*n_tx, 1);
+                assert!(matches!(**err, VerifyError::InputNotFound { n_in: 0, .. }));
+            }
+            _ => panic!("expected invalid tx"),
         }
-        true
+        assert_eq!(block_verifier.txs_are_valid_in_parallel(), res);
+        let spend_graph = SpendGraph::new(&block_verifier.block.txs, &tx_out_bn_map, 1).unwrap();
+        assert!(spend_graph.inputs[1].map.is_empty());
     }
 
-    pub fn is_valid_now(&mut self) -> bool {
-        let timestamp = Header::get_new_timestamp();
-        self.is_valid_at(timestamp)
+    // a block at height 1 where each tx spends one output of a funding tx
+    fn block_with_spends(n: u32) -> (HeaderChain, TxOutBnMap, Block) {
+        let key = KeyPair::from_random();
+        let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
+        let mut pkh_key_map = PkhKeyMap::new();
+        pkh_key_map.add(key, &pkh.buf);
+        let mut lch = HeaderChain::new();
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        let coinbase_tx_0 = lch.get_next_coinbase_tx(&pkh, &"example.com".to_string());
+        lch.add(block_from_txs(&lch, vec![coinbase_tx_0]).header);
+
+        let outputs = (0..n)
+            .map(|_| TxOut::new(100, Script::from_pkh_output(&pkh.buf)))
+            .collect();
+        let funding_tx = Tx::new(1, vec![], outputs, 0);
+        tx_out_bn_map.add_tx_outputs(&funding_tx, 0);
+
+        let mut txs = vec![lch.get_next_coinbase_tx(&pkh, &"example.com".to_string())];
+        for tx_out_num in 0..n {
+            let tx_in = TxIn::new(
+                funding_tx.id(),
+                tx_out_num,
+                Script::from_pkh_input_placeholder(),
+                0,
+            );
+            let tx_out = TxOut::new(100, Script::from_pkh_output(&pkh.buf));
+            let tx = Tx::new(1, vec![tx_in], vec![tx_out], 1);
+            let mut tx_signer = TxSigner::new(tx, &tx_out_bn_map, &pkh_key_map, 1);
+            txs.push(tx_signer.sign().unwrap());
+        }
+        let block = block_from_txs(&lch, txs);
+        (lch, tx_out_bn_map, block)
+    }
+
+    #[test]
+    fn test_txs_are_valid_with_threads() {
+        for n_threads in [1, 4] {
+            let (lch, tx_out_bn_map, block) = block_with_spends(20);
+            let mut block_verifier = BlockVerifier::new(block, &tx_out_bn_map, &lch);
+            block_verifier.n_threads = n_threads;
+            assert!(block_verifier.txs_are_valid().is_ok());
+            assert_eq!(block_verifier.utxo_delta.created.map.len(), 21);
+            assert_eq!(block_verifier.utxo_delta.spent.map.len(), 20);
+
+            block_verifier.utxo_delta = UtxoDelta::new(1);
+            assert!(block_verifier.txs_are_valid_in_parallel().is_ok());
+            assert_eq!(block_verifier.utxo_delta.created.map.len(), 21);
+            assert_eq!(block_verifier.utxo_delta.spent.map.len(), 20);
+
+            block_verifier.utxo_delta = UtxoDelta::new(1);
+            assert!(block_verifier.txs_are_valid_in_order().is_ok());
+            assert_eq!(block_verifier.utxo_delta.created.map.len(), 21);
+            assert_eq!(block_verifier.utxo_delta.spent.map.len(), 20);
+        }
+    }
+
+    #[test]
+    fn test_bad_signature_is_reported_as_without_deferral() {
+        let (lch, tx_out_bn_map, mut block) = block_with_spends(20);
+        let script = &mut block.txs[7].inputs[0].script;
+        let mut sig_buf = script.chunks[0].buffer.clone().unwrap();
+        sig_buf[20] ^= 1;
+        *script = Script::from_pkh_input(&sig_buf, &script.chunks[1].buffer.clone().unwrap());
+        let tx_id = block.txs[7].id();
+
+        let mut block_verifier = BlockVerifier::new(block, &tx_out_bn_map, &lch);
+        block_verifier.n_threads = 4;
+        let res = block_verifier.txs_are_valid();
+        match &res {
+            Err(VerifyError::InvalidTx {
+                n_tx,
+                tx_id: err_tx_id,
+                err,
+            }) => {
+                assert_eq!(*n_tx, 7);
+                assert_eq!(*err_tx_id, tx_id);
+                assert!(matches!(
+                    **err,
+                    VerifyError::InputScriptFailed { n_in: 0, .. }
+                ));
+            }
+            _ => panic!("expected invalid tx"),
+        }
+        assert_eq!(block_verifier.txs_are_valid_in_parallel(), res);
+    }
+
+    #[test]
+    fn test_sig_cache_shared_with_mempool() {
+        let (lch, tx_out_bn_map, block) = block_with_spends(10);
+        let sig_cache = Arc::new(SigCache::default());
+        let mut mempool = Mempool::new();
+        mempool.set_sig_cache(sig_cache.clone());
+        for tx in &block.txs[1..] {
+            mempool.add_tx(tx.clone(), &tx_out_bn_map, 1).unwrap();
+        }
+        let stats = sig_cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (0, 10, 10));
+
+        let mut block_verifier = BlockVerifier::new(block, &tx_out_bn_map, &lch);
+        block_verifier.sig_cache = Some(sig_cache.clone());
+        assert!(block_verifier.txs_are_valid().is_ok());
+        assert_eq!(sig_cache.stats().hits, 10);
+        assert!(block_verifier.txs_are_valid_in_parallel().is_ok());
+        let stats = sig_cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (20, 10, 10));
+    }
+
+    #[test]
+    fn test_txs_are_valid_with_spends_in_block() {
+        let key = KeyPair::from_random();
+        let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
+        let mut pkh_key_map = PkhKeyMap::new();
+        pkh_key_map.add(key, &pkh.buf);
+        let mut lch = HeaderChain::new();
+        let coinbase_tx_0 = lch.get_next_coinbase_tx(&pkh, &"example.com".to_string());
+        lch.add(block_from_txs(&lch, vec![coinbase_tx_0]).header);
+
+        // tx_2 spends the output of tx_1, which spends the funding tx
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        let script = Script::from_pkh_output(&pkh.buf);
+        let funding_tx = Tx::new(1, vec![], vec![TxOut::new(100, script.clone())], 0);
+        tx_out_bn_map.add_tx_outputs(&funding_tx, 0);
+        let mut txs = Vec::new();
+        let mut signing_map = tx_out_bn_map.clone();
+        let mut input_tx_id = funding_tx.id();
+        for _ in 0..2 {
+            let tx_in = TxIn::new(input_tx_id, 0, Script::from_pkh_input_placeholder(), 0);
+            let tx = Tx::new(1, vec![tx_in], vec![TxOut::new(100, script.clone())], 1);
+            let mut tx_signer = TxSigner::new(tx, &signing_map, &pkh_key_map, 1);
+            let tx = tx_signer.sign().unwrap();
+            signing_map.add_tx_outputs(&tx, 1);
+            input_tx_id = tx.id();
+            txs.push(tx);
+        }
+
+        let coinbase_tx_1 = lch.get_next_coinbase_tx(&pkh, &"example.com".to_string());
+        let block = block_from_txs(
+            &lch,
+            vec![coinbase_tx_1.clone(), txs[0].clone(), txs[1].clone()],
+        );
+        let mut block_verifier = BlockVerifier::new(block, &tx_out_bn_map, &lch);
+        block_verifier.n_threads = 4;
+        assert!(block_verifier.txs_are_valid().is_ok());
+        assert_eq!(block_verifier.utxo_delta.spent.map.len(), 1);
+
+        // in the other order the spent output does not exist yet
+        let block = block_from_txs(&lch, vec![coinbase_tx_1, txs[1].clone(), txs[0].clone()]);
+        let mut block_verifier = BlockVerifier::new(block, &tx_out_bn_map, &lch);
+        block_verifier.n_threads = 4;
+        match block_verifier.txs_are_valid() {
+            Err(VerifyError::InvalidTx { n_tx, err, .. }) => {
+                assert_eq!(n_tx, 1);
+                assert!(matches!(*err, VerifyError::InputNotFound { n_in: 0, .. }));
+            }
+            _ => panic!("expected invalid tx"),
+        }
+    }
+
+    // a utxo set on a failing disk
+    struct FailingStore;
+
+    impl TxOutBnStore for FailingStore {
+        fn get(&self, _: &[u8; 32], _: u32) -> Result<Option<TxOutBn>, EbxError> {
+            Err(EbxError::generic("disk failed"))
+        }
+
+        fn for_each(&self, _: &mut dyn FnMut(&[u8; 32], u32, &TxOutBn)) -> Result<(), EbxError> {
+            Err(EbxError::generic("disk failed"))
+        }
+
+        fn len(&self) -> usize {
+            0
+        }
+
+        fn apply_batch(&mut self, _: &TxOutBnBatch) -> Result<TxOutBnBatch, EbxError> {
+            Err(EbxError::generic("disk failed"))
+        }
+    }
+
+    #[test]
+    fn test_store_error_is_not_an_invalid_tx() {
+        let (lch, _, block) = block_with_spends(3);
+        let mut tx_verifier = TxVerifier::new(block.txs[1].clone(), &FailingStore, 1);
+        assert!(matches!(
+            tx_verifier.verify(),
+            Err(VerifyError::StoreError { .. })
+        ));
+
+        let mut block_verifier = BlockVerifier::new(block, &FailingStore, &lch);
+        assert!(matches!(
+            block_verifier.txs_are_valid(),
+            Err(VerifyError::StoreError { .. })
+        ));
+        assert!(matches!(
+            block_verifier.txs_are_valid_in_parallel(),
+            Err(VerifyError::StoreError { .. })
+        ));
+        assert!(matches!(
+            block_verifier.verify_txs_in_order(),
+            Err(VerifyError::StoreError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_txs_are_valid_fills_sig_cache() {
+        let (lch, tx_out_bn_map, block) = block_with_spends(10);
+        let sig_cache = Arc::new(SigCache::default());
+        let mut block_verifier = BlockVerifier::new(block, &tx_out_bn_map, &lch);
+        block_verifier.n_threads = 4;
+        block_verifier.sig_cache = Some(sig_cache.clone());
+        assert!(block_verifier.txs_are_valid().is_ok());
+        let stats = sig_cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (0, 10, 10));
+
+        assert!(block_verifier.txs_are_valid().is_ok());
+        let stats = sig_cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (10, 10, 10));
     }
 }
@@ -86,6 +86,62 @@ impl<const N: usize> EbxBuf for [u8; N] {
     }
 }
 
+// serde adapter for fixed size buffers such as ids and hashes, which are
+// strict hex in json. use with #[serde(with = "crate::buf::hex_fixed")].
+pub mod hex_fixed {
+    use super::EbxBuf;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        buf: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&buf.to_strict_hex())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if !super::is_valid(&s) {
+            return Err(de::Error::custom("invalid hex"));
+        }
+        <[u8; N]>::from_strict_hex(&s).map_err(de::Error::custom)
+    }
+}
+
+// the same for a list of fixed size buffers, such as the pub keys of a mine.
+// use with #[serde(with = "crate::buf::hex_fixed_vec")].
+pub mod hex_fixed_vec {
+    use super::EbxBuf;
+    use serde::{de, ser::SerializeSeq, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bufs: &[[u8; N]],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(bufs.len()))?;
+        for buf in bufs {
+            seq.serialize_element(&buf.to_strict_hex())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<Vec<[u8; N]>, D::Error> {
+        let strs = Vec::<String>::deserialize(deserializer)?;
+        strs.iter()
+            .map(|s| {
+                if !super::is_valid(s) {
+                    return Err(de::Error::custom("invalid hex"));
+                }
+                <[u8; N]>::from_strict_hex(s).map_err(de::Error::custom)
+            })
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,492 @@
+use crate::block_verifier::BlockVerifier;
+use crate::error::EbxError;
+use crate::header::Header;
+use crate::header_chain::{HeaderChain, HeaderInsert};
+use crate::tx_out_bn_store::TxOutBnStore;
+use crate::verify_error::VerifyError;
+use crate::wire_message::{InvItem, WireMessage};
+use std::net::TcpStream;
+use std::time::Duration;
+
+// headers-first sync with one peer. all headers are fetched and checked
+// first, which is cheap, and then the blocks of the best chain are fetched one
+// at a time, checked against those headers and applied to the utxo set.
+//
+// the state machine does no io itself. start gives the first request, and
+// each message from the peer gives the next request, if any. sync_with drives
+// it over a tcp stream.
+//
+// a block that does not match the merkle root of its header may just have been
+// mangled by the peer, but one whose txs are invalid makes the header invalid
+// too, and it is marked so in lch, so that the next sync goes on with the best
+// valid branch instead of asking for the same block again.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainSyncState {
+    Headers,
+    Blocks,
+    Synced,
+}
+
+pub struct ChainSync {
+    pub lch: HeaderChain,
+    pub tx_out_bn_store: Box<dyn TxOutBnStore>,
+    pub n_blocks: u32, // blocks of lch already applied to tx_out_bn_store
+    state: ChainSyncState,
+}
+
+impl ChainSync {
+    pub fn new(lch: HeaderChain, tx_out_bn_store: Box<dyn TxOutBnStore>, n_blocks: u32) -> Self {
+        Self {
+            lch,
+            tx_out_bn_store,
+            n_blocks,
+            state: ChainSyncState::Synced,
+        }
+    }
+
+    pub fn state(&self) -> ChainSyncState {
+        self.state
+    }
+
+    pub fn start(&mut self) -> WireMessage {
+        self.state = ChainSyncState::Headers;
+        WireMessage::GetHeaders {
+            locator: self.lch.get_locator(),
+        }
+    }
+
+    fn next_block_request(&mut self) -> Option<WireMessage> {
+        match self.lch.headers.get(self.n_blocks as usize) {
+            Some(header) => {
+                self.state = ChainSyncState::Blocks;
+                Some(WireMessage::GetBlock { id: header.id() })
+            }
+            None => {
+                self.state = ChainSyncState::Synced;
+                None
+            }
+        }
+    }
+
+    fn unexpected(message: &WireMessage) -> EbxError {
+        EbxError::GenericError {
+            source: None,
+            message: format!("unexpected message {}", message.command()),
+        }
+    }
+
+    // returns the next request to send to the peer, if any. timestamp is the
+    // current time, which headers must not be ahead of.
+    pub fn handle_message_at(
+        &mut self,
+        message: WireMessage,
+        timestamp: u64,
+    ) -> Result<Option<WireMessage>, EbxError> {
+        match message {
+            WireMessage::Headers(headers) => {
+                if self.state != ChainSyncState::Headers {
+                    return Err(ChainSync::unexpected(&WireMessage::Headers(headers)));
+                }
+                // no reorgs: blocks that have been applied must stay
+                self.lch.pin(self.n_blocks)?;
+                let mut is_new = false;
+                for header in headers {
+                    if self.lch.insert_at(header, timestamp)? != HeaderInsert::Known {
+                        is_new = true;
+                    }
+                }
+                if is_new {
+                    return Ok(Some(self.start()));
+                }
+                Ok(self.next_block_request())
+            }
+            WireMessage::Block(block) => {
+                let expected_id = self.lch.headers.get(self.n_blocks as usize).map(Header::id);
+                if self.state != ChainSyncState::Blocks || expected_id != Some(block.header.id()) {
+                    return Err(ChainSync::unexpected(&WireMessage::Block(block)));
+                }
+                let id = block.header.id();
+                let block_is_invalid = |err: VerifyError| EbxError::GenericError {
+                    source: None,
+                    message: format!("block is invalid: {}", err),
+                };
+                let mut block_verifier =
+                    BlockVerifier::new(block, self.tx_out_bn_store.as_ref(), &self.lch);
+                // the header was checked when it was added to lch
+                block_verifier
+                    .merkle_root_is_valid()
+                    .map_err(block_is_invalid)?;
+                let result = block_verifier.txs_are_valid();
+                let utxo_delta = block_verifier.utxo_delta;
+                match result {
+                    Ok(()) => {}
+                    // nothing is known about the block if the utxo set could
+                    // not be read
+                    Err(err @ VerifyError::StoreError { .. }) => return Err(err.into()),
+                    Err(err) => {
+                        self.lch.invalidate(&id)?;
+                        return Err(block_is_invalid(err));
+                    }
+                }
+                utxo_delta.apply(self.tx_out_bn_store.as_mut())?;
+                self.n_blocks += 1;
+                Ok(self.next_block_request())
+            }
+            WireMessage::Inv(items) => {
+                let has_new_block = items.iter().any(|item| match item {
+                    InvItem::Block(id) => !self.lch.contains(id),
+                    InvItem::Tx(_) => false,
+                });
+                if has_new_block && self.state == ChainSyncState::Synced {
+                    return Ok(Some(self.start()));
+                }
+                Ok(None)
+            }
+            // serving peers and relaying txs are not part of sync
+            WireMessage::GetHeaders { .. } | WireMessage::GetBlock { .. } | WireMessage::Tx(_) => {
+                Ok(None)
+            }
+        }
+    }
+
+    // runs until every header and block the peer has is synced. a peer that
+    // does not answer a request within timeout, for example because it does
+    // not have the block, ends the sync with an error.
+    pub fn sync_with(&mut self, stream: &mut TcpStream, timeout: Duration) -> Result<(), EbxError> {
+        stream
+            .set_read_timeout(Some(timeout))
+            .map_err(|e| EbxError::io("unable to set read timeout", &e))?;
+        self.start().write_to(stream)?;
+        while self.state != ChainSyncState::Synced {
+            let message = WireMessage::read_from(stream)?;
+            if let Some(request) = self.handle_message_at(message, Header::get_new_timestamp())? {
+                request.write_to(stream)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::key_pair::KeyPair;
+    use crate::merkle_txs::MerkleTxs;
+    use crate::pkh::Pkh;
+    use crate::pkh_key_map::PkhKeyMap;
+    use crate::script::Script;
+    use crate::tx_builder::TxBuilder;
+    use crate::tx_out::TxOut;
+    use crate::tx_out_bn_map::TxOutBnMap;
+    use crate::tx_signer::TxSigner;
+    use std::collections::HashMap;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    // a mine that answers requests from its own chain
+    struct FakeMine {
+        lch: HeaderChain,
+        blocks: HashMap<[u8; 32], Block>,
+        max_headers: usize,
+    }
+
+    impl FakeMine {
+        // n_blocks blocks where block 1 spends the coinbase of block 0
+        fn new(n_blocks: u32) -> Self {
+            let key = KeyPair::from_random();
+            let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
+            let mut pkh_key_map = PkhKeyMap::new();
+            pkh_key_map.add(key, &pkh.buf);
+            let mut mine = Self {
+                lch: HeaderChain::new(),
+                blocks: HashMap::new(),
+                max_headers: WireMessage::MAX_HEADERS,
+            };
+            let mut tx_out_bn_map = TxOutBnMap::new();
+            for block_num in 0..n_blocks {
+                let mut txs = vec![mine
+                    .lch
+                    .get_next_coinbase_tx(&pkh, &"example.com".to_string())];
+                if block_num == 1 {
+                    let mut tx_builder =
+                        TxBuilder::new(&tx_out_bn_map, Script::from_pkh_output(&pkh.buf), 1);
+                    let amount = Header::coinbase_amount(0);
+                    tx_builder.add_output(TxOut::new(amount, Script::from_pkh_output(&pkh.buf)));
+                    let tx = tx_builder.build().unwrap();
+                    let mut tx_signer = TxSigner::new(tx, &tx_out_bn_map, &pkh_key_map, 1);
+                    txs.push(tx_signer.sign().unwrap());
+                }
+                let merkle_root = MerkleTxs::new(txs.clone()).root;
+                let timestamp = (block_num as u64 + 1) * Header::BLOCK_INTERVAL;
+                let header = mine.lch.get_next_header(merkle_root, timestamp).unwrap();
+                for tx in &txs {
+                    tx_out_bn_map.add_tx_outputs(tx, block_num);
+                }
+                mine.lch.add(header.clone());
+                mine.blocks.insert(header.id(), Block::new(header, txs));
+            }
+            mine
+        }
+
+        // the first n_keep blocks of this mine followed by coinbase only blocks
+        // of another mine, up to n_blocks in all
+        fn fork(&self, n_keep: u32, n_blocks: u32) -> Self {
+            self.fork_paying(n_keep, n_blocks, 0)
+        }
+
+        // a fork whose first coinbase pays extra more than it may
+        fn fork_paying(&self, n_keep: u32, n_blocks: u32, extra: u64) -> Self {
+            let pkh = Pkh::from_pub_key_buffer(KeyPair::from_random().pub_key.buf.to_vec());
+            let mut fork = Self {
+                lch: HeaderChain::new(),
+                blocks: HashMap::new(),
+                max_headers: self.max_headers,
+            };
+            for header in &self.lch.headers[..n_keep as usize] {
+                fork.lch.add(header.clone());
+                fork.blocks
+                    .insert(header.id(), self.blocks[&header.id()].clone());
+            }
+            for block_num in n_keep..n_blocks {
+                let mut txs = vec![fork
+                    .lch
+                    .get_next_coinbase_tx(&pkh, &"fork.example.com".to_string())];
+                if block_num == n_keep {
+                    txs[0].outputs[0].value += extra;
+                }
+                let merkle_root = MerkleTxs::new(txs.clone()).root;
+                let timestamp = (block_num as u64 + 1) * Header::BLOCK_INTERVAL;
+                let header = fork.lch.get_next_header(merkle_root, timestamp).unwrap();
+                fork.lch.add(header.clone());
+                fork.blocks.insert(header.id(), Block::new(header, txs));
+            }
+            fork
+        }
+
+        fn respond(&self, message: WireMessage) -> Option<WireMessage> {
+            match message {
+                WireMessage::GetHeaders { locator } => Some(WireMessage::Headers(
+                    self.lch.get_headers_after(&locator, self.max_headers),
+                )),
+                WireMessage::GetBlock { id } => {
+                    self.blocks.get(&id).cloned().map(WireMessage::Block)
+                }
+                _ => None,
+            }
+        }
+
+        // serves one connection on a loopback port until it is closed
+        fn serve(self) -> (u16, thread::JoinHandle<()>) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let handle = thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                while let Ok(message) = WireMessage::read_from(&mut stream) {
+                    if let Some(response) = self.respond(message) {
+                        response.write_to(&mut stream).unwrap();
+                    }
+                }
+            });
+            (port, handle)
+        }
+    }
+
+    fn new_sync() -> ChainSync {
+        ChainSync::new(HeaderChain::new(), Box::new(TxOutBnMap::new()), 0)
+    }
+
+    #[test]
+    fn test_sync_over_tcp() {
+        let mut mine = FakeMine::new(6);
+        // several rounds of getheaders
+        mine.max_headers = 4;
+        let tip_id = mine.lch.get_tip().unwrap().id();
+        let (port, handle) = mine.serve();
+
+        let mut sync = new_sync();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        sync.sync_with(&mut stream, Duration::from_secs(10))
+            .unwrap();
+        drop(stream);
+        handle.join().unwrap();
+
+        assert_eq!(sync.state(), ChainSyncState::Synced);
+        assert_eq!(sync.lch.headers.len(), 6);
+        assert_eq!(sync.lch.get_tip().unwrap().id(), tip_id);
+        assert_eq!(sync.n_blocks, 6);
+        // 6 coinbase outputs, one spent and replaced by block 1
+        assert_eq!(sync.tx_out_bn_store.len(), 6);
+    }
+
+    #[test]
+    fn test_catch_up_after_inv() {
+        let mine = FakeMine::new(5);
+        let mut sync = new_sync();
+        let now = Header::get_new_timestamp();
+
+        // sync the first 3 blocks by hand
+        let mut request = Some(sync.start());
+        while let Some(message) = request {
+            let mut response = mine.respond(message).unwrap();
+            if let WireMessage::Headers(headers) = &mut response {
+                headers.truncate(3usize.saturating_sub(sync.lch.headers.len()));
+            }
+            request = sync.handle_message_at(response, now).unwrap();
+        }
+        assert_eq!(sync.n_blocks, 3);
+
+        // an inv for a known block is ignored, an unknown one restarts sync
+        let known = WireMessage::Inv(vec![InvItem::Block(mine.lch.headers[2].id())]);
+        assert!(sync.handle_message_at(known, now).unwrap().is_none());
+        let new_block = InvItem::Block(mine.lch.get_tip().unwrap().id());
+        let mut request = sync
+            .handle_message_at(WireMessage::Inv(vec![new_block]), now)
+            .unwrap();
+        assert_eq!(sync.state(), ChainSyncState::Headers);
+        while let Some(message) = request {
+            request = sync
+                .handle_message_at(mine.respond(message).unwrap(), now)
+                .unwrap();
+        }
+        assert_eq!(sync.n_blocks, 5);
+    }
+
+    #[test]
+    fn test_refuse_reorg_of_applied_blocks() {
+        let mine = FakeMine::new(3);
+        let mut sync = new_sync();
+        let now = Header::get_new_timestamp();
+        let mut request = Some(sync.start());
+        while let Some(message) = request {
+            request = sync
+                .handle_message_at(mine.respond(message).unwrap(), now)
+                .unwrap();
+        }
+        assert_eq!(sync.n_blocks, 3);
+
+        // a heavier chain that forks off below the applied blocks
+        let fork = mine.fork(1, 5);
+        let request = sync.start();
+        assert!(sync
+            .handle_message_at(fork.respond(request).unwrap(), now)
+            .is_err());
+        assert_eq!(sync.lch.headers.len(), 3);
+        assert_eq!(
+            sync.lch.get_tip().unwrap().id(),
+            mine.lch.get_tip().unwrap().id()
+        );
+
+        // one that keeps them is synced
+        let fork = mine.fork(3, 5);
+        let mut request = Some(sync.start());
+        while let Some(message) = request {
+            request = sync
+                .handle_message_at(fork.respond(message).unwrap(), now)
+                .unwrap();
+        }
+        assert_eq!(sync.n_blocks, 5);
+        assert_eq!(
+            sync.lch.get_tip().unwrap().id(),
+            fork.lch.get_tip().unwrap().id()
+        );
+    }
+
+    #[test]
+    fn test_reject_bad_block() {
+        let mine = FakeMine::new(2);
+        let mut sync = new_sync();
+        let now = Header::get_new_timestamp();
+        let request = sync.start();
+        let request = sync
+            .handle_message_at(mine.respond(request).unwrap(), now)
+            .unwrap()
+            .unwrap();
+        let request = sync
+            .handle_message_at(mine.respond(request).unwrap(), now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(sync.state(), ChainSyncState::Blocks);
+
+        // a block that does not match the requested header
+        let WireMessage::GetBlock { id } = request else {
+            panic!("expected getblock");
+        };
+        let mut block = mine.blocks[&id].clone();
+        block.header.timestamp += 1;
+        assert!(sync
+            .handle_message_at(WireMessage::Block(block), now)
+            .is_err());
+
+        // a block whose txs do not match the merkle root
+        let mut block = mine.blocks[&id].clone();
+        block.txs.push(block.txs[0].clone());
+        assert!(sync
+            .handle_message_at(WireMessage::Block(block), now)
+            .is_err());
+        assert_eq!(sync.n_blocks, 0);
+
+        // headers are not expected while fetching blocks
+        let headers = WireMessage::Headers(vec![]);
+        assert!(sync.handle_message_at(headers, now).is_err());
+    }
+
+    #[test]
+    fn test_invalidate_bad_block() {
+        let mine = FakeMine::new(2);
+        let bad = mine.fork_paying(1, 4, 1);
+        let mut sync = new_sync();
+        let now = Header::get_new_timestamp();
+        let mut request = Some(sync.start());
+        let result = loop {
+            match sync.handle_message_at(bad.respond(request.unwrap()).unwrap(), now) {
+                Ok(next) => request = next,
+                Err(err) => break err,
+            }
+        };
+        assert!(result.to_string().contains("block is invalid"));
+        assert_eq!(sync.n_blocks, 1);
+        assert!(sync.lch.is_invalid(&bad.lch.headers[1].id()));
+        assert!(sync.lch.is_invalid(&bad.lch.get_tip().unwrap().id()));
+        assert_eq!(sync.lch.headers.len(), 1);
+
+        // the bad branch is not asked for again
+        let request = sync.start();
+        assert!(sync
+            .handle_message_at(bad.respond(request).unwrap(), now)
+            .unwrap()
+            .is_none());
+        assert_eq!(sync.state(), ChainSyncState::Synced);
+
+        // and a valid one is synced
+        let mut request = Some(sync.start());
+        while let Some(message) = request {
+            request = sync
+                .handle_message_at(mine.respond(message).unwrap(), now)
+                .unwrap();
+        }
+        assert_eq!(sync.n_blocks, 2);
+        assert_eq!(
+            sync.lch.get_tip().unwrap().id(),
+            mine.lch.get_tip().unwrap().id()
+        );
+    }
+
+    #[test]
+    fn test_sync_times_out_on_missing_block() {
+        let mut mine = FakeMine::new(3);
+        let id = mine.lch.headers[1].id();
+        mine.blocks.remove(&id);
+        let (port, handle) = mine.serve();
+
+        let mut sync = new_sync();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        assert!(sync
+            .sync_with(&mut stream, Duration::from_millis(200))
+            .is_err());
+        drop(stream);
+        handle.join().unwrap();
+        assert_eq!(sync.n_blocks, 1);
+    }
+}
@@ -1,65 +1,159 @@
-use regex::Regex;
+use crate::error::EbxError;
+use lazy_static::lazy_static;
+use publicsuffix::{List, Psl};
 
+lazy_static! {
+    // the public suffix list from https://publicsuffix.org, both its icann
+    // and its private sections, as of 2023-02-09. mines are told apart by
+    // their registrable domains, so every node must use the same copy, and it
+    // only changes along with the code.
+    static ref PUBLIC_SUFFIX_LIST: List = include_str!("public_suffix_list.dat")
+        .parse()
+        .expect("public suffix list is valid");
+}
+
+// a domain in its strict form is lower case ascii, with any international
+// labels in punycode, and is what goes in a coinbase. two domains are equal
+// exactly when their strict forms are equal, so anything typed by a user
+// goes through Domain::new first.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Domain {
     domain_str: String,
 }
 
 impl Domain {
-    pub fn new(domain_str: String) -> Self {
-        Self { domain_str }
+    pub const MIN_LEN: usize = 4;
+    pub const MAX_LEN: usize = 253;
+    pub const MAX_LABEL_LEN: usize = 63;
+    pub const MAX_LABELS: usize = 10;
+
+    // top level domains that never resolve on the public internet (rfc 2606,
+    // rfc 6761, rfc 7686)
+    pub const RESERVED_TLDS: [&'static str; 7] = [
+        "example",
+        "internal",
+        "invalid",
+        "local",
+        "localhost",
+        "onion",
+        "test",
+    ];
+
+    // normalizes case and converts international labels to punycode
+    pub fn new(domain_str: &str) -> Result<Self, EbxError> {
+        let invalid = || EbxError::GenericError {
+            source: None,
+            message: format!("invalid domain {:?}", domain_str),
+        };
+        let ascii = idna::domain_to_ascii_strict(domain_str).map_err(|_| invalid())?;
+        let domain = Self::from_strict_str(ascii);
+        if !domain.is_valid() {
+            return Err(invalid());
+        }
+        Ok(domain)
     }
 
     pub fn from_strict_str(domain_str: String) -> Self {
-        Self::new(domain_str)
+        Self { domain_str }
+    }
+
+    pub fn to_strict_str(&self) -> String {
+        self.domain_str.clone()
     }
 
     pub fn is_valid(&self) -> bool {
         Self::is_valid_domain(&self.domain_str)
     }
 
+    // true only for a domain in its strict form
     pub fn is_valid_domain(domain_str: &str) -> bool {
-        let domain_str = domain_str.trim();
-        if domain_str.len() < 4 {
+        if domain_str.len() < Domain::MIN_LEN || domain_str.len() > Domain::MAX_LEN {
             return false;
         }
-        if domain_str.starts_with('.') {
+        let labels: Vec<&str> = domain_str.split('.').collect();
+        if labels.len() < 2 || labels.len() > Domain::MAX_LABELS {
             return false;
         }
-        if domain_str.ends_with('.') {
+        if !labels.iter().all(|label| Self::is_valid_label(label)) {
             return false;
         }
-        if !domain_str.contains('.') {
+        // an ip literal such as 127.0.0.1 is not a domain
+        let tld = labels[labels.len() - 1];
+        if tld.bytes().all(|b| b.is_ascii_digit()) {
             return false;
         }
-        if domain_str.contains("..") {
+        if Domain::RESERVED_TLDS.contains(&tld) {
             return false;
         }
-        let domain_parts: Vec<&str> = domain_str.split('.').collect();
-        if domain_parts.len() < 2 {
+        // punycode labels must decode, and mapping must change nothing
+        idna::domain_to_ascii_strict(domain_str).is_ok_and(|ascii| ascii == domain_str)
+    }
+
+    // the consensus rule for the domain at the top of a coinbase input script,
+    // the same as Domain.isValidDomain in ts. it is looser than
+    // is_valid_domain about whitespace and reserved tlds and stricter about
+    // hyphens, and must not change without ts changing with it.
+    pub fn is_valid_coinbase_domain(domain_str: &str) -> bool {
+        let domain_str = domain_str.trim();
+        if domain_str.len() < Domain::MIN_LEN {
             return false;
         }
-        if domain_parts.len() > 10 {
+        if domain_str.starts_with('.') || domain_str.ends_with('.') {
             return false;
         }
-        if domain_parts.iter().any(|&part| part.len() > 63) {
+        if !domain_str.contains('.') || domain_str.contains("..") {
             return false;
         }
-        let re = Regex::new(r"^[a-z0-9]+$").unwrap();
-        if domain_parts.iter().any(|&part| !re.is_match(part)) {
+        let labels: Vec<&str> = domain_str.split('.').collect();
+        if labels.len() < 2 || labels.len() > Domain::MAX_LABELS {
             return false;
         }
+        labels.iter().all(|label| {
+            label.len() <= Domain::MAX_LABEL_LEN
+                && !label.is_empty()
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+        })
+    }
 
-        if domain_parts.iter().any(|&part| part.starts_with('-')) {
+    fn is_valid_label(label: &str) -> bool {
+        if label.is_empty() || label.len() > Domain::MAX_LABEL_LEN {
+            return false;
+        }
+        if !label
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        {
             return false;
         }
-        if domain_parts.iter().any(|&part| part.ends_with('-')) {
+        if label.starts_with('-') || label.ends_with('-') {
             return false;
         }
-        if domain_parts.iter().any(|&part| part.contains("--")) {
+        // "--" is only allowed as the punycode prefix
+        if label.strip_prefix("xn--").unwrap_or(label).contains("--") {
             return false;
         }
         true
     }
+
+    // the public suffix plus one label, which is the part that is bought from
+    // a registry, or handed out by one such as github.io. a tld that is not
+    // on the list counts as a public suffix. none if the domain is itself a
+    // public suffix or is not valid.
+    pub fn registrable_domain(&self) -> Option<Domain> {
+        if !self.is_valid() {
+            return None;
+        }
+        let domain = PUBLIC_SUFFIX_LIST.domain(self.domain_str.as_bytes())?;
+        let domain_str = std::str::from_utf8(domain.as_bytes()).ok()?;
+        Some(Self::from_strict_str(domain_str.to_string()))
+    }
+
+    // the domain with international labels shown as unicode
+    pub fn to_unicode_str(&self) -> String {
+        idna::domain_to_unicode(&self.domain_str).0
+    }
 }
 
 #[cfg(test)]
@@ -79,4 +173,120 @@ mod tests {
         )
         .is_valid());
     }
+
+    #[test]
+    fn test_strict_form_only() {
+        assert!(Domain::is_valid_domain("my-mine.earthbucks.com"));
+        assert!(Domain::is_valid_domain("xn--bcher-kva.com"));
+        assert!(!Domain::is_valid_domain("EarthBucks.com"));
+        assert!(!Domain::is_valid_domain(" earthbucks.com"));
+        assert!(!Domain::is_valid_domain("bücher.com"));
+        assert!(!Domain::is_valid_domain("-mine.earthbucks.com"));
+        assert!(!Domain::is_valid_domain("mine-.earthbucks.com"));
+        assert!(!Domain::is_valid_domain("ab--cd.com"));
+        // not valid punycode
+        assert!(!Domain::is_valid_domain("xn--a.com"));
+    }
+
+    #[test]
+    fn test_length_limits() {
+        let label = "a".repeat(63);
+        assert!(Domain::is_valid_domain(&format!("{}.com", label)));
+        assert!(!Domain::is_valid_domain(&format!("a{}.com", label)));
+        let long = [label.as_str(); 4].join(".");
+        assert_eq!(long.len(), 255);
+        assert!(!Domain::is_valid_domain(&long));
+        assert!(!Domain::is_valid_domain("a.b"));
+    }
+
+    #[test]
+    fn test_reject_ip_literals_and_reserved_tlds() {
+        assert!(!Domain::is_valid_domain("127.0.0.1"));
+        assert!(!Domain::is_valid_domain("[::1]"));
+        assert!(Domain::new("10.0.0.1").is_err());
+        assert!(!Domain::is_valid_domain("mine.localhost"));
+        assert!(!Domain::is_valid_domain("earthbucks.test"));
+        assert!(!Domain::is_valid_domain("earthbucks.onion"));
+        // a reserved second level domain is still a domain
+        assert!(Domain::is_valid_domain("example.com"));
+    }
+
+    #[test]
+    fn test_new_normalizes() {
+        let domain = Domain::new("EarthBucks.COM").unwrap();
+        assert_eq!(domain.to_strict_str(), "earthbucks.com");
+        let domain = Domain::new("Bücher.com").unwrap();
+        assert_eq!(domain.to_strict_str(), "xn--bcher-kva.com");
+        assert_eq!(domain.to_unicode_str(), "bücher.com");
+        assert_eq!(domain, Domain::new("xn--bcher-kva.com").unwrap());
+
+        assert!(Domain::new("earthbucks.com.").is_err());
+        assert!(Domain::new("earth bucks.com").is_err());
+        assert!(Domain::new("earthbucks").is_err());
+        assert!(Domain::new("").is_err());
+    }
+
+    #[test]
+    fn test_is_valid_coinbase_domain() {
+        assert!(Domain::is_valid_coinbase_domain("earthbucks.com"));
+        assert!(Domain::is_valid_coinbase_domain(" earthbucks.com "));
+        assert!(Domain::is_valid_coinbase_domain("mine.example"));
+        assert!(Domain::is_valid_coinbase_domain("127.0.0.1"));
+        assert!(!Domain::is_valid_coinbase_domain("my-mine.earthbucks.com"));
+        assert!(!Domain::is_valid_coinbase_domain("xn--bcher-kva.com"));
+        assert!(!Domain::is_valid_coinbase_domain("EarthBucks.com"));
+        assert!(!Domain::is_valid_coinbase_domain("earthbucks..com"));
+        assert!(!Domain::is_valid_coinbase_domain("earthbucks.com."));
+        assert!(!Domain::is_valid_coinbase_domain("a.b"));
+        assert!(!Domain::is_valid_coinbase_domain(&format!(
+            "{}.com",
+            "a".repeat(64)
+        )));
+        assert!(!Domain::is_valid_coinbase_domain(
+            "node.node.node.node.node.node.node.node.node.earthbucks.com"
+        ));
+    }
+
+    #[test]
+    fn test_registrable_domain() {
+        let registrable = |s: &str| {
+            Domain::new(s)
+                .unwrap()
+                .registrable_domain()
+                .map(|d| d.to_strict_str())
+        };
+        assert_eq!(
+            registrable("earthbucks.com"),
+            Some("earthbucks.com".to_string())
+        );
+        assert_eq!(
+            registrable("node.Mine.EarthBucks.com"),
+            Some("earthbucks.com".to_string())
+        );
+        assert_eq!(
+            registrable("mine.example.co.uk"),
+            Some("example.co.uk".to_string())
+        );
+        assert_eq!(registrable("co.uk"), None);
+        // suffixes from deeper in the list, and private ones
+        assert_eq!(registrable("a.me.uk"), Some("a.me.uk".to_string()));
+        assert_eq!(
+            registrable("node.x.github.io"),
+            Some("x.github.io".to_string())
+        );
+        assert_eq!(
+            registrable("mine.xn--55qx5d.cn"),
+            Some("mine.xn--55qx5d.cn".to_string())
+        );
+        assert_eq!(
+            registrable("node.mine.earthbucks"),
+            Some("mine.earthbucks".to_string())
+        );
+        assert_eq!(registrable("github.io"), None);
+        // an ip literal is not a domain
+        assert_eq!(
+            Domain::from_strict_str("127.0.0.1".to_string()).registrable_domain(),
+            None
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use crate::verify_error::VerifyError;
 use std::fmt;
 
 #[derive(Debug)]
@@ -36,6 +37,23 @@ pub enum EbxError {
     InvalidChecksumError {
         source: Option<Box<EbxError>>,
     },
+    VerifyError {
+        source: VerifyError,
+    },
+}
+
+impl EbxError {
+    pub fn generic(message: &str) -> Self {
+        EbxError::GenericError {
+            source: None,
+            message: message.to_string(),
+        }
+    }
+
+    // an error from the file system or network, with what was being done
+    pub fn io(message: &str, e: &std::io::Error) -> Self {
+        EbxError::generic(&format!("{}: {}", message, e))
+    }
 }
 
 impl fmt::Display for EbxError {
@@ -74,6 +92,36 @@ impl fmt::Display for EbxError {
             EbxError::InvalidChecksumError { .. } => {
                 write!(f, "invalid checksum")
             }
+            EbxError::VerifyError { source } => {
+                write!(f, "invalid: {}", source)
+            }
         }
     }
 }
+
+impl std::error::Error for EbxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EbxError::GenericError { source, .. }
+            | EbxError::InvalidSizeError { source }
+            | EbxError::NotEnoughDataError { source }
+            | EbxError::TooMuchDataError { source }
+            | EbxError::NonMinimalEncodingError { source }
+            | EbxError::InsufficientPrecisionError { source }
+            | EbxError::InvalidOpcodeError { source }
+            | EbxError::InvalidHexError { source }
+            | EbxError::InvalidEncodingError { source }
+            | EbxError::InvalidKeyError { source }
+            | EbxError::InvalidChecksumError { source } => source
+                .as_deref()
+                .map(|source| source as &(dyn std::error::Error + 'static)),
+            EbxError::VerifyError { source } => Some(source),
+        }
+    }
+}
+
+impl From<VerifyError> for EbxError {
+    fn from(source: VerifyError) -> Self {
+        EbxError::VerifyError { source }
+    }
+}
@@ -0,0 +1,349 @@
+use crate::buf::EbxBuf;
+use crate::error::EbxError;
+use crate::hash::blake3_hash;
+use crate::key_pair::KeyPair;
+use crate::priv_key::PrivKey;
+use crate::pub_key::PubKey;
+use secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey};
+
+// hierarchical deterministic keys. this follows the shape of bip32 but uses
+// blake3 everywhere bip32 uses hmac-sha512:
+//
+// - the master key and chain code are the 64 byte blake3 xof output of the
+//   seed in derive-key mode with the context HdPrivKey::MASTER_CONTEXT.
+// - a child is the 64 byte blake3 xof output keyed with the parent chain code
+//   over either 0x00 || parent priv key || index (hardened) or parent pub key
+//   || index (normal), with the index big endian. the first 32 bytes are added
+//   to the parent key and the last 32 bytes are the child chain code.
+//
+// normal children can be derived from the pub key alone, which is what
+// watch-only servers use. hardened children need the priv key.
+
+pub const HARDENED: u32 = 0x8000_0000;
+
+fn blake3_xof_64(hasher: &mut blake3::Hasher) -> ([u8; 32], [u8; 32]) {
+    let mut out = [0u8; 64];
+    hasher.finalize_xof().fill(&mut out);
+    (
+        out[0..32].try_into().unwrap(),
+        out[32..64].try_into().unwrap(),
+    )
+}
+
+fn child_tweak(chain_code: &[u8; 32], data: &[u8]) -> Result<(Scalar, [u8; 32]), EbxError> {
+    let mut hasher = blake3::Hasher::new_keyed(chain_code);
+    hasher.update(data);
+    let (tweak, child_chain_code) = blake3_xof_64(&mut hasher);
+    let tweak =
+        Scalar::from_be_bytes(tweak).map_err(|_| EbxError::InvalidKeyError { source: None })?;
+    Ok((tweak, child_chain_code))
+}
+
+// parses paths like "m/44'/0/1". a ' or h marks a hardened index.
+pub fn parse_path(path: &str) -> Result<Vec<u32>, EbxError> {
+    let invalid = || EbxError::GenericError {
+        source: None,
+        message: format!("invalid path: {}", path),
+    };
+    let mut parts = path.split('/');
+    if parts.next() != Some("m") {
+        return Err(invalid());
+    }
+    parts
+        .map(|part| {
+            let (num_str, hardened) = match part.strip_suffix(['\'', 'h']) {
+                Some(num_str) => (num_str, true),
+                None => (part, false),
+            };
+            if num_str.is_empty() || !num_str.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            let index: u32 = num_str.parse().map_err(|_| invalid())?;
+            if index >= HARDENED {
+                return Err(invalid());
+            }
+            Ok(if hardened { index | HARDENED } else { index })
+        })
+        .collect()
+}
+
+fn to_strict_str(prefix: &str, buf: &[u8]) -> String {
+    let check_sum: [u8; 4] = blake3_hash(buf)[0..4].try_into().unwrap();
+    prefix.to_string() + &check_sum.to_strict_hex() + &buf.to_vec().to_base58()
+}
+
+fn from_strict_str(prefix: &str, s: &str) -> Result<Vec<u8>, EbxError> {
+    if !s.starts_with(prefix) {
+        return Err(EbxError::InvalidEncodingError { source: None });
+    }
+    let check_str = s
+        .get(prefix.len()..prefix.len() + 8)
+        .ok_or(EbxError::InvalidEncodingError { source: None })?;
+    let check_sum = <[u8; 4]>::from_strict_hex(check_str)?;
+    let buf = Vec::<u8>::from_base58(&s[prefix.len() + 8..])?;
+    if check_sum != blake3_hash(&buf)[0..4] {
+        return Err(EbxError::InvalidChecksumError { source: None });
+    }
+    Ok(buf)
+}
+
+#[derive(Clone, Debug)]
+pub struct HdPrivKey {
+    pub priv_key: PrivKey,
+    pub chain_code: [u8; 32],
+}
+
+impl HdPrivKey {
+    pub const MASTER_CONTEXT: &'static str = "earthbucks hd master key";
+    pub const MIN_SEED_SIZE: usize = 16;
+    pub const MAX_SEED_SIZE: usize = 64;
+
+    pub fn new(priv_key: PrivKey, chain_code: [u8; 32]) -> Self {
+        Self {
+            priv_key,
+            chain_code,
+        }
+    }
+
+    pub fn from_seed(seed: &[u8]) -> Result<Self, EbxError> {
+        if seed.len() < HdPrivKey::MIN_SEED_SIZE {
+            return Err(EbxError::NotEnoughDataError { source: None });
+        }
+        if seed.len() > HdPrivKey::MAX_SEED_SIZE {
+            return Err(EbxError::TooMuchDataError { source: None });
+        }
+        let mut hasher = blake3::Hasher::new_derive_key(HdPrivKey::MASTER_CONTEXT);
+        hasher.update(seed);
+        let (key, chain_code) = blake3_xof_64(&mut hasher);
+        SecretKey::from_slice(&key).map_err(|_| EbxError::InvalidKeyError { source: None })?;
+        Ok(Self::new(PrivKey::new(key), chain_code))
+    }
+
+    pub fn derive_child(&self, index: u32) -> Result<Self, EbxError> {
+        let secret_key = SecretKey::from_slice(&self.priv_key.buf)
+            .map_err(|_| EbxError::InvalidKeyError { source: None })?;
+        let mut data = Vec::with_capacity(37);
+        if index >= HARDENED {
+            data.push(0);
+            data.extend_from_slice(&self.priv_key.buf);
+        } else {
+            data.extend_from_slice(&self.priv_key.to_pub_key_buffer()?);
+        }
+        data.extend_from_slice(&index.to_be_bytes());
+        let (tweak, chain_code) = child_tweak(&self.chain_code, &data)?;
+        let child = secret_key
+            .add_tweak(&tweak)
+            .map_err(|_| EbxError::InvalidKeyError { source: None })?;
+        Ok(Self::new(PrivKey::new(child.secret_bytes()), chain_code))
+    }
+
+    pub fn derive_path(&self, path: &str) -> Result<Self, EbxError> {
+        parse_path(path)?
+            .into_iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(index))
+    }
+
+    pub fn to_hd_pub_key(&self) -> Result<HdPubKey, EbxError> {
+        Ok(HdPubKey::new(
+            PubKey::from_priv_key(&self.priv_key)?,
+            self.chain_code,
+        ))
+    }
+
+    pub fn to_key_pair(&self) -> Result<KeyPair, EbxError> {
+        KeyPair::from_priv_key(&self.priv_key)
+    }
+
+    pub fn to_strict_str(&self) -> String {
+        let mut buf = self.chain_code.to_vec();
+        buf.extend_from_slice(&self.priv_key.buf);
+        to_strict_str("ebxhdprv", &buf)
+    }
+
+    pub fn from_strict_str(s: &str) -> Result<Self, EbxError> {
+        let buf = from_strict_str("ebxhdprv", s)?;
+        if buf.len() != 64 {
+            return Err(EbxError::InvalidSizeError { source: None });
+        }
+        let priv_key = PrivKey::from_buf(buf[32..64].to_vec())?;
+        SecretKey::from_slice(&priv_key.buf)
+            .map_err(|_| EbxError::InvalidKeyError { source: None })?;
+        Ok(Self::new(priv_key, buf[0..32].try_into().unwrap()))
+    }
+
+    pub fn is_valid_string_fmt(s: &str) -> bool {
+        Self::from_strict_str(s).is_ok()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HdPubKey {
+    pub pub_key: PubKey,
+    pub chain_code: [u8; 32],
+}
+
+impl HdPubKey {
+    pub fn new(pub_key: PubKey, chain_code: [u8; 32]) -> Self {
+        Self {
+            pub_key,
+            chain_code,
+        }
+    }
+
+    // only normal children can be derived without the priv key
+    pub fn derive_child(&self, index: u32) -> Result<Self, EbxError> {
+        if index >= HARDENED {
+            return Err(EbxError::GenericError {
+                source: None,
+                message: "cannot derive hardened child from pub key".to_string(),
+            });
+        }
+        let public_key = PublicKey::from_slice(&self.pub_key.buf)
+            .map_err(|_| EbxError::InvalidKeyError { source: None })?;
+        let mut data = self.pub_key.buf.to_vec();
+        data.extend_from_slice(&index.to_be_bytes());
+        let (tweak, chain_code) = child_tweak(&self.chain_code, &data)?;
+        let secp = Secp256k1::verification_only();
+        let child = public_key
+            .add_exp_tweak(&secp, &tweak)
+            .map_err(|_| EbxError::InvalidKeyError { source: None })?;
+        Ok(Self::new(PubKey::new(child.serialize()), chain_code))
+    }
+
+    pub fn derive_path(&self, path: &str) -> Result<Self, EbxError> {
+        parse_path(path)?
+            .into_iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(index))
+    }
+
+    pub fn to_strict_str(&self) -> String {
+        let mut buf = self.chain_code.to_vec();
+        buf.extend_from_slice(&self.pub_key.buf);
+        to_strict_str("ebxhdpub", &buf)
+    }
+
+    pub fn from_strict_str(s: &str) -> Result<Self, EbxError> {
+        let buf = from_strict_str("ebxhdpub", s)?;
+        if buf.len() != 32 + PubKey::SIZE {
+            return Err(EbxError::InvalidSizeError { source: None });
+        }
+        let pub_key = PubKey::from_buf(buf[32..].to_vec())?;
+        if !pub_key.is_valid() {
+            return Err(EbxError::InvalidKeyError { source: None });
+        }
+        Ok(Self::new(pub_key, buf[0..32].try_into().unwrap()))
+    }
+
+    pub fn is_valid_string_fmt(s: &str) -> bool {
+        Self::from_strict_str(s).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn master() -> HdPrivKey {
+        HdPrivKey::from_seed(&[1u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_from_seed_is_deterministic() {
+        let key1 = master();
+        let key2 = master();
+        assert_eq!(key1.priv_key.buf, key2.priv_key.buf);
+        assert_eq!(key1.chain_code, key2.chain_code);
+
+        let key3 = HdPrivKey::from_seed(&[2u8; 32]).unwrap();
+        assert_ne!(key1.priv_key.buf, key3.priv_key.buf);
+    }
+
+    #[test]
+    fn test_from_seed_size() {
+        assert!(HdPrivKey::from_seed(&[1u8; 15]).is_err());
+        assert!(HdPrivKey::from_seed(&[1u8; 65]).is_err());
+    }
+
+    #[test]
+    fn test_pub_derivation_matches_priv_derivation() {
+        let hd_priv_key = master().derive_path("m/44'/0'").unwrap();
+        let hd_pub_key = hd_priv_key.to_hd_pub_key().unwrap();
+        for index in 0..5 {
+            let from_priv = hd_priv_key.derive_child(index).unwrap();
+            let from_pub = hd_pub_key.derive_child(index).unwrap();
+            assert_eq!(
+                from_priv.to_hd_pub_key().unwrap().pub_key.buf,
+                from_pub.pub_key.buf
+            );
+            assert_eq!(from_priv.chain_code, from_pub.chain_code);
+        }
+    }
+
+    #[test]
+    fn test_hardened_differs_from_normal() {
+        let key = master();
+        let normal = key.derive_child(0).unwrap();
+        let hardened = key.derive_child(HARDENED).unwrap();
+        assert_ne!(normal.priv_key.buf, hardened.priv_key.buf);
+    }
+
+    #[test]
+    fn test_pub_cannot_derive_hardened() {
+        let hd_pub_key = master().to_hd_pub_key().unwrap();
+        assert!(hd_pub_key.derive_child(HARDENED).is_err());
+        assert!(hd_pub_key.derive_path("m/0/1'").is_err());
+    }
+
+    #[test]
+    fn test_derive_path() {
+        let key = master();
+        let by_path = key.derive_path("m/1'/2/3h").unwrap();
+        let by_child = key
+            .derive_child(1 | HARDENED)
+            .unwrap()
+            .derive_child(2)
+            .unwrap()
+            .derive_child(3 | HARDENED)
+            .unwrap();
+        assert_eq!(by_path.priv_key.buf, by_child.priv_key.buf);
+        assert_eq!(key.derive_path("m").unwrap().priv_key.buf, key.priv_key.buf);
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(parse_path("m").unwrap(), Vec::<u32>::new());
+        assert_eq!(parse_path("m/0/1'").unwrap(), vec![0, 1 | HARDENED]);
+        assert!(parse_path("").is_err());
+        assert!(parse_path("0/1").is_err());
+        assert!(parse_path("m/").is_err());
+        assert!(parse_path("m/x").is_err());
+        assert!(parse_path("m/+1").is_err());
+        assert!(parse_path("m/2147483648").is_err());
+    }
+
+    #[test]
+    fn test_strict_str_round_trip() {
+        let hd_priv_key = master().derive_path("m/0'").unwrap();
+        let s = hd_priv_key.to_strict_str();
+        assert!(s.starts_with("ebxhdprv"));
+        let hd_priv_key2 = HdPrivKey::from_strict_str(&s).unwrap();
+        assert_eq!(hd_priv_key.priv_key.buf, hd_priv_key2.priv_key.buf);
+        assert_eq!(hd_priv_key.chain_code, hd_priv_key2.chain_code);
+
+        let hd_pub_key = hd_priv_key.to_hd_pub_key().unwrap();
+        let s = hd_pub_key.to_strict_str();
+        assert!(s.starts_with("ebxhdpub"));
+        let hd_pub_key2 = HdPubKey::from_strict_str(&s).unwrap();
+        assert_eq!(hd_pub_key.pub_key.buf, hd_pub_key2.pub_key.buf);
+        assert_eq!(hd_pub_key.chain_code, hd_pub_key2.chain_code);
+    }
+
+    #[test]
+    fn test_is_valid_string_fmt() {
+        let s = master().to_strict_str();
+        assert!(HdPrivKey::is_valid_string_fmt(&s));
+        assert!(!HdPrivKey::is_valid_string_fmt(&s[0..s.len() - 1]));
+        assert!(!HdPrivKey::is_valid_string_fmt("ebxhdprv"));
+        assert!(!HdPubKey::is_valid_string_fmt(&s));
+    }
+}
@@ -5,20 +5,29 @@ use crate::error::EbxError;
 use crate::hash::{blake3_hash, double_blake3_hash};
 use crate::numbers::u256;
 use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Header {
     pub version: u8,
+    #[serde(with = "crate::buf::hex_fixed")]
     pub prev_block_id: [u8; 32],
+    #[serde(with = "crate::buf::hex_fixed")]
     pub merkle_root: [u8; 32],
     pub timestamp: u64, // milliseconds
     pub block_num: u32,
+    #[serde(with = "crate::numbers::u256_dec")]
     pub target: u256,
+    #[serde(with = "crate::numbers::u256_dec")]
     pub nonce: u256,
     pub work_ser_algo: u16,
+    #[serde(with = "crate::buf::hex_fixed")]
     pub work_ser_hash: [u8; 32],
     pub work_par_algo: u16,
+    #[serde(with = "crate::buf::hex_fixed")]
     pub work_par_hash: [u8; 32],
 }
 
@@ -143,13 +152,29 @@ impl Header {
         if self.block_num != lch.len() as u32 {
             return false;
         }
-        if self.prev_block_id != lch.last().unwrap().id() {
+        self.is_valid_after(lch)
+    }
+
+    // prev_headers are the headers just before this one, ending with its
+    // parent. only the last BLOCKS_PER_TARGET_ADJ_PERIOD of them are used, so
+    // a header on a side branch can be checked without the whole branch.
+    pub fn is_valid_after(&self, prev_headers: &[Header]) -> bool {
+        let Some(prev_header) = prev_headers.last() else {
+            return false;
+        };
+        if !self.is_version_valid() {
+            return false;
+        }
+        if self.block_num != prev_header.block_num + 1 {
+            return false;
+        }
+        if self.prev_block_id != prev_header.id() {
             return false;
         }
-        if self.timestamp <= lch.last().unwrap().timestamp {
+        if self.timestamp <= prev_header.timestamp {
             return false;
         }
-        if !self.is_target_valid(lch) {
+        if !self.is_target_valid(prev_headers) {
             return false;
         }
         if !self.is_id_valid() {
@@ -249,9 +274,7 @@ impl Header {
         let first_header = adjh[0].clone();
         let mut targets: Vec<BigUint> = Vec::new();
         for header in adjh {
-            let target =
-                BigUint::from_bytes_be(&BufWriter::new().write_u256_be(header.target).to_buf());
-            targets.push(target);
+            targets.push(header.target_num());
         }
         let target_sum: BigUint = targets.iter().sum();
         if new_timestamp <= first_header.timestamp {
@@ -281,6 +304,46 @@ impl Header {
         //u256::from_be_bytes(&res.to_bytes_be())
     }
 
+    fn target_num(&self) -> BigUint {
+        BigUint::from_bytes_be(&BufWriter::new().write_u256_be(self.target).to_buf())
+    }
+
+    // the expected number of hashes needed to find an id below the target
+    pub fn work(&self) -> BigUint {
+        (BigUint::from(1u8) << 256) / (self.target_num() + 1u8)
+    }
+
+    // how many times harder the target is than the easiest possible target,
+    // which has a difficulty of 1
+    pub fn difficulty(&self) -> f64 {
+        let max_target = BigUint::from_bytes_be(&Header::MAX_TARGET_BYTES);
+        max_target.to_f64().unwrap() / self.target_num().to_f64().unwrap()
+    }
+
+    // the total work of a chain of headers
+    pub fn chain_work(headers: &[Header]) -> BigUint {
+        headers.iter().map(Header::work).sum()
+    }
+
+    // hashes per second over the last window blocks of the chain: the work
+    // of those blocks divided by the time it took to find them. none if the
+    // chain is too short or the time is not positive.
+    pub fn estimate_hashrate(lch: &[Header], window: usize) -> Option<f64> {
+        if window == 0 || lch.len() < window + 1 {
+            return None;
+        }
+        let headers = &lch[lch.len() - window - 1..];
+        let first_timestamp = headers[0].timestamp;
+        let last_timestamp = headers[window].timestamp;
+        if last_timestamp <= first_timestamp {
+            return None;
+        }
+        // timestamps are in milliseconds
+        let seconds = (last_timestamp - first_timestamp) as f64 / 1000.0;
+        let work = Header::chain_work(&headers[1..]);
+        Some(work.to_f64().unwrap() / seconds)
+    }
+
     pub fn coinbase_amount(block_num: u32) -> u64 {
         // shift every 210,000 blocks ("halving")
         let shift_by = block_num / 210_000;
@@ -320,6 +383,39 @@ mod tests {
         assert_eq!(bh1.block_num, bh2.block_num);
     }
 
+    #[test]
+    fn test_json_round_trip() {
+        let bh1 = Header {
+            version: 0,
+            prev_block_id: [1; 32],
+            merkle_root: [2; 32],
+            timestamp: 3,
+            block_num: 4,
+            target: u256::MAX,
+            nonce: u256::from(5u8),
+            work_ser_algo: 6,
+            work_ser_hash: [7; 32],
+            work_par_algo: 8,
+            work_par_hash: [9; 32],
+        };
+        let json = serde_json::to_value(&bh1).unwrap();
+        assert_eq!(json["prevBlockId"], [1u8; 32].to_strict_hex());
+        assert_eq!(json["blockNum"], 4);
+        assert_eq!(json["target"], u256::MAX.to_str_radix(10));
+        assert_eq!(json["nonce"], "5");
+
+        let bh2: Header = serde_json::from_value(json).unwrap();
+        assert_eq!(bh2.to_buf(), bh1.to_buf());
+    }
+
+    #[test]
+    fn test_json_rejects_bad_u256() {
+        let bh1 = Header::from_buf([0; Header::SIZE]).unwrap();
+        let mut json = serde_json::to_value(&bh1).unwrap();
+        json["nonce"] = serde_json::json!("0x05");
+        assert!(serde_json::from_value::<Header>(json).is_err());
+    }
+
     #[test]
     fn test_to_buffer() {
         let bh1 = Header {
@@ -428,6 +524,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_work() {
+        let mut header = Header::from_genesis(0);
+        assert_eq!(header.work(), BigUint::from(1u8));
+        header.target = (u256::from(1u8) << 255) - u256::from(1u8);
+        assert_eq!(header.work(), BigUint::from(1u8) << 1);
+        header.target = u256::from(0u8);
+        assert_eq!(header.work(), BigUint::from(1u8) << 256);
+    }
+
+    #[test]
+    fn test_difficulty() {
+        let mut header = Header::from_genesis(0);
+        assert_eq!(header.difficulty(), 1.0);
+        header.target = u256::from_be_slice(&[0x0f; 32]).unwrap();
+        assert_eq!(header.difficulty(), 17.0);
+        header.target = u256::from(0u8);
+        assert!(header.difficulty().is_infinite());
+    }
+
+    #[test]
+    fn test_chain_work_and_hashrate() {
+        let mut lch = Vec::new();
+        for i in 0..4u64 {
+            let mut header = Header::from_genesis(i * Header::BLOCK_INTERVAL);
+            // work of 2^(i + 1)
+            header.target = (u256::from(1u8) << (255 - i as u32)) - u256::from(1u8);
+            lch.push(header);
+        }
+        assert_eq!(Header::chain_work(&lch), BigUint::from(30u8));
+        assert_eq!(Header::chain_work(&[]), BigUint::from(0u8));
+
+        // the work of the last blocks over the time between them
+        let interval = Header::BLOCK_INTERVAL as f64 / 1000.0;
+        assert_eq!(
+            Header::estimate_hashrate(&lch, 2),
+            Some(24.0 / (2.0 * interval))
+        );
+        assert_eq!(
+            Header::estimate_hashrate(&lch, 3),
+            Some(28.0 / (3.0 * interval))
+        );
+        assert_eq!(Header::estimate_hashrate(&lch, 4), None);
+        assert_eq!(Header::estimate_hashrate(&lch, 0), None);
+    }
+
     #[test]
     fn test_coinbase_amount() {
         assert_eq!(Header::coinbase_amount(0), 10_000_000_000);
@@ -1,36 +1,482 @@
 use crate::error::EbxError;
+use crate::hash::blake3_hash;
 use crate::header::Header;
 use crate::pkh::Pkh;
 use crate::script::Script;
 use crate::script_chunk::ScriptChunk;
 use crate::tx::Tx;
+use num_bigint::BigUint;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
-// add Default
+// every known header, keyed by id, on any number of branches. headers is the
+// branch with the most cumulative work, from genesis to the tip. when a
+// branch gets more work than the current one it becomes the best chain, and
+// the number of headers that had to be dropped is reported as the reorg
+// depth.
+//
+// once opened with a path, each header passed to insert_at is appended to the
+// file as
+//
+//   blake3 hash of header (32 bytes) | header
+//
+// and the file is replayed when it is opened again. a torn record at the end
+// is dropped. a failed write is cut back off the file straight away, so that
+// later records are not lost behind it, and if that fails too no more headers
+// are accepted.
+//
+// the first pinned_len headers of the best chain can be pinned with pin, and
+// insert_at then refuses any header that would switch to a branch leaving the
+// best chain before them. with a path the pin is saved next to the header
+// file, as a big endian u32 in <path>.pin, and read back by open, so it holds
+// across restarts without the caller pinning again.
+//
+// a header whose block turns out to be invalid is marked with invalidate. it
+// and the headers after it stay known, so they are not fetched again, but
+// nothing more is added after them and the best chain moves to the valid
+// branch with the most work. these marks are not saved: a reopened chain finds
+// them again when it verifies the blocks.
 #[derive(Default, Clone)]
 pub struct HeaderChain {
     pub headers: Vec<Header>,
+    entries: HashMap<[u8; 32], HeaderEntry>,
+    path: Option<PathBuf>,
+    file_len: u64,
+    poisoned: bool,
+    pinned_len: u32,
+    invalid: HashSet<[u8; 32]>,
+}
+
+#[derive(Clone)]
+struct HeaderEntry {
+    header: Header,
+    chain_work: BigUint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderInsert {
+    Known,
+    SideBranch,
+    // reorg_depth is 0 when the header simply extends the old tip
+    NewTip { reorg_depth: u32 },
 }
 
 impl HeaderChain {
     pub const LENGTH_TARGET_ADJ_PERIOD: u32 = Header::BLOCKS_PER_TARGET_ADJ_PERIOD;
     pub const LENGTH_EXPIRY_PERIOD: u32 = Script::PKHXR_90D_60D_X_LOCK_REL;
     pub const LENGTH_SAFETY_PERIOD: u32 = HeaderChain::LENGTH_EXPIRY_PERIOD * 2;
+    const RECORD_SIZE: usize = 32 + Header::SIZE;
 
     pub fn new() -> Self {
         Self {
             headers: Vec::new(),
+            entries: HashMap::new(),
+            path: None,
+            file_len: 0,
+            poisoned: false,
+            pinned_len: 0,
+            invalid: HashSet::new(),
         }
     }
 
+    pub fn open(path: &Path) -> Result<Self, EbxError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(|e| EbxError::io("unable to open header file", &e))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)
+            .map_err(|e| EbxError::io("unable to read header file", &e))?;
+
+        let mut chain = HeaderChain::new();
+        let mut offset = 0;
+        for record in buf.chunks_exact(HeaderChain::RECORD_SIZE) {
+            let header_buf: [u8; Header::SIZE] = record[32..].try_into().unwrap();
+            if blake3_hash(&header_buf) != record[..32] {
+                break;
+            }
+            let Ok(header) = Header::from_buf(header_buf) else {
+                break;
+            };
+            // the headers were checked before they were saved
+            if chain.store(header).is_err() {
+                break;
+            }
+            offset += HeaderChain::RECORD_SIZE;
+        }
+        if offset < buf.len() {
+            file.set_len(offset as u64)
+                .map_err(|e| EbxError::io("unable to truncate header file", &e))?;
+            file.sync_all()
+                .map_err(|e| EbxError::io("unable to sync header file", &e))?;
+        }
+        chain.path = Some(path.to_path_buf());
+        chain.file_len = offset as u64;
+
+        // the pin is only saved after the headers it covers, so a chain
+        // shorter than its pin has lost headers it must not forget
+        chain.pinned_len = match fs::read(HeaderChain::pin_path(path)) {
+            Ok(pin_buf) => u32::from_be_bytes(
+                pin_buf
+                    .try_into()
+                    .map_err(|_| EbxError::generic("header pin file is invalid"))?,
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(EbxError::io("unable to read header pin file", &e)),
+        };
+        if chain.pinned_len as usize > chain.headers.len() {
+            return Err(EbxError::generic("header file is shorter than its pin"));
+        }
+        Ok(chain)
+    }
+
+    fn pin_path(path: &Path) -> PathBuf {
+        let mut path = path.to_path_buf().into_os_string();
+        path.push(".pin");
+        PathBuf::from(path)
+    }
+
+    // appends to the best chain without any checks and without saving it.
+    // headers from other nodes go through insert_at instead.
     pub fn add(&mut self, header: Header) -> &mut Self {
-        self.headers.push(header);
+        let id = header.id();
+        let chain_work = self.chain_work_after(&header.prev_block_id) + header.work();
+        self.headers.push(header.clone());
+        self.entries.insert(id, HeaderEntry { header, chain_work });
         self
     }
 
+    // the first len headers of the best chain must stay in it. the pin is
+    // written to a temporary file and renamed over the old one, so a crash
+    // leaves one or the other.
+    pub fn pin(&mut self, len: u32) -> Result<(), EbxError> {
+        if len == self.pinned_len {
+            return Ok(());
+        }
+        if len as usize > self.headers.len() {
+            return Err(EbxError::generic("pin is longer than the best chain"));
+        }
+        if let Some(path) = &self.path {
+            let pin_path = HeaderChain::pin_path(path);
+            let mut tmp_path = pin_path.clone().into_os_string();
+            tmp_path.push(".tmp");
+            File::create(&tmp_path)
+                .and_then(|mut file| {
+                    file.write_all(&len.to_be_bytes())?;
+                    file.sync_all()
+                })
+                .and_then(|_| fs::rename(&tmp_path, &pin_path))
+                .and_then(|_| match pin_path.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+                    _ => File::open(".")?.sync_all(),
+                })
+                .map_err(|e| EbxError::io("unable to write header pin file", &e))?;
+        }
+        self.pinned_len = len;
+        Ok(())
+    }
+
+    pub fn get_pinned_len(&self) -> u32 {
+        self.pinned_len
+    }
+
+    // marks the header and every header after it as invalid. if it is in the
+    // best chain, the best chain is cut back to its parent and then switches
+    // to the valid branch with the most work that keeps the pinned headers.
+    // between branches with the same work the lowest tip id is taken.
+    pub fn invalidate(&mut self, id: &[u8; 32]) -> Result<(), EbxError> {
+        let Some(entry) = self.entries.get(id) else {
+            return Err(EbxError::generic("header is unknown"));
+        };
+        let block_num = entry.header.block_num;
+        let is_in_best_chain = self.is_in_best_chain(id);
+        if is_in_best_chain && block_num < self.pinned_len {
+            return Err(EbxError::generic("header is pinned"));
+        }
+
+        // a parent always has a lower block number than its children
+        let mut later: Vec<(u32, [u8; 32], [u8; 32])> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.header.block_num > block_num)
+            .map(|(id, entry)| (entry.header.block_num, *id, entry.header.prev_block_id))
+            .collect();
+        later.sort_unstable();
+        self.invalid.insert(*id);
+        for (_, id, prev_block_id) in later {
+            if self.invalid.contains(&prev_block_id) {
+                self.invalid.insert(id);
+            }
+        }
+        if !is_in_best_chain {
+            return Ok(());
+        }
+
+        self.headers.truncate(block_num as usize);
+        let tip_chain_work = self.get_tip_chain_work();
+        let best = self
+            .entries
+            .iter()
+            .filter(|(id, entry)| {
+                !self.invalid.contains(*id)
+                    && entry.chain_work > tip_chain_work
+                    && self.fork_len(id) >= self.pinned_len
+            })
+            .max_by(|(a_id, a), (b_id, b)| {
+                a.chain_work.cmp(&b.chain_work).then_with(|| b_id.cmp(a_id))
+            })
+            .map(|(id, _)| *id);
+        if let Some(best) = best {
+            self.switch_to(&best);
+        }
+        Ok(())
+    }
+
+    pub fn is_invalid(&self, id: &[u8; 32]) -> bool {
+        self.invalid.contains(id)
+    }
+
     pub fn get_tip(&self) -> Option<&Header> {
         self.headers.last()
     }
 
+    pub fn get(&self, id: &[u8; 32]) -> Option<&Header> {
+        self.entries.get(id).map(|entry| &entry.header)
+    }
+
+    pub fn contains(&self, id: &[u8; 32]) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    // the sum of the work of the header and all of its ancestors
+    pub fn get_chain_work(&self, id: &[u8; 32]) -> Option<&BigUint> {
+        self.entries.get(id).map(|entry| &entry.chain_work)
+    }
+
+    pub fn get_tip_chain_work(&self) -> BigUint {
+        match self.get_tip() {
+            Some(tip) => self.chain_work_after(&tip.id()),
+            None => BigUint::from(0u8),
+        }
+    }
+
+    pub fn is_in_best_chain(&self, id: &[u8; 32]) -> bool {
+        self.entries.get(id).is_some_and(|entry| {
+            self.headers
+                .get(entry.header.block_num as usize)
+                .is_some_and(|header| header.id() == *id)
+        })
+    }
+
+    // ids of the best chain from the tip back to genesis, close together near
+    // the tip and doubling the gap after that, so a peer on another branch can
+    // find where the chains meet
+    pub fn get_locator(&self) -> Vec<[u8; 32]> {
+        let mut locator = Vec::new();
+        let mut step = 1;
+        let mut block_num = self.headers.len();
+        while block_num > 0 {
+            block_num = block_num.saturating_sub(step);
+            locator.push(self.headers[block_num].id());
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+        }
+        locator
+    }
+
+    // the headers of the best chain after the first locator id in it, or from
+    // genesis if there is none
+    pub fn get_headers_after(&self, locator: &[[u8; 32]], max: usize) -> Vec<Header> {
+        let start = locator
+            .iter()
+            .find(|id| self.is_in_best_chain(id))
+            .map_or(0, |id| self.entries[id].header.block_num as usize + 1);
+        let end = self.headers.len().min(start.saturating_add(max));
+        self.headers.get(start..end).unwrap_or_default().to_vec()
+    }
+
+    fn chain_work_after(&self, prev_block_id: &[u8; 32]) -> BigUint {
+        self.entries
+            .get(prev_block_id)
+            .map_or(BigUint::from(0u8), |entry| entry.chain_work.clone())
+    }
+
+    // the parent of the header and the headers before it, enough of them to
+    // check the target
+    fn prev_headers(&self, prev_block_id: &[u8; 32]) -> Vec<Header> {
+        let mut prev_headers = Vec::new();
+        let mut id = *prev_block_id;
+        while prev_headers.len() < Header::BLOCKS_PER_TARGET_ADJ_PERIOD as usize {
+            if self.is_in_best_chain(&id) {
+                let end = self.entries[&id].header.block_num as usize + 1;
+                let start = end.saturating_sub(
+                    Header::BLOCKS_PER_TARGET_ADJ_PERIOD as usize - prev_headers.len(),
+                );
+                prev_headers.extend(self.headers[start..end].iter().rev().cloned());
+                break;
+            }
+            let Some(entry) = self.entries.get(&id) else {
+                break;
+            };
+            prev_headers.push(entry.header.clone());
+            if entry.header.block_num == 0 {
+                break;
+            }
+            id = entry.header.prev_block_id;
+        }
+        prev_headers.reverse();
+        prev_headers
+    }
+
+    pub fn insert_at(&mut self, header: Header, timestamp: u64) -> Result<HeaderInsert, EbxError> {
+        let invalid = |message: &str| EbxError::GenericError {
+            source: None,
+            message: message.to_string(),
+        };
+        if self.contains(&header.id()) {
+            return Ok(HeaderInsert::Known);
+        }
+        if self.is_invalid(&header.prev_block_id) {
+            return Err(invalid("header parent is invalid"));
+        }
+        if !header.is_timestamp_valid_at(timestamp) {
+            return Err(invalid("header timestamp is in the future"));
+        }
+        if header.block_num == 0 {
+            if !self.entries.is_empty() {
+                return Err(invalid("genesis header is already known"));
+            }
+            if !header.is_valid_in_lch(&[]) {
+                return Err(invalid("genesis header is invalid"));
+            }
+        } else {
+            if !self.contains(&header.prev_block_id) {
+                return Err(invalid("header parent is unknown"));
+            }
+            if !header.is_valid_after(&self.prev_headers(&header.prev_block_id)) {
+                return Err(invalid("header is invalid"));
+            }
+        }
+        if self
+            .fork_len_if_new_tip(&header)
+            .is_some_and(|len| len < self.pinned_len)
+        {
+            return Err(invalid("header reorgs pinned headers"));
+        }
+        if self.path.is_some() {
+            let header_buf = header.to_buf();
+            let mut record = blake3_hash(&header_buf).to_vec();
+            record.extend_from_slice(&header_buf);
+            self.append_record(&record)?;
+        }
+        self.store(header)
+    }
+
+    fn append_record(&mut self, record: &[u8]) -> Result<(), EbxError> {
+        if self.poisoned {
+            return Err(EbxError::generic(
+                "header file failed an earlier write and must be reopened",
+            ));
+        }
+        let path = self.path.as_ref().unwrap();
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|e| EbxError::io("unable to open header file", &e))?;
+        let result = file
+            .seek(SeekFrom::Start(self.file_len))
+            .and_then(|_| file.write_all(record))
+            .and_then(|_| file.sync_data());
+        if let Err(e) = result {
+            if file
+                .set_len(self.file_len)
+                .and_then(|_| file.sync_data())
+                .is_err()
+            {
+                self.poisoned = true;
+            }
+            return Err(EbxError::io("unable to write header file", &e));
+        }
+        self.file_len += record.len() as u64;
+        Ok(())
+    }
+
+    pub fn insert_now(&mut self, header: Header) -> Result<HeaderInsert, EbxError> {
+        self.insert_at(header, Header::get_new_timestamp())
+    }
+
+    // if the header would become the new tip, the number of headers of the
+    // best chain that its branch keeps
+    fn fork_len_if_new_tip(&self, header: &Header) -> Option<u32> {
+        let chain_work = self.chain_work_after(&header.prev_block_id) + header.work();
+        if chain_work <= self.get_tip_chain_work() {
+            return None;
+        }
+        if header.block_num == 0 {
+            return Some(0);
+        }
+        Some(self.fork_len(&header.prev_block_id))
+    }
+
+    // the number of headers of the best chain that the branch of a known
+    // header keeps
+    fn fork_len(&self, id: &[u8; 32]) -> u32 {
+        let mut id = *id;
+        while !self.is_in_best_chain(&id) {
+            let header = &self.entries[&id].header;
+            if header.block_num == 0 {
+                return 0;
+            }
+            id = header.prev_block_id;
+        }
+        self.entries[&id].header.block_num + 1
+    }
+
+    // adds a header whose parent is known and switches to its branch if that
+    // has more work. on a tie the branch seen first stays.
+    fn store(&mut self, header: Header) -> Result<HeaderInsert, EbxError> {
+        let id = header.id();
+        if self.contains(&id) {
+            return Ok(HeaderInsert::Known);
+        }
+        if header.block_num > 0 && !self.contains(&header.prev_block_id) {
+            return Err(EbxError::generic("header parent is unknown"));
+        }
+        let chain_work = self.chain_work_after(&header.prev_block_id) + header.work();
+        let is_new_tip = chain_work > self.get_tip_chain_work();
+        self.entries.insert(id, HeaderEntry { header, chain_work });
+        if !is_new_tip {
+            return Ok(HeaderInsert::SideBranch);
+        }
+        let reorg_depth = self.switch_to(&id);
+        Ok(HeaderInsert::NewTip { reorg_depth })
+    }
+
+    // makes the branch of a known header the best chain and returns the
+    // number of headers dropped from the old one
+    fn switch_to(&mut self, id: &[u8; 32]) -> u32 {
+        let mut branch = Vec::new();
+        let mut branch_id = *id;
+        while !self.is_in_best_chain(&branch_id) {
+            let header = &self.entries[&branch_id].header;
+            branch.push(header.clone());
+            if header.block_num == 0 {
+                break;
+            }
+            branch_id = header.prev_block_id;
+        }
+        let fork_len = branch.last().map_or(0, |header| header.block_num as usize);
+        let reorg_depth = (self.headers.len() - fork_len) as u32;
+        self.headers.truncate(fork_len);
+        self.headers.extend(branch.into_iter().rev());
+        reorg_depth
+    }
+
     pub fn new_header_is_valid_at(&self, header: &Header, timestamp: u64) -> bool {
         header.is_valid_at(&self.headers, timestamp)
     }
@@ -105,4 +551,238 @@ mod tests {
         chain.add(header);
         assert_eq!(chain.get_tip().unwrap().version, 0);
     }
+
+    fn branch(prev_headers: &[Header], len: usize, merkle_byte: u8) -> Vec<Header> {
+        let mut headers = prev_headers.to_vec();
+        for _ in 0..len {
+            let timestamp = match headers.last() {
+                Some(header) => header.timestamp + Header::BLOCK_INTERVAL,
+                None => Header::BLOCK_INTERVAL,
+            };
+            let mut header = Header::from_lch(&headers, timestamp).unwrap();
+            header.merkle_root = [merkle_byte; 32];
+            headers.push(header);
+        }
+        headers[prev_headers.len()..].to_vec()
+    }
+
+    fn temp_path() -> PathBuf {
+        use crate::buf::EbxBuf;
+        use rand::Rng;
+        let name: [u8; 8] = rand::thread_rng().gen();
+        std::env::temp_dir().join(format!("ebx-headers-{}.dat", name.to_strict_hex()))
+    }
+
+    #[test]
+    fn test_insert_and_reorg() {
+        let mut chain = HeaderChain::new();
+        let genesis = branch(&[], 1, 0);
+        let a = branch(&genesis, 2, 1);
+        let b = branch(&genesis, 3, 2);
+        let now = 10 * Header::BLOCK_INTERVAL;
+
+        assert_eq!(
+            chain.insert_at(genesis[0].clone(), now).unwrap(),
+            HeaderInsert::NewTip { reorg_depth: 0 }
+        );
+        for header in &a {
+            assert_eq!(
+                chain.insert_at(header.clone(), now).unwrap(),
+                HeaderInsert::NewTip { reorg_depth: 0 }
+            );
+        }
+        // as much work as the best chain is not enough
+        for header in &b[0..2] {
+            assert_eq!(
+                chain.insert_at(header.clone(), now).unwrap(),
+                HeaderInsert::SideBranch
+            );
+        }
+        assert_eq!(chain.get_tip().unwrap().id(), a[1].id());
+        assert_eq!(
+            chain.insert_at(b[2].clone(), now).unwrap(),
+            HeaderInsert::NewTip { reorg_depth: 2 }
+        );
+        assert_eq!(chain.headers.len(), 4);
+        assert_eq!(chain.get_tip().unwrap().id(), b[2].id());
+        assert!(chain.is_in_best_chain(&b[0].id()));
+        assert!(!chain.is_in_best_chain(&a[0].id()));
+        assert!(chain.get(&a[1].id()).is_some());
+        assert_eq!(chain.get_tip_chain_work(), BigUint::from(4u8));
+        assert_eq!(
+            chain.insert_at(a[0].clone(), now).unwrap(),
+            HeaderInsert::Known
+        );
+    }
+
+    #[test]
+    fn test_pin_refuses_reorg() {
+        let path = temp_path();
+        let genesis = branch(&[], 1, 0);
+        let a = branch(&genesis, 2, 1);
+        let b = branch(&genesis, 3, 2);
+        let c = branch(&[genesis[0].clone(), a[0].clone()], 2, 3);
+        let now = 10 * Header::BLOCK_INTERVAL;
+        let mut chain = HeaderChain::open(&path).unwrap();
+        for header in genesis.iter().chain(&a) {
+            chain.insert_at(header.clone(), now).unwrap();
+        }
+        chain.pin(2).unwrap();
+        let file_len = std::fs::metadata(&path).unwrap().len();
+
+        // a heavier branch from genesis is refused before it is saved
+        for header in &b[0..2] {
+            assert_eq!(
+                chain.insert_at(header.clone(), now).unwrap(),
+                HeaderInsert::SideBranch
+            );
+        }
+        let file_len_with_side_branch = std::fs::metadata(&path).unwrap().len();
+        assert!(file_len_with_side_branch > file_len);
+        assert!(chain.insert_at(b[2].clone(), now).is_err());
+        assert!(!chain.contains(&b[2].id()));
+        assert_eq!(chain.get_tip().unwrap().id(), a[1].id());
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            file_len_with_side_branch
+        );
+
+        // the pin is still there after a restart
+        let mut chain = HeaderChain::open(&path).unwrap();
+        assert_eq!(chain.get_pinned_len(), 2);
+        assert!(chain.insert_at(b[2].clone(), now).is_err());
+        assert_eq!(chain.get_tip().unwrap().id(), a[1].id());
+
+        // a heavier branch that keeps the pinned headers is fine
+        assert_eq!(
+            chain.insert_at(c[0].clone(), now).unwrap(),
+            HeaderInsert::SideBranch
+        );
+        assert_eq!(
+            chain.insert_at(c[1].clone(), now).unwrap(),
+            HeaderInsert::NewTip { reorg_depth: 1 }
+        );
+        assert!(chain.pin(5).is_err());
+        std::fs::remove_file(HeaderChain::pin_path(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalidate_switches_branch() {
+        let genesis = branch(&[], 1, 0);
+        let a = branch(&genesis, 3, 1);
+        let b = branch(&genesis, 2, 2);
+        let c = branch(&[genesis[0].clone(), a[0].clone()], 1, 3);
+        let now = 10 * Header::BLOCK_INTERVAL;
+        let mut chain = HeaderChain::new();
+        for header in genesis.iter().chain(&a).chain(&b).chain(&c) {
+            chain.insert_at(header.clone(), now).unwrap();
+        }
+        chain.pin(1).unwrap();
+        assert_eq!(chain.get_tip().unwrap().id(), a[2].id());
+
+        // c is not heavier than b, so the tie goes to the lowest id
+        chain.invalidate(&a[1].id()).unwrap();
+        assert!(chain.is_invalid(&a[2].id()));
+        assert!(!chain.is_invalid(&a[0].id()));
+        assert_eq!(chain.headers.len(), 3);
+        let tip_id = b[1].id().min(c[0].id());
+        assert_eq!(chain.get_tip().unwrap().id(), tip_id);
+
+        // invalid headers stay known, but nothing is added after them
+        assert_eq!(
+            chain.insert_at(a[2].clone(), now).unwrap(),
+            HeaderInsert::Known
+        );
+        let d = branch(&[genesis[0].clone(), a[0].clone(), a[1].clone()], 1, 4);
+        assert!(chain.insert_at(d[0].clone(), now).is_err());
+        assert!(!chain.contains(&d[0].id()));
+
+        // a header on a side branch is only marked
+        let side_id = if tip_id == b[1].id() {
+            c[0].id()
+        } else {
+            b[1].id()
+        };
+        chain.invalidate(&side_id).unwrap();
+        assert_eq!(chain.get_tip().unwrap().id(), tip_id);
+
+        // pinned headers can not be invalidated
+        assert!(chain.invalidate(&genesis[0].id()).is_err());
+        assert!(chain.invalidate(&[9; 32]).is_err());
+    }
+
+    #[test]
+    fn test_insert_rejects_invalid_headers() {
+        let mut chain = HeaderChain::new();
+        let headers = branch(&[], 3, 0);
+        let now = 10 * Header::BLOCK_INTERVAL;
+        // the parent must be known
+        assert!(chain.insert_at(headers[1].clone(), now).is_err());
+        chain.insert_at(headers[0].clone(), now).unwrap();
+        assert!(chain.insert_at(headers[0].clone(), now).is_ok());
+        assert!(chain.insert_at(branch(&[], 1, 9)[0].clone(), now).is_err());
+
+        let mut bad_target = headers[1].clone();
+        bad_target.target = u256::from(1u8);
+        assert!(chain.insert_at(bad_target, now).is_err());
+        assert!(chain.insert_at(headers[1].clone(), 0).is_err());
+        assert!(chain.insert_at(headers[1].clone(), now).is_ok());
+        assert_eq!(chain.headers.len(), 2);
+    }
+
+    #[test]
+    fn test_open_replays_file() {
+        let path = temp_path();
+        let genesis = branch(&[], 1, 0);
+        let a = branch(&genesis, 2, 1);
+        let b = branch(&genesis, 3, 2);
+        let now = 10 * Header::BLOCK_INTERVAL;
+        {
+            let mut chain = HeaderChain::open(&path).unwrap();
+            for header in genesis.iter().chain(&a).chain(&b) {
+                chain.insert_at(header.clone(), now).unwrap();
+            }
+        }
+        let chain = HeaderChain::open(&path).unwrap();
+        assert_eq!(chain.headers.len(), 4);
+        assert_eq!(chain.get_tip().unwrap().id(), b[2].id());
+        assert!(chain.contains(&a[1].id()));
+
+        // a torn record at the end is dropped
+        let len = std::fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[1, 2, 3]).unwrap();
+        let mut chain = HeaderChain::open(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        let c = branch(&chain.headers, 1, 3);
+        chain.insert_at(c[0].clone(), now).unwrap();
+        let chain = HeaderChain::open(&path).unwrap();
+        assert_eq!(chain.headers.len(), 5);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_failed_write_poisons_chain() {
+        let path = temp_path();
+        let genesis = branch(&[], 1, 0);
+        let a = branch(&genesis, 2, 1);
+        let now = 10 * Header::BLOCK_INTERVAL;
+        let mut chain = HeaderChain::open(&path).unwrap();
+        chain.insert_at(genesis[0].clone(), now).unwrap();
+
+        // every write to /dev/full fails, and it can not be truncated either
+        chain.path = Some(PathBuf::from("/dev/full"));
+        assert!(chain.insert_at(a[0].clone(), now).is_err());
+        assert!(chain.poisoned);
+        assert_eq!(chain.headers.len(), 1);
+        chain.path = Some(path.clone());
+        assert!(chain.insert_at(a[0].clone(), now).is_err());
+
+        let mut chain = HeaderChain::open(&path).unwrap();
+        assert_eq!(chain.headers.len(), 1);
+        chain.insert_at(a[0].clone(), now).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,281 @@
+use crate::buf_reader::BufReader;
+use crate::header::Header;
+use crate::numbers::u256;
+use rand::Rng;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub struct HeaderMine {
+    pub header: Header,
+    cancelled: Arc<AtomicBool>,
+    hash_count: Arc<AtomicU64>,
+    elapsed: Duration,
+}
+
+impl HeaderMine {
+    // number of hashes a thread computes before checking whether it has been
+    // cancelled and before reporting its progress to the shared hash count.
+    pub const HASHES_PER_ROUND: u64 = 1_000;
+
+    pub fn new(header: Header) -> Self {
+        Self {
+            header,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            hash_count: Arc::new(AtomicU64::new(0)),
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    pub fn random_nonce() -> u256 {
+        let mut rng = rand::thread_rng();
+        let mut buf = [0u8; 32];
+        rng.fill(&mut buf);
+        BufReader::new(buf.to_vec()).read_u256_be().unwrap()
+    }
+
+    pub fn randomize_nonce(&mut self) {
+        self.header.nonce = HeaderMine::random_nonce();
+    }
+
+    pub fn get_id_hash_num(&self) -> u256 {
+        HeaderMine::id_hash_num(&self.header)
+    }
+
+    fn id_hash_num(header: &Header) -> u256 {
+        let id = header.id();
+        BufReader::new(id.to_vec()).read_u256_be().unwrap()
+    }
+
+    pub fn get_lowest_id_for_n_times(&mut self, n: u32) -> u256 {
+        let mut lowest = self.get_id_hash_num();
+        for _ in 0..n {
+            self.randomize_nonce();
+            let hash_num = self.get_id_hash_num();
+            if hash_num < lowest {
+                lowest = hash_num;
+            }
+        }
+        lowest
+    }
+
+    pub fn get_lowest_nonce_for_n_times(&mut self, n: u32) -> u256 {
+        let mut lowest = self.get_id_hash_num();
+        let mut nonce = self.header.nonce;
+        for _ in 0..n {
+            self.randomize_nonce();
+            let hash_num = self.get_id_hash_num();
+            if hash_num < lowest {
+                lowest = hash_num;
+                nonce = self.header.nonce;
+            }
+        }
+        nonce
+    }
+
+    // handle that can be moved to another thread to stop a running search.
+    // cancelling is final: a search started after it returns at once, so a
+    // cancel that races with the start of mine is never lost. use a new
+    // HeaderMine to search again.
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // handle that can be polled from another thread while a search is running
+    pub fn hash_count_handle(&self) -> Arc<AtomicU64> {
+        self.hash_count.clone()
+    }
+
+    pub fn get_hash_count(&self) -> u64 {
+        self.hash_count.load(Ordering::SeqCst)
+    }
+
+    pub fn get_elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    // hashes per second of the most recent search
+    pub fn get_hashrate(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.get_hash_count() as f64 / secs
+    }
+
+    // search for a nonce that makes the header id valid for its target. each
+    // thread starts at a random nonce and counts upwards. when a valid nonce
+    // is found, the header is updated and true is returned. false means the
+    // search was cancelled or that max_hashes was reached first (zero means
+    // no limit).
+    pub fn mine(&mut self, n_threads: usize, max_hashes: u64) -> bool {
+        let n_threads = n_threads.max(1);
+        self.hash_count.store(0, Ordering::SeqCst);
+        let found: Arc<Mutex<Option<u256>>> = Arc::new(Mutex::new(None));
+        let done = Arc::new(AtomicBool::new(false));
+        let start = Instant::now();
+
+        let mut handles = Vec::with_capacity(n_threads);
+        for _ in 0..n_threads {
+            let mut header = self.header.clone();
+            let cancelled = self.cancelled.clone();
+            let hash_count = self.hash_count.clone();
+            let found = found.clone();
+            let done = done.clone();
+            handles.push(thread::spawn(move || {
+                header.nonce = HeaderMine::random_nonce();
+                loop {
+                    if done.load(Ordering::SeqCst) || cancelled.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    if max_hashes > 0 && hash_count.load(Ordering::SeqCst) >= max_hashes {
+                        return;
+                    }
+                    for _ in 0..HeaderMine::HASHES_PER_ROUND {
+                        if HeaderMine::id_hash_num(&header) < header.target {
+                            let mut found = found.lock().unwrap();
+                            if found.is_none() {
+                                *found = Some(header.nonce);
+                            }
+                            // stop the other threads
+                            done.store(true, Ordering::SeqCst);
+                            return;
+                        }
+                        header.nonce = header.nonce.wrapping_add(u256::ONE);
+                    }
+                    hash_count.fetch_add(HeaderMine::HASHES_PER_ROUND, Ordering::SeqCst);
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        self.elapsed = start.elapsed();
+
+        let nonce = *found.lock().unwrap();
+        match nonce {
+            Some(nonce) => {
+                self.header.nonce = nonce;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_with_target(target: u256) -> Header {
+        Header {
+            version: 0,
+            prev_block_id: [0; 32],
+            merkle_root: [0; 32],
+            timestamp: 0,
+            block_num: 0,
+            target,
+            nonce: u256::from(0u8),
+            work_ser_algo: 0,
+            work_ser_hash: [0; 32],
+            work_par_algo: 0,
+            work_par_hash: [0; 32],
+        }
+    }
+
+    #[test]
+    fn test_get_lowest_id_for_n_times() {
+        let header = header_with_target(u256::from(0u8));
+        let mut header_mine = HeaderMine::new(header);
+        let first = header_mine.get_id_hash_num();
+        let lowest = header_mine.get_lowest_id_for_n_times(10);
+        assert!(lowest <= first);
+    }
+
+    #[test]
+    fn test_get_lowest_nonce_for_n_times() {
+        let header = header_with_target(u256::from(0u8));
+        let mut header_mine = HeaderMine::new(header);
+        let first = header_mine.get_id_hash_num();
+        let nonce = header_mine.get_lowest_nonce_for_n_times(10);
+        header_mine.header.nonce = nonce;
+        assert!(header_mine.get_id_hash_num() <= first);
+    }
+
+    #[test]
+    fn test_mine_one_thread() {
+        // one in 256 ids are valid
+        let target = u256::MAX >> 8;
+        let header = header_with_target(target);
+        let mut header_mine = HeaderMine::new(header);
+        assert!(header_mine.mine(1, 0));
+        assert!(header_mine.header.is_id_valid());
+    }
+
+    #[test]
+    fn test_mine_many_threads() {
+        let target = u256::MAX >> 12;
+        let header = header_with_target(target);
+        let mut header_mine = HeaderMine::new(header);
+        assert!(header_mine.mine(4, 0));
+        assert!(header_mine.header.is_id_valid());
+        assert!(!header_mine.is_cancelled());
+        assert!(header_mine.get_elapsed() > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_mine_max_hashes() {
+        // no id is less than zero
+        let header = header_with_target(u256::from(0u8));
+        let mut header_mine = HeaderMine::new(header);
+        assert!(!header_mine.mine(2, 5_000));
+        assert!(header_mine.get_hash_count() >= 5_000);
+        assert!(header_mine.get_hashrate() > 0.0);
+    }
+
+    #[test]
+    fn test_mine_cancel() {
+        let header = header_with_target(u256::from(0u8));
+        let mut header_mine = HeaderMine::new(header);
+        let cancel = header_mine.cancel_handle();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            cancel.store(true, Ordering::SeqCst);
+        });
+        assert!(!header_mine.mine(2, 0));
+        canceller.join().unwrap();
+        assert!(header_mine.is_cancelled());
+        assert!(!header_mine.header.is_id_valid());
+    }
+
+    #[test]
+    fn test_cancel_before_mine() {
+        // one in 256 ids are valid, so this would succeed if it ran
+        let header = header_with_target(u256::MAX >> 8);
+        let mut header_mine = HeaderMine::new(header);
+        header_mine.cancel();
+        assert!(!header_mine.mine(1, 0));
+        assert!(header_mine.is_cancelled());
+        assert_eq!(header_mine.get_hash_count(), 0);
+    }
+
+    #[test]
+    fn test_mine_header_from_header_chain() {
+        use crate::header_chain::HeaderChain;
+
+        let chain = HeaderChain::new();
+        let header = chain.get_next_header([0; 32], 1).unwrap();
+        let mut header_mine = HeaderMine::new(header);
+        assert!(header_mine.mine(2, 0));
+        assert!(chain.new_header_is_valid_at(&header_mine.header, 1));
+    }
+}
@@ -4,32 +4,58 @@ pub mod block_verifier;
 pub mod buf;
 pub mod buf_reader;
 pub mod buf_writer;
+pub mod chain_sync;
 pub mod domain;
 pub mod error;
 pub mod hash;
+pub mod hd_key;
 pub mod header;
 pub mod header_chain;
+pub mod header_mine;
 pub mod key_pair;
+pub mod mempool;
 pub mod merkle_node;
 pub mod merkle_proof;
 pub mod merkle_txs;
+pub mod mine_descriptor;
+pub mod mine_registry;
+pub mod mnemonic;
 pub mod numbers;
 pub mod opcode;
 pub mod pkh;
 pub mod pkh_key_map;
+pub mod pkh_key_vault;
 pub mod priv_key;
 pub mod pub_key;
+pub mod schnorr;
 pub mod script;
 pub mod script_chunk;
+pub mod script_error;
 pub mod script_interpreter;
 pub mod script_num;
+pub mod script_trace;
+pub mod sig_cache;
+pub mod sig_check;
+pub mod signed_message;
+pub mod spend_graph;
+#[cfg(test)]
+mod test_util;
 pub mod tx;
 pub mod tx_builder;
 pub mod tx_in;
 pub mod tx_out;
 pub mod tx_out_bn;
+pub mod tx_out_bn_batch;
+pub mod tx_out_bn_expiry_index;
+pub mod tx_out_bn_file_store;
 pub mod tx_out_bn_map;
+pub mod tx_out_bn_overlay;
+pub mod tx_out_bn_store;
 pub mod tx_signature;
 pub mod tx_signer;
 pub mod tx_verifier;
+pub mod utxo_delta;
 pub mod var_int;
+pub mod verify_error;
+pub mod vote;
+pub mod wire_message;
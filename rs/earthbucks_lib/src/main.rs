@@ -1,42 +1,300 @@
+use earthbucks_lib::block::Block;
+use earthbucks_lib::buf::EbxBuf;
+use earthbucks_lib::error::EbxError;
+use earthbucks_lib::header::Header;
 use earthbucks_lib::key_pair::KeyPair;
 use earthbucks_lib::pkh;
+use earthbucks_lib::pkh::Pkh;
+use earthbucks_lib::pkh_key_map::PkhKeyMap;
+use earthbucks_lib::priv_key::PrivKey;
+use earthbucks_lib::pub_key::PubKey;
+use earthbucks_lib::script::Script;
+use earthbucks_lib::tx::Tx;
+use earthbucks_lib::tx_builder::TxBuilder;
+use earthbucks_lib::tx_out::TxOut;
+use earthbucks_lib::tx_out_bn_map::TxOutBnMap;
+use earthbucks_lib::tx_signer::TxSigner;
+use earthbucks_lib::tx_verifier::TxVerifier;
+use serde::Deserialize;
+use serde_json::{json, Value};
 use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str = "usage:
+  earthbucks_lib key
+  earthbucks_lib pkh
+  earthbucks_lib derive <ebxprv>
+  earthbucks_lib validate <ebxprv|ebxpub|ebxpkh>
+  earthbucks_lib build --utxos <file> --key <ebxprv>... --to <ebxpkh>:<value>... --change <ebxpkh> [--block-num <n>] [--lock-abs <n>]
+  earthbucks_lib verify --utxos <file> [--block-num <n>] <tx hex>
+  earthbucks_lib decode <tx|header|block> <hex>
+
+the utxo file is a json array of objects with the fields txId (hex),
+txOutNum, value, script (strict string) and blockNum.";
+
+// "--name value" pairs in the order given
+type Flags = Vec<(String, String)>;
+
+// one entry of the json utxo file
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UtxoEntry {
+    tx_id: String,
+    tx_out_num: u32,
+    value: u64,
+    script: String,
+    block_num: u32,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    match args.len() {
-        1 => {
-            println!("Please provide an argument: key or pkh");
-        }
-        2 => match args[1].as_str() {
-            "key" => {
-                let key = KeyPair::from_random();
-                let priv_key_str = key.priv_key.to_strict_str();
-                let pub_key_str = key.pub_key.to_strict_str();
-
-                println!("Private key: {}", priv_key_str);
-                println!("Public key: {}", pub_key_str);
-            }
-            "pkh" => {
-                let key = KeyPair::from_random();
-                let public_key = key.pub_key.buf;
-                let pkh = pkh::Pkh::from_pub_key_buffer(public_key.to_vec());
-
-                let prv_key_str = key.priv_key.to_strict_str();
-                let pub_key_str = key.pub_key.to_strict_str();
-                let pkh_str = pkh.to_strict_str();
-
-                println!("Private key: {}", prv_key_str);
-                println!("Public key: {}", pub_key_str);
-                println!("Address: {}", pkh_str);
+    if args.is_empty() {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let res = match args[0].as_str() {
+        "key" => key(),
+        "pkh" => pkh(),
+        "derive" => derive(&args[1..]),
+        "validate" => validate(&args[1..]),
+        "build" => build(&args[1..]),
+        "verify" => verify(&args[1..]),
+        "decode" => decode(&args[1..]),
+        _ => Err(EbxError::generic(&format!("unknown command: {}", args[0]))),
+    };
+
+    if let Err(err) = res {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn key() -> Result<(), EbxError> {
+    let key = KeyPair::from_random();
+    let priv_key_str = key.priv_key.to_strict_str();
+    let pub_key_str = key.pub_key.to_strict_str();
+
+    println!("Private key: {}", priv_key_str);
+    println!("Public key: {}", pub_key_str);
+    Ok(())
+}
+
+fn pkh() -> Result<(), EbxError> {
+    let key = KeyPair::from_random();
+    let public_key = key.pub_key.buf;
+    let pkh = pkh::Pkh::from_pub_key_buffer(public_key.to_vec());
+
+    let prv_key_str = key.priv_key.to_strict_str();
+    let pub_key_str = key.pub_key.to_strict_str();
+    let pkh_str = pkh.to_strict_str();
+
+    println!("Private key: {}", prv_key_str);
+    println!("Public key: {}", pub_key_str);
+    println!("Address: {}", pkh_str);
+    Ok(())
+}
+
+fn derive(args: &[String]) -> Result<(), EbxError> {
+    let [priv_key_str] = args else {
+        return Err(EbxError::generic("derive takes exactly one ebxprv"));
+    };
+    let key = KeyPair::from_priv_key(&PrivKey::from_strict_str(priv_key_str)?)?;
+    let pkh = Pkh::from_pub_key(key.pub_key.clone());
+
+    println!("Public key: {}", key.pub_key.to_strict_str());
+    println!("Address: {}", pkh.to_strict_str());
+    Ok(())
+}
+
+fn validate(args: &[String]) -> Result<(), EbxError> {
+    let [s] = args else {
+        return Err(EbxError::generic("validate takes exactly one string"));
+    };
+    let res = if s.starts_with("ebxprv") {
+        PrivKey::from_strict_str(s).map(|_| "private key")
+    } else if s.starts_with("ebxpub") {
+        PubKey::from_strict_str(s).and_then(|pub_key| {
+            if pub_key.is_valid() {
+                Ok("public key")
+            } else {
+                Err(EbxError::InvalidKeyError { source: None })
             }
-            _ => {
-                println!("Invalid argument. Please provide key or pkh");
+        })
+    } else if s.starts_with("ebxpkh") {
+        Pkh::from_strict_str(s).map(|_| "address")
+    } else {
+        Err(EbxError::generic("unknown prefix"))
+    };
+
+    match res {
+        Ok(kind) => {
+            println!("valid {}", kind);
+            Ok(())
+        }
+        Err(err) => Err(EbxError::generic(&format!("invalid: {}", err))),
+    }
+}
+
+fn build(args: &[String]) -> Result<(), EbxError> {
+    let (flags, rest) = parse_flags(args)?;
+    if !rest.is_empty() {
+        return Err(EbxError::generic("build takes no positional arguments"));
+    }
+    let tx_out_bn_map = read_utxo_file(get_flag(&flags, "utxos")?)?;
+    let change_pkh = Pkh::from_strict_str(get_flag(&flags, "change")?)?;
+    let block_num = get_num_flag(&flags, "block-num")?.unwrap_or(0);
+    let lock_abs = get_num_flag(&flags, "lock-abs")?.unwrap_or(0);
+
+    let mut pkh_key_map = PkhKeyMap::new();
+    for priv_key_str in get_flags(&flags, "key") {
+        let key = KeyPair::from_priv_key(&PrivKey::from_strict_str(priv_key_str)?)?;
+        let pkh = Pkh::from_pub_key(key.pub_key.clone());
+        pkh_key_map.add(key, pkh.to_buf());
+    }
+
+    let change_script = Script::from_pkh_output(change_pkh.to_buf());
+    let mut tx_builder = TxBuilder::new(&tx_out_bn_map, change_script, lock_abs);
+    let tos = get_flags(&flags, "to");
+    if tos.is_empty() {
+        return Err(EbxError::generic("build needs at least one --to"));
+    }
+    for to in tos {
+        let (pkh_str, value_str) = to
+            .split_once(':')
+            .ok_or_else(|| EbxError::generic("--to must be <ebxpkh>:<value>"))?;
+        let pkh = Pkh::from_strict_str(pkh_str)?;
+        let value: u64 = value_str
+            .parse()
+            .map_err(|_| EbxError::generic(&format!("invalid value: {}", value_str)))?;
+        tx_builder.add_output(TxOut::new(value, Script::from_pkh_output(pkh.to_buf())));
+    }
+    let tx = tx_builder.build()?;
+
+    let mut tx_signer = TxSigner::new(tx, &tx_out_bn_map, &pkh_key_map, block_num);
+    let tx = tx_signer.sign()?;
+    println!("{}", tx.to_strict_hex());
+    Ok(())
+}
+
+fn verify(args: &[String]) -> Result<(), EbxError> {
+    let (flags, rest) = parse_flags(args)?;
+    let [tx_hex] = rest.as_slice() else {
+        return Err(EbxError::generic("verify takes exactly one tx hex"));
+    };
+    let tx_out_bn_map = read_utxo_file(get_flag(&flags, "utxos")?)?;
+    let block_num = get_num_flag(&flags, "block-num")?.unwrap_or(0);
+    let tx = Tx::from_strict_hex(tx_hex)?;
+
+    let mut tx_verifier = TxVerifier::new(tx, &tx_out_bn_map, block_num);
+    tx_verifier.verify()?;
+    println!("valid");
+    Ok(())
+}
+
+fn decode(args: &[String]) -> Result<(), EbxError> {
+    let [kind, hex] = args else {
+        return Err(EbxError::generic("decode takes a kind and a hex string"));
+    };
+    let value = match kind.as_str() {
+        "tx" => tx_to_json(&Tx::from_strict_hex(hex)?),
+        "header" => header_to_json(&Header::from_strict_hex(hex)?),
+        "block" => {
+            let block = Block::from_buf(Vec::<u8>::from_strict_hex(hex)?)?;
+            json!({
+                "header": header_to_json(&block.header),
+                "txs": block.txs.iter().map(tx_to_json).collect::<Vec<_>>(),
+            })
+        }
+        _ => return Err(EbxError::generic(&format!("unknown kind: {}", kind))),
+    };
+    println!("{}", serde_json::to_string_pretty(&value).unwrap());
+    Ok(())
+}
+
+// the serde form plus the id, which is derived and so not part of it
+fn tx_to_json(tx: &Tx) -> Value {
+    let mut value = serde_json::to_value(tx).unwrap();
+    value["id"] = json!(tx.id().to_strict_hex());
+    value
+}
+
+fn header_to_json(header: &Header) -> Value {
+    let mut value = serde_json::to_value(header).unwrap();
+    value["id"] = json!(header.id().to_strict_hex());
+    value
+}
+
+fn read_utxo_file(path: &str) -> Result<TxOutBnMap, EbxError> {
+    let data = fs::read_to_string(path)
+        .map_err(|err| EbxError::generic(&format!("could not read {}: {}", path, err)))?;
+    let entries: Vec<UtxoEntry> = serde_json::from_str(&data)
+        .map_err(|err| EbxError::generic(&format!("invalid utxo file {}: {}", path, err)))?;
+
+    let mut tx_out_bn_map = TxOutBnMap::new();
+    for entry in entries {
+        let tx_id = <[u8; 32]>::from_strict_hex(&entry.tx_id)?;
+        let script = Script::from_strict_str(&entry.script)?;
+        tx_out_bn_map.add(
+            &tx_id,
+            entry.tx_out_num,
+            TxOut::new(entry.value, script),
+            entry.block_num,
+        );
+    }
+    Ok(tx_out_bn_map)
+}
+
+// splits "--name value" pairs from positional arguments. flags may repeat.
+fn parse_flags(args: &[String]) -> Result<(Flags, Vec<String>), EbxError> {
+    let mut flags = Vec::new();
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.strip_prefix("--") {
+            Some(name) => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| EbxError::generic(&format!("--{} needs a value", name)))?;
+                flags.push((name.to_string(), value.clone()));
             }
-        },
-        _ => {
-            println!("Too many arguments. Please provide only one argument: key or pkh");
+            None => rest.push(arg.clone()),
         }
     }
+    Ok((flags, rest))
+}
+
+fn get_flags<'a>(flags: &'a [(String, String)], name: &str) -> Vec<&'a str> {
+    flags
+        .iter()
+        .filter(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+        .collect()
+}
+
+fn get_flag<'a>(flags: &'a [(String, String)], name: &str) -> Result<&'a str, EbxError> {
+    match get_flags(flags, name).as_slice() {
+        [value] => Ok(value),
+        [] => Err(EbxError::generic(&format!("missing --{}", name))),
+        _ => Err(EbxError::generic(&format!(
+            "--{} given more than once",
+            name
+        ))),
+    }
+}
+
+fn get_num_flag(flags: &[(String, String)], name: &str) -> Result<Option<u32>, EbxError> {
+    match get_flags(flags, name).as_slice() {
+        [] => Ok(None),
+        [value] => value
+            .parse()
+            .map(Some)
+            .map_err(|_| EbxError::generic(&format!("--{} must be a number", name))),
+        _ => Err(EbxError::generic(&format!(
+            "--{} given more than once",
+            name
+        ))),
+    }
 }
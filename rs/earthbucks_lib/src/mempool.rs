@@ -0,0 +1,414 @@
+use crate::block::Block;
+use crate::error::EbxError;
+use crate::sig_cache::SigCache;
+use crate::tx::Tx;
+use crate::tx_out_bn::TxOutBn;
+use crate::tx_out_bn_batch::TxOutBnBatch;
+use crate::tx_out_bn_map::TxOutBnMap;
+use crate::tx_out_bn_store::TxOutBnStore;
+use crate::tx_verifier::TxVerifier;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+// unconfirmed txs waiting to be included in a block. a tx may spend outputs
+// from the utxo set or from other txs in the pool (its parents). no two txs in
+// the pool may spend the same output.
+#[derive(Debug, Clone, Default)]
+pub struct Mempool {
+    txs: HashMap<[u8; 32], Tx>,
+    order: Vec<[u8; 32]>,                // tx ids in the order they were added
+    spent_by: HashMap<String, [u8; 32]>, // tx_out name -> id of spending tx
+    tx_out_bn_map: TxOutBnMap,           // outputs of txs in the pool
+    parents: HashMap<[u8; 32], HashSet<[u8; 32]>>,
+    children: HashMap<[u8; 32], HashSet<[u8; 32]>>,
+    sig_cache: Option<Arc<SigCache>>,
+}
+
+// the outputs of the txs in the pool on top of the utxo set, both borrowed.
+// the pool's outputs are looked up first.
+struct PoolTxOutBns<'a> {
+    pool: &'a TxOutBnMap,
+    store: &'a dyn TxOutBnStore,
+}
+
+impl<'a> TxOutBnStore for PoolTxOutBns<'a> {
+    fn get(&self, tx_id: &[u8; 32], tx_out_num: u32) -> Result<Option<TxOutBn>, EbxError> {
+        match self.pool.get(tx_id, tx_out_num) {
+            Some(tx_out_bn) => Ok(Some(tx_out_bn.clone())),
+            None => self.store.get(tx_id, tx_out_num),
+        }
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&[u8; 32], u32, &TxOutBn)) -> Result<(), EbxError> {
+        self.store.for_each(&mut |tx_id, tx_out_num, tx_out_bn| {
+            if self.pool.get(tx_id, tx_out_num).is_none() {
+                f(tx_id, tx_out_num, tx_out_bn);
+            }
+        })?;
+        self.pool.for_each(f)
+    }
+
+    // pool txs never recreate an output of the utxo set, as their ids differ
+    fn len(&self) -> usize {
+        self.pool.map.len() + self.store.len()
+    }
+
+    fn apply_batch(&mut self, _: &TxOutBnBatch) -> Result<TxOutBnBatch, EbxError> {
+        Err(EbxError::generic("pool outputs are read only"))
+    }
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self {
+            txs: HashMap::new(),
+            order: Vec::new(),
+            spent_by: HashMap::new(),
+            tx_out_bn_map: TxOutBnMap::new(),
+            parents: HashMap::new(),
+            children: HashMap::new(),
+            sig_cache: None,
+        }
+    }
+
+    // signatures verified when txs are added are remembered here, so pass the
+    // same cache to BlockVerifier to skip them when the block comes in
+    pub fn set_sig_cache(&mut self, sig_cache: Arc<SigCache>) {
+        self.sig_cache = Some(sig_cache);
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    pub fn contains(&self, tx_id: &[u8; 32]) -> bool {
+        self.txs.contains_key(tx_id)
+    }
+
+    pub fn get(&self, tx_id: &[u8; 32]) -> Option<&Tx> {
+        self.txs.get(tx_id)
+    }
+
+    // id of the tx in the pool that spends this output, if any
+    pub fn get_spender(&self, tx_id: &[u8; 32], tx_out_num: u32) -> Option<&[u8; 32]> {
+        self.spent_by
+            .get(&TxOutBnMap::name_from_output(tx_id, tx_out_num))
+    }
+
+    pub fn get_parents(&self, tx_id: &[u8; 32]) -> Vec<[u8; 32]> {
+        self.parents
+            .get(tx_id)
+            .map(|parents| parents.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get_children(&self, tx_id: &[u8; 32]) -> Vec<[u8; 32]> {
+        self.children
+            .get(tx_id)
+            .map(|children| children.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    // verify the tx against the utxo set plus the outputs of the txs already
+    // in the pool. block_num is the number of the block being built.
+    pub fn add_tx(
+        &mut self,
+        tx: Tx,
+        tx_out_bn_store: &dyn TxOutBnStore,
+        block_num: u32,
+    ) -> Result<[u8; 32], EbxError> {
+        let tx_id = tx.id();
+        if self.txs.contains_key(&tx_id) {
+            return Err(EbxError::GenericError {
+                source: None,
+                message: "tx already in mempool".to_string(),
+            });
+        }
+        for tx_in in &tx.inputs {
+            if self
+                .get_spender(&tx_in.input_tx_id, tx_in.input_tx_out_num)
+                .is_some()
+            {
+                return Err(EbxError::GenericError {
+                    source: None,
+                    message: "tx double spends a tx in mempool".to_string(),
+                });
+            }
+        }
+
+        let tx_out_bns = PoolTxOutBns {
+            pool: &self.tx_out_bn_map,
+            store: tx_out_bn_store,
+        };
+        let mut tx_verifier = TxVerifier::new(tx.clone(), &tx_out_bns, block_num);
+        if let Some(sig_cache) = &self.sig_cache {
+            tx_verifier.set_sig_cache(sig_cache.clone());
+        }
+        tx_verifier.verify()?;
+
+        let mut parents = HashSet::new();
+        for tx_in in &tx.inputs {
+            let name = TxOutBnMap::name_from_output(&tx_in.input_tx_id, tx_in.input_tx_out_num);
+            self.spent_by.insert(name, tx_id);
+            if self.txs.contains_key(&tx_in.input_tx_id) {
+                parents.insert(tx_in.input_tx_id);
+            }
+        }
+        for parent_id in &parents {
+            self.children.entry(*parent_id).or_default().insert(tx_id);
+        }
+        self.parents.insert(tx_id, parents);
+        self.tx_out_bn_map.add_tx_outputs(&tx, block_num);
+        self.txs.insert(tx_id, tx);
+        self.order.push(tx_id);
+        Ok(tx_id)
+    }
+
+    // remove a tx and every tx that depends on it. returns the removed txs,
+    // parents before children.
+    pub fn remove_tx(&mut self, tx_id: &[u8; 32]) -> Vec<Tx> {
+        let mut removed = Vec::new();
+        let mut stack = vec![*tx_id];
+        let mut to_remove = HashSet::new();
+        while let Some(id) = stack.pop() {
+            if !self.txs.contains_key(&id) || !to_remove.insert(id) {
+                continue;
+            }
+            stack.extend(self.get_children(&id));
+        }
+        let ids: Vec<[u8; 32]> = self
+            .order
+            .iter()
+            .filter(|id| to_remove.contains(*id))
+            .copied()
+            .collect();
+        for id in ids {
+            if let Some(tx) = self.remove_one(&id) {
+                removed.push(tx);
+            }
+        }
+        removed
+    }
+
+    // remove only this tx. its children stay in the pool.
+    fn remove_one(&mut self, tx_id: &[u8; 32]) -> Option<Tx> {
+        let tx = self.txs.remove(tx_id)?;
+        self.order.retain(|id| id != tx_id);
+        for tx_in in &tx.inputs {
+            let name = TxOutBnMap::name_from_output(&tx_in.input_tx_id, tx_in.input_tx_out_num);
+            if self.spent_by.get(&name) == Some(tx_id) {
+                self.spent_by.remove(&name);
+            }
+        }
+        for tx_out_num in 0..tx.outputs.len() as u32 {
+            self.tx_out_bn_map.remove(tx_id, tx_out_num);
+        }
+        if let Some(parents) = self.parents.remove(tx_id) {
+            for parent_id in parents {
+                if let Some(children) = self.children.get_mut(&parent_id) {
+                    children.remove(tx_id);
+                }
+            }
+        }
+        if let Some(children) = self.children.remove(tx_id) {
+            for child_id in children {
+                if let Some(parents) = self.parents.get_mut(&child_id) {
+                    parents.remove(tx_id);
+                }
+            }
+        }
+        Some(tx)
+    }
+
+    // update the pool for a block that was connected to the tip. txs in the
+    // block are confirmed and leave the pool, and any other tx that spends an
+    // output the block spent is evicted along with its descendants. returns
+    // the evicted txs.
+    pub fn remove_block_txs(&mut self, block: &Block) -> Vec<Tx> {
+        let mut evicted = Vec::new();
+        for tx in &block.txs {
+            let tx_id = tx.id();
+            self.remove_one(&tx_id);
+            if tx.is_coinbase() {
+                continue;
+            }
+            for tx_in in &tx.inputs {
+                let spender = self
+                    .get_spender(&tx_in.input_tx_id, tx_in.input_tx_out_num)
+                    .copied();
+                if let Some(spender) = spender {
+                    evicted.extend(self.remove_tx(&spender));
+                }
+            }
+        }
+        evicted
+    }
+
+    // every tx in the pool, parents before children
+    pub fn get_txs(&self) -> Vec<Tx> {
+        let txs: Vec<Tx> = self.order.iter().map(|id| self.txs[id].clone()).collect();
+        Mempool::sort_txs(txs)
+    }
+
+    // order txs so that every tx comes after the txs in the list it spends
+    // from. txs that do not depend on each other keep their relative order.
+    pub fn sort_txs(txs: Vec<Tx>) -> Vec<Tx> {
+        let ids: Vec<[u8; 32]> = txs.iter().map(|tx| tx.id()).collect();
+        let id_set: HashSet<[u8; 32]> = ids.iter().copied().collect();
+        let mut placed: HashSet<[u8; 32]> = HashSet::new();
+        let mut sorted = Vec::with_capacity(txs.len());
+        let mut remaining: Vec<usize> = (0..txs.len()).collect();
+        loop {
+            let mut next = Vec::new();
+            let mut progress = false;
+            for i in remaining {
+                if placed.contains(&ids[i]) {
+                    // duplicate
+                    continue;
+                }
+                let ready = txs[i].inputs.iter().all(|tx_in| {
+                    !id_set.contains(&tx_in.input_tx_id) || placed.contains(&tx_in.input_tx_id)
+                });
+                if ready {
+                    placed.insert(ids[i]);
+                    sorted.push(txs[i].clone());
+                    progress = true;
+                } else {
+                    next.push(i);
+                }
+            }
+            // a tx id commits to its inputs, so txs can't spend from each
+            // other in a cycle. stop anyway rather than loop forever.
+            if next.is_empty() || !progress {
+                break;
+            }
+            remaining = next;
+        }
+        sorted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header_chain::HeaderChain;
+    use crate::test_util::Wallet;
+    use crate::tx_builder::TxBuilder;
+    use crate::tx_out::TxOut;
+    use crate::tx_signer::TxSigner;
+    use crate::verify_error::VerifyError;
+
+    // spend all of the given outputs to one output of the same value
+    fn spend(wallet: &Wallet, inputs: &TxOutBnMap, value: u64, lock_abs: u32) -> Tx {
+        let mut tx_builder = TxBuilder::new(inputs, wallet.script(), lock_abs);
+        tx_builder.add_output(TxOut::new(value, wallet.script()));
+        let tx = tx_builder.build().unwrap();
+        let mut tx_signer = TxSigner::new(tx, inputs, &wallet.pkh_key_map, 1);
+        tx_signer.sign().unwrap()
+    }
+
+    fn setup() -> (Wallet, TxOutBnMap, Tx, Tx) {
+        let wallet = Wallet::new();
+        let mut utxos = TxOutBnMap::new();
+        utxos.add(&[0; 32], 0, TxOut::new(100, wallet.script()), 0);
+        let parent = spend(&wallet, &utxos, 100, 0);
+        let mut parent_outputs = TxOutBnMap::new();
+        parent_outputs.add_tx_outputs(&parent, 1);
+        let child = spend(&wallet, &parent_outputs, 100, 0);
+        (wallet, utxos, parent, child)
+    }
+
+    fn block_from_txs(txs: Vec<Tx>) -> Block {
+        let header = HeaderChain::new().get_next_header([0; 32], 1).unwrap();
+        Block::new(header, txs)
+    }
+
+    #[test]
+    fn test_add_tx_with_parent() {
+        let (_, utxos, parent, child) = setup();
+        let mut mempool = Mempool::new();
+
+        // the child can't be added before its parent
+        assert!(matches!(
+            mempool.add_tx(child.clone(), &utxos, 1),
+            Err(EbxError::VerifyError {
+                source: VerifyError::InputNotFound { n_in: 0, .. }
+            })
+        ));
+        let parent_id = mempool.add_tx(parent.clone(), &utxos, 1).unwrap();
+        let child_id = mempool.add_tx(child.clone(), &utxos, 1).unwrap();
+        assert_eq!(mempool.len(), 2);
+        assert_eq!(mempool.get_parents(&child_id), vec![parent_id]);
+        assert_eq!(mempool.get_children(&parent_id), vec![child_id]);
+        assert_eq!(mempool.get_spender(&[0; 32], 0), Some(&parent_id));
+
+        // adding the same tx twice is an error
+        assert!(mempool.add_tx(parent, &utxos, 1).is_err());
+    }
+
+    #[test]
+    fn test_reject_double_spend() {
+        let (wallet, utxos, parent, _) = setup();
+        let mut mempool = Mempool::new();
+        mempool.add_tx(parent, &utxos, 1).unwrap();
+        let conflict = spend(&wallet, &utxos, 100, 1);
+        assert!(mempool.add_tx(conflict, &utxos, 1).is_err());
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_tx_removes_descendants() {
+        let (_, utxos, parent, child) = setup();
+        let mut mempool = Mempool::new();
+        mempool.add_tx(parent.clone(), &utxos, 1).unwrap();
+        mempool.add_tx(child.clone(), &utxos, 1).unwrap();
+        let removed = mempool.remove_tx(&parent.id());
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed[0].id(), parent.id());
+        assert_eq!(removed[1].id(), child.id());
+        assert!(mempool.is_empty());
+        assert!(mempool.get_spender(&[0; 32], 0).is_none());
+    }
+
+    #[test]
+    fn test_remove_block_txs_confirms_parent() {
+        let (_, utxos, parent, child) = setup();
+        let mut mempool = Mempool::new();
+        mempool.add_tx(parent.clone(), &utxos, 1).unwrap();
+        mempool.add_tx(child.clone(), &utxos, 1).unwrap();
+        let evicted = mempool.remove_block_txs(&block_from_txs(vec![parent]));
+        assert!(evicted.is_empty());
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.get_parents(&child.id()).is_empty());
+    }
+
+    #[test]
+    fn test_remove_block_txs_evicts_conflicts() {
+        let (wallet, utxos, parent, child) = setup();
+        let mut mempool = Mempool::new();
+        mempool.add_tx(parent, &utxos, 1).unwrap();
+        mempool.add_tx(child, &utxos, 1).unwrap();
+        let conflict = spend(&wallet, &utxos, 100, 1);
+        let evicted = mempool.remove_block_txs(&block_from_txs(vec![conflict]));
+        assert_eq!(evicted.len(), 2);
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn test_get_txs_and_sort_txs() {
+        let (_, utxos, parent, child) = setup();
+        let mut mempool = Mempool::new();
+        mempool.add_tx(parent.clone(), &utxos, 1).unwrap();
+        mempool.add_tx(child.clone(), &utxos, 1).unwrap();
+        let ids: Vec<[u8; 32]> = mempool.get_txs().iter().map(|tx| tx.id()).collect();
+        assert_eq!(ids, vec![parent.id(), child.id()]);
+
+        let sorted = Mempool::sort_txs(vec![child.clone(), parent.clone()]);
+        let ids: Vec<[u8; 32]> = sorted.iter().map(|tx| tx.id()).collect();
+        assert_eq!(ids, vec![parent.id(), child.id()]);
+    }
+}
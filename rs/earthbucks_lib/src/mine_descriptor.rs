@@ -0,0 +1,442 @@
+use crate::domain::Domain;
+use crate::error::EbxError;
+use crate::header::Header;
+use crate::mine_registry::MineRegistry;
+use crate::pkh::Pkh;
+use crate::priv_key::PrivKey;
+use crate::pub_key::PubKey;
+use crate::signed_message::SignedMessage;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+// what a mine says about itself, published under the domain in its coinbases
+// at
+//
+//   <domain>/.well-known/earthbucks.json
+//
+// as the json of a SignedMineDescriptor. the signed message is the json of
+// the descriptor, signed by one of the listed pub keys. a node believes a
+// descriptor only if that key's pkh is paid by a recent coinbase with exactly
+// that domain, so that whoever controls the domain cannot speak for the mine
+// without its keys, and the other way around, and a key paid only under one
+// subdomain cannot speak for another. nor does it believe one signed more than
+// MAX_AGE ago, so a mine must re-sign its descriptor now and then and an old
+// one cannot be served in place of a newer one for long.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MineDescriptor {
+    pub domain: String,
+    #[serde(with = "crate::buf::hex_fixed_vec")]
+    pub pub_keys: Vec<[u8; PubKey::SIZE]>,
+    pub endpoints: Vec<String>, // api base urls
+    pub work_ser_algos: Vec<u16>,
+    pub work_par_algos: Vec<u16>,
+    pub timestamp: u64, // when it was signed, in ms like header timestamps
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedMineDescriptor {
+    pub signed_message: String, // strict hex of the SignedMessage
+}
+
+impl MineDescriptor {
+    pub const KEY_STR: &'static str = "earthbucks mine descriptor";
+    pub const WELL_KNOWN_PATH: &'static str = "/.well-known/earthbucks.json";
+    pub const MAX_AGE: u64 = 30 * 24 * 60 * 60 * 1000; // 30 days
+
+    fn message(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    pub fn sign(&self, priv_key: &PrivKey) -> Result<SignedMineDescriptor, EbxError> {
+        let signed_message =
+            SignedMessage::from_sign_message(priv_key, self.message(), MineDescriptor::KEY_STR)?;
+        Ok(SignedMineDescriptor {
+            signed_message: signed_message.to_strict_hex(),
+        })
+    }
+
+    // signed no later than timestamp and no more than MAX_AGE before it
+    pub fn is_fresh_at(&self, timestamp: u64) -> bool {
+        self.timestamp <= timestamp && timestamp - self.timestamp <= MineDescriptor::MAX_AGE
+    }
+}
+
+impl SignedMineDescriptor {
+    // the most a well-known document may take up, headers included
+    pub const MAX_RESPONSE_SIZE: u64 = 64 * 1024;
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, EbxError> {
+        serde_json::from_str(json).map_err(|e| EbxError::GenericError {
+            source: None,
+            message: format!("invalid mine descriptor: {}", e),
+        })
+    }
+
+    // the signed message is a descriptor signed by one of the keys it lists.
+    // returns the descriptor and the signing key.
+    fn open(&self) -> Result<(MineDescriptor, [u8; PubKey::SIZE]), EbxError> {
+        let signed_message =
+            SignedMessage::from_strict_hex(&self.signed_message, MineDescriptor::KEY_STR)?;
+        let descriptor: MineDescriptor = serde_json::from_slice(&signed_message.message)
+            .map_err(|e| invalid(&format!("invalid mine descriptor: {}", e)))?;
+        if !descriptor.pub_keys.contains(&signed_message.pub_key) {
+            return Err(invalid("signed by a key that is not listed"));
+        }
+        if !signed_message.is_valid(
+            &PubKey::new(signed_message.pub_key),
+            MineDescriptor::KEY_STR,
+        ) {
+            return Err(EbxError::InvalidKeyError { source: None });
+        }
+        Ok((descriptor, signed_message.pub_key))
+    }
+
+    pub fn verify_signature(&self) -> Result<MineDescriptor, EbxError> {
+        self.open().map(|(descriptor, _)| descriptor)
+    }
+
+    // the descriptor belongs to the recent mine that put domain in its
+    // coinbases: the domain matches and the signing key is paid by a coinbase
+    // in the registry with that very domain. it must also be fresh at
+    // timestamp.
+    pub fn verify_for_mine_at(
+        &self,
+        domain: &Domain,
+        registry: &MineRegistry,
+        timestamp: u64,
+    ) -> Result<MineDescriptor, EbxError> {
+        let (descriptor, pub_key) = self.open()?;
+        if descriptor.domain != domain.to_strict_str() {
+            return Err(invalid("descriptor is for another domain"));
+        }
+        if registry.get(domain).is_none() {
+            return Err(invalid("domain is not a recent mine"));
+        }
+        let pkh = Pkh::from_pub_key_buffer(pub_key.to_vec());
+        if !registry.get_coinbase_pkhs(domain).contains(&pkh.buf) {
+            return Err(invalid("signing key is not paid by the domain's coinbases"));
+        }
+        if !descriptor.is_fresh_at(timestamp) {
+            return Err(invalid("descriptor is too old or from the future"));
+        }
+        Ok(descriptor)
+    }
+
+    pub fn verify_for_mine_now(
+        &self,
+        domain: &Domain,
+        registry: &MineRegistry,
+    ) -> Result<MineDescriptor, EbxError> {
+        self.verify_for_mine_at(domain, registry, Header::get_new_timestamp())
+    }
+
+    // gets the descriptor with a plain http/1.1 request over stream, which is
+    // already connected to the domain's server. a tls stream can be passed
+    // for https.
+    pub fn fetch<S: Read + Write>(stream: &mut S, domain: &Domain) -> Result<Self, EbxError> {
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nAccept: application/json\r\nConnection: close\r\n\r\n",
+            MineDescriptor::WELL_KNOWN_PATH,
+            domain.to_strict_str()
+        );
+        stream
+            .write_all(request.as_bytes())
+            .and_then(|_| stream.flush())
+            .map_err(|e| EbxError::io("unable to send request", &e))?;
+        let mut response = Vec::new();
+        stream
+            .take(SignedMineDescriptor::MAX_RESPONSE_SIZE + 1)
+            .read_to_end(&mut response)
+            .map_err(|e| EbxError::io("unable to read response", &e))?;
+        if response.len() as u64 > SignedMineDescriptor::MAX_RESPONSE_SIZE {
+            return Err(EbxError::TooMuchDataError { source: None });
+        }
+        let response = String::from_utf8(response).map_err(|_| invalid("response is not utf8"))?;
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| invalid("response has no body"))?;
+        let status = head.lines().next().unwrap_or_default();
+        if status.split(' ').nth(1) != Some("200") {
+            return Err(invalid(&format!("unexpected response {:?}", status)));
+        }
+        let is_chunked = head.lines().any(|line| {
+            line.to_ascii_lowercase()
+                .starts_with("transfer-encoding: chunked")
+        });
+        if is_chunked {
+            return Err(invalid("chunked responses are not supported"));
+        }
+        SignedMineDescriptor::from_json(body)
+    }
+
+    // fetches the descriptor of a recent mine and checks it belongs to it
+    pub fn fetch_verified_at<S: Read + Write>(
+        stream: &mut S,
+        domain: &Domain,
+        registry: &MineRegistry,
+        timestamp: u64,
+    ) -> Result<MineDescriptor, EbxError> {
+        SignedMineDescriptor::fetch(stream, domain)?.verify_for_mine_at(domain, registry, timestamp)
+    }
+
+    pub fn fetch_verified_now<S: Read + Write>(
+        stream: &mut S,
+        domain: &Domain,
+        registry: &MineRegistry,
+    ) -> Result<MineDescriptor, EbxError> {
+        SignedMineDescriptor::fetch_verified_at(
+            stream,
+            domain,
+            registry,
+            Header::get_new_timestamp(),
+        )
+    }
+}
+
+fn invalid(message: &str) -> EbxError {
+    EbxError::GenericError {
+        source: None,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_pair::KeyPair;
+    use crate::test_util::{chain, Mine};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    const NOW: u64 = 1_000_000_000_000;
+
+    fn mine_domain(mine: &Mine) -> Domain {
+        Domain::new(mine.domain).unwrap()
+    }
+
+    fn descriptor(mine: &Mine) -> MineDescriptor {
+        MineDescriptor {
+            domain: mine.domain.to_string(),
+            pub_keys: vec![mine.key.pub_key.buf],
+            endpoints: vec![format!("https://{}/api", mine.domain)],
+            work_ser_algos: vec![0],
+            work_par_algos: vec![0],
+            timestamp: NOW,
+        }
+    }
+
+    fn signed_descriptor(mine: &Mine) -> SignedMineDescriptor {
+        descriptor(mine).sign(&mine.key.priv_key).unwrap()
+    }
+
+    // a registry in which the mine made one block
+    fn one_block_registry(mine: &Mine) -> MineRegistry {
+        let (lch, coinbase_txs) = chain(&[mine]);
+        MineRegistry::from_coinbases(&lch, &coinbase_txs).unwrap()
+    }
+
+    // a stand-in web server that gives the same response to one request
+    fn serve(response: String) -> (u16, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            // the client may hang up early on a response that is too big
+            let _ = stream.write_all(response.as_bytes());
+            String::from_utf8(request).unwrap()
+        });
+        (port, handle)
+    }
+
+    fn ok_response(body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let mine = Mine::new("mine.example.com");
+        let signed = signed_descriptor(&mine);
+        let signed = SignedMineDescriptor::from_json(&signed.to_json()).unwrap();
+        assert_eq!(signed.verify_signature().unwrap(), descriptor(&mine));
+        let registry = one_block_registry(&mine);
+        assert_eq!(
+            signed
+                .verify_for_mine_at(&mine_domain(&mine), &registry, NOW)
+                .unwrap(),
+            descriptor(&mine)
+        );
+
+        // the descriptor was changed after signing
+        let mut signed_message =
+            SignedMessage::from_strict_hex(&signed.signed_message, MineDescriptor::KEY_STR)
+                .unwrap();
+        signed_message.message = MineDescriptor {
+            endpoints: vec!["https://attacker.example.com".to_string()],
+            ..descriptor(&mine)
+        }
+        .message();
+        let changed = SignedMineDescriptor {
+            signed_message: signed_message.to_strict_hex(),
+        };
+        assert!(changed.verify_signature().is_err());
+
+        // a message that is not a descriptor
+        let not_descriptor = SignedMineDescriptor {
+            signed_message: SignedMessage::from_sign_message(
+                &mine.key.priv_key,
+                b"hello".to_vec(),
+                MineDescriptor::KEY_STR,
+            )
+            .unwrap()
+            .to_strict_hex(),
+        };
+        assert!(not_descriptor.verify_signature().is_err());
+
+        // a key that is not listed
+        let other = KeyPair::from_random();
+        let mut unlisted = descriptor(&mine).sign(&other.priv_key).unwrap();
+        assert!(unlisted.verify_signature().is_err());
+        // listed, but not paid by the mine's coinbases
+        unlisted = MineDescriptor {
+            pub_keys: vec![other.pub_key.buf],
+            ..descriptor(&mine)
+        }
+        .sign(&other.priv_key)
+        .unwrap();
+        assert!(unlisted.verify_signature().is_ok());
+        assert!(unlisted
+            .verify_for_mine_at(&mine_domain(&mine), &registry, NOW)
+            .is_err());
+
+        // a descriptor for a different domain
+        let other_domain = Domain::new("other.example.com").unwrap();
+        assert!(signed
+            .verify_for_mine_at(&other_domain, &registry, NOW)
+            .is_err());
+        // a domain that is not a recent mine
+        let other = Mine::new("other.example.org");
+        assert!(signed_descriptor(&other)
+            .verify_for_mine_at(&mine_domain(&other), &registry, NOW)
+            .is_err());
+    }
+
+    #[test]
+    fn test_subdomain_key_cannot_sign() {
+        let mine = Mine::new("mine.example.com");
+        let node = Mine::new("node.mine.example.com");
+        let (lch, coinbase_txs) = chain(&[&mine, &node]);
+        let registry = MineRegistry::from_coinbases(&lch, &coinbase_txs).unwrap();
+        assert_eq!(registry.len(), 1);
+
+        // a key paid only under node.mine.example.com speaks for that domain
+        assert!(signed_descriptor(&node)
+            .verify_for_mine_at(&mine_domain(&node), &registry, NOW)
+            .is_ok());
+        // but not for mine.example.com
+        let signed = MineDescriptor {
+            pub_keys: vec![node.key.pub_key.buf],
+            ..descriptor(&mine)
+        }
+        .sign(&node.key.priv_key)
+        .unwrap();
+        assert!(signed
+            .verify_for_mine_at(&mine_domain(&mine), &registry, NOW)
+            .is_err());
+    }
+
+    #[test]
+    fn test_freshness() {
+        let mine = Mine::new("mine.example.com");
+        let signed = signed_descriptor(&mine);
+        let registry = one_block_registry(&mine);
+        let at = |timestamp| signed.verify_for_mine_at(&mine_domain(&mine), &registry, timestamp);
+        assert!(at(NOW).is_ok());
+        assert!(at(NOW + MineDescriptor::MAX_AGE).is_ok());
+        // too old
+        assert!(at(NOW + MineDescriptor::MAX_AGE + 1).is_err());
+        // from the future
+        assert!(at(NOW - 1).is_err());
+
+        // a descriptor signed just now
+        let fresh = MineDescriptor {
+            timestamp: Header::get_new_timestamp(),
+            ..descriptor(&mine)
+        }
+        .sign(&mine.key.priv_key)
+        .unwrap();
+        assert!(fresh
+            .verify_for_mine_now(&mine_domain(&mine), &registry)
+            .is_ok());
+        assert!(signed
+            .verify_for_mine_now(&mine_domain(&mine), &registry)
+            .is_err());
+    }
+
+    #[test]
+    fn test_fetch_from_local_server() {
+        let mine = Mine::new("mine.example.com");
+        let (port, handle) = serve(ok_response(&signed_descriptor(&mine).to_json()));
+        let registry = one_block_registry(&mine);
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let fetched = SignedMineDescriptor::fetch_verified_at(
+            &mut stream,
+            &mine_domain(&mine),
+            &registry,
+            NOW,
+        )
+        .unwrap();
+        assert_eq!(fetched, descriptor(&mine));
+
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("GET /.well-known/earthbucks.json HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nHost: mine.example.com\r\n"));
+    }
+
+    #[test]
+    fn test_fetch_rejects_bad_responses() {
+        let mine = Mine::new("mine.example.com");
+        let json = signed_descriptor(&mine).to_json();
+        let responses = [
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n".to_string(),
+            ok_response("not json"),
+            ok_response(&" ".repeat(SignedMineDescriptor::MAX_RESPONSE_SIZE as usize)),
+        ];
+        for response in responses {
+            let (port, handle) = serve(response);
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            assert!(SignedMineDescriptor::fetch(&mut stream, &mine_domain(&mine)).is_err());
+            handle.join().unwrap();
+        }
+
+        // a valid descriptor from a server for a mine that is not this one
+        let impostor = Mine::new("mine.example.com");
+        let (port, handle) = serve(ok_response(&json));
+        let registry = one_block_registry(&impostor);
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        assert!(SignedMineDescriptor::fetch_verified_at(
+            &mut stream,
+            &mine_domain(&impostor),
+            &registry,
+            NOW,
+        )
+        .is_err());
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,282 @@
+use crate::domain::Domain;
+use crate::error::EbxError;
+use crate::header::Header;
+use crate::header_chain::HeaderChain;
+use crate::tx::Tx;
+use std::collections::{HashMap, HashSet};
+
+// the mines that made a window of consecutive blocks, found from the domain at
+// the top of each coinbase input script. these are the recent mines that are
+// asked for votes. a mine is known by the registrable domain of its coinbase
+// domains. blocks must be added in order with no gaps.
+#[derive(Debug, Clone, Default)]
+pub struct MineRegistry {
+    mines: HashMap<Domain, RecentMine>,
+    blocks: Vec<RecentBlock>, // one per block in the window, oldest first
+    start_block_num: u32,
+}
+
+#[derive(Debug, Clone)]
+struct RecentBlock {
+    domain: Domain,          // of the mine
+    coinbase_domain: Domain, // as it is in the coinbase
+    pkhs: HashSet<[u8; 32]>, // paid by its coinbase
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentMine {
+    pub n_blocks: u64,
+    pub last_block_num: u32,
+    pub last_block_id: [u8; 32],
+    pub pkhs: HashSet<[u8; 32]>, // paid by any of its coinbases
+}
+
+impl MineRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // coinbase_txs are the coinbases of the last coinbase_txs.len() blocks of
+    // lch, oldest first
+    pub fn from_coinbases(lch: &HeaderChain, coinbase_txs: &[Tx]) -> Result<Self, EbxError> {
+        let start = lch
+            .headers
+            .len()
+            .checked_sub(coinbase_txs.len())
+            .ok_or_else(|| EbxError::GenericError {
+                source: None,
+                message: "more coinbases than blocks".to_string(),
+            })?;
+        let mut registry = MineRegistry::new();
+        for (header, tx) in lch.headers[start..].iter().zip(coinbase_txs) {
+            registry.add_coinbase(header, tx)?;
+        }
+        Ok(registry)
+    }
+
+    pub fn add_coinbase(&mut self, header: &Header, coinbase_tx: &Tx) -> Result<(), EbxError> {
+        let invalid = |message: &str| EbxError::GenericError {
+            source: None,
+            message: message.to_string(),
+        };
+        if self.blocks.is_empty() {
+            self.start_block_num = header.block_num;
+        } else if header.block_num != self.next_block_num() {
+            return Err(invalid("block is not the next block"));
+        }
+        if !coinbase_tx.is_coinbase() || coinbase_tx.lock_abs != header.block_num {
+            return Err(invalid("coinbase does not match block number"));
+        }
+        let coinbase_domain = coinbase_tx
+            .get_coinbase_domain()
+            .ok_or_else(|| invalid("coinbase has no valid domain"))?;
+        let domain = MineRegistry::mine_domain(&coinbase_domain);
+        let mine = self
+            .mines
+            .entry(domain.clone())
+            .or_insert_with(|| RecentMine {
+                n_blocks: 0,
+                last_block_num: header.block_num,
+                last_block_id: header.id(),
+                pkhs: HashSet::new(),
+            });
+        mine.n_blocks += 1;
+        mine.last_block_num = header.block_num;
+        mine.last_block_id = header.id();
+        let mut pkhs = HashSet::new();
+        for tx_out in &coinbase_tx.outputs {
+            if tx_out.script.is_pkh_output() {
+                let pkh = tx_out.script.chunks[2].buffer.as_ref().unwrap();
+                pkhs.insert(pkh[..].try_into().unwrap());
+            }
+        }
+        mine.pkhs.extend(&pkhs);
+        self.blocks.push(RecentBlock {
+            domain,
+            coinbase_domain,
+            pkhs,
+        });
+        Ok(())
+    }
+
+    // the number of blocks in the window made by the mine whose coinbase pays
+    // any of pkhs. a key paid by only some of the mine's blocks speaks for
+    // just those blocks.
+    pub fn get_weight(&self, domain: &Domain, pkhs: &HashSet<[u8; 32]>) -> u64 {
+        let domain = MineRegistry::mine_domain(domain);
+        self.blocks
+            .iter()
+            .filter(|block| block.domain == domain && !block.pkhs.is_disjoint(pkhs))
+            .count() as u64
+    }
+
+    // the pkhs paid by the coinbases in the window whose domain is exactly
+    // coinbase_domain, rather than any domain of the same mine
+    pub fn get_coinbase_pkhs(&self, coinbase_domain: &Domain) -> HashSet<[u8; 32]> {
+        self.blocks
+            .iter()
+            .filter(|block| block.coinbase_domain == *coinbase_domain)
+            .flat_map(|block| block.pkhs.iter().copied())
+            .collect()
+    }
+
+    // every subdomain of one registrable domain is the same mine, which is
+    // known by its registrable domain
+    pub fn mine_domain(domain: &Domain) -> Domain {
+        domain
+            .registrable_domain()
+            .unwrap_or_else(|| domain.clone())
+    }
+
+    pub fn get(&self, domain: &Domain) -> Option<&RecentMine> {
+        self.mines.get(&MineRegistry::mine_domain(domain))
+    }
+
+    pub fn mines(&self) -> impl Iterator<Item = (&Domain, &RecentMine)> {
+        self.mines.iter()
+    }
+
+    // the mine that made the block, if the block is in the window
+    pub fn get_block_domain(&self, block_num: u32) -> Option<&Domain> {
+        let n = block_num.checked_sub(self.start_block_num)?;
+        self.blocks.get(n as usize).map(|block| &block.domain)
+    }
+
+    pub fn n_blocks(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    pub fn start_block_num(&self) -> u32 {
+        self.start_block_num
+    }
+
+    pub fn next_block_num(&self) -> u32 {
+        self.start_block_num + self.blocks.len() as u32
+    }
+
+    pub fn len(&self) -> usize {
+        self.mines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mines.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{chain, Mine};
+
+    fn domain(s: &str) -> Domain {
+        Domain::from_strict_str(s.to_string())
+    }
+
+    #[test]
+    fn test_from_coinbases() {
+        let a1 = Mine::new("minea.com");
+        let a2 = Mine::new("minea.com");
+        let b = Mine::new("mineb.com");
+        let (lch, coinbase_txs) = chain(&[&b, &a1, &b, &a2, &b]);
+        let (pkh_a1, pkh_a2, pkh_b) = (a1.pkh(), a2.pkh(), b.pkh());
+        let registry = MineRegistry::from_coinbases(&lch, &coinbase_txs[1..]).unwrap();
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.n_blocks(), 4);
+        assert_eq!(registry.start_block_num(), 1);
+        assert_eq!(registry.next_block_num(), 5);
+
+        let a = registry.get(&domain("minea.com")).unwrap();
+        assert_eq!(a.n_blocks, 2);
+        assert_eq!(a.last_block_num, 3);
+        assert_eq!(a.last_block_id, lch.headers[3].id());
+        assert_eq!(a.pkhs, HashSet::from([pkh_a1.buf, pkh_a2.buf]));
+        let b = registry.get(&domain("mineb.com")).unwrap();
+        assert_eq!(b.n_blocks, 2);
+        assert_eq!(b.last_block_num, 4);
+        assert_eq!(b.pkhs, HashSet::from([pkh_b.buf]));
+        assert!(registry.get(&domain("minec.com")).is_none());
+
+        assert_eq!(registry.get_block_domain(0), None);
+        assert_eq!(registry.get_block_domain(3), Some(&domain("minea.com")));
+        assert_eq!(registry.get_block_domain(5), None);
+
+        let a_domain = domain("minea.com");
+        assert_eq!(
+            registry.get_weight(&a_domain, &HashSet::from([pkh_a1.buf])),
+            1
+        );
+        assert_eq!(
+            registry.get_weight(&a_domain, &HashSet::from([pkh_a1.buf, pkh_a2.buf])),
+            2
+        );
+        assert_eq!(
+            registry.get_weight(&a_domain, &HashSet::from([pkh_b.buf])),
+            0
+        );
+        assert_eq!(registry.get_weight(&a_domain, &HashSet::new()), 0);
+    }
+
+    #[test]
+    fn test_subdomains_are_one_mine() {
+        let a = Mine::new("minea.com");
+        let node = Mine::new("node.minea.com");
+        let (lch, coinbase_txs) = chain(&[&a, &node]);
+        let registry = MineRegistry::from_coinbases(&lch, &coinbase_txs).unwrap();
+        assert_eq!(registry.len(), 1);
+        let mine = registry.get(&domain("other.minea.com")).unwrap();
+        assert_eq!(mine.n_blocks, 2);
+        assert_eq!(mine.pkhs, HashSet::from([a.pkh().buf, node.pkh().buf]));
+        assert_eq!(registry.get_block_domain(1), Some(&domain("minea.com")));
+        assert_eq!(
+            registry.get_coinbase_pkhs(&domain("node.minea.com")),
+            HashSet::from([node.pkh().buf])
+        );
+        assert!(registry
+            .get_coinbase_pkhs(&domain("other.minea.com"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_public_suffixes_split_mines() {
+        let a = Mine::new("a.me.uk");
+        let b = Mine::new("b.me.uk");
+        let x = Mine::new("x.github.io");
+        let y = Mine::new("node.y.github.io");
+        let (lch, coinbase_txs) = chain(&[&a, &b, &x, &y]);
+        let registry = MineRegistry::from_coinbases(&lch, &coinbase_txs).unwrap();
+        assert_eq!(registry.len(), 4);
+        assert_eq!(
+            registry.get(&domain("a.me.uk")).unwrap().pkhs,
+            HashSet::from([a.pkh().buf])
+        );
+        assert_eq!(registry.get_block_domain(3), Some(&domain("y.github.io")));
+        assert_eq!(
+            registry.get_weight(&domain("x.github.io"), &HashSet::from([y.pkh().buf])),
+            0
+        );
+    }
+
+    #[test]
+    fn test_reject_bad_coinbases() {
+        let mine = Mine::new("example.com");
+        let (lch, coinbase_txs) = chain(&[&mine, &mine]);
+        // coinbases that do not line up with the last blocks
+        assert!(MineRegistry::from_coinbases(&lch, &coinbase_txs[..1]).is_err());
+        let mut too_many = coinbase_txs.clone();
+        too_many.push(coinbase_txs[1].clone());
+        assert!(MineRegistry::from_coinbases(&lch, &too_many).is_err());
+
+        // blocks must follow on from the window
+        let mut registry = MineRegistry::new();
+        registry
+            .add_coinbase(&lch.headers[0], &coinbase_txs[0])
+            .unwrap();
+        assert!(registry
+            .add_coinbase(&lch.headers[0], &coinbase_txs[0])
+            .is_err());
+
+        // no valid domain
+        let (lch, coinbase_txs) = chain(&[&Mine::new("not a domain")]);
+        assert!(MineRegistry::from_coinbases(&lch, &coinbase_txs).is_err());
+    }
+}
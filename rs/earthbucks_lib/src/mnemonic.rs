@@ -0,0 +1,198 @@
+use crate::error::EbxError;
+use crate::hash::blake3_hash;
+use crate::hd_key::HdPrivKey;
+use crate::key_pair::KeyPair;
+use lazy_static::lazy_static;
+use rand::Rng;
+
+// mnemonic seed phrases. the encoding is bip39 with the english wordlist, but
+// the checksum is the first bits of the blake3 hash of the entropy rather than
+// sha256, and the seed is derived with blake3 rather than pbkdf2. phrases are
+// therefore not interchangeable with bip39 wallets.
+
+lazy_static! {
+    static ref WORDS: Vec<&'static str> = include_str!("mnemonic_english.txt").lines().collect();
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mnemonic {
+    entropy: Vec<u8>,
+}
+
+impl Mnemonic {
+    pub const SEED_CONTEXT: &'static str = "earthbucks mnemonic seed";
+    pub const MIN_ENTROPY_SIZE: usize = 16;
+    pub const MAX_ENTROPY_SIZE: usize = 32;
+
+    pub fn from_entropy(entropy: Vec<u8>) -> Result<Self, EbxError> {
+        if entropy.len() < Mnemonic::MIN_ENTROPY_SIZE {
+            return Err(EbxError::NotEnoughDataError { source: None });
+        }
+        if entropy.len() > Mnemonic::MAX_ENTROPY_SIZE {
+            return Err(EbxError::TooMuchDataError { source: None });
+        }
+        if !entropy.len().is_multiple_of(4) {
+            return Err(EbxError::InvalidSizeError { source: None });
+        }
+        Ok(Self { entropy })
+    }
+
+    // 24 words
+    pub fn from_random() -> Self {
+        let mut entropy = vec![0u8; Mnemonic::MAX_ENTROPY_SIZE];
+        rand::thread_rng().fill(&mut entropy[..]);
+        Self { entropy }
+    }
+
+    pub fn entropy(&self) -> &[u8] {
+        &self.entropy
+    }
+
+    pub fn to_words(&self) -> Vec<&'static str> {
+        let check_bits = self.entropy.len() / 4;
+        let mut bits: Vec<bool> = self
+            .entropy
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+            .collect();
+        let check_byte = blake3_hash(&self.entropy)[0];
+        bits.extend((0..check_bits).map(|i| (check_byte >> (7 - i)) & 1 == 1));
+        bits.chunks(11)
+            .map(|chunk| {
+                let index = chunk
+                    .iter()
+                    .fold(0usize, |acc, &bit| (acc << 1) | bit as usize);
+                WORDS[index]
+            })
+            .collect()
+    }
+
+    pub fn to_strict_str(&self) -> String {
+        self.to_words().join(" ")
+    }
+
+    // words must be lowercase and separated by single spaces
+    pub fn from_strict_str(s: &str) -> Result<Self, EbxError> {
+        let words: Vec<&str> = s.split(' ').collect();
+        if !words.len().is_multiple_of(3) || words.len() < 12 || words.len() > 24 {
+            return Err(EbxError::InvalidSizeError { source: None });
+        }
+        let mut bits = Vec::with_capacity(words.len() * 11);
+        for word in words {
+            let index = WORDS
+                .binary_search(&word)
+                .map_err(|_| EbxError::InvalidEncodingError { source: None })?;
+            bits.extend((0..11).rev().map(|i| (index >> i) & 1 == 1));
+        }
+        let check_bits = bits.len() / 33;
+        let (entropy_bits, check) = bits.split_at(bits.len() - check_bits);
+        let entropy: Vec<u8> = entropy_bits
+            .chunks(8)
+            .map(|chunk| chunk.iter().fold(0u8, |acc, &bit| (acc << 1) | bit as u8))
+            .collect();
+        let check_byte = blake3_hash(&entropy)[0];
+        let expected = (0..check_bits).map(|i| (check_byte >> (7 - i)) & 1 == 1);
+        if !check.iter().copied().eq(expected) {
+            return Err(EbxError::InvalidChecksumError { source: None });
+        }
+        Mnemonic::from_entropy(entropy)
+    }
+
+    pub fn is_valid_string_fmt(s: &str) -> bool {
+        Self::from_strict_str(s).is_ok()
+    }
+
+    // the passphrase is optional and may be empty. a different passphrase
+    // gives a different seed, and there is no way to tell which is right.
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        let mut hasher = blake3::Hasher::new_derive_key(Mnemonic::SEED_CONTEXT);
+        hasher.update(&[self.entropy.len() as u8]);
+        hasher.update(&self.entropy);
+        hasher.update(passphrase.as_bytes());
+        let mut seed = [0u8; 64];
+        hasher.finalize_xof().fill(&mut seed);
+        seed
+    }
+
+    pub fn to_hd_priv_key(&self, passphrase: &str) -> Result<HdPrivKey, EbxError> {
+        HdPrivKey::from_seed(&self.to_seed(passphrase))
+    }
+
+    // the master key of the hd key
+    pub fn to_key_pair(&self, passphrase: &str) -> Result<KeyPair, EbxError> {
+        self.to_hd_priv_key(passphrase)?.to_key_pair()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wordlist() {
+        assert_eq!(WORDS.len(), 2048);
+        assert!(WORDS.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_word_count() {
+        for (size, count) in [(16, 12), (20, 15), (24, 18), (28, 21), (32, 24)] {
+            let mnemonic = Mnemonic::from_entropy(vec![0xab; size]).unwrap();
+            assert_eq!(mnemonic.to_words().len(), count);
+        }
+        assert!(Mnemonic::from_entropy(vec![0; 12]).is_err());
+        assert!(Mnemonic::from_entropy(vec![0; 17]).is_err());
+        assert!(Mnemonic::from_entropy(vec![0; 36]).is_err());
+    }
+
+    #[test]
+    fn test_strict_str_round_trip() {
+        for _ in 0..10 {
+            let mnemonic = Mnemonic::from_random();
+            let s = mnemonic.to_strict_str();
+            assert!(Mnemonic::is_valid_string_fmt(&s));
+            assert_eq!(Mnemonic::from_strict_str(&s).unwrap(), mnemonic);
+        }
+    }
+
+    #[test]
+    fn test_zero_entropy() {
+        let mnemonic = Mnemonic::from_entropy(vec![0; 16]).unwrap();
+        let words = mnemonic.to_words();
+        assert!(words[0..11].iter().all(|&w| w == "abandon"));
+        // the last word holds 7 zero bits and the 4 checksum bits
+        let check = blake3_hash(&[0; 16])[0] >> 4;
+        assert_eq!(words[11], WORDS[check as usize]);
+    }
+
+    #[test]
+    fn test_is_valid_string_fmt() {
+        let s = Mnemonic::from_entropy(vec![7; 16]).unwrap().to_strict_str();
+        assert!(Mnemonic::is_valid_string_fmt(&s));
+        assert!(!Mnemonic::is_valid_string_fmt(&s.to_uppercase()));
+        assert!(!Mnemonic::is_valid_string_fmt(&s.replace(' ', "  ")));
+        assert!(!Mnemonic::is_valid_string_fmt(&format!("{} ", s)));
+        assert!(!Mnemonic::is_valid_string_fmt(""));
+
+        // flipping a checksum bit in the last word
+        let mut words: Vec<&str> = s.split(' ').collect();
+        let last = WORDS.binary_search(&words[11]).unwrap();
+        words[11] = WORDS[last ^ 1];
+        assert!(!Mnemonic::is_valid_string_fmt(&words.join(" ")));
+
+        let mut words: Vec<&str> = s.split(' ').collect();
+        words[0] = "notaword";
+        assert!(!Mnemonic::is_valid_string_fmt(&words.join(" ")));
+    }
+
+    #[test]
+    fn test_to_seed() {
+        let mnemonic = Mnemonic::from_entropy(vec![1; 32]).unwrap();
+        assert_eq!(mnemonic.to_seed(""), mnemonic.to_seed(""));
+        assert_ne!(mnemonic.to_seed(""), mnemonic.to_seed("passphrase"));
+
+        let hd_priv_key = mnemonic.to_hd_priv_key("").unwrap();
+        let key_pair = mnemonic.to_key_pair("").unwrap();
+        assert_eq!(hd_priv_key.priv_key.buf, key_pair.priv_key.buf);
+    }
+}
@@ -1,5 +1,25 @@
 pub use bnum::types::U256 as u256;
 
+// serde adapter for u256 values, which are decimal strings in json because
+// they do not fit in a json number. use with
+// #[serde(with = "crate::numbers::u256_dec")].
+pub mod u256_dec {
+    use super::u256;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(n: &u256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&n.to_str_radix(10))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u256, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(de::Error::custom("invalid decimal u256"));
+        }
+        u256::from_str_radix(&s, 10).map_err(de::Error::custom)
+    }
+}
+
 // pub use num256::uint256::Uint256 as u256;
 // use num_bigint::BigUint;
 
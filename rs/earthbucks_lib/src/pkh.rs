@@ -47,7 +47,10 @@ impl Pkh {
         if !s.starts_with("ebxpkh") {
             return Err(EbxError::InvalidEncodingError { source: None });
         }
-        let check_sum = <[u8; 4]>::from_strict_hex(&s[6..14])?;
+        let check_str = s
+            .get(6..14)
+            .ok_or(EbxError::InvalidEncodingError { source: None })?;
+        let check_sum = <[u8; 4]>::from_strict_hex(check_str)?;
 
         let buf = Vec::<u8>::from_base58(&s[14..])?;
         let check_buf = blake3_hash(&buf);
@@ -108,6 +111,7 @@ mod tests {
         assert!(!Pkh::is_valid_string_fmt(
             "ebxpkh31a042833G3ZzV3uEraE8B2Pvea3rKP2QkaQRVZkxmADrm3LEcN"
         ));
+        assert!(!Pkh::is_valid_string_fmt("ebxpkh31a0"));
 
         let pkh =
             Pkh::from_strict_str("ebxpkh31a042833G3ZzV3uEraE8B2Pvea3rKP2QkaQRVZkxmADrm3LEcN4")
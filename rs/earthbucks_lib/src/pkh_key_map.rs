@@ -1,6 +1,10 @@
 use crate::buf::EbxBuf;
+use crate::error::EbxError;
+use crate::hd_key::HdPrivKey;
 use crate::key_pair::KeyPair;
+use crate::pkh::Pkh;
 use std::collections::HashMap;
+use std::ops::Range;
 
 #[derive(Debug, Clone, Default)]
 pub struct PkhKeyMap {
@@ -29,6 +33,21 @@ impl PkhKeyMap {
         self.map.get(&pkh_hex)
     }
 
+    // adds the normal children of the hd key at the given indices, so that a
+    // wallet only needs to back up the hd key.
+    pub fn add_hd_children(
+        &mut self,
+        hd_priv_key: &HdPrivKey,
+        indices: Range<u32>,
+    ) -> Result<(), EbxError> {
+        for index in indices {
+            let key = hd_priv_key.derive_child(index)?.to_key_pair()?;
+            let pkh = Pkh::from_pub_key(key.pub_key.clone());
+            self.add(key, pkh.to_buf());
+        }
+        Ok(())
+    }
+
     pub fn values(&self) -> std::collections::hash_map::Values<'_, String, KeyPair> {
         self.map.values()
     }
@@ -37,8 +56,8 @@ impl PkhKeyMap {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hd_key::HdPubKey;
     use crate::key_pair::KeyPair;
-    use crate::pkh::Pkh;
     use hex;
 
     #[test]
@@ -109,4 +128,20 @@ mod tests {
         assert!(values_encoded.contains(&key1_encoded));
         assert!(values_encoded.contains(&key2_encoded));
     }
+
+    #[test]
+    fn test_add_hd_children() {
+        let hd_priv_key = HdPrivKey::from_seed(&[1u8; 32]).unwrap();
+        let mut pkh_key_map = PkhKeyMap::new();
+        pkh_key_map.add_hd_children(&hd_priv_key, 0..3).unwrap();
+        assert_eq!(pkh_key_map.values().len(), 3);
+
+        // a watch-only server derives the same pkhs from the hd pub key
+        let hd_pub_key: HdPubKey = hd_priv_key.to_hd_pub_key().unwrap();
+        for index in 0..3 {
+            let pub_key = hd_pub_key.derive_child(index).unwrap().pub_key;
+            let pkh = Pkh::from_pub_key(pub_key);
+            assert!(pkh_key_map.get(pkh.to_buf()).is_some());
+        }
+    }
 }
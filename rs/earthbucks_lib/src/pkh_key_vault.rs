@@ -0,0 +1,445 @@
+use crate::buf_reader::BufReader;
+use crate::buf_writer::BufWriter;
+use crate::error::EbxError;
+use crate::key_pair::KeyPair;
+use crate::pkh::Pkh;
+use crate::pkh_key_map::PkhKeyMap;
+use crate::priv_key::PrivKey;
+use crate::var_int::VarInt;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::Rng;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+// the cost parameters of argon2id. m_cost is in KiB.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl KdfParams {
+    // the params are read from the vault before the password can be checked,
+    // so they are capped to keep a crafted vault from exhausting memory or
+    // time on unlock
+    pub const MAX_M_COST: u32 = 1024 * 1024; // 1 GiB
+    pub const MAX_T_COST: u32 = 64;
+    pub const MAX_P_COST: u32 = 16;
+
+    pub fn is_within_caps(&self) -> bool {
+        self.m_cost <= KdfParams::MAX_M_COST
+            && self.t_cost <= KdfParams::MAX_T_COST
+            && self.p_cost <= KdfParams::MAX_P_COST
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+// a password-encrypted PkhKeyMap. the serialized form is:
+//
+//   magic "ebxvault" | version (u8) | m_cost (u32) | t_cost (u32) |
+//   p_cost (u32) | salt (16 bytes) | nonce (24 bytes) | ciphertext
+//
+// the key is argon2id of the password and salt, and the ciphertext is
+// xchacha20-poly1305 of the priv keys with everything before it as associated
+// data, so the header can't be changed without unlock failing. only the
+// encrypted form is ever written out. the key map is held in memory while the
+// vault is unlocked and dropped by lock. the derived key and the plaintext are
+// zeroed once they are no longer needed.
+#[derive(Clone, Debug)]
+pub struct PkhKeyVault {
+    header: Vec<u8>,
+    ciphertext: Vec<u8>,
+    pkh_key_map: Option<PkhKeyMap>,
+}
+
+impl PkhKeyVault {
+    pub const MAGIC: &'static [u8; 8] = b"ebxvault";
+    pub const VERSION: u8 = 1;
+    const SALT_SIZE: usize = 16;
+    const NONCE_SIZE: usize = 24;
+    const HEADER_SIZE: usize = 8 + 1 + 4 + 4 + 4 + PkhKeyVault::SALT_SIZE + PkhKeyVault::NONCE_SIZE;
+
+    // encrypts the key map. the new vault is unlocked.
+    pub fn new(
+        pkh_key_map: PkhKeyMap,
+        password: &str,
+        kdf_params: KdfParams,
+    ) -> Result<Self, EbxError> {
+        if !kdf_params.is_within_caps() {
+            return Err(EbxError::generic("kdf params are too large"));
+        }
+        let mut rng = rand::thread_rng();
+        let salt: [u8; PkhKeyVault::SALT_SIZE] = rng.gen();
+        let nonce: [u8; PkhKeyVault::NONCE_SIZE] = rng.gen();
+        let header = BufWriter::new()
+            .write(PkhKeyVault::MAGIC.to_vec())
+            .write_u8(PkhKeyVault::VERSION)
+            .write_u32_be(kdf_params.m_cost)
+            .write_u32_be(kdf_params.t_cost)
+            .write_u32_be(kdf_params.p_cost)
+            .write(salt.to_vec())
+            .write(nonce.to_vec())
+            .to_buf();
+
+        // sized up front so that no copy of the keys is left behind by a
+        // reallocation
+        let keys = pkh_key_map.values();
+        let count = VarInt::from_u64(keys.len() as u64).to_buf();
+        let mut plaintext = Zeroizing::new(Vec::with_capacity(count.len() + keys.len() * 32));
+        plaintext.extend_from_slice(&count);
+        for key in keys {
+            plaintext.extend_from_slice(&key.priv_key.buf);
+        }
+
+        let cipher = PkhKeyVault::cipher(password, &kdf_params, &salt)?;
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: &header,
+                },
+            )
+            .map_err(|_| EbxError::generic("unable to encrypt key map"))?;
+
+        Ok(Self {
+            header,
+            ciphertext,
+            pkh_key_map: Some(pkh_key_map),
+        })
+    }
+
+    pub fn from_buf(buf: Vec<u8>) -> Result<Self, EbxError> {
+        if buf.len() < PkhKeyVault::HEADER_SIZE {
+            return Err(EbxError::NotEnoughDataError { source: None });
+        }
+        if &buf[0..8] != PkhKeyVault::MAGIC {
+            return Err(EbxError::InvalidEncodingError { source: None });
+        }
+        if buf[8] != PkhKeyVault::VERSION {
+            return Err(EbxError::generic(&format!(
+                "unsupported key vault version {}",
+                buf[8]
+            )));
+        }
+        let (header, ciphertext) = buf.split_at(PkhKeyVault::HEADER_SIZE);
+        let vault = Self {
+            header: header.to_vec(),
+            ciphertext: ciphertext.to_vec(),
+            pkh_key_map: None,
+        };
+        if !vault.kdf_params().is_within_caps() {
+            return Err(EbxError::generic("kdf params are too large"));
+        }
+        Ok(vault)
+    }
+
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut buf = self.header.clone();
+        buf.extend_from_slice(&self.ciphertext);
+        buf
+    }
+
+    pub fn kdf_params(&self) -> KdfParams {
+        let mut reader = BufReader::new(self.header[9..21].to_vec());
+        KdfParams {
+            m_cost: reader.read_u32_be().unwrap(),
+            t_cost: reader.read_u32_be().unwrap(),
+            p_cost: reader.read_u32_be().unwrap(),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.pkh_key_map.is_none()
+    }
+
+    // the key map, if unlocked
+    pub fn pkh_key_map(&self) -> Option<&PkhKeyMap> {
+        self.pkh_key_map.as_ref()
+    }
+
+    pub fn lock(&mut self) {
+        self.pkh_key_map = None;
+    }
+
+    pub fn unlock(&mut self, password: &str) -> Result<&PkhKeyMap, EbxError> {
+        let pkh_key_map = self.decrypt(password)?;
+        Ok(self.pkh_key_map.insert(pkh_key_map))
+    }
+
+    // re-encrypts the keys under a new password with a new salt and nonce. the
+    // vault is left unlocked.
+    pub fn change_password(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+        kdf_params: KdfParams,
+    ) -> Result<(), EbxError> {
+        let pkh_key_map = self.decrypt(old_password)?;
+        *self = PkhKeyVault::new(pkh_key_map, new_password, kdf_params)?;
+        Ok(())
+    }
+
+    // writes to a temporary file and renames it over the path, so a crash
+    // leaves either the old vault or the new one.
+    pub fn save(&self, path: &Path) -> Result<(), EbxError> {
+        let mut tmp_path = path.to_path_buf().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut tmp_file =
+            File::create(&tmp_path).map_err(|e| EbxError::io("unable to create key vault", &e))?;
+        tmp_file
+            .write_all(&self.to_buf())
+            .map_err(|e| EbxError::io("unable to write key vault", &e))?;
+        tmp_file
+            .sync_all()
+            .map_err(|e| EbxError::io("unable to sync key vault", &e))?;
+        fs::rename(&tmp_path, path).map_err(|e| EbxError::io("unable to replace key vault", &e))?;
+        Ok(())
+    }
+
+    // the loaded vault is locked
+    pub fn load(path: &Path) -> Result<Self, EbxError> {
+        let buf = fs::read(path).map_err(|e| EbxError::io("unable to read key vault", &e))?;
+        PkhKeyVault::from_buf(buf)
+    }
+
+    fn decrypt(&self, password: &str) -> Result<PkhKeyMap, EbxError> {
+        let salt = &self.header[21..21 + PkhKeyVault::SALT_SIZE];
+        let nonce = &self.header[21 + PkhKeyVault::SALT_SIZE..PkhKeyVault::HEADER_SIZE];
+        let cipher = PkhKeyVault::cipher(password, &self.kdf_params(), salt)?;
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(
+                    XNonce::from_slice(nonce),
+                    Payload {
+                        msg: &self.ciphertext,
+                        aad: &self.header,
+                    },
+                )
+                .map_err(|_| EbxError::generic("wrong password or corrupt key vault"))?,
+        );
+
+        // only the count goes through a BufReader, so that the keys are not
+        // copied out of the zeroed buffer
+        let count_len = match plaintext.first() {
+            Some(0xfd) => 3,
+            Some(0xfe) => 5,
+            Some(0xff) => 9,
+            _ => 1,
+        };
+        let count_len = count_len.min(plaintext.len());
+        let count = BufReader::new(plaintext[..count_len].to_vec()).read_var_int()?;
+        let keys = &plaintext[count_len..];
+        if (keys.len() as u64) < count * 32 {
+            return Err(EbxError::NotEnoughDataError { source: None });
+        }
+        if (keys.len() as u64) > count * 32 {
+            return Err(EbxError::TooMuchDataError { source: None });
+        }
+        let mut pkh_key_map = PkhKeyMap::new();
+        for priv_key_buf in keys.chunks_exact(32) {
+            let priv_key = PrivKey::from_buffer(priv_key_buf.try_into().unwrap());
+            let key = KeyPair::from_priv_key(&priv_key)?;
+            let pkh = Pkh::from_pub_key(key.pub_key.clone());
+            pkh_key_map.add(key, pkh.to_buf());
+        }
+        Ok(pkh_key_map)
+    }
+
+    fn cipher(
+        password: &str,
+        kdf_params: &KdfParams,
+        salt: &[u8],
+    ) -> Result<XChaCha20Poly1305, EbxError> {
+        let params = Params::new(
+            kdf_params.m_cost,
+            kdf_params.t_cost,
+            kdf_params.p_cost,
+            Some(32),
+        )
+        .map_err(|e| EbxError::generic(&format!("invalid kdf params: {}", e)))?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), salt, &mut *key)
+            .map_err(|e| EbxError::generic(&format!("unable to derive key: {}", e)))?;
+        Ok(XChaCha20Poly1305::new(Key::from_slice(&*key)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buf::EbxBuf;
+    use crate::script::Script;
+    use crate::tx::Tx;
+    use crate::tx_builder::TxBuilder;
+    use crate::tx_out::TxOut;
+    use crate::tx_out_bn_map::TxOutBnMap;
+    use crate::tx_signer::TxSigner;
+    use crate::tx_verifier::TxVerifier;
+
+    // cheap enough for tests
+    const TEST_PARAMS: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn key_map(n: usize) -> PkhKeyMap {
+        let mut pkh_key_map = PkhKeyMap::new();
+        for _ in 0..n {
+            let key = KeyPair::from_random();
+            let pkh = Pkh::from_pub_key(key.pub_key.clone());
+            pkh_key_map.add(key, pkh.to_buf());
+        }
+        pkh_key_map
+    }
+
+    fn temp_path() -> PathBuf {
+        let mut rng = rand::thread_rng();
+        let name: [u8; 8] = rng.gen();
+        std::env::temp_dir().join(format!("ebx-vault-{}.dat", name.to_strict_hex()))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let pkh_key_map = key_map(3);
+        let vault = PkhKeyVault::new(pkh_key_map.clone(), "password", TEST_PARAMS).unwrap();
+        assert!(!vault.is_locked());
+
+        let mut vault2 = PkhKeyVault::from_buf(vault.to_buf()).unwrap();
+        assert!(vault2.is_locked());
+        assert_eq!(vault2.kdf_params(), TEST_PARAMS);
+        let unlocked = vault2.unlock("password").unwrap();
+        assert_eq!(unlocked.values().len(), 3);
+        for key in pkh_key_map.values() {
+            let pkh = Pkh::from_pub_key(key.pub_key.clone());
+            assert_eq!(
+                unlocked.get(pkh.to_buf()).unwrap().priv_key.buf,
+                key.priv_key.buf
+            );
+        }
+
+        vault2.lock();
+        assert!(vault2.is_locked());
+        assert!(vault2.pkh_key_map().is_none());
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let vault = PkhKeyVault::new(key_map(1), "password", TEST_PARAMS).unwrap();
+        let mut vault = PkhKeyVault::from_buf(vault.to_buf()).unwrap();
+        assert!(vault.unlock("wrong").is_err());
+        assert!(vault.is_locked());
+    }
+
+    #[test]
+    fn test_tampered_header_or_ciphertext() {
+        let buf = PkhKeyVault::new(key_map(1), "password", TEST_PARAMS)
+            .unwrap()
+            .to_buf();
+
+        // bump t_cost
+        let mut tampered = buf.clone();
+        tampered[16] ^= 1;
+        let mut vault = PkhKeyVault::from_buf(tampered).unwrap();
+        assert!(vault.unlock("password").is_err());
+
+        let mut tampered = buf.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let mut vault = PkhKeyVault::from_buf(tampered).unwrap();
+        assert!(vault.unlock("password").is_err());
+    }
+
+    #[test]
+    fn test_from_buf_rejects_bad_header() {
+        let buf = PkhKeyVault::new(key_map(1), "password", TEST_PARAMS)
+            .unwrap()
+            .to_buf();
+        assert!(PkhKeyVault::from_buf(buf[0..10].to_vec()).is_err());
+
+        let mut bad_magic = buf.clone();
+        bad_magic[0] = b'x';
+        assert!(PkhKeyVault::from_buf(bad_magic).is_err());
+
+        let mut bad_version = buf.clone();
+        bad_version[8] = 2;
+        assert!(PkhKeyVault::from_buf(bad_version).is_err());
+    }
+
+    #[test]
+    fn test_kdf_params_are_capped() {
+        let buf = PkhKeyVault::new(key_map(1), "password", TEST_PARAMS)
+            .unwrap()
+            .to_buf();
+        // m_cost of about 4 TiB, which would be allocated before the password
+        // is checked
+        let mut huge_m_cost = buf.clone();
+        huge_m_cost[9..13].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(PkhKeyVault::from_buf(huge_m_cost).is_err());
+
+        let mut huge_t_cost = buf.clone();
+        huge_t_cost[13..17].copy_from_slice(&(KdfParams::MAX_T_COST + 1).to_be_bytes());
+        assert!(PkhKeyVault::from_buf(huge_t_cost).is_err());
+
+        let huge_p_cost = KdfParams {
+            p_cost: KdfParams::MAX_P_COST + 1,
+            ..TEST_PARAMS
+        };
+        assert!(PkhKeyVault::new(key_map(1), "password", huge_p_cost).is_err());
+    }
+
+    #[test]
+    fn test_change_password() {
+        let mut vault = PkhKeyVault::new(key_map(2), "old", TEST_PARAMS).unwrap();
+        assert!(vault.change_password("wrong", "new", TEST_PARAMS).is_err());
+        vault.change_password("old", "new", TEST_PARAMS).unwrap();
+
+        let mut vault = PkhKeyVault::from_buf(vault.to_buf()).unwrap();
+        assert!(vault.unlock("old").is_err());
+        assert_eq!(vault.unlock("new").unwrap().values().len(), 2);
+    }
+
+    #[test]
+    fn test_save_load_and_sign() {
+        let pkh_key_map = key_map(1);
+        let key = pkh_key_map.values().next().unwrap().clone();
+        let pkh = Pkh::from_pub_key(key.pub_key.clone());
+        let path = temp_path();
+        PkhKeyVault::new(pkh_key_map, "password", TEST_PARAMS)
+            .unwrap()
+            .save(&path)
+            .unwrap();
+
+        let mut vault = PkhKeyVault::load(&path).unwrap();
+        let pkh_key_map = vault.unlock("password").unwrap();
+
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        let script = Script::from_pkh_output(pkh.to_buf());
+        tx_out_bn_map.add(&[1; 32], 0, TxOut::new(100, script.clone()), 0);
+        let mut tx_builder = TxBuilder::new(&tx_out_bn_map, script.clone(), 0);
+        tx_builder.add_output(TxOut::new(100, script));
+        let tx: Tx = tx_builder.build().unwrap();
+        let tx = TxSigner::new(tx, &tx_out_bn_map, pkh_key_map, 0)
+            .sign()
+            .unwrap();
+        assert!(TxVerifier::new(tx, &tx_out_bn_map, 0).verify().is_ok());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
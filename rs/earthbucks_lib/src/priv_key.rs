@@ -77,7 +77,10 @@ impl PrivKey {
         if !s.starts_with("ebxprv") {
             return Err(EbxError::InvalidEncodingError { source: None });
         }
-        let check_sum: [u8; 4] = <[u8; 4]>::from_strict_hex(&s[6..14])?;
+        let check_str = s
+            .get(6..14)
+            .ok_or(EbxError::InvalidEncodingError { source: None })?;
+        let check_sum: [u8; 4] = <[u8; 4]>::from_strict_hex(check_str)?;
         let buf = Vec::<u8>::from_base58(&s[14..])
             .map_err(|_| EbxError::InvalidEncodingError { source: None })?;
         let check_buf = blake3::hash(&buf);
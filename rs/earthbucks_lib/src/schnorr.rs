@@ -0,0 +1,240 @@
+use crate::error::EbxError;
+use crate::pub_key::PubKey;
+use lazy_static::lazy_static;
+use num_bigint::BigUint;
+use secp256k1::constants::CURVE_ORDER;
+use secp256k1::{All, PublicKey, Scalar, Secp256k1, SecretKey};
+
+// schnorr signatures over secp256k1 in the style of bip340, with blake3 in
+// place of the tagged sha256 hashes. a signature is r || s where r is the x
+// coordinate of the nonce point R, which always has an even y, and
+//
+//   s = k + e * d  (mod n)
+//   e = blake3(R.x || P || msg) in derive-key mode with CHALLENGE_CONTEXT,
+//       reduced mod n
+//
+// unlike bip340, P is the full 33 byte compressed pub key, so keys work as they
+// are and never need to be negated. the nonce k is derived from the priv key
+// and the message, so signing is deterministic.
+
+pub const CHALLENGE_CONTEXT: &str = "earthbucks schnorr challenge";
+
+lazy_static! {
+    // verifying multiplies by the generator too, so it needs a full context
+    static ref SECP: Secp256k1<All> = Secp256k1::new();
+}
+
+fn challenge(r_x: &[u8], pub_key: &[u8; PubKey::SIZE], msg: &[u8; 32]) -> Option<Scalar> {
+    let mut hasher = blake3::Hasher::new_derive_key(CHALLENGE_CONTEXT);
+    hasher.update(r_x);
+    hasher.update(pub_key);
+    hasher.update(msg);
+    let hash: [u8; 32] = hasher.finalize().into();
+    let e = BigUint::from_bytes_be(&hash) % BigUint::from_bytes_be(&CURVE_ORDER);
+    let mut e_buf = [0u8; 32];
+    let e_bytes = e.to_bytes_be();
+    e_buf[32 - e_bytes.len()..].copy_from_slice(&e_bytes);
+    Scalar::from_be_bytes(e_buf).ok()
+}
+
+fn nonce(priv_key: &[u8; 32], msg: &[u8; 32]) -> SecretKey {
+    let mut counter: u32 = 0;
+    loop {
+        let mut hasher = blake3::Hasher::new_keyed(priv_key);
+        hasher.update(msg);
+        hasher.update(&counter.to_be_bytes());
+        let k: [u8; 32] = hasher.finalize().into();
+        // fails only for zero or for values of at least n
+        if let Ok(k) = SecretKey::from_slice(&k) {
+            return k;
+        }
+        counter += 1;
+    }
+}
+
+pub fn sign(priv_key: &[u8; 32], msg: &[u8; 32]) -> Result<[u8; 64], EbxError> {
+    let secp = &*SECP;
+    let d =
+        SecretKey::from_slice(priv_key).map_err(|_| EbxError::InvalidKeyError { source: None })?;
+    let pub_key = PublicKey::from_secret_key(secp, &d).serialize();
+
+    let mut k = nonce(priv_key, msg);
+    let r = PublicKey::from_secret_key(secp, &k).serialize();
+    if r[0] == 0x03 {
+        k = k.negate();
+    }
+    let r_x = &r[1..33];
+
+    let invalid = || EbxError::GenericError {
+        source: None,
+        message: "unable to make schnorr signature".to_string(),
+    };
+    let e = challenge(r_x, &pub_key, msg).ok_or_else(invalid)?;
+    let s = d
+        .mul_tweak(&e)
+        .and_then(|ed| ed.add_tweak(&Scalar::from(k)))
+        .map_err(|_| invalid())?;
+
+    let mut sig = [0u8; 64];
+    sig[0..32].copy_from_slice(r_x);
+    sig[32..64].copy_from_slice(&s.secret_bytes());
+    Ok(sig)
+}
+
+// checks s * G == R + e * P
+pub fn verify(pub_key: &[u8; PubKey::SIZE], msg: &[u8; 32], sig: &[u8; 64]) -> bool {
+    let secp = &*SECP;
+    let Ok(p) = PublicKey::from_slice(pub_key) else {
+        return false;
+    };
+    let mut r_buf = [0x02; 33];
+    r_buf[1..33].copy_from_slice(&sig[0..32]);
+    let Ok(r) = PublicKey::from_slice(&r_buf) else {
+        return false;
+    };
+    let Ok(s) = SecretKey::from_slice(&sig[32..64]) else {
+        return false;
+    };
+    let Some(e) = challenge(&sig[0..32], pub_key, msg) else {
+        return false;
+    };
+    let Ok(ep) = p.mul_tweak(secp, &e) else {
+        return false;
+    };
+    let Ok(rhs) = r.combine(&ep) else {
+        return false;
+    };
+    PublicKey::from_secret_key(secp, &s) == rhs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buf::EbxBuf;
+    use crate::key_pair::KeyPair;
+    use crate::priv_key::PrivKey;
+    use crate::tx::Tx;
+    use crate::tx_signature::TxSignature;
+    use serde::Deserialize;
+    use std::fs;
+
+    #[test]
+    fn test_sign_and_verify() {
+        for i in 0..20u8 {
+            let key = KeyPair::from_random();
+            let msg = [i; 32];
+            let sig = sign(&key.priv_key.buf, &msg).unwrap();
+            assert!(verify(&key.pub_key.buf, &msg, &sig));
+        }
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let key = KeyPair::from_random();
+        let msg = [1; 32];
+        assert_eq!(
+            sign(&key.priv_key.buf, &msg).unwrap(),
+            sign(&key.priv_key.buf, &msg).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_wrong_msg_key_or_sig() {
+        let key = KeyPair::from_random();
+        let other_key = KeyPair::from_random();
+        let msg = [1; 32];
+        let sig = sign(&key.priv_key.buf, &msg).unwrap();
+        assert!(!verify(&key.pub_key.buf, &[2; 32], &sig));
+        assert!(!verify(&other_key.pub_key.buf, &msg, &sig));
+
+        let mut bad_r = sig;
+        bad_r[0] ^= 1;
+        assert!(!verify(&key.pub_key.buf, &msg, &bad_r));
+        let mut bad_s = sig;
+        bad_s[63] ^= 1;
+        assert!(!verify(&key.pub_key.buf, &msg, &bad_s));
+        // s out of range
+        let mut big_s = sig;
+        big_s[32..64].copy_from_slice(&[0xff; 32]);
+        assert!(!verify(&key.pub_key.buf, &msg, &big_s));
+    }
+
+    #[test]
+    fn test_invalid_pub_key() {
+        let key = KeyPair::from_random();
+        let msg = [1; 32];
+        let sig = sign(&key.priv_key.buf, &msg).unwrap();
+        assert!(!verify(&[0; PubKey::SIZE], &msg, &sig));
+    }
+
+    #[derive(Deserialize)]
+    struct JsonSchnorr {
+        priv_key: String,
+        pub_key: String,
+        message: String,
+        sig: String,
+    }
+
+    #[derive(Deserialize)]
+    struct JsonTxSignature {
+        priv_key: String,
+        pub_key: String,
+        tx: String,
+        input_index: usize,
+        script: String,
+        amount: u64,
+        hash_type: u8,
+        sig: String,
+    }
+
+    #[derive(Deserialize)]
+    struct JsonSchnorrVectors {
+        schnorr: Vec<JsonSchnorr>,
+        tx_signature: Vec<JsonTxSignature>,
+    }
+
+    #[test]
+    fn test_vectors() {
+        let data = fs::read_to_string("./test_vectors/schnorr.json").expect("Unable to read file");
+        let test_vectors: JsonSchnorrVectors =
+            serde_json::from_str(&data).expect("Unable to parse JSON");
+
+        for test_vector in test_vectors.schnorr {
+            let priv_key = PrivKey::from_strict_str(&test_vector.priv_key).unwrap();
+            let pub_key = PubKey::from_strict_str(&test_vector.pub_key).unwrap();
+            let msg: [u8; 32] = Vec::<u8>::from_strict_hex(&test_vector.message)
+                .unwrap()
+                .try_into()
+                .unwrap();
+            let sig = sign(&priv_key.buf, &msg).unwrap();
+            assert_eq!(sig.to_strict_hex(), test_vector.sig);
+            assert!(verify(&pub_key.buf, &msg, &sig));
+        }
+
+        for test_vector in test_vectors.tx_signature {
+            let priv_key = PrivKey::from_strict_str(&test_vector.priv_key).unwrap();
+            let pub_key = PubKey::from_strict_str(&test_vector.pub_key).unwrap();
+            let mut tx = Tx::from_strict_hex(&test_vector.tx).unwrap();
+            let script = Vec::<u8>::from_strict_hex(&test_vector.script).unwrap();
+            let sig = tx.sign_no_cache(
+                test_vector.input_index,
+                priv_key.buf,
+                script.clone(),
+                test_vector.amount,
+                test_vector.hash_type,
+            );
+            assert!(sig.is_schnorr());
+            assert_eq!(sig.to_buf().to_strict_hex(), test_vector.sig);
+
+            let sig = TxSignature::from_buf(Vec::<u8>::from_strict_hex(&test_vector.sig).unwrap())
+                .unwrap();
+            assert!(tx.verify_no_cache(
+                test_vector.input_index,
+                pub_key.buf,
+                sig,
+                script,
+                test_vector.amount,
+            ));
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::buf::EbxBuf;
 use crate::buf_reader::BufReader;
 use crate::error::EbxError;
 use crate::opcode::Opcode;
@@ -5,6 +6,7 @@ use crate::pub_key::PubKey;
 use crate::script_chunk::ScriptChunk;
 use crate::script_num::ScriptNum;
 use crate::tx_signature::TxSignature;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(PartialEq, Debug, Clone, Default)]
 pub struct Script {
@@ -530,10 +532,42 @@ impl Script {
     }
 }
 
+// in json a script is both its strict string and its hex. the hex is what gets
+// decoded, so any script round-trips. the string is null if the script has an
+// opcode with no name, and otherwise must agree with the hex.
+#[derive(Serialize, Deserialize)]
+struct ScriptJson {
+    str: Option<String>,
+    hex: String,
+}
+
+impl Serialize for Script {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ScriptJson {
+            str: self.to_strict_str().ok(),
+            hex: self.to_buf().to_strict_hex(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Script {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let json = ScriptJson::deserialize(deserializer)?;
+        let buf = Vec::<u8>::from_strict_hex(&json.hex).map_err(de::Error::custom)?;
+        let script = Script::from_buf(&buf).map_err(de::Error::custom)?;
+        if let Some(s) = json.str {
+            if script.to_strict_str().ok().as_deref() != Some(s.as_str()) {
+                return Err(de::Error::custom("script str does not match hex"));
+            }
+        }
+        Ok(script)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::buf::EbxBuf;
     use crate::script_chunk::ScriptChunk;
     use serde::Deserialize;
 
@@ -646,6 +680,32 @@ mod tests {
 
     // standard test vectors
 
+    #[test]
+    fn test_json_round_trip() {
+        let script =
+            Script::from_strict_str("DUP DOUBLEBLAKE3 0x0102 EQUALVERIFY CHECKSIG").unwrap();
+        let json = serde_json::to_value(&script).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "str": "DUP DOUBLEBLAKE3 0x0102 EQUALVERIFY CHECKSIG",
+                "hex": script.to_buf().to_strict_hex(),
+            })
+        );
+        let script2: Script = serde_json::from_value(json).unwrap();
+        assert_eq!(script2, script);
+    }
+
+    #[test]
+    fn test_json_str_must_match_hex() {
+        let script = Script::from_strict_str("DUP").unwrap();
+        let json = serde_json::json!({
+            "str": "CHECKSIG",
+            "hex": script.to_buf().to_strict_hex(),
+        });
+        assert!(serde_json::from_value::<Script>(json).is_err());
+    }
+
     #[derive(Deserialize)]
     struct TestVectorScript {
         from_buf: TestVectorErrors,
@@ -0,0 +1,53 @@
+use std::fmt;
+
+// why a script failed to evaluate. the display strings are the error strings
+// used by the script interpreter test vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptError {
+    UnbalancedConditional,
+    InvalidPushdata,
+    InvalidStackOperation,
+    VerifyFailed,
+    EqualVerifyFailed,
+    NumEqualVerifyFailed,
+    CheckSigVerifyFailed,
+    CheckMultiSigVerifyFailed,
+    DivisionByZero,
+    InvalidNumberOfKeys,
+    InvalidNumberOfSignatures,
+    InvalidPublicKeyLength,
+    InvalidSignatureLength,
+    NegativeLockAbs,
+    LockAbsRequirementNotMet,
+    NegativeLockRel,
+    LockRelRequirementNotMet,
+    InvalidOpcode,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            ScriptError::UnbalancedConditional => "unbalanced conditional",
+            ScriptError::InvalidPushdata => "invalid pushdata",
+            ScriptError::InvalidStackOperation => "invalid stack operation",
+            ScriptError::VerifyFailed => "VERIFY failed",
+            ScriptError::EqualVerifyFailed => "EQUALVERIFY failed",
+            ScriptError::NumEqualVerifyFailed => "NUMEQUALVERIFY failed",
+            ScriptError::CheckSigVerifyFailed => "CHECKSIGVERIFY failed",
+            ScriptError::CheckMultiSigVerifyFailed => "CHECKMULTISIGVERIFY failed",
+            ScriptError::DivisionByZero => "division by zero",
+            ScriptError::InvalidNumberOfKeys => "invalid number of keys",
+            ScriptError::InvalidNumberOfSignatures => "invalid number of signatures",
+            ScriptError::InvalidPublicKeyLength => "invalid public key length",
+            ScriptError::InvalidSignatureLength => "invalid signature length",
+            ScriptError::NegativeLockAbs => "negative lockabs",
+            ScriptError::LockAbsRequirementNotMet => "lockabs requirement not met",
+            ScriptError::NegativeLockRel => "negative lockrel",
+            ScriptError::LockRelRequirementNotMet => "lockrel requirement not met",
+            ScriptError::InvalidOpcode => "invalid opcode",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl std::error::Error for ScriptError {}
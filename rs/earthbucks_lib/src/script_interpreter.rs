@@ -2,7 +2,10 @@ use crate::hash::{blake3_hash, double_blake3_hash};
 use crate::opcode::{Opcode, OP};
 use crate::pub_key::PubKey;
 use crate::script::Script;
+use crate::script_error::ScriptError;
 use crate::script_num::ScriptNum;
+use crate::script_trace::{ScriptTrace, ScriptTraceStep};
+use crate::sig_check::SigCheck;
 use crate::tx::{HashCache, Tx};
 use crate::tx_signature::TxSignature;
 use num_bigint::{BigInt, ToBigInt};
@@ -19,9 +22,13 @@ pub struct ScriptInterpreter<'a> {
     pub if_stack: Vec<bool>,
     pub return_value: Option<Vec<u8>>,
     pub return_success: Option<bool>,
-    pub err_str: String,
+    pub err: Option<ScriptError>,
     pub value: u64,
     pub hash_cache: &'a mut HashCache,
+    pub trace: Option<ScriptTrace>,
+    // when set, OP_CHECKSIG records its check here and assumes it passes
+    pub deferred_sigs: Option<Vec<SigCheck>>,
+    halted: bool,
 }
 
 impl<'a> ScriptInterpreter<'a> {
@@ -42,9 +49,12 @@ impl<'a> ScriptInterpreter<'a> {
             if_stack: Vec::new(),
             return_value: None,
             return_success: None,
-            err_str: "".to_string(),
+            err: None,
             value: 0,
             hash_cache,
+            trace: None,
+            deferred_sigs: None,
+            halted: false,
         }
     }
 
@@ -67,9 +77,12 @@ impl<'a> ScriptInterpreter<'a> {
             if_stack: Vec::new(),
             return_value: None,
             return_success: None,
-            err_str: "".to_string(),
+            err: None,
             value,
             hash_cache,
+            trace: None,
+            deferred_sigs: None,
+            halted: false,
         }
     }
 
@@ -77,1002 +90,1072 @@ impl<'a> ScriptInterpreter<'a> {
         !buf.iter().all(|&x| x == 0)
     }
 
-    pub fn eval_script(&mut self) -> bool {
-        while self.pc < self.script.chunks.len() {
-            let chunk = &self.script.chunks[self.pc];
-            let opcode = chunk.opcode;
-            let if_exec = !self.if_stack.contains(&false);
-
-            if !(if_exec
-                || opcode == Opcode::OP_IF
-                || opcode == Opcode::OP_NOTIF
-                || opcode == Opcode::OP_ELSE
-                || opcode == Opcode::OP_ENDIF)
-            {
-                self.pc += 1;
-                continue;
-            }
-
-            match opcode {
-                Opcode::OP_IF => {
-                    let mut if_value = false;
-                    if if_exec {
-                        if self.stack.is_empty() {
-                            self.err_str = "unbalanced conditional".to_string();
-                            break;
-                        }
-                        let buf = self.stack.pop().unwrap();
-                        if_value = ScriptInterpreter::cast_to_bool(&buf);
-                    }
-                    self.if_stack.push(if_value);
-                }
-                Opcode::OP_NOTIF => {
-                    let mut if_value = false;
-                    if if_exec {
-                        if self.stack.is_empty() {
-                            self.err_str = "unbalanced conditional".to_string();
-                            break;
-                        }
-                        let buf = self.stack.pop().unwrap();
-                        if_value = !ScriptInterpreter::cast_to_bool(&buf);
-                    }
-                    self.if_stack.push(if_value);
-                }
-                Opcode::OP_ELSE => {
-                    if self.if_stack.is_empty() {
-                        self.err_str = "unbalanced conditional".to_string();
-                        break;
-                    }
-                    let if_stack_len = self.if_stack.len();
-                    self.if_stack[if_stack_len - 1] = !self.if_stack[self.if_stack.len() - 1];
-                }
-                Opcode::OP_ENDIF => {
-                    if self.if_stack.is_empty() {
-                        self.err_str = "unbalanced conditional".to_string();
-                        break;
-                    }
-                    self.if_stack.pop();
-                }
-                Opcode::OP_0 => {
-                    self.stack.push(vec![]);
-                }
-                Opcode::OP_PUSHDATA1 | Opcode::OP_PUSHDATA2 | Opcode::OP_PUSHDATA4 => {
-                    if let Some(buffer) = &chunk.buffer {
-                        self.stack.push(buffer.clone());
-                    } else {
-                        self.err_str = "invalid pushdata".to_string();
-                    }
-                }
-                Opcode::OP_1NEGATE => {
-                    let script_num = ScriptNum::new((-1).to_bigint().unwrap());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_1 => {
-                    let script_num = ScriptNum::new(1.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_2 => {
-                    let script_num = ScriptNum::new(2.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_3 => {
-                    let script_num = ScriptNum::new(3.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_4 => {
-                    let script_num = ScriptNum::new(4.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_5 => {
-                    let script_num = ScriptNum::new(5.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_6 => {
-                    let script_num = ScriptNum::new(6.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_7 => {
-                    let script_num = ScriptNum::new(7.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_8 => {
-                    let script_num = ScriptNum::new(8.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_9 => {
-                    let script_num = ScriptNum::new(9.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_10 => {
-                    let script_num = ScriptNum::new(10.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_11 => {
-                    let script_num = ScriptNum::new(11.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_12 => {
-                    let script_num = ScriptNum::new(12.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_13 => {
-                    let script_num = ScriptNum::new(13.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_14 => {
-                    let script_num = ScriptNum::new(14.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_15 => {
-                    let script_num = ScriptNum::new(15.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_16 => {
-                    let script_num = ScriptNum::new(16.into());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_VERIFY => {
+    pub fn is_finished(&self) -> bool {
+        self.halted || self.pc >= self.script.chunks.len()
+    }
+
+    // run one chunk of the script. returns true if there is more to run.
+    pub fn step(&mut self) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+        let trace_step = self.trace.as_ref().map(|_| {
+            ScriptTraceStep::new(
+                self.pc,
+                &self.script.chunks[self.pc],
+                !self.if_stack.contains(&false),
+                &self.stack,
+                &self.alt_stack,
+                &self.if_stack,
+            )
+        });
+        if !self.exec_chunk() {
+            self.halted = true;
+        }
+        if let Some(mut trace_step) = trace_step {
+            trace_step.set_after(&self.stack, &self.alt_stack, &self.if_stack, self.err);
+            self.trace.as_mut().unwrap().steps.push(trace_step);
+        }
+        !self.is_finished()
+    }
+
+    // record every step from now on in self.trace
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(ScriptTrace::new());
+    }
+
+    // from now on OP_CHECKSIG and OP_CHECKSIGVERIFY push true without checking
+    // the signature and record the check in self.deferred_sigs instead. the
+    // result of the script only stands if every deferred check then passes.
+    // OP_CHECKMULTISIG always checks at once, because which signature matches
+    // which key depends on the results. signatures found in the sig cache are
+    // not deferred.
+    pub fn enable_deferred_sigs(&mut self) {
+        self.deferred_sigs = Some(Vec::new());
+    }
+
+    // run the chunk at pc. returns false if the script must stop here.
+    fn exec_chunk(&mut self) -> bool {
+        let chunk = &self.script.chunks[self.pc];
+        let opcode = chunk.opcode;
+        let if_exec = !self.if_stack.contains(&false);
+
+        if !(if_exec
+            || opcode == Opcode::OP_IF
+            || opcode == Opcode::OP_NOTIF
+            || opcode == Opcode::OP_ELSE
+            || opcode == Opcode::OP_ENDIF)
+        {
+            self.pc += 1;
+            return true;
+        }
+
+        match opcode {
+            Opcode::OP_IF => {
+                let mut if_value = false;
+                if if_exec {
                     if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
+                        self.err = Some(ScriptError::UnbalancedConditional);
+                        return false;
                     }
                     let buf = self.stack.pop().unwrap();
-                    if !ScriptInterpreter::cast_to_bool(&buf) {
-                        self.err_str = "VERIFY failed".to_string();
-                        break;
-                    }
-                }
-                Opcode::OP_RETURN => {
-                    break;
-                }
-                Opcode::OP_TOALTSTACK => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    self.alt_stack.push(self.stack.pop().unwrap());
-                }
-                Opcode::OP_FROMALTSTACK => {
-                    if self.alt_stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    self.stack.push(self.alt_stack.pop().unwrap());
-                }
-                Opcode::OP_2DROP => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    self.stack.pop();
-                    self.stack.pop();
-                }
-                Opcode::OP_2DUP => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack[self.stack.len() - 2].clone();
-                    let buf2 = self.stack[self.stack.len() - 1].clone();
-                    self.stack.push(buf1);
-                    self.stack.push(buf2);
-                }
-                Opcode::OP_3DUP => {
-                    if self.stack.len() < 3 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack[self.stack.len() - 3].clone();
-                    let buf2 = self.stack[self.stack.len() - 2].clone();
-                    let buf3 = self.stack[self.stack.len() - 1].clone();
-                    self.stack.push(buf1);
-                    self.stack.push(buf2);
-                    self.stack.push(buf3);
-                }
-                Opcode::OP_2OVER => {
-                    if self.stack.len() < 4 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack[self.stack.len() - 4].clone();
-                    let buf2 = self.stack[self.stack.len() - 3].clone();
-                    self.stack.push(buf1);
-                    self.stack.push(buf2);
-                }
-                Opcode::OP_2ROT => {
-                    if self.stack.len() < 6 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack[self.stack.len() - 6].clone();
-                    let buf2 = self.stack[self.stack.len() - 5].clone();
-                    self.stack.remove(self.stack.len() - 6);
-                    self.stack.remove(self.stack.len() - 5);
-                    self.stack.push(buf1);
-                    self.stack.push(buf2);
-                }
-                Opcode::OP_2SWAP => {
-                    if self.stack.len() < 4 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack[self.stack.len() - 4].clone();
-                    let buf2 = self.stack[self.stack.len() - 3].clone();
-                    self.stack.remove(self.stack.len() - 4);
-                    self.stack.remove(self.stack.len() - 3);
-                    self.stack.push(buf1);
-                    self.stack.push(buf2);
-                }
-                Opcode::OP_IFDUP => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf = self.stack[self.stack.len() - 1].clone();
-                    if ScriptInterpreter::cast_to_bool(&buf) {
-                        self.stack.push(buf);
-                    }
+                    if_value = ScriptInterpreter::cast_to_bool(&buf);
                 }
-                Opcode::OP_DEPTH => {
-                    let script_num = ScriptNum::new(self.stack.len().to_bigint().unwrap());
-                    self.stack.push(script_num.to_buf());
-                }
-                Opcode::OP_DROP => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    self.stack.pop();
-                }
-                Opcode::OP_DUP => {
+                self.if_stack.push(if_value);
+            }
+            Opcode::OP_NOTIF => {
+                let mut if_value = false;
+                if if_exec {
                     if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf = self.stack[self.stack.len() - 1].clone();
-                    self.stack.push(buf);
-                }
-                Opcode::OP_NIP => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
+                        self.err = Some(ScriptError::UnbalancedConditional);
+                        return false;
                     }
                     let buf = self.stack.pop().unwrap();
-                    self.stack.pop();
-                    self.stack.push(buf);
+                    if_value = !ScriptInterpreter::cast_to_bool(&buf);
                 }
-                Opcode::OP_OVER => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf = self.stack[self.stack.len() - 2].clone();
-                    self.stack.push(buf);
+                self.if_stack.push(if_value);
+            }
+            Opcode::OP_ELSE => {
+                if self.if_stack.is_empty() {
+                    self.err = Some(ScriptError::UnbalancedConditional);
+                    return false;
                 }
-                Opcode::OP_PICK => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    if (script_num.num < 0.to_bigint().unwrap())
-                        || (script_num.num >= self.stack.len().to_bigint().unwrap())
-                    {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let num = script_num.to_u32() as usize;
-                    if num >= self.stack.len() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf = self.stack[self.stack.len() - num - 1].clone();
-                    self.stack.push(buf);
+                let if_stack_len = self.if_stack.len();
+                self.if_stack[if_stack_len - 1] = !self.if_stack[self.if_stack.len() - 1];
+            }
+            Opcode::OP_ENDIF => {
+                if self.if_stack.is_empty() {
+                    self.err = Some(ScriptError::UnbalancedConditional);
+                    return false;
                 }
-                Opcode::OP_ROLL => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    if (script_num.num < 0.to_bigint().unwrap())
-                        || (script_num.num >= self.stack.len().to_bigint().unwrap())
-                    {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let num = script_num.to_u32() as usize;
-                    if num >= self.stack.len() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf = self.stack.remove(self.stack.len() - num - 1);
-                    self.stack.push(buf);
+                self.if_stack.pop();
+            }
+            Opcode::OP_0 => {
+                self.stack.push(vec![]);
+            }
+            Opcode::OP_PUSHDATA1 | Opcode::OP_PUSHDATA2 | Opcode::OP_PUSHDATA4 => {
+                if let Some(buffer) = &chunk.buffer {
+                    self.stack.push(buffer.clone());
+                } else {
+                    self.err = Some(ScriptError::InvalidPushdata);
                 }
-                Opcode::OP_ROT => {
-                    if self.stack.len() < 3 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf = self.stack.remove(self.stack.len() - 3);
-                    self.stack.push(buf);
+            }
+            Opcode::OP_1NEGATE => {
+                let script_num = ScriptNum::new((-1).to_bigint().unwrap());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_1 => {
+                let script_num = ScriptNum::new(1.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_2 => {
+                let script_num = ScriptNum::new(2.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_3 => {
+                let script_num = ScriptNum::new(3.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_4 => {
+                let script_num = ScriptNum::new(4.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_5 => {
+                let script_num = ScriptNum::new(5.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_6 => {
+                let script_num = ScriptNum::new(6.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_7 => {
+                let script_num = ScriptNum::new(7.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_8 => {
+                let script_num = ScriptNum::new(8.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_9 => {
+                let script_num = ScriptNum::new(9.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_10 => {
+                let script_num = ScriptNum::new(10.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_11 => {
+                let script_num = ScriptNum::new(11.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_12 => {
+                let script_num = ScriptNum::new(12.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_13 => {
+                let script_num = ScriptNum::new(13.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_14 => {
+                let script_num = ScriptNum::new(14.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_15 => {
+                let script_num = ScriptNum::new(15.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_16 => {
+                let script_num = ScriptNum::new(16.into());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_VERIFY => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_SWAP => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf = self.stack.remove(self.stack.len() - 2);
-                    self.stack.push(buf);
+                let buf = self.stack.pop().unwrap();
+                if !ScriptInterpreter::cast_to_bool(&buf) {
+                    self.err = Some(ScriptError::VerifyFailed);
+                    return false;
                 }
-                Opcode::OP_TUCK => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf = self.stack[self.stack.len() - 1].clone();
-                    self.stack.insert(self.stack.len() - 2, buf);
+            }
+            Opcode::OP_RETURN => {
+                return false;
+            }
+            Opcode::OP_TOALTSTACK => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_CAT => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack.pop().unwrap();
-                    let buf2 = self.stack.pop().unwrap();
-                    let mut new_buf = Vec::new();
-                    new_buf.extend_from_slice(&buf2);
-                    new_buf.extend_from_slice(&buf1);
-                    self.stack.push(new_buf);
-                }
-                Opcode::OP_SUBSTR => {
-                    if self.stack.len() < 3 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2_bn = script_num2.clone().num;
-                    let script_num1_bn = script_num1.clone().num;
-                    let buf = self.stack.pop().unwrap();
-                    let buf_len = buf.len();
-                    if script_num1_bn < 0.to_bigint().unwrap()
-                        || script_num2_bn < 0.to_bigint().unwrap()
-                        || script_num1_bn + script_num2_bn > buf_len.to_bigint().unwrap()
-                    {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let start = script_num1.to_u32() as usize;
-                    let len = script_num2.to_u32() as usize;
-                    let new_buf = buf[start..start + len].to_vec();
-                    self.stack.push(new_buf);
-                }
-                Opcode::OP_LEFT => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let buf = self.stack.pop().unwrap();
-                    let len_bn = script_num.clone().num;
-                    if len_bn < 0.to_bigint().unwrap() || len_bn > buf.len().to_bigint().unwrap() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let len = script_num.to_u32() as usize;
-                    let new_buf = buf[0..len].to_vec();
-                    self.stack.push(new_buf);
+                self.alt_stack.push(self.stack.pop().unwrap());
+            }
+            Opcode::OP_FROMALTSTACK => {
+                if self.alt_stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_RIGHT => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let buf = self.stack.pop().unwrap();
-                    let len_bn = script_num.clone().num;
-                    if len_bn < 0.to_bigint().unwrap() || len_bn > buf.len().to_bigint().unwrap() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let len = script_num.to_u32() as usize;
-                    let new_buf = buf[buf.len() - len..buf.len()].to_vec();
-                    self.stack.push(new_buf);
+                self.stack.push(self.alt_stack.pop().unwrap());
+            }
+            Opcode::OP_2DROP => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_SIZE => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num =
-                        ScriptNum::new(self.stack[self.stack.len() - 1].len().to_bigint().unwrap());
-                    self.stack.push(script_num.to_buf());
+                self.stack.pop();
+                self.stack.pop();
+            }
+            Opcode::OP_2DUP => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack[self.stack.len() - 2].clone();
+                let buf2 = self.stack[self.stack.len() - 1].clone();
+                self.stack.push(buf1);
+                self.stack.push(buf2);
+            }
+            Opcode::OP_3DUP => {
+                if self.stack.len() < 3 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack[self.stack.len() - 3].clone();
+                let buf2 = self.stack[self.stack.len() - 2].clone();
+                let buf3 = self.stack[self.stack.len() - 1].clone();
+                self.stack.push(buf1);
+                self.stack.push(buf2);
+                self.stack.push(buf3);
+            }
+            Opcode::OP_2OVER => {
+                if self.stack.len() < 4 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack[self.stack.len() - 4].clone();
+                let buf2 = self.stack[self.stack.len() - 3].clone();
+                self.stack.push(buf1);
+                self.stack.push(buf2);
+            }
+            Opcode::OP_2ROT => {
+                if self.stack.len() < 6 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack[self.stack.len() - 6].clone();
+                let buf2 = self.stack[self.stack.len() - 5].clone();
+                self.stack.remove(self.stack.len() - 6);
+                self.stack.remove(self.stack.len() - 5);
+                self.stack.push(buf1);
+                self.stack.push(buf2);
+            }
+            Opcode::OP_2SWAP => {
+                if self.stack.len() < 4 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack[self.stack.len() - 4].clone();
+                let buf2 = self.stack[self.stack.len() - 3].clone();
+                self.stack.remove(self.stack.len() - 4);
+                self.stack.remove(self.stack.len() - 3);
+                self.stack.push(buf1);
+                self.stack.push(buf2);
+            }
+            Opcode::OP_IFDUP => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_INVERT => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let mut buf = self.stack.pop().unwrap();
-                    buf.iter_mut().for_each(|byte| *byte = !*byte);
+                let buf = self.stack[self.stack.len() - 1].clone();
+                if ScriptInterpreter::cast_to_bool(&buf) {
                     self.stack.push(buf);
                 }
-                Opcode::OP_AND => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack.pop().unwrap();
-                    let buf2 = self.stack.pop().unwrap();
-                    let len1 = buf1.len();
-                    let len2 = buf2.len();
-                    if len1 != len2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let len = len1;
-                    let mut new_buf = Vec::new();
-                    for i in 0..len {
-                        new_buf.push(buf1[i] & buf2[i]);
-                    }
-                    self.stack.push(new_buf);
-                }
-                Opcode::OP_OR => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack.pop().unwrap();
-                    let buf2 = self.stack.pop().unwrap();
-                    let len1 = buf1.len();
-                    let len2 = buf2.len();
-                    if len1 != len2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let len = len1;
-                    let mut new_buf = Vec::new();
-                    for i in 0..len {
-                        new_buf.push(buf1[i] | buf2[i]);
-                    }
-                    self.stack.push(new_buf);
-                }
-                Opcode::OP_XOR => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack.pop().unwrap();
-                    let buf2 = self.stack.pop().unwrap();
-                    let len1 = buf1.len();
-                    let len2 = buf2.len();
-                    if len1 != len2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let len = len1;
-                    let mut new_buf = Vec::new();
-                    for i in 0..len {
-                        new_buf.push(buf1[i] ^ buf2[i]);
-                    }
-                    self.stack.push(new_buf);
-                }
-                Opcode::OP_EQUAL => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack.pop().unwrap();
-                    let buf2 = self.stack.pop().unwrap();
-                    let equal = buf1 == buf2;
-                    self.stack.push(if equal { vec![1] } else { vec![] });
-                }
-                Opcode::OP_EQUALVERIFY => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack.pop().unwrap();
-                    let buf2 = self.stack.pop().unwrap();
-                    if buf1 != buf2 {
-                        self.err_str = "EQUALVERIFY failed".to_string();
-                        break;
-                    }
-                }
-                Opcode::OP_1ADD => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let new_num = script_num.num + 1.to_bigint().unwrap();
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_1SUB => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let new_num = script_num.num - 1.to_bigint().unwrap();
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_2MUL => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let new_num = script_num.num * 2.to_bigint().unwrap();
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_2DIV => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let new_num = script_num.num / 2.to_bigint().unwrap();
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_NEGATE => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let new_num = -script_num.num;
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_ABS => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let mut script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    if script_num.num < 0.to_bigint().unwrap() {
-                        script_num.num = -script_num.num;
-                    }
-                    self.stack.push(ScriptNum::new(script_num.num).to_buf());
-                }
-                Opcode::OP_NOT => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let new_num = if script_num.num == 0.to_bigint().unwrap() {
-                        1.to_bigint().unwrap()
-                    } else {
-                        0.to_bigint().unwrap()
-                    };
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_0NOTEQUAL => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let new_num = if script_num.num == 0.to_bigint().unwrap() {
-                        0.to_bigint().unwrap()
-                    } else {
-                        1.to_bigint().unwrap()
-                    };
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_ADD => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let new_num = script_num1.num + script_num2.num;
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_SUB => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let new_num = script_num2.num - script_num1.num;
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_MUL => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let new_num = script_num1.num * script_num2.num;
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_DIV => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    if script_num1.num == 0.to_bigint().unwrap() {
-                        self.err_str = "division by zero".to_string();
-                        break;
-                    }
-                    let new_num = script_num2.num / script_num1.num;
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_MOD => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    if script_num1.num == 0.to_bigint().unwrap() {
-                        self.err_str = "division by zero".to_string();
-                        break;
-                    }
-                    let new_num = script_num2.num % script_num1.num;
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_LSHIFT => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    if script_num1.num < 0.to_bigint().unwrap() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let new_num = script_num2.num << script_num1.to_u32();
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_RSHIFT => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    if script_num1.num < 0.to_bigint().unwrap() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let new_num = script_num2.num >> script_num1.to_u32();
-                    self.stack.push(ScriptNum::new(new_num).to_buf());
-                }
-                Opcode::OP_BOOLAND => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack.pop().unwrap();
-                    let buf2 = self.stack.pop().unwrap();
-                    let bool1 = ScriptInterpreter::cast_to_bool(&buf1);
-                    let bool2 = ScriptInterpreter::cast_to_bool(&buf2);
-                    self.stack
-                        .push(if bool1 && bool2 { vec![1] } else { vec![] });
-                }
-                Opcode::OP_BOOLOR => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf1 = self.stack.pop().unwrap();
-                    let buf2 = self.stack.pop().unwrap();
-                    let bool1 = ScriptInterpreter::cast_to_bool(&buf1);
-                    let bool2 = ScriptInterpreter::cast_to_bool(&buf2);
-                    self.stack
-                        .push(if bool1 || bool2 { vec![1] } else { vec![] });
-                }
-                Opcode::OP_NUMEQUAL => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    self.stack.push(if script_num1.num == script_num2.num {
-                        vec![1]
-                    } else {
-                        vec![]
-                    });
-                }
-                Opcode::OP_NUMEQUALVERIFY => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    if script_num1.num != script_num2.num {
-                        self.err_str = "NUMEQUALVERIFY failed".to_string();
-                        break;
-                    }
+            }
+            Opcode::OP_DEPTH => {
+                let script_num = ScriptNum::new(self.stack.len().to_bigint().unwrap());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_DROP => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_NUMNOTEQUAL => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    self.stack.push(if script_num1.num != script_num2.num {
-                        vec![1]
-                    } else {
-                        vec![]
-                    });
+                self.stack.pop();
+            }
+            Opcode::OP_DUP => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_LESSTHAN => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    self.stack.push(if script_num2.num < script_num1.num {
-                        vec![1]
-                    } else {
-                        vec![]
-                    });
+                let buf = self.stack[self.stack.len() - 1].clone();
+                self.stack.push(buf);
+            }
+            Opcode::OP_NIP => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf = self.stack.pop().unwrap();
+                self.stack.pop();
+                self.stack.push(buf);
+            }
+            Opcode::OP_OVER => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_GREATERTHAN => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    self.stack.push(if script_num2.num > script_num1.num {
-                        vec![1]
-                    } else {
-                        vec![]
-                    });
+                let buf = self.stack[self.stack.len() - 2].clone();
+                self.stack.push(buf);
+            }
+            Opcode::OP_PICK => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                if (script_num.num < 0.to_bigint().unwrap())
+                    || (script_num.num >= self.stack.len().to_bigint().unwrap())
+                {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let num = script_num.to_u32() as usize;
+                if num >= self.stack.len() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf = self.stack[self.stack.len() - num - 1].clone();
+                self.stack.push(buf);
+            }
+            Opcode::OP_ROLL => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                if (script_num.num < 0.to_bigint().unwrap())
+                    || (script_num.num >= self.stack.len().to_bigint().unwrap())
+                {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let num = script_num.to_u32() as usize;
+                if num >= self.stack.len() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf = self.stack.remove(self.stack.len() - num - 1);
+                self.stack.push(buf);
+            }
+            Opcode::OP_ROT => {
+                if self.stack.len() < 3 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_LESSTHANOREQUAL => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    self.stack.push(if script_num2.num <= script_num1.num {
-                        vec![1]
-                    } else {
-                        vec![]
-                    });
+                let buf = self.stack.remove(self.stack.len() - 3);
+                self.stack.push(buf);
+            }
+            Opcode::OP_SWAP => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_GREATERTHANOREQUAL => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    self.stack.push(if script_num2.num >= script_num1.num {
-                        vec![1]
-                    } else {
-                        vec![]
-                    });
+                let buf = self.stack.remove(self.stack.len() - 2);
+                self.stack.push(buf);
+            }
+            Opcode::OP_TUCK => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_MIN => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    self.stack.push(if script_num2.num < script_num1.num {
-                        script_num2.to_buf()
-                    } else {
-                        script_num1.to_buf()
-                    });
+                let buf = self.stack[self.stack.len() - 1].clone();
+                self.stack.insert(self.stack.len() - 2, buf);
+            }
+            Opcode::OP_CAT => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack.pop().unwrap();
+                let buf2 = self.stack.pop().unwrap();
+                let mut new_buf = Vec::new();
+                new_buf.extend_from_slice(&buf2);
+                new_buf.extend_from_slice(&buf1);
+                self.stack.push(new_buf);
+            }
+            Opcode::OP_SUBSTR => {
+                if self.stack.len() < 3 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2_bn = script_num2.clone().num;
+                let script_num1_bn = script_num1.clone().num;
+                let buf = self.stack.pop().unwrap();
+                let buf_len = buf.len();
+                if script_num1_bn < 0.to_bigint().unwrap()
+                    || script_num2_bn < 0.to_bigint().unwrap()
+                    || script_num1_bn + script_num2_bn > buf_len.to_bigint().unwrap()
+                {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let start = script_num1.to_u32() as usize;
+                let len = script_num2.to_u32() as usize;
+                let new_buf = buf[start..start + len].to_vec();
+                self.stack.push(new_buf);
+            }
+            Opcode::OP_LEFT => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let buf = self.stack.pop().unwrap();
+                let len_bn = script_num.clone().num;
+                if len_bn < 0.to_bigint().unwrap() || len_bn > buf.len().to_bigint().unwrap() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let len = script_num.to_u32() as usize;
+                let new_buf = buf[0..len].to_vec();
+                self.stack.push(new_buf);
+            }
+            Opcode::OP_RIGHT => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let buf = self.stack.pop().unwrap();
+                let len_bn = script_num.clone().num;
+                if len_bn < 0.to_bigint().unwrap() || len_bn > buf.len().to_bigint().unwrap() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let len = script_num.to_u32() as usize;
+                let new_buf = buf[buf.len() - len..buf.len()].to_vec();
+                self.stack.push(new_buf);
+            }
+            Opcode::OP_SIZE => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num =
+                    ScriptNum::new(self.stack[self.stack.len() - 1].len().to_bigint().unwrap());
+                self.stack.push(script_num.to_buf());
+            }
+            Opcode::OP_INVERT => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let mut buf = self.stack.pop().unwrap();
+                buf.iter_mut().for_each(|byte| *byte = !*byte);
+                self.stack.push(buf);
+            }
+            Opcode::OP_AND => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack.pop().unwrap();
+                let buf2 = self.stack.pop().unwrap();
+                let len1 = buf1.len();
+                let len2 = buf2.len();
+                if len1 != len2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let len = len1;
+                let mut new_buf = Vec::new();
+                for i in 0..len {
+                    new_buf.push(buf1[i] & buf2[i]);
+                }
+                self.stack.push(new_buf);
+            }
+            Opcode::OP_OR => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack.pop().unwrap();
+                let buf2 = self.stack.pop().unwrap();
+                let len1 = buf1.len();
+                let len2 = buf2.len();
+                if len1 != len2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let len = len1;
+                let mut new_buf = Vec::new();
+                for i in 0..len {
+                    new_buf.push(buf1[i] | buf2[i]);
+                }
+                self.stack.push(new_buf);
+            }
+            Opcode::OP_XOR => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack.pop().unwrap();
+                let buf2 = self.stack.pop().unwrap();
+                let len1 = buf1.len();
+                let len2 = buf2.len();
+                if len1 != len2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let len = len1;
+                let mut new_buf = Vec::new();
+                for i in 0..len {
+                    new_buf.push(buf1[i] ^ buf2[i]);
+                }
+                self.stack.push(new_buf);
+            }
+            Opcode::OP_EQUAL => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack.pop().unwrap();
+                let buf2 = self.stack.pop().unwrap();
+                let equal = buf1 == buf2;
+                self.stack.push(if equal { vec![1] } else { vec![] });
+            }
+            Opcode::OP_EQUALVERIFY => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_MAX => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    self.stack.push(if script_num2.num > script_num1.num {
-                        script_num2.to_buf()
-                    } else {
-                        script_num1.to_buf()
-                    });
+                let buf1 = self.stack.pop().unwrap();
+                let buf2 = self.stack.pop().unwrap();
+                if buf1 != buf2 {
+                    self.err = Some(ScriptError::EqualVerifyFailed);
+                    return false;
                 }
-                Opcode::OP_WITHIN => {
-                    // (x min max -- out)
-                    if self.stack.len() < 3 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_max = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_min = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let script_x = ScriptNum::from_buf(&self.stack.pop().unwrap());
-                    let min = script_min.num;
-                    let max = script_max.num;
-                    let x = script_x.num;
-                    self.stack
-                        .push(if x >= min && x < max { vec![1] } else { vec![] });
-                }
-                Opcode::OP_BLAKE3 => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf = self.stack.pop().unwrap();
-                    let hash = blake3_hash(&buf);
-                    self.stack.push(hash.to_vec());
+            }
+            Opcode::OP_1ADD => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let new_num = script_num.num + 1.to_bigint().unwrap();
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_1SUB => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let new_num = script_num.num - 1.to_bigint().unwrap();
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_2MUL => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let new_num = script_num.num * 2.to_bigint().unwrap();
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_2DIV => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let new_num = script_num.num / 2.to_bigint().unwrap();
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_NEGATE => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let new_num = -script_num.num;
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_ABS => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_DOUBLEBLAKE3 => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let buf = self.stack.pop().unwrap();
-                    let hash = double_blake3_hash(&buf);
-                    self.stack.push(hash.to_vec());
+                let mut script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                if script_num.num < 0.to_bigint().unwrap() {
+                    script_num.num = -script_num.num;
                 }
-                Opcode::OP_CHECKSIG | Opcode::OP_CHECKSIGVERIFY => {
-                    if self.stack.len() < 2 {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let pub_key_buf = self.stack.pop().unwrap();
-                    if pub_key_buf.len() != PubKey::SIZE {
-                        self.err_str = "invalid public key length".to_string();
-                        break;
-                    }
-                    let sig_buf = self.stack.pop().unwrap();
-                    if sig_buf.len() != TxSignature::SIZE {
-                        self.err_str = "invalid signature length".to_string();
-                        break;
-                    }
-                    let signature = TxSignature::from_buf(sig_buf);
+                self.stack.push(ScriptNum::new(script_num.num).to_buf());
+            }
+            Opcode::OP_NOT => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let new_num = if script_num.num == 0.to_bigint().unwrap() {
+                    1.to_bigint().unwrap()
+                } else {
+                    0.to_bigint().unwrap()
+                };
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_0NOTEQUAL => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let new_num = if script_num.num == 0.to_bigint().unwrap() {
+                    0.to_bigint().unwrap()
+                } else {
+                    1.to_bigint().unwrap()
+                };
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_ADD => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let new_num = script_num1.num + script_num2.num;
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_SUB => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let new_num = script_num2.num - script_num1.num;
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_MUL => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let new_num = script_num1.num * script_num2.num;
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_DIV => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                if script_num1.num == 0.to_bigint().unwrap() {
+                    self.err = Some(ScriptError::DivisionByZero);
+                    return false;
+                }
+                let new_num = script_num2.num / script_num1.num;
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_MOD => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                if script_num1.num == 0.to_bigint().unwrap() {
+                    self.err = Some(ScriptError::DivisionByZero);
+                    return false;
+                }
+                let new_num = script_num2.num % script_num1.num;
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_LSHIFT => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                if script_num1.num < 0.to_bigint().unwrap() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let new_num = script_num2.num << script_num1.to_u32();
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_RSHIFT => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                if script_num1.num < 0.to_bigint().unwrap() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let new_num = script_num2.num >> script_num1.to_u32();
+                self.stack.push(ScriptNum::new(new_num).to_buf());
+            }
+            Opcode::OP_BOOLAND => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack.pop().unwrap();
+                let buf2 = self.stack.pop().unwrap();
+                let bool1 = ScriptInterpreter::cast_to_bool(&buf1);
+                let bool2 = ScriptInterpreter::cast_to_bool(&buf2);
+                self.stack
+                    .push(if bool1 && bool2 { vec![1] } else { vec![] });
+            }
+            Opcode::OP_BOOLOR => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf1 = self.stack.pop().unwrap();
+                let buf2 = self.stack.pop().unwrap();
+                let bool1 = ScriptInterpreter::cast_to_bool(&buf1);
+                let bool2 = ScriptInterpreter::cast_to_bool(&buf2);
+                self.stack
+                    .push(if bool1 || bool2 { vec![1] } else { vec![] });
+            }
+            Opcode::OP_NUMEQUAL => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                self.stack.push(if script_num1.num == script_num2.num {
+                    vec![1]
+                } else {
+                    vec![]
+                });
+            }
+            Opcode::OP_NUMEQUALVERIFY => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                if script_num1.num != script_num2.num {
+                    self.err = Some(ScriptError::NumEqualVerifyFailed);
+                    return false;
+                }
+            }
+            Opcode::OP_NUMNOTEQUAL => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                self.stack.push(if script_num1.num != script_num2.num {
+                    vec![1]
+                } else {
+                    vec![]
+                });
+            }
+            Opcode::OP_LESSTHAN => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                self.stack.push(if script_num2.num < script_num1.num {
+                    vec![1]
+                } else {
+                    vec![]
+                });
+            }
+            Opcode::OP_GREATERTHAN => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                self.stack.push(if script_num2.num > script_num1.num {
+                    vec![1]
+                } else {
+                    vec![]
+                });
+            }
+            Opcode::OP_LESSTHANOREQUAL => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                self.stack.push(if script_num2.num <= script_num1.num {
+                    vec![1]
+                } else {
+                    vec![]
+                });
+            }
+            Opcode::OP_GREATERTHANOREQUAL => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                self.stack.push(if script_num2.num >= script_num1.num {
+                    vec![1]
+                } else {
+                    vec![]
+                });
+            }
+            Opcode::OP_MIN => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                self.stack.push(if script_num2.num < script_num1.num {
+                    script_num2.to_buf()
+                } else {
+                    script_num1.to_buf()
+                });
+            }
+            Opcode::OP_MAX => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num1 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_num2 = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                self.stack.push(if script_num2.num > script_num1.num {
+                    script_num2.to_buf()
+                } else {
+                    script_num1.to_buf()
+                });
+            }
+            Opcode::OP_WITHIN => {
+                // (x min max -- out)
+                if self.stack.len() < 3 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_max = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_min = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let script_x = ScriptNum::from_buf(&self.stack.pop().unwrap());
+                let min = script_min.num;
+                let max = script_max.num;
+                let x = script_x.num;
+                self.stack
+                    .push(if x >= min && x < max { vec![1] } else { vec![] });
+            }
+            Opcode::OP_BLAKE3 => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf = self.stack.pop().unwrap();
+                let hash = blake3_hash(&buf);
+                self.stack.push(hash.to_vec());
+            }
+            Opcode::OP_DOUBLEBLAKE3 => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let buf = self.stack.pop().unwrap();
+                let hash = double_blake3_hash(&buf);
+                self.stack.push(hash.to_vec());
+            }
+            Opcode::OP_CHECKSIG | Opcode::OP_CHECKSIGVERIFY => {
+                if self.stack.len() < 2 {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let pub_key_buf = self.stack.pop().unwrap();
+                if pub_key_buf.len() != PubKey::SIZE {
+                    self.err = Some(ScriptError::InvalidPublicKeyLength);
+                    return false;
+                }
+                let sig_buf = self.stack.pop().unwrap();
+                if sig_buf.len() != TxSignature::SIZE {
+                    self.err = Some(ScriptError::InvalidSignatureLength);
+                    return false;
+                }
+                let signature = TxSignature::from_buf(sig_buf);
 
-                    let exec_script_buf = self.script.to_buf();
+                let exec_script_buf = self.script.to_buf();
 
-                    let pub_key_arr: [u8; PubKey::SIZE] =
-                        pub_key_buf.try_into().unwrap_or_else(|v: Vec<u8>| {
-                            panic!(
-                                "Expected a Vec of length {} but it was {}",
-                                PubKey::SIZE,
-                                v.len()
-                            )
-                        });
+                let pub_key_arr: [u8; PubKey::SIZE] =
+                    pub_key_buf.try_into().unwrap_or_else(|v: Vec<u8>| {
+                        panic!(
+                            "Expected a Vec of length {} but it was {}",
+                            PubKey::SIZE,
+                            v.len()
+                        )
+                    });
 
-                    let success = self.tx.verify_with_cache(
+                let signature = signature.unwrap();
+                let success = match self.deferred_sigs.as_mut() {
+                    Some(deferred_sigs) => {
+                        let sighash = self.tx.sighash_with_cache(
+                            self.n_in,
+                            exec_script_buf,
+                            self.value,
+                            signature.hash_type,
+                            self.hash_cache,
+                        );
+                        let sig_check = SigCheck::new(sighash, pub_key_arr, signature);
+                        let cached = self
+                            .hash_cache
+                            .sig_cache
+                            .as_ref()
+                            .is_some_and(|sig_cache| sig_cache.get(&sig_check));
+                        if !cached {
+                            deferred_sigs.push(sig_check);
+                        }
+                        true
+                    }
+                    None => self.tx.verify_with_cache(
                         self.n_in,
                         pub_key_arr,
-                        signature.unwrap(),
+                        signature,
                         exec_script_buf,
                         self.value,
                         self.hash_cache,
-                    );
+                    ),
+                };
 
-                    self.stack.push(if success { vec![1] } else { vec![] });
-                    if opcode == OP["CHECKSIGVERIFY"] && !success {
-                        self.err_str = "CHECKSIGVERIFY failed".to_string();
-                        break;
-                    }
+                self.stack.push(if success { vec![1] } else { vec![] });
+                if opcode == OP["CHECKSIGVERIFY"] && !success {
+                    self.err = Some(ScriptError::CheckSigVerifyFailed);
+                    return false;
                 }
-                Opcode::OP_CHECKMULTISIG | Opcode::OP_CHECKMULTISIGVERIFY => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let n_keys = ScriptNum::from_buf(&self.stack.pop().unwrap()).num;
-                    if n_keys < BigInt::from(0) || n_keys > BigInt::from(16) {
-                        self.err_str = "invalid number of keys".to_string();
-                        break;
-                    }
-                    if self.stack.len() < (n_keys.to_usize().unwrap() + 1) {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let mut pub_keys: Vec<Vec<u8>> = Vec::new();
-                    for _ in 0..n_keys.to_usize().unwrap() {
-                        let pub_key_buf = self.stack.pop().unwrap();
-                        if pub_key_buf.len() != PubKey::SIZE {
-                            self.err_str = "invalid public key length".to_string();
-                            break;
-                        }
-                        pub_keys.push(pub_key_buf);
-                    }
-                    let n_sigs = ScriptNum::from_buf(&self.stack.pop().unwrap()).num;
-                    if n_sigs < BigInt::from(0) || n_sigs > n_keys {
-                        self.err_str = "invalid number of signatures".to_string();
+            }
+            Opcode::OP_CHECKMULTISIG | Opcode::OP_CHECKMULTISIGVERIFY => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let n_keys = ScriptNum::from_buf(&self.stack.pop().unwrap()).num;
+                if n_keys < BigInt::from(0) || n_keys > BigInt::from(16) {
+                    self.err = Some(ScriptError::InvalidNumberOfKeys);
+                    return false;
+                }
+                if self.stack.len() < (n_keys.to_usize().unwrap() + 1) {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let mut pub_keys: Vec<Vec<u8>> = Vec::new();
+                for _ in 0..n_keys.to_usize().unwrap() {
+                    let pub_key_buf = self.stack.pop().unwrap();
+                    if pub_key_buf.len() != PubKey::SIZE {
+                        self.err = Some(ScriptError::InvalidPublicKeyLength);
                         break;
                     }
-                    if self.stack.len() < n_sigs.to_usize().unwrap() {
-                        self.err_str = "invalid stack operation".to_string();
+                    pub_keys.push(pub_key_buf);
+                }
+                let n_sigs = ScriptNum::from_buf(&self.stack.pop().unwrap()).num;
+                if n_sigs < BigInt::from(0) || n_sigs > n_keys {
+                    self.err = Some(ScriptError::InvalidNumberOfSignatures);
+                    return false;
+                }
+                if self.stack.len() < n_sigs.to_usize().unwrap() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let mut sigs: Vec<Vec<u8>> = Vec::new();
+                for _ in 0..n_sigs.to_usize().unwrap() {
+                    let sig_buf = self.stack.pop().unwrap();
+                    if sig_buf.len() != TxSignature::SIZE {
+                        self.err = Some(ScriptError::InvalidSignatureLength);
                         break;
                     }
-                    let mut sigs: Vec<Vec<u8>> = Vec::new();
-                    for _ in 0..n_sigs.to_usize().unwrap() {
-                        let sig_buf = self.stack.pop().unwrap();
-                        if sig_buf.len() != TxSignature::SIZE {
-                            self.err_str = "invalid signature length".to_string();
+                    sigs.push(sig_buf);
+                }
+                let exec_script_buf = self.script.to_buf();
+
+                let mut matched_sigs = 0;
+                for sig in sigs {
+                    for j in 0..pub_keys.len() {
+                        let success = self.tx.verify_with_cache(
+                            self.n_in,
+                            pub_keys[j][..PubKey::SIZE].try_into().unwrap(),
+                            TxSignature::from_buf(sig.clone()).unwrap(),
+                            exec_script_buf.clone(),
+                            self.value,
+                            self.hash_cache,
+                        );
+                        if success {
+                            matched_sigs += 1;
+                            pub_keys.remove(j); // Remove the matched public key
                             break;
                         }
-                        sigs.push(sig_buf);
-                    }
-                    let exec_script_buf = self.script.to_buf();
-
-                    let mut matched_sigs = 0;
-                    for sig in sigs {
-                        for j in 0..pub_keys.len() {
-                            let success = self.tx.verify_with_cache(
-                                self.n_in,
-                                pub_keys[j][..PubKey::SIZE].try_into().unwrap(),
-                                TxSignature::from_buf(sig.clone()).unwrap(),
-                                exec_script_buf.clone(),
-                                self.value,
-                                self.hash_cache,
-                            );
-                            if success {
-                                matched_sigs += 1;
-                                pub_keys.remove(j); // Remove the matched public key
-                                break;
-                            }
-                        }
                     }
-                    let success = matched_sigs == n_sigs.to_usize().unwrap();
+                }
+                let success = matched_sigs == n_sigs.to_usize().unwrap();
 
-                    self.stack.push(if success { vec![1] } else { vec![] });
-                    if opcode == OP["CHECKMULTISIGVERIFY"] && !success {
-                        self.err_str = "CHECKMULTISIGVERIFY failed".to_string();
-                        break;
-                    }
+                self.stack.push(if success { vec![1] } else { vec![] });
+                if opcode == OP["CHECKMULTISIGVERIFY"] && !success {
+                    self.err = Some(ScriptError::CheckMultiSigVerifyFailed);
+                    return false;
                 }
-                Opcode::OP_CHECKLOCKABSVERIFY => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(self.stack.last().unwrap());
-                    if script_num.num < 0.into() {
-                        self.err_str = "negative lockabs".to_string();
-                        break;
-                    }
-                    if self.tx.lock_abs.to_bigint().unwrap() < script_num.num {
-                        self.err_str = "lockabs requirement not met".to_string();
-                        break;
-                    }
+            }
+            Opcode::OP_CHECKLOCKABSVERIFY => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
                 }
-                Opcode::OP_CHECKLOCKRELVERIFY => {
-                    if self.stack.is_empty() {
-                        self.err_str = "invalid stack operation".to_string();
-                        break;
-                    }
-                    let script_num = ScriptNum::from_buf(self.stack.last().unwrap());
-                    if script_num.num < 0.into() {
-                        self.err_str = "negative lockrel".to_string();
-                        break;
-                    }
-                    let tx_input = &self.tx.inputs[self.n_in];
-                    if tx_input.lock_rel.to_bigint().unwrap() < script_num.num {
-                        self.err_str = "lockrel requirement not met".to_string();
-                        break;
-                    }
+                let script_num = ScriptNum::from_buf(self.stack.last().unwrap());
+                if script_num.num < 0.into() {
+                    self.err = Some(ScriptError::NegativeLockAbs);
+                    return false;
                 }
-                _ => {
-                    self.err_str = "invalid opcode".to_string();
-                    break;
+                if self.tx.lock_abs.to_bigint().unwrap() < script_num.num {
+                    self.err = Some(ScriptError::LockAbsRequirementNotMet);
+                    return false;
                 }
             }
-
-            self.pc += 1;
+            Opcode::OP_CHECKLOCKRELVERIFY => {
+                if self.stack.is_empty() {
+                    self.err = Some(ScriptError::InvalidStackOperation);
+                    return false;
+                }
+                let script_num = ScriptNum::from_buf(self.stack.last().unwrap());
+                if script_num.num < 0.into() {
+                    self.err = Some(ScriptError::NegativeLockRel);
+                    return false;
+                }
+                let tx_input = &self.tx.inputs[self.n_in];
+                if tx_input.lock_rel.to_bigint().unwrap() < script_num.num {
+                    self.err = Some(ScriptError::LockRelRequirementNotMet);
+                    return false;
+                }
+            }
+            _ => {
+                self.err = Some(ScriptError::InvalidOpcode);
+                return false;
+            }
         }
-        if !self.err_str.is_empty() {
+
+        self.pc += 1;
+        true
+    }
+
+    pub fn eval_script(&mut self) -> bool {
+        while self.step() {}
+        if self.err.is_some() {
             if !self.stack.is_empty() {
                 self.return_value = Some(self.stack[self.stack.len() - 1].clone());
             } else {
@@ -1103,7 +1186,7 @@ mod tests {
     use crate::tx_out::TxOut;
 
     mod sanity_tests {
-        use crate::{priv_key::PrivKey, pub_key::PubKey};
+        use crate::{key_pair::KeyPair, priv_key::PrivKey, pub_key::PubKey};
 
         use super::*;
 
@@ -1119,6 +1202,59 @@ mod tests {
             assert_eq!(hex::encode(script_interpreter.return_value.unwrap()), "");
         }
 
+        #[test]
+        fn test_step() {
+            let tx = Tx::new(0, Vec::new(), Vec::new(), 0);
+            let script = Script::from_strict_str("1 2 ADD 3 EQUAL").unwrap();
+            let mut hash_cache = HashCache::new();
+            let mut script_interpreter =
+                ScriptInterpreter::from_script_tx(script, tx, 0, &mut hash_cache);
+            assert!(script_interpreter.step());
+            assert!(script_interpreter.step());
+            assert_eq!(script_interpreter.stack.len(), 2);
+            assert!(script_interpreter.step());
+            assert_eq!(hex::encode(&script_interpreter.stack[0]), "03");
+            assert!(script_interpreter.step());
+            assert!(!script_interpreter.step());
+            assert!(script_interpreter.is_finished());
+            assert!(!script_interpreter.step());
+            assert!(script_interpreter.eval_script());
+        }
+
+        #[test]
+        fn test_trace() {
+            let tx = Tx::new(0, Vec::new(), Vec::new(), 0);
+            let script = Script::from_strict_str("0 IF 1 ELSE 2 ENDIF VERIFY VERIFY").unwrap();
+            let mut hash_cache = HashCache::new();
+            let mut script_interpreter =
+                ScriptInterpreter::from_script_tx(script, tx, 0, &mut hash_cache);
+            script_interpreter.enable_trace();
+            assert!(!script_interpreter.eval_script());
+            assert_eq!(
+                script_interpreter.err,
+                Some(ScriptError::InvalidStackOperation)
+            );
+
+            let trace = script_interpreter.trace.unwrap();
+            assert_eq!(trace.steps.len(), 8);
+            assert!(!trace.steps[2].if_exec);
+            assert_eq!(trace.steps[3].if_stack_before, vec![false]);
+            assert_eq!(trace.steps[3].if_stack_after, vec![true]);
+            assert_eq!(trace.steps[4].stack_after, vec!["02".to_string()]);
+            assert_eq!(trace.steps[6].stack_after, Vec::<String>::new());
+            assert_eq!(
+                trace.steps[7].err,
+                Some("invalid stack operation".to_string())
+            );
+
+            let json: serde_json::Value = serde_json::from_str(&trace.to_json()).unwrap();
+            assert_eq!(json["steps"][4]["chunk"], "2");
+            let table = trace.to_table();
+            assert_eq!(table.lines().count(), 9);
+            assert!(table.contains("ELSE"));
+            assert!(table.contains("invalid stack operation"));
+        }
+
         #[test]
         fn test_pushdata1() {
             let tx = Tx::new(0, Vec::new(), Vec::new(), 0);
@@ -1234,6 +1370,145 @@ mod tests {
             assert!(result);
         }
 
+        #[test]
+        fn test_checksig_schnorr() {
+            let output_key = KeyPair::from_random();
+            let output_pub_key = output_key.pub_key.buf;
+            let output_pkh = Pkh::from_pub_key_buffer(output_pub_key.to_vec());
+            let output_script = Script::from_pkh_output(output_pkh.to_buf());
+            let output_amount = 100;
+
+            let mut tx = Tx::new(
+                1,
+                vec![TxIn::new([0; 32], 0, Script::from_empty(), 0xffffffff)],
+                vec![TxOut::new(output_amount, output_script.clone())],
+                0,
+            );
+
+            let hash_type = TxSignature::SIGHASH_ALL | TxSignature::SIGHASH_SCHNORR;
+            let sig = tx.sign_no_cache(
+                0,
+                output_key.priv_key.buf,
+                output_script.to_buf(),
+                output_amount,
+                hash_type,
+            );
+            assert!(sig.is_schnorr());
+
+            // the same signature bytes with the schnorr flag cleared are not a
+            // valid ecdsa signature
+            let mut ecdsa_sig = sig.to_buf();
+            ecdsa_sig[0] = TxSignature::SIGHASH_ALL;
+
+            for (sig_buf, expected) in [(sig.to_buf(), true), (ecdsa_sig, false)] {
+                let stack = vec![sig_buf.to_vec(), output_pub_key.to_vec()];
+                let mut hash_cache = HashCache::new();
+                let mut script_interpreter = ScriptInterpreter::from_output_script_tx(
+                    output_script.clone(),
+                    tx.clone(),
+                    0,
+                    stack,
+                    output_amount,
+                    &mut hash_cache,
+                );
+                assert_eq!(script_interpreter.eval_script(), expected);
+            }
+        }
+
+        #[test]
+        fn test_checksig_deferred() {
+            let output_key = KeyPair::from_random();
+            let output_pub_key = output_key.pub_key.buf;
+            let output_pkh = Pkh::from_pub_key_buffer(output_pub_key.to_vec());
+            let output_script = Script::from_pkh_output(output_pkh.to_buf());
+            let output_amount = 100;
+
+            let mut tx = Tx::new(
+                1,
+                vec![TxIn::new([0; 32], 0, Script::from_empty(), 0xffffffff)],
+                vec![TxOut::new(output_amount, output_script.clone())],
+                0,
+            );
+            let sig = tx.sign_no_cache(
+                0,
+                output_key.priv_key.buf,
+                output_script.to_buf(),
+                output_amount,
+                TxSignature::SIGHASH_ALL,
+            );
+            let mut bad_sig = sig.to_buf();
+            bad_sig[10] ^= 1;
+
+            // the script passes either way, and the recorded check tells the
+            // good signature from the bad one
+            for (sig_buf, expected) in [(sig.to_buf(), true), (bad_sig, false)] {
+                let stack = vec![sig_buf.to_vec(), output_pub_key.to_vec()];
+                let mut hash_cache = HashCache::new();
+                let mut script_interpreter = ScriptInterpreter::from_output_script_tx(
+                    output_script.clone(),
+                    tx.clone(),
+                    0,
+                    stack,
+                    output_amount,
+                    &mut hash_cache,
+                );
+                script_interpreter.enable_deferred_sigs();
+                assert!(script_interpreter.eval_script());
+                let deferred_sigs = script_interpreter.deferred_sigs.unwrap();
+                assert_eq!(deferred_sigs.len(), 1);
+                assert_eq!(deferred_sigs[0].pub_key, output_pub_key);
+                assert_eq!(deferred_sigs[0].verify(), expected);
+            }
+        }
+
+        #[test]
+        fn test_checkmultisig_mixed_schnorr_and_ecdsa() {
+            let keys: Vec<KeyPair> = (0..3).map(|_| KeyPair::from_random()).collect();
+            let pub_keys: Vec<Vec<u8>> = keys.iter().map(|key| key.pub_key.buf.to_vec()).collect();
+            let output_script = Script::from_multi_sig_output(2, pub_keys);
+            let output_amount = 100;
+
+            let mut tx = Tx::new(
+                1,
+                vec![TxIn::new([0; 32], 0, Script::from_empty(), 0xffffffff)],
+                vec![TxOut::new(output_amount, output_script.clone())],
+                0,
+            );
+
+            let hash_types = [
+                TxSignature::SIGHASH_ALL | TxSignature::SIGHASH_SCHNORR,
+                TxSignature::SIGHASH_ALL,
+            ];
+            let stack: Vec<Vec<u8>> = keys[0..2]
+                .iter()
+                .zip(hash_types)
+                .map(|(key, hash_type)| {
+                    tx.sign_no_cache(
+                        0,
+                        key.priv_key.buf,
+                        output_script.to_buf(),
+                        output_amount,
+                        hash_type,
+                    )
+                    .to_buf()
+                    .to_vec()
+                })
+                .collect();
+
+            let mut hash_cache = HashCache::new();
+            let mut script_interpreter = ScriptInterpreter::from_output_script_tx(
+                output_script,
+                tx,
+                0,
+                stack,
+                output_amount,
+                &mut hash_cache,
+            );
+            let result = script_interpreter.eval_script();
+            assert_eq!(script_interpreter.err, None);
+            assert!(result);
+        }
+
         #[test]
         fn test_checkmultisig() {
             // Define private keys
@@ -1317,7 +1592,7 @@ mod tests {
 
             // Evaluate the script
             let result = script_interpreter.eval_script();
-            assert_eq!(script_interpreter.err_str, "");
+            assert_eq!(script_interpreter.err, None);
             assert!(result);
         }
     }
@@ -1365,8 +1640,12 @@ mod tests {
                 let mut script_interpreter =
                     ScriptInterpreter::from_script_tx(script, tx, 0, &mut hash_cache);
                 script_interpreter.eval_script();
+                let err_str = script_interpreter
+                    .err
+                    .map(|err| err.to_string())
+                    .unwrap_or_default();
                 assert_eq!(
-                    script_interpreter.err_str, test_script.expected_error,
+                    err_str, test_script.expected_error,
                     "Test '{}' failed on error value",
                     test_script.name
                 );
@@ -0,0 +1,174 @@
+use crate::script_chunk::ScriptChunk;
+use crate::script_error::ScriptError;
+use serde::Serialize;
+
+// the state of the script interpreter before and after running one chunk.
+// stack items are hex, with the top of the stack last.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScriptTraceStep {
+    pub pc: usize,
+    pub opcode: u8,
+    pub chunk: String,
+    pub if_exec: bool,
+    pub stack_before: Vec<String>,
+    pub alt_stack_before: Vec<String>,
+    pub if_stack_before: Vec<bool>,
+    pub stack_after: Vec<String>,
+    pub alt_stack_after: Vec<String>,
+    pub if_stack_after: Vec<bool>,
+    pub err: Option<String>,
+}
+
+impl ScriptTraceStep {
+    pub fn new(
+        pc: usize,
+        chunk: &ScriptChunk,
+        if_exec: bool,
+        stack: &[Vec<u8>],
+        alt_stack: &[Vec<u8>],
+        if_stack: &[bool],
+    ) -> Self {
+        let chunk_str = chunk
+            .to_strict_str()
+            .unwrap_or_else(|_| format!("0x{:02x}", chunk.opcode));
+        Self {
+            pc,
+            opcode: chunk.opcode,
+            chunk: chunk_str,
+            if_exec,
+            stack_before: ScriptTraceStep::stack_to_hex(stack),
+            alt_stack_before: ScriptTraceStep::stack_to_hex(alt_stack),
+            if_stack_before: if_stack.to_vec(),
+            stack_after: Vec::new(),
+            alt_stack_after: Vec::new(),
+            if_stack_after: Vec::new(),
+            err: None,
+        }
+    }
+
+    pub fn set_after(
+        &mut self,
+        stack: &[Vec<u8>],
+        alt_stack: &[Vec<u8>],
+        if_stack: &[bool],
+        err: Option<ScriptError>,
+    ) {
+        self.stack_after = ScriptTraceStep::stack_to_hex(stack);
+        self.alt_stack_after = ScriptTraceStep::stack_to_hex(alt_stack);
+        self.if_stack_after = if_stack.to_vec();
+        self.err = err.map(|err| err.to_string());
+    }
+
+    fn stack_to_hex(stack: &[Vec<u8>]) -> Vec<String> {
+        stack.iter().map(hex::encode).collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ScriptTrace {
+    pub steps: Vec<ScriptTraceStep>,
+}
+
+impl ScriptTrace {
+    const TABLE_HEADER: [&'static str; 7] = [
+        "pc",
+        "chunk",
+        "exec",
+        "stack",
+        "alt stack",
+        "if stack",
+        "error",
+    ];
+
+    pub fn new() -> Self {
+        Self { steps: Vec::new() }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // one row per step showing the state after the step. an empty stack item
+    // is shown as "" so that it can be told apart from no item.
+    pub fn to_table(&self) -> String {
+        let fmt_stack = |stack: &[String]| -> String {
+            let items: Vec<String> = stack
+                .iter()
+                .map(|item| {
+                    if item.is_empty() {
+                        "\"\"".to_string()
+                    } else {
+                        item.clone()
+                    }
+                })
+                .collect();
+            format!("[{}]", items.join(" "))
+        };
+        let fmt_if_stack = |if_stack: &[bool]| -> String {
+            let items: Vec<&str> = if_stack
+                .iter()
+                .map(|b| if *b { "1" } else { "0" })
+                .collect();
+            format!("[{}]", items.join(" "))
+        };
+
+        let mut rows: Vec<Vec<String>> = vec![ScriptTrace::TABLE_HEADER
+            .iter()
+            .map(|s| s.to_string())
+            .collect()];
+        for step in &self.steps {
+            rows.push(vec![
+                step.pc.to_string(),
+                step.chunk.clone(),
+                if step.if_exec { "yes" } else { "no" }.to_string(),
+                fmt_stack(&step.stack_after),
+                fmt_stack(&step.alt_stack_after),
+                fmt_if_stack(&step.if_stack_after),
+                step.err.clone().unwrap_or_default(),
+            ]);
+        }
+
+        let mut widths = [0; 7];
+        for row in &rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(cell.len());
+            }
+        }
+        let mut table = String::new();
+        for mut row in rows {
+            while row.last().is_some_and(|cell| cell.is_empty()) {
+                row.pop();
+            }
+            let cells: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| format!("{:width$}", cell, width = widths[i]))
+                .collect();
+            table.push_str(cells.join(" | ").trim_end());
+            table.push('\n');
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::Opcode;
+
+    #[test]
+    fn test_to_table() {
+        let chunk = ScriptChunk::new(Opcode::OP_DUP, None);
+        let mut step = ScriptTraceStep::new(0, &chunk, true, &[vec![]], &[], &[]);
+        step.set_after(&[vec![], vec![]], &[vec![1]], &[true], None);
+        let trace = ScriptTrace { steps: vec![step] };
+        let table = trace.to_table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("pc | chunk | exec"));
+        assert_eq!(
+            lines[1],
+            "0  | DUP   | yes  | [\"\" \"\"] | [01]      | [1]"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use crate::sig_check::SigCheck;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+
+// signatures that are already known to be valid, so that a tx verified when
+// it enters the mempool is not verified again when it shows up in a block.
+// only valid signatures are kept, and once the cache is full the oldest ones
+// are dropped first. share it between threads with an Arc.
+#[derive(Debug)]
+pub struct SigCache {
+    max_entries: usize,
+    inner: Mutex<SigCacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct SigCacheInner {
+    entries: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>, // oldest first
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SigCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl SigCache {
+    pub const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            inner: Mutex::new(SigCacheInner::default()),
+        }
+    }
+
+    fn key(sig_check: &SigCheck) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&sig_check.sighash);
+        hasher.update(&sig_check.pub_key);
+        hasher.update(&sig_check.sig.to_buf());
+        hasher.finalize().into()
+    }
+
+    // true if the signature is known to be valid. counts a hit or a miss.
+    pub fn get(&self, sig_check: &SigCheck) -> bool {
+        let key = SigCache::key(sig_check);
+        let mut inner = self.inner.lock().unwrap();
+        let found = inner.entries.contains(&key);
+        if found {
+            inner.hits += 1;
+        } else {
+            inner.misses += 1;
+        }
+        found
+    }
+
+    // the signature must already have been verified
+    pub fn insert(&self, sig_check: &SigCheck) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let key = SigCache::key(sig_check);
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.entries.insert(key) {
+            return;
+        }
+        inner.order.push_back(key);
+        while inner.order.len() > self.max_entries {
+            let oldest = inner.order.pop_front().unwrap();
+            inner.entries.remove(&oldest);
+        }
+    }
+
+    // checks the cache first and remembers the signature if it is valid. the
+    // lock is not held while verifying.
+    pub fn verify(&self, sig_check: &SigCheck) -> bool {
+        if self.get(sig_check) {
+            return true;
+        }
+        if !sig_check.verify() {
+            return false;
+        }
+        self.insert(sig_check);
+        true
+    }
+
+    pub fn stats(&self) -> SigCacheStats {
+        let inner = self.inner.lock().unwrap();
+        SigCacheStats {
+            hits: inner.hits,
+            misses: inner.misses,
+            entries: inner.entries.len(),
+        }
+    }
+
+    pub fn clear(&self) {
+        *self.inner.lock().unwrap() = SigCacheInner::default();
+    }
+}
+
+impl Default for SigCache {
+    fn default() -> Self {
+        Self::new(SigCache::DEFAULT_MAX_ENTRIES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use crate::tx_signature::TxSignature;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn sig_check(value: u64) -> SigCheck {
+        test_util::sig_check(value, TxSignature::SIGHASH_ALL)
+    }
+
+    #[test]
+    fn test_verify_caches_valid_sigs() {
+        let sig_cache = SigCache::default();
+        let check = sig_check(1);
+        assert!(sig_cache.verify(&check));
+        assert!(sig_cache.verify(&check));
+        let stats = sig_cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+
+        // invalid signatures are never cached
+        let mut bad_check = check.clone();
+        bad_check.sighash[0] ^= 1;
+        assert!(!sig_cache.verify(&bad_check));
+        assert!(!sig_cache.verify(&bad_check));
+        let stats = sig_cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 3, 1));
+
+        sig_cache.clear();
+        assert_eq!(sig_cache.stats().entries, 0);
+        assert!(!sig_cache.get(&check));
+    }
+
+    #[test]
+    fn test_oldest_entries_are_evicted() {
+        let sig_cache = SigCache::new(2);
+        let checks: Vec<SigCheck> = (0..3).map(sig_check).collect();
+        for check in &checks {
+            sig_cache.insert(check);
+        }
+        assert_eq!(sig_cache.stats().entries, 2);
+        assert!(!sig_cache.get(&checks[0]));
+        assert!(sig_cache.get(&checks[1]));
+        assert!(sig_cache.get(&checks[2]));
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let sig_cache = Arc::new(SigCache::default());
+        let checks: Vec<SigCheck> = (0..8).map(sig_check).collect();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let sig_cache = sig_cache.clone();
+                let checks = &checks;
+                scope.spawn(move || {
+                    for check in checks {
+                        assert!(sig_cache.verify(check));
+                    }
+                });
+            }
+        });
+        let stats = sig_cache.stats();
+        assert_eq!(stats.entries, 8);
+        assert_eq!(stats.hits + stats.misses, 32);
+        assert!(stats.misses >= 8);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::pub_key::PubKey;
+use crate::tx::Tx;
+use crate::tx_signature::TxSignature;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+// a signature check recorded by the script interpreter instead of being run
+// when OP_CHECKSIG is executed, so that the checks of a whole block can be run
+// together across threads.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SigCheck {
+    pub sighash: [u8; 32],
+    pub pub_key: [u8; PubKey::SIZE],
+    pub sig: TxSignature,
+}
+
+impl SigCheck {
+    pub fn new(sighash: [u8; 32], pub_key: [u8; PubKey::SIZE], sig: TxSignature) -> Self {
+        Self {
+            sighash,
+            pub_key,
+            sig,
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        Tx::verify_sighash(&self.sighash, &self.pub_key, &self.sig)
+    }
+
+    // true if every check passes. the checks are split evenly across threads
+    // and all threads stop early once any check fails. this is not batch
+    // verification: each signature is still checked on its own, and the only
+    // speedup is from the threads. ecdsa signatures can not be batched, and
+    // without multi-scalar multiplication in the secp256k1 crate a schnorr
+    // batch would cost as much as checking the signatures one by one.
+    pub fn verify_in_parallel(sig_checks: &[SigCheck], n_threads: usize) -> bool {
+        let n_threads = n_threads.clamp(1, sig_checks.len().max(1));
+        if n_threads == 1 {
+            return sig_checks.iter().all(SigCheck::verify);
+        }
+        let failed = AtomicBool::new(false);
+        let chunk_size = sig_checks.len().div_ceil(n_threads);
+        thread::scope(|scope| {
+            for chunk in sig_checks.chunks(chunk_size) {
+                let failed = &failed;
+                scope.spawn(move || {
+                    for sig_check in chunk {
+                        if failed.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        if !sig_check.verify() {
+                            failed.store(true, Ordering::Relaxed);
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        !failed.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sig_check;
+
+    // alternating ecdsa and schnorr
+    fn sig_checks(n: usize) -> Vec<SigCheck> {
+        (0..n)
+            .map(|i| {
+                let hash_type = if i % 2 == 0 {
+                    TxSignature::SIGHASH_ALL
+                } else {
+                    TxSignature::SIGHASH_ALL | TxSignature::SIGHASH_SCHNORR
+                };
+                sig_check(i as u64, hash_type)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_in_parallel() {
+        let checks = sig_checks(10);
+        assert!(SigCheck::verify_in_parallel(&checks, 1));
+        assert!(SigCheck::verify_in_parallel(&checks, 4));
+        assert!(SigCheck::verify_in_parallel(&checks, 100));
+        assert!(SigCheck::verify_in_parallel(&[], 4));
+    }
+
+    #[test]
+    fn test_verify_in_parallel_with_bad_check() {
+        for bad in [0, 5, 9] {
+            let mut checks = sig_checks(10);
+            checks[bad].sighash[0] ^= 1;
+            assert!(!SigCheck::verify_in_parallel(&checks, 1));
+            assert!(!SigCheck::verify_in_parallel(&checks, 4));
+        }
+    }
+
+    #[test]
+    fn test_unparsable_pub_key_is_invalid() {
+        let mut checks = sig_checks(1);
+        checks[0].pub_key = [0; PubKey::SIZE];
+        assert!(!checks[0].verify());
+    }
+}
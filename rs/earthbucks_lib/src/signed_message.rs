@@ -0,0 +1,252 @@
+use crate::buf::EbxBuf;
+use crate::buf_reader::BufReader;
+use crate::buf_writer::BufWriter;
+use crate::error::EbxError;
+use crate::hash::{blake3_hash, blake3_mac};
+use crate::priv_key::PrivKey;
+use crate::pub_key::PubKey;
+use secp256k1::ecdsa::Signature;
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+#[derive(Debug, Clone)]
+pub struct SignedMessage {
+    pub sig: [u8; 64],
+    pub pub_key: [u8; PubKey::SIZE],
+    pub mac: [u8; 32],
+    pub message: Vec<u8>,
+    pub key_str: String,
+}
+
+impl SignedMessage {
+    pub fn new(
+        sig: [u8; 64],
+        pub_key: [u8; PubKey::SIZE],
+        mac: [u8; 32],
+        message: Vec<u8>,
+        key_str: String,
+    ) -> Self {
+        Self {
+            sig,
+            pub_key,
+            mac,
+            message,
+            key_str,
+        }
+    }
+
+    // the key string separates the domain of the mac so that a signature for
+    // one purpose (e.g. "signin") can never be replayed for another
+    pub fn create_mac(message: &[u8], key_str: &str) -> [u8; 32] {
+        let key = blake3_hash(key_str.as_bytes());
+        blake3_mac(&key, message)
+    }
+
+    pub fn from_sign_message(
+        priv_key: &PrivKey,
+        message: Vec<u8>,
+        key_str: &str,
+    ) -> Result<Self, EbxError> {
+        let mac = SignedMessage::create_mac(&message, key_str);
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&priv_key.buf)
+            .map_err(|_| EbxError::InvalidKeyError { source: None })?;
+        let sig = secp
+            .sign_ecdsa(&Message::from_digest(mac), &secret_key)
+            .serialize_compact();
+        let pub_key = priv_key.to_pub_key_buffer()?;
+        Ok(SignedMessage::new(
+            sig,
+            pub_key,
+            mac,
+            message,
+            key_str.to_string(),
+        ))
+    }
+
+    pub fn is_valid(&self, pub_key: &PubKey, key_str: &str) -> bool {
+        if key_str != self.key_str {
+            return false;
+        }
+        let mac = SignedMessage::create_mac(&self.message, &self.key_str);
+        if mac != self.mac {
+            return false;
+        }
+        if pub_key.buf != self.pub_key {
+            return false;
+        }
+        let public_key = match PublicKey::from_slice(&self.pub_key) {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
+        let sig = match Signature::from_compact(&self.sig) {
+            Ok(sig) => sig,
+            Err(_) => return false,
+        };
+        let secp = Secp256k1::new();
+        secp.verify_ecdsa(&Message::from_digest(mac), &sig, &public_key)
+            .is_ok()
+    }
+
+    pub fn from_buf(buf: Vec<u8>, key_str: &str) -> Result<Self, EbxError> {
+        let mut reader = BufReader::new(buf);
+        let sig: [u8; 64] = reader.read(64)?.try_into().unwrap();
+        let pub_key: [u8; PubKey::SIZE] = reader.read(PubKey::SIZE)?.try_into().unwrap();
+        let mac: [u8; 32] = reader.read(32)?.try_into().unwrap();
+        let message = reader.read_remainder();
+        Ok(SignedMessage::new(
+            sig,
+            pub_key,
+            mac,
+            message,
+            key_str.to_string(),
+        ))
+    }
+
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut writer = BufWriter::new();
+        writer.write(self.sig.to_vec());
+        writer.write(self.pub_key.to_vec());
+        writer.write(self.mac.to_vec());
+        writer.write(self.message.clone());
+        writer.to_buf()
+    }
+
+    pub fn to_strict_hex(&self) -> String {
+        self.to_buf().to_strict_hex()
+    }
+
+    pub fn from_strict_hex(hex: &str, key_str: &str) -> Result<Self, EbxError> {
+        SignedMessage::from_buf(Vec::<u8>::from_strict_hex(hex)?, key_str)
+    }
+
+    pub fn to_strict_str(&self) -> String {
+        self.to_strict_hex()
+    }
+
+    pub fn from_strict_str(s: &str, key_str: &str) -> Result<Self, EbxError> {
+        SignedMessage::from_strict_hex(s, key_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::fs;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let priv_key = PrivKey::from_random();
+        let pub_key = PubKey::from_priv_key(&priv_key).unwrap();
+        let message = "message".as_bytes().to_vec();
+        let key_str = "signed message";
+        let signed_message = SignedMessage::from_sign_message(&priv_key, message, key_str).unwrap();
+        assert!(signed_message.is_valid(&pub_key, key_str));
+    }
+
+    #[test]
+    fn test_wrong_key_str() {
+        let priv_key = PrivKey::from_random();
+        let pub_key = PubKey::from_priv_key(&priv_key).unwrap();
+        let message = "message".as_bytes().to_vec();
+        let signed_message =
+            SignedMessage::from_sign_message(&priv_key, message, "signed message").unwrap();
+        assert!(!signed_message.is_valid(&pub_key, "other message"));
+    }
+
+    #[test]
+    fn test_wrong_pub_key() {
+        let priv_key = PrivKey::from_random();
+        let other_pub_key = PubKey::from_priv_key(&PrivKey::from_random()).unwrap();
+        let message = "message".as_bytes().to_vec();
+        let key_str = "signed message";
+        let signed_message = SignedMessage::from_sign_message(&priv_key, message, key_str).unwrap();
+        assert!(!signed_message.is_valid(&other_pub_key, key_str));
+    }
+
+    #[test]
+    fn test_tampered_message() {
+        let priv_key = PrivKey::from_random();
+        let pub_key = PubKey::from_priv_key(&priv_key).unwrap();
+        let message = "message".as_bytes().to_vec();
+        let key_str = "signed message";
+        let mut signed_message =
+            SignedMessage::from_sign_message(&priv_key, message, key_str).unwrap();
+        signed_message.message = "massage".as_bytes().to_vec();
+        assert!(!signed_message.is_valid(&pub_key, key_str));
+    }
+
+    #[test]
+    fn test_to_buf_and_from_buf() {
+        let priv_key = PrivKey::from_random();
+        let pub_key = PubKey::from_priv_key(&priv_key).unwrap();
+        let message = "message".as_bytes().to_vec();
+        let key_str = "signed message";
+        let signed_message1 =
+            SignedMessage::from_sign_message(&priv_key, message, key_str).unwrap();
+        let buf = signed_message1.to_buf();
+        let signed_message2 = SignedMessage::from_buf(buf, key_str).unwrap();
+        assert_eq!(signed_message1.to_buf(), signed_message2.to_buf());
+        assert!(signed_message2.is_valid(&pub_key, key_str));
+    }
+
+    #[test]
+    fn test_from_buf_not_enough_data() {
+        let buf = vec![0u8; 64 + PubKey::SIZE + 31];
+        assert!(SignedMessage::from_buf(buf, "signed message").is_err());
+    }
+
+    #[test]
+    fn test_to_strict_str_and_from_strict_str() {
+        let priv_key = PrivKey::from_random();
+        let message = "message".as_bytes().to_vec();
+        let key_str = "signed message";
+        let signed_message1 =
+            SignedMessage::from_sign_message(&priv_key, message, key_str).unwrap();
+        let s = signed_message1.to_strict_str();
+        let signed_message2 = SignedMessage::from_strict_str(&s, key_str).unwrap();
+        assert_eq!(s, signed_message2.to_strict_str());
+        assert!(SignedMessage::from_strict_str("0g", key_str).is_err());
+    }
+
+    // standard test vectors: signed_message.json
+    #[derive(Deserialize)]
+    struct JsonSignedMessage {
+        priv_key: String,
+        pub_key: String,
+        key_str: String,
+        message: String,
+        mac: String,
+        sig: String,
+        buf: String,
+    }
+
+    #[derive(Deserialize)]
+    struct JsonSignedMessages {
+        signed_message: Vec<JsonSignedMessage>,
+    }
+
+    #[test]
+    fn test_vectors() {
+        let data =
+            fs::read_to_string("./test_vectors/signed_message.json").expect("Unable to read file");
+        let test_vectors: JsonSignedMessages =
+            serde_json::from_str(&data).expect("Unable to parse JSON");
+
+        for test_vector in test_vectors.signed_message {
+            let priv_key = PrivKey::from_strict_str(&test_vector.priv_key).unwrap();
+            let pub_key = PubKey::from_strict_str(&test_vector.pub_key).unwrap();
+            let message = Vec::<u8>::from_strict_hex(&test_vector.message).unwrap();
+            let key_str = &test_vector.key_str;
+
+            let signed_message =
+                SignedMessage::from_sign_message(&priv_key, message, key_str).unwrap();
+            assert_eq!(signed_message.mac.to_strict_hex(), test_vector.mac);
+            assert_eq!(signed_message.sig.to_strict_hex(), test_vector.sig);
+            assert_eq!(signed_message.to_strict_hex(), test_vector.buf);
+
+            let signed_message = SignedMessage::from_strict_hex(&test_vector.buf, key_str).unwrap();
+            assert!(signed_message.is_valid(&pub_key, key_str));
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use crate::sig_cache::SigCache;
+use crate::tx::Tx;
+use crate::tx_out_bn_map::TxOutBnMap;
+use crate::tx_out_bn_overlay::TxOutBnOverlay;
+use crate::tx_out_bn_store::TxOutBnStore;
+use crate::tx_verifier::TxVerifier;
+use crate::utxo_delta::UtxoDelta;
+use crate::verify_error::VerifyError;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+// which txs of a block spend the outputs of which other txs of the block. the
+// first tx is the coinbase, which is checked separately and never verified
+// here.
+//
+// building the graph walks the block in order the same way sequential
+// verification does, which is cheap because no scripts are run. it records
+// for each tx the outputs it spends as they look at that point in the block,
+// so each tx can then be verified on its own against just those outputs and
+// give the same result as it would in sequence. an output spent twice in the
+// block is simply missing for the second spender, as it is in sequence.
+pub struct SpendGraph {
+    pub inputs: Vec<TxOutBnMap>,
+    pub children: Vec<Vec<usize>>,
+    pub n_parents: Vec<usize>,
+    pub utxo_delta: UtxoDelta, // valid only if every tx is valid
+}
+
+struct Schedule {
+    ready: BinaryHeap<Reverse<usize>>,
+    n_parents: Vec<usize>,
+    in_flight: usize,
+    err: Option<(usize, VerifyError)>,
+}
+
+impl SpendGraph {
+    // fails only if the utxo set can not be read
+    pub fn new(
+        txs: &[Tx],
+        tx_out_bn_map: &dyn TxOutBnStore,
+        block_num: u32,
+    ) -> Result<Self, VerifyError> {
+        let mut inputs = vec![TxOutBnMap::new(); txs.len()];
+        let mut children = vec![Vec::new(); txs.len()];
+        let mut n_parents = vec![0; txs.len()];
+        let mut utxo_delta = UtxoDelta::new(block_num);
+        let mut tx_out_bn_overlay = TxOutBnOverlay::new(tx_out_bn_map);
+        let mut created_by: HashMap<([u8; 32], u32), usize> = HashMap::new();
+        // the coinbase outputs are created by the block but cannot be spent
+        // in it
+        if let Some(coinbase_tx) = txs.first() {
+            utxo_delta.add_tx_outputs(coinbase_tx);
+        }
+        for (n_tx, tx) in txs.iter().enumerate().skip(1) {
+            for tx_input in &tx.inputs {
+                let tx_id = &tx_input.input_tx_id;
+                let tx_out_num = tx_input.input_tx_out_num;
+                let Some(tx_out_bn) = tx_out_bn_overlay
+                    .get(tx_id, tx_out_num)
+                    .map_err(VerifyError::store)?
+                else {
+                    continue;
+                };
+                inputs[n_tx].add(
+                    tx_id,
+                    tx_out_num,
+                    tx_out_bn.tx_out.clone(),
+                    tx_out_bn.block_num,
+                );
+                if let Some(&parent) = created_by.get(&(*tx_id, tx_out_num)) {
+                    if !children[parent].contains(&n_tx) {
+                        children[parent].push(n_tx);
+                        n_parents[n_tx] += 1;
+                    }
+                }
+            }
+
+            let new_tx_id = tx.id();
+            utxo_delta.add_tx_outputs(tx);
+            tx_out_bn_overlay
+                .add_tx_outputs(tx, block_num)
+                .map_err(VerifyError::store)?;
+            for tx_out_num in 0..tx.outputs.len() as u32 {
+                created_by.insert((new_tx_id, tx_out_num), n_tx);
+            }
+            for tx_input in &tx.inputs {
+                let tx_id = &tx_input.input_tx_id;
+                let tx_out_num = tx_input.input_tx_out_num;
+                // missing if the tx is invalid, which verify_txs will report
+                let tx_out_bn = tx_out_bn_overlay
+                    .get(tx_id, tx_out_num)
+                    .map_err(VerifyError::store)?;
+                if let Some(tx_out_bn) = tx_out_bn {
+                    utxo_delta.spend(tx_id, tx_out_num, &tx_out_bn);
+                    tx_out_bn_overlay
+                        .remove(tx_id, tx_out_num)
+                        .map_err(VerifyError::store)?;
+                    created_by.remove(&(*tx_id, tx_out_num));
+                }
+            }
+        }
+        Ok(Self {
+            inputs,
+            children,
+            n_parents,
+            utxo_delta,
+        })
+    }
+
+    // verifies every tx but the coinbase on n_threads threads. a tx is only
+    // started once the txs it spends from have passed, and the children of a
+    // failed tx are never started. the error is the one for the first invalid
+    // tx in block order, which is what sequential verification would return.
+    pub fn verify_txs(
+        &self,
+        txs: &[Tx],
+        block_num: u32,
+        n_threads: usize,
+        sig_cache: Option<&Arc<SigCache>>,
+    ) -> Result<(), VerifyError> {
+        let mut ready = BinaryHeap::new();
+        for n_tx in 1..txs.len() {
+            if self.n_parents[n_tx] == 0 {
+                ready.push(Reverse(n_tx));
+            }
+        }
+        let schedule = Mutex::new(Schedule {
+            ready,
+            n_parents: self.n_parents.clone(),
+            in_flight: 0,
+            err: None,
+        });
+        let cvar = Condvar::new();
+        thread::scope(|scope| {
+            for _ in 0..n_threads.max(1) {
+                scope.spawn(|| loop {
+                    let n_tx = {
+                        let mut s = schedule.lock().unwrap();
+                        loop {
+                            if let Some(Reverse(n_tx)) = s.ready.pop() {
+                                // an earlier tx already failed
+                                if s.err.as_ref().is_some_and(|(n_err, _)| *n_err < n_tx) {
+                                    continue;
+                                }
+                                s.in_flight += 1;
+                                break n_tx;
+                            }
+                            if s.in_flight == 0 {
+                                cvar.notify_all();
+                                return;
+                            }
+                            s = cvar.wait(s).unwrap();
+                        }
+                    };
+                    let mut tx_verifier =
+                        TxVerifier::new(txs[n_tx].clone(), &self.inputs[n_tx], block_num);
+                    if let Some(sig_cache) = sig_cache {
+                        tx_verifier.set_sig_cache(sig_cache.clone());
+                    }
+                    let res = tx_verifier.verify();
+                    let mut s = schedule.lock().unwrap();
+                    s.in_flight -= 1;
+                    match res {
+                        Ok(()) => {
+                            for &child in &self.children[n_tx] {
+                                s.n_parents[child] -= 1;
+                                if s.n_parents[child] == 0 {
+                                    s.ready.push(Reverse(child));
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            if s.err.as_ref().is_none_or(|(n_err, _)| n_tx < *n_err) {
+                                s.err = Some((n_tx, err));
+                            }
+                        }
+                    }
+                    cvar.notify_all();
+                });
+            }
+        });
+        match schedule.into_inner().unwrap().err {
+            Some((n_tx, err)) => Err(VerifyError::InvalidTx {
+                n_tx,
+                tx_id: txs[n_tx].id(),
+                err: Box::new(err),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+    use crate::test_util::Wallet;
+    use crate::tx_in::TxIn;
+    use crate::tx_out::TxOut;
+    use crate::tx_signer::TxSigner;
+
+    // a signed tx moving the output to the same pkh
+    fn spend(wallet: &Wallet, tx_out_bn_map: &TxOutBnMap, tx_id: [u8; 32], tx_out_num: u32) -> Tx {
+        let tx_in = TxIn::new(tx_id, tx_out_num, Script::from_pkh_input_placeholder(), 0);
+        let tx = Tx::new(1, vec![tx_in], vec![TxOut::new(100, wallet.script())], 1);
+        let mut tx_signer = TxSigner::new(tx, tx_out_bn_map, &wallet.pkh_key_map, 1);
+        tx_signer.sign().unwrap()
+    }
+
+    // a coinbase placeholder and chains of txs each spending the one before,
+    // starting from the outputs of a funding tx
+    fn chains(wallet: &Wallet, n_chains: u32, len: usize) -> (TxOutBnMap, Vec<Tx>) {
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        let outputs = (0..n_chains)
+            .map(|_| TxOut::new(100, Script::from_pkh_output(&wallet.pkh.buf)))
+            .collect();
+        let funding_tx = Tx::new(1, vec![], outputs, 0);
+        tx_out_bn_map.add_tx_outputs(&funding_tx, 0);
+        let base = tx_out_bn_map.clone();
+
+        let mut txs = vec![Tx::new(1, vec![], vec![], 1)];
+        for tx_out_num in 0..n_chains {
+            let mut tx = spend(wallet, &tx_out_bn_map, funding_tx.id(), tx_out_num);
+            txs.push(tx.clone());
+            for _ in 1..len {
+                tx_out_bn_map.add_tx_outputs(&tx, 1);
+                tx = spend(wallet, &tx_out_bn_map, tx.id(), 0);
+                txs.push(tx.clone());
+            }
+        }
+        (base, txs)
+    }
+
+    #[test]
+    fn test_graph() {
+        let wallet = Wallet::new();
+        let (tx_out_bn_map, txs) = chains(&wallet, 2, 3);
+        let spend_graph = SpendGraph::new(&txs, &tx_out_bn_map, 1).unwrap();
+        assert_eq!(spend_graph.n_parents, vec![0, 0, 1, 1, 0, 1, 1]);
+        assert_eq!(
+            spend_graph.children,
+            vec![vec![], vec![2], vec![3], vec![], vec![5], vec![6], vec![]]
+        );
+        // only the last output of each chain is left
+        assert_eq!(spend_graph.utxo_delta.created.map.len(), 2);
+        assert_eq!(spend_graph.utxo_delta.spent.map.len(), 2);
+        for n_threads in [1, 3] {
+            assert!(spend_graph.verify_txs(&txs, 1, n_threads, None).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_first_invalid_tx_is_reported() {
+        let wallet = Wallet::new();
+        let (tx_out_bn_map, mut txs) = chains(&wallet, 4, 3);
+        // break the middle of the second chain and the start of the last
+        for n_tx in [5, 10] {
+            let script = &mut txs[n_tx].inputs[0].script;
+            let mut sig_buf = script.chunks[0].buffer.clone().unwrap();
+            sig_buf[20] ^= 1;
+            *script = Script::from_pkh_input(&sig_buf, &script.chunks[1].buffer.clone().unwrap());
+        }
+        let spend_graph = SpendGraph::new(&txs, &tx_out_bn_map, 1).unwrap();
+        for n_threads in [1, 4] {
+            match spend_graph.verify_txs(&txs, 1, n_threads, None) {
+                Err(VerifyError::InvalidTx { n_tx, tx_id, err }) => {
+                    assert_eq!(n_tx, 5);
+                    assert_eq!(tx_id, txs[5].id());
+                    assert!(matches!(
+                        *err,
+                        VerifyError::InputScriptFailed { n_in: 0, .. }
+                    ));
+                }
+                _ => panic!("expected invalid tx"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_double_spend_in_block() {
+        let wallet = Wallet::new();
+        let (tx_out_bn_map, mut txs) = chains(&wallet, 2, 1);
+        // the second tx spends the same output as the first
+        let other_wallet = Wallet::new();
+        let tx_in = txs[1].inputs[0].clone();
+        let mut tx = spend(
+            &wallet,
+            &tx_out_bn_map,
+            tx_in.input_tx_id,
+            tx_in.input_tx_out_num,
+        );
+        tx.outputs[0].script = Script::from_pkh_output(&other_wallet.pkh.buf);
+        let mut tx_signer = TxSigner::new(tx, &tx_out_bn_map, &wallet.pkh_key_map, 1);
+        txs[2] = tx_signer.sign().unwrap();
+
+        let spend_graph = SpendGraph::new(&txs, &tx_out_bn_map, 1).unwrap();
+        assert!(spend_graph.inputs[2].map.is_empty());
+        match spend_graph.verify_txs(&txs, 1, 2, None) {
+            Err(VerifyError::InvalidTx { n_tx, err, .. }) => {
+                assert_eq!(n_tx, 2);
+                assert!(matches!(*err, VerifyError::InputNotFound { n_in: 0, .. }));
+            }
+            _ => panic!("expected invalid tx"),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// fixtures shared by the tests of several modules
+
+use crate::header::Header;
+use crate::header_chain::HeaderChain;
+use crate::key_pair::KeyPair;
+use crate::pkh::Pkh;
+use crate::pkh_key_map::PkhKeyMap;
+use crate::script::Script;
+use crate::sig_check::SigCheck;
+use crate::tx::Tx;
+use crate::tx_in::TxIn;
+use crate::tx_out::TxOut;
+
+// one key and the pkh outputs it can spend
+pub struct Wallet {
+    pub pkh: Pkh,
+    pub pkh_key_map: PkhKeyMap,
+}
+
+impl Wallet {
+    pub fn new() -> Self {
+        let key = KeyPair::from_random();
+        let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
+        let mut pkh_key_map = PkhKeyMap::new();
+        pkh_key_map.add(key, &pkh.buf);
+        Self { pkh, pkh_key_map }
+    }
+
+    pub fn script(&self) -> Script {
+        Script::from_pkh_output(&self.pkh.buf)
+    }
+}
+
+// a mine whose coinbases pay one key
+pub struct Mine {
+    pub key: KeyPair,
+    pub domain: &'static str,
+}
+
+impl Mine {
+    pub fn new(domain: &'static str) -> Self {
+        Self {
+            key: KeyPair::from_random(),
+            domain,
+        }
+    }
+
+    pub fn pkh(&self) -> Pkh {
+        Pkh::from_pub_key_buffer(self.key.pub_key.buf.to_vec())
+    }
+}
+
+// a chain whose blocks are made by the given mines in turn, and their
+// coinbases
+pub fn chain(mines: &[&Mine]) -> (HeaderChain, Vec<Tx>) {
+    let mut lch = HeaderChain::new();
+    let mut coinbase_txs = Vec::new();
+    for (i, mine) in mines.iter().enumerate() {
+        coinbase_txs.push(lch.get_next_coinbase_tx(&mine.pkh(), &mine.domain.to_string()));
+        let timestamp = (i as u64 + 1) * Header::BLOCK_INTERVAL;
+        lch.add(lch.get_next_header([0; 32], timestamp).unwrap());
+    }
+    (lch, coinbase_txs)
+}
+
+// a passing check of a new key's signature of a tx paying value
+pub fn sig_check(value: u64, hash_type: u8) -> SigCheck {
+    let mut tx = Tx::new(
+        1,
+        vec![TxIn::new([0; 32], 0, Script::from_empty(), 0)],
+        vec![TxOut::new(value, Script::from_empty())],
+        0,
+    );
+    let key = KeyPair::from_random();
+    let sig = tx.sign_no_cache(0, key.priv_key.buf, vec![], 100, hash_type);
+    let sighash = tx.sighash_no_cache(0, vec![], 100, hash_type);
+    SigCheck::new(sighash, key.pub_key.buf, sig)
+}
@@ -1,23 +1,36 @@
 use crate::buf::EbxBuf;
 use crate::buf_reader::BufReader;
 use crate::buf_writer::BufWriter;
+use crate::domain::Domain;
 use crate::error::EbxError;
 use crate::hash::blake3_hash;
 use crate::hash::double_blake3_hash;
 use crate::pub_key::PubKey;
+use crate::schnorr;
 use crate::script::Script;
+use crate::sig_cache::SigCache;
+use crate::sig_check::SigCheck;
 use crate::tx_in::TxIn;
 use crate::tx_out::TxOut;
 use crate::tx_signature::TxSignature;
 use crate::var_int::VarInt;
+use lazy_static::lazy_static;
 use secp256k1::ecdsa::Signature;
-use secp256k1::{Message, PublicKey, Secp256k1};
+use secp256k1::{All, Message, PublicKey, Secp256k1, VerifyOnly};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+lazy_static! {
+    // creating a context is much slower than verifying a signature
+    static ref SECP: Secp256k1<VerifyOnly> = Secp256k1::verification_only();
+}
 
 #[derive(Debug, Default)]
 pub struct HashCache {
     pub prevouts_hash: Option<[u8; 32]>,
     pub lock_rel_hash: Option<[u8; 32]>,
     pub outputs_hash: Option<[u8; 32]>,
+    pub sig_cache: Option<Arc<SigCache>>, // shared by every tx, unlike the rest
 }
 
 impl HashCache {
@@ -26,12 +39,14 @@ impl HashCache {
             prevouts_hash: None,
             lock_rel_hash: None,
             outputs_hash: None,
+            sig_cache: None,
         }
     }
 }
 
 // add clone support
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Tx {
     pub version: u8,
     pub inputs: Vec<TxIn>,
@@ -122,6 +137,18 @@ impl Tx {
         self.inputs.len() == 1 && self.inputs[0].is_coinbase()
     }
 
+    // the domain of the mine that made the block, which is the top push of the
+    // coinbase input script. none if it is missing or not a valid domain.
+    pub fn get_coinbase_domain(&self) -> Option<Domain> {
+        if !self.is_coinbase() {
+            return None;
+        }
+        let chunk = self.inputs[0].script.chunks.last()?;
+        let domain_str = String::from_utf8(chunk.buffer.clone()?).ok()?;
+        Domain::is_valid_coinbase_domain(&domain_str)
+            .then(|| Domain::from_strict_str(domain_str.trim().to_string()))
+    }
+
     pub fn blake3_hash(&self) -> [u8; 32] {
         blake3_hash(&self.to_buf())
     }
@@ -233,6 +260,7 @@ impl Tx {
             prevouts_hash: None,
             lock_rel_hash: None,
             outputs_hash: None,
+            sig_cache: None,
         };
         let preimage = self.sighash_preimage(
             input_index,
@@ -273,10 +301,7 @@ impl Tx {
             hash_type,
         ))
         .expect("32 bytes");
-        let key = secp256k1::SecretKey::from_slice(&private_key).expect("32 bytes");
-        let sig = secp.sign_ecdsa(&message, &key);
-        let sig = sig.serialize_compact();
-        TxSignature::new(hash_type, sig)
+        Tx::sign_message(&secp, &message, private_key, hash_type)
     }
 
     pub fn sign_with_cache(
@@ -297,10 +322,7 @@ impl Tx {
             hash_cache,
         ))
         .expect("32 bytes");
-        let key = secp256k1::SecretKey::from_slice(&private_key).expect("32 bytes");
-        let sig = secp.sign_ecdsa(&message, &key);
-        let sig = sig.serialize_compact();
-        TxSignature::new(hash_type, sig)
+        Tx::sign_message(&secp, &message, private_key, hash_type)
     }
 
     pub fn verify_no_cache(
@@ -311,18 +333,8 @@ impl Tx {
         script: Vec<u8>,
         amount: u64,
     ) -> bool {
-        let hash_type = signature.hash_type;
-        let secp = Secp256k1::new();
-        let pubkey = PublicKey::from_slice(&public_key).expect("33 bytes");
-        let message = Message::from_digest_slice(&self.sighash_no_cache(
-            input_index,
-            script,
-            amount,
-            hash_type,
-        ))
-        .expect("32 bytes");
-        let signature = Signature::from_compact(&signature.sig_buf).expect("64 bytes");
-        secp.verify_ecdsa(&message, &signature, &pubkey).is_ok()
+        let sighash = self.sighash_no_cache(input_index, script, amount, signature.hash_type);
+        Tx::verify_sighash(&sighash, &public_key, &signature)
     }
 
     pub fn verify_with_cache(
@@ -334,28 +346,79 @@ impl Tx {
         amount: u64,
         hash_cache: &mut HashCache,
     ) -> bool {
-        let hash_type = signature.hash_type;
-        let secp = Secp256k1::new();
-        let pubkey = PublicKey::from_slice(&public_key).expect("33 bytes");
-        let message = Message::from_digest_slice(&self.sighash_with_cache(
-            input_index,
-            script,
-            amount,
-            hash_type,
-            hash_cache,
-        ))
-        .expect("32 bytes");
-        let signature = Signature::from_compact(&signature.sig_buf).expect("64 bytes");
-        secp.verify_ecdsa(&message, &signature, &pubkey).is_ok()
+        let sighash =
+            self.sighash_with_cache(input_index, script, amount, signature.hash_type, hash_cache);
+        match &hash_cache.sig_cache {
+            Some(sig_cache) => sig_cache.verify(&SigCheck::new(sighash, public_key, signature)),
+            None => Tx::verify_sighash(&sighash, &public_key, &signature),
+        }
+    }
+
+    fn sign_message(
+        secp: &Secp256k1<All>,
+        message: &Message,
+        private_key: [u8; 32],
+        hash_type: u8,
+    ) -> TxSignature {
+        let sig = if hash_type & TxSignature::SIGHASH_SCHNORR != 0 {
+            schnorr::sign(&private_key, message.as_ref()).expect("valid key")
+        } else {
+            let key = secp256k1::SecretKey::from_slice(&private_key).expect("32 bytes");
+            secp.sign_ecdsa(message, &key).serialize_compact()
+        };
+        TxSignature::new(hash_type, sig)
+    }
+
+    // checks a signature against a sighash that has already been computed. a
+    // pub key or signature that can't be parsed is invalid.
+    pub fn verify_sighash(
+        sighash: &[u8; 32],
+        public_key: &[u8; PubKey::SIZE],
+        signature: &TxSignature,
+    ) -> bool {
+        if signature.is_schnorr() {
+            return schnorr::verify(public_key, sighash, &signature.sig_buf);
+        }
+        let Ok(pubkey) = PublicKey::from_slice(public_key) else {
+            return false;
+        };
+        let Ok(signature) = Signature::from_compact(&signature.sig_buf) else {
+            return false;
+        };
+        let message = Message::from_digest(*sighash);
+        SECP.verify_ecdsa(&message, &signature, &pubkey).is_ok()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::header_chain::HeaderChain;
+    use crate::pkh::Pkh;
     use crate::priv_key::PrivKey;
     use crate::script::Script;
 
+    #[test]
+    fn test_get_coinbase_domain() {
+        let pkh = Pkh::from_pub_key_buffer(vec![1; 33]);
+        let domain = |domain_str: &str| {
+            HeaderChain::new()
+                .get_next_coinbase_tx(&pkh, &domain_str.to_string())
+                .get_coinbase_domain()
+                .map(|domain| domain.to_strict_str())
+        };
+        assert_eq!(domain("earthbucks.com"), Some("earthbucks.com".to_string()));
+        assert_eq!(
+            domain(" earthbucks.com"),
+            Some("earthbucks.com".to_string())
+        );
+        // the coinbase rule is the baseline one, which is not is_valid_domain
+        assert_eq!(domain("mine.test"), Some("mine.test".to_string()));
+        assert_eq!(domain("my-mine.com"), None);
+        assert_eq!(domain("xn--bcher-kva.com"), None);
+        assert_eq!(domain("earthbucks"), None);
+    }
+
     #[test]
     fn test_tx() -> Result<(), String> {
         let input_tx_id = [0; 32];
@@ -383,6 +446,40 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_json_round_trip() {
+        let script = Script::from_strict_str("DOUBLEBLAKE3 BLAKE3 DOUBLEBLAKE3 EQUAL").unwrap();
+        let tx_input = TxIn::new([1; 32], 2, script.clone(), 3);
+        let tx_output = TxOut::new(u64::MAX, script.clone());
+        let tx = Tx::new(1, vec![tx_input], vec![tx_output], 4);
+
+        let json = serde_json::to_value(&tx).unwrap();
+        let script_json = serde_json::json!({
+            "str": "DOUBLEBLAKE3 BLAKE3 DOUBLEBLAKE3 EQUAL",
+            "hex": script.to_buf().to_strict_hex(),
+        });
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": 1,
+                "inputs": [{
+                    "inputTxId": "01".repeat(32),
+                    "inputTxNOut": 2,
+                    "script": script_json,
+                    "lockRel": 3,
+                }],
+                "outputs": [{
+                    "value": u64::MAX,
+                    "script": script_json,
+                }],
+                "lockAbs": 4,
+            })
+        );
+
+        let tx2: Tx = serde_json::from_value(json).unwrap();
+        assert_eq!(tx2.to_buf(), tx.to_buf());
+    }
+
     #[test]
     fn test_from_buf_reader() -> Result<(), String> {
         let input_tx_id = [0; 32];
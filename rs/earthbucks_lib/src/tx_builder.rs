@@ -3,21 +3,27 @@ use crate::script::Script;
 use crate::tx::Tx;
 use crate::tx_in::TxIn;
 use crate::tx_out::TxOut;
+use crate::tx_out_bn::TxOutBn;
 use crate::tx_out_bn_map::TxOutBnMap;
+use crate::tx_out_bn_store::TxOutBnStore;
 
-pub struct TxBuilder {
-    input_tx_out_bn_map: TxOutBnMap,
+pub struct TxBuilder<'a> {
+    input_tx_out_bn_map: &'a dyn TxOutBnStore, // the wallet's own outputs
     tx: Tx,
     change_script: Script,
     input_amount: u64,
     lock_abs: u32,
 }
 
-impl TxBuilder {
-    pub fn new(input_tx_out_bn_map: &TxOutBnMap, change_script: Script, lock_abs: u32) -> Self {
+impl<'a> TxBuilder<'a> {
+    pub fn new(
+        input_tx_out_bn_map: &'a dyn TxOutBnStore,
+        change_script: Script,
+        lock_abs: u32,
+    ) -> Self {
         Self {
             tx: Tx::new(0, vec![], vec![], 0),
-            input_tx_out_bn_map: input_tx_out_bn_map.clone(),
+            input_tx_out_bn_map,
             change_script,
             input_amount: 0,
             lock_abs,
@@ -48,18 +54,24 @@ impl TxBuilder {
         // this logic means we use the "most confirmed" outputs first, which is
         // what we want, and then we have a deterministic way to sort the UTXOs
         // in the same block.
-        let mut sorted_tx_out_bns: Vec<_> = self.input_tx_out_bn_map.map.iter().collect();
-        sorted_tx_out_bns
-            .sort_by(|a, b| a.1.block_num.cmp(&b.1.block_num).then_with(|| a.0.cmp(b.0)));
-
-        for (tx_out_id, tx_out_bn) in sorted_tx_out_bns {
+        let mut sorted_tx_out_bns: Vec<(String, [u8; 32], u32, TxOutBn)> = Vec::new();
+        self.input_tx_out_bn_map
+            .for_each(&mut |tx_id, tx_out_num, tx_out_bn| {
+                let tx_out_id = TxOutBnMap::name_from_output(tx_id, tx_out_num);
+                sorted_tx_out_bns.push((tx_out_id, *tx_id, tx_out_num, tx_out_bn.clone()));
+            })?;
+        sorted_tx_out_bns.sort_by(|a, b| {
+            a.3.block_num
+                .cmp(&b.3.block_num)
+                .then_with(|| a.0.cmp(&b.0))
+        });
+
+        for (_, tx_id, tx_out_num, tx_out_bn) in sorted_tx_out_bns {
             if input_amount >= total_spend_amount {
                 change_amount = input_amount - total_spend_amount;
                 break;
             }
             let tx_out = &tx_out_bn.tx_out;
-            let tx_id: [u8; 32] = TxOutBnMap::name_to_tx_id(tx_out_id).try_into().unwrap();
-            let tx_out_num = TxOutBnMap::name_to_tx_out_num(tx_out_id);
 
             let input_script: Script = if tx_out.script.is_pkh_output() {
                 Script::from_pkh_input_placeholder()
@@ -95,11 +107,10 @@ mod tests {
     use crate::key_pair::KeyPair;
     use crate::pkh::Pkh;
     use crate::script::Script;
+    use crate::tx_out_bn_overlay::TxOutBnOverlay;
 
-    fn setup() -> TxBuilder {
+    fn setup() -> TxOutBnMap {
         let mut tx_out_bn_map = TxOutBnMap::new();
-        let change_script = Script::from_strict_str("");
-
         for i in 0..5 {
             let key = KeyPair::from_random();
             let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
@@ -109,12 +120,14 @@ mod tests {
             tx_out_bn_map.add(&[0; 32], i, tx_out, block_num);
         }
 
-        TxBuilder::new(&tx_out_bn_map, change_script.unwrap(), 0)
+        tx_out_bn_map
     }
 
     #[test]
     fn test_build_valid_tx_when_input_is_enough_to_cover_output() {
-        let mut tx_builder = setup();
+        let tx_out_bn_map = setup();
+        let change_script = Script::from_empty();
+        let mut tx_builder = TxBuilder::new(&tx_out_bn_map, change_script, 0);
         let tx_out = TxOut::new(50, Script::from_empty());
         tx_builder.add_output(tx_out);
 
@@ -127,7 +140,9 @@ mod tests {
 
     #[test]
     fn test_build_invalid_tx_when_input_is_insufficient_to_cover_output() {
-        let mut tx_builder = setup();
+        let tx_out_bn_map = setup();
+        let change_script = Script::from_empty();
+        let mut tx_builder = TxBuilder::new(&tx_out_bn_map, change_script, 0);
         let tx_out = TxOut::new(10000, Script::from_empty());
         tx_builder.add_output(tx_out);
 
@@ -138,4 +153,21 @@ mod tests {
         assert_eq!(tx_builder.input_amount, 500);
         assert_eq!(tx.outputs[0].value, 10000);
     }
+
+    #[test]
+    fn test_build_from_store() {
+        let tx_out_bn_map = setup();
+        // outputs 0 and 1 are spent in a pending block
+        let mut overlay = TxOutBnOverlay::new(&tx_out_bn_map);
+        overlay.remove(&[0; 32], 0).unwrap();
+        overlay.remove(&[0; 32], 1).unwrap();
+        let mut tx_builder = TxBuilder::new(&overlay, Script::from_empty(), 0);
+        tx_builder.add_output(TxOut::new(10000, Script::from_empty()));
+
+        let tx = tx_builder.build().unwrap();
+
+        assert_eq!(tx.inputs.len(), 3);
+        assert_eq!(tx.inputs[0].input_tx_out_num, 2);
+        assert_eq!(tx_builder.input_amount, 300);
+    }
 }
@@ -3,11 +3,15 @@ use crate::buf_writer::BufWriter;
 use crate::error::EbxError;
 use crate::script::Script;
 use crate::var_int::VarInt;
+use serde::{Deserialize, Serialize};
 
 // add clone support
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TxIn {
+    #[serde(with = "crate::buf::hex_fixed")]
     pub input_tx_id: [u8; 32],
+    #[serde(rename = "inputTxNOut")]
     pub input_tx_out_num: u32,
     pub script: Script,
     pub lock_rel: u32,
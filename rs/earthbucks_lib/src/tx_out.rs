@@ -3,8 +3,9 @@ use crate::buf_writer::BufWriter;
 use crate::error::EbxError;
 use crate::script::Script;
 use crate::var_int::VarInt;
+use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Debug, Clone, Default)]
+#[derive(PartialEq, Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TxOut {
     pub value: u64,
     pub script: Script,
@@ -1,3 +1,6 @@
+use crate::buf_reader::BufReader;
+use crate::buf_writer::BufWriter;
+use crate::error::EbxError;
 use crate::tx_out::TxOut;
 
 #[derive(Debug, Clone, PartialEq, Default)]
@@ -5,3 +8,41 @@ pub struct TxOutBn {
     pub tx_out: TxOut,
     pub block_num: u32,
 }
+
+impl TxOutBn {
+    pub fn new(tx_out: TxOut, block_num: u32) -> Self {
+        Self { tx_out, block_num }
+    }
+
+    pub fn from_buf_reader(reader: &mut BufReader) -> Result<Self, EbxError> {
+        let block_num = reader.read_u32_be()?;
+        let tx_out = TxOut::from_buf_reader(reader)?;
+        Ok(Self::new(tx_out, block_num))
+    }
+
+    pub fn from_buf(buf: Vec<u8>) -> Result<Self, EbxError> {
+        let mut reader = BufReader::new(buf);
+        Self::from_buf_reader(&mut reader)
+    }
+
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut writer = BufWriter::new();
+        writer.write_u32_be(self.block_num);
+        writer.write(self.tx_out.to_buf());
+        writer.to_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+
+    #[test]
+    fn test_to_buf_and_from_buf() {
+        let script = Script::from_strict_str("DOUBLEBLAKE3 BLAKE3 DOUBLEBLAKE3 EQUAL").unwrap();
+        let tx_out_bn1 = TxOutBn::new(TxOut::new(100, script), 12);
+        let tx_out_bn2 = TxOutBn::from_buf(tx_out_bn1.to_buf()).unwrap();
+        assert_eq!(tx_out_bn1, tx_out_bn2);
+    }
+}
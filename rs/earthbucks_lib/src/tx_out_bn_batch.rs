@@ -0,0 +1,146 @@
+use crate::buf_reader::BufReader;
+use crate::buf_writer::BufWriter;
+use crate::error::EbxError;
+use crate::tx::Tx;
+use crate::tx_out::TxOut;
+use crate::tx_out_bn::TxOutBn;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxOutBnOp {
+    Add {
+        tx_id: [u8; 32],
+        tx_out_num: u32,
+        tx_out_bn: TxOutBn,
+    },
+    Remove {
+        tx_id: [u8; 32],
+        tx_out_num: u32,
+    },
+}
+
+// a list of changes to a utxo set that is applied all at once, e.g. all the
+// changes made by one block. applying a batch to a TxOutBnStore returns the
+// batch that undoes it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TxOutBnBatch {
+    pub ops: Vec<TxOutBnOp>,
+}
+
+impl TxOutBnBatch {
+    const OP_ADD: u8 = 0;
+    const OP_REMOVE: u8 = 1;
+
+    pub fn new() -> Self {
+        Self { ops: Vec::new() }
+    }
+
+    pub fn add(&mut self, tx_id: &[u8; 32], tx_out_num: u32, tx_out: TxOut, block_num: u32) {
+        self.ops.push(TxOutBnOp::Add {
+            tx_id: *tx_id,
+            tx_out_num,
+            tx_out_bn: TxOutBn::new(tx_out, block_num),
+        });
+    }
+
+    pub fn remove(&mut self, tx_id: &[u8; 32], tx_out_num: u32) {
+        self.ops.push(TxOutBnOp::Remove {
+            tx_id: *tx_id,
+            tx_out_num,
+        });
+    }
+
+    pub fn add_tx_outputs(&mut self, tx: &Tx, block_num: u32) {
+        let tx_id = tx.id();
+        for (output_index, output) in tx.outputs.iter().enumerate() {
+            self.add(&tx_id, output_index as u32, output.clone(), block_num);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut writer = BufWriter::new();
+        writer.write_var_int(self.ops.len() as u64);
+        for op in &self.ops {
+            match op {
+                TxOutBnOp::Add {
+                    tx_id,
+                    tx_out_num,
+                    tx_out_bn,
+                } => {
+                    writer.write_u8(TxOutBnBatch::OP_ADD);
+                    writer.write(tx_id.to_vec());
+                    writer.write_u32_be(*tx_out_num);
+                    writer.write(tx_out_bn.to_buf());
+                }
+                TxOutBnOp::Remove { tx_id, tx_out_num } => {
+                    writer.write_u8(TxOutBnBatch::OP_REMOVE);
+                    writer.write(tx_id.to_vec());
+                    writer.write_u32_be(*tx_out_num);
+                }
+            }
+        }
+        writer.to_buf()
+    }
+
+    pub fn from_buf_reader(reader: &mut BufReader) -> Result<Self, EbxError> {
+        let len = reader.read_var_int()? as usize;
+        let mut ops = Vec::new();
+        for _ in 0..len {
+            let op_type = reader.read_u8()?;
+            let tx_id: [u8; 32] = reader.read(32)?.try_into().unwrap();
+            let tx_out_num = reader.read_u32_be()?;
+            match op_type {
+                TxOutBnBatch::OP_ADD => {
+                    let tx_out_bn = TxOutBn::from_buf_reader(reader)?;
+                    ops.push(TxOutBnOp::Add {
+                        tx_id,
+                        tx_out_num,
+                        tx_out_bn,
+                    });
+                }
+                TxOutBnBatch::OP_REMOVE => {
+                    ops.push(TxOutBnOp::Remove { tx_id, tx_out_num });
+                }
+                _ => return Err(EbxError::InvalidEncodingError { source: None }),
+            }
+        }
+        Ok(Self { ops })
+    }
+
+    pub fn from_buf(buf: Vec<u8>) -> Result<Self, EbxError> {
+        let mut reader = BufReader::new(buf);
+        Self::from_buf_reader(&mut reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+
+    #[test]
+    fn test_to_buf_and_from_buf() {
+        let mut batch1 = TxOutBnBatch::new();
+        batch1.add(&[1; 32], 0, TxOut::new(100, Script::from_empty()), 5);
+        batch1.remove(&[2; 32], 3);
+        let batch2 = TxOutBnBatch::from_buf(batch1.to_buf()).unwrap();
+        assert_eq!(batch1, batch2);
+        assert_eq!(batch2.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_op() {
+        let mut batch = TxOutBnBatch::new();
+        batch.remove(&[2; 32], 3);
+        let mut buf = batch.to_buf();
+        buf[1] = 7;
+        assert!(TxOutBnBatch::from_buf(buf).is_err());
+    }
+}
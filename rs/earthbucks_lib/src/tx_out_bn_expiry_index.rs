@@ -0,0 +1,348 @@
+use crate::error::EbxError;
+use crate::header_chain::HeaderChain;
+use crate::script::Script;
+use crate::tx_out_bn::TxOutBn;
+use crate::tx_out_bn_file_store::TxOutBnFileStore;
+use crate::tx_out_bn_map::TxOutBnMap;
+use crate::tx_out_bn_store::TxOutBnStore;
+use crate::utxo_delta::UtxoDelta;
+use std::collections::{BTreeMap, BTreeSet};
+
+// index of the outputs in a utxo set that expire, keyed by the block number
+// they were created in. plain pkh outputs never expire and are not indexed.
+//
+// an expired output can be spent by anyone, and an expired pkhxr output can
+// first be recovered by the holder of the recovery key. once an output is
+// older than the safety period nobody has any reason to wait for it any more,
+// and it is pruned from the utxo set.
+#[derive(Debug, Clone, Default)]
+pub struct TxOutBnExpiryIndex {
+    pub by_block_num: BTreeMap<u32, BTreeSet<([u8; 32], u32)>>,
+}
+
+impl TxOutBnExpiryIndex {
+    const EXPIRY_LOCK_RELS: [u32; 4] = [
+        Script::PKHX_90D_LOCK_REL,
+        Script::PKHXR_90D_60D_X_LOCK_REL,
+        Script::PKHX_1H_LOCK_REL,
+        Script::PKHXR_1H_40M_X_LOCK_REL,
+    ];
+    const RECOVERY_LOCK_RELS: [u32; 2] = [
+        Script::PKHXR_90D_60D_R_LOCK_REL,
+        Script::PKHXR_1H_40M_R_LOCK_REL,
+    ];
+
+    pub fn new() -> Self {
+        Self {
+            by_block_num: BTreeMap::new(),
+        }
+    }
+
+    pub fn from_map(tx_out_bn_map: &TxOutBnMap) -> Self {
+        let mut index = Self::new();
+        for (tx_id, tx_out_num, tx_out_bn) in tx_out_bn_map.entries() {
+            index.add(&tx_id, tx_out_num, tx_out_bn);
+        }
+        index
+    }
+
+    pub fn from_file_store(tx_out_bn_file_store: &TxOutBnFileStore) -> Result<Self, EbxError> {
+        let mut index = Self::new();
+        tx_out_bn_file_store.for_each(&mut |tx_id, tx_out_num, tx_out_bn| {
+            index.add(tx_id, tx_out_num, tx_out_bn);
+        })?;
+        Ok(index)
+    }
+
+    pub fn is_expiring(tx_out_bn: &TxOutBn) -> bool {
+        let script = &tx_out_bn.tx_out.script;
+        script.is_pkhx_90d_output()
+            || script.is_pkhxr_90d_60d_output()
+            || script.is_pkhx_1h_output()
+            || script.is_pkhxr_1h_40m_output()
+    }
+
+    pub fn is_expired(tx_out_bn: &TxOutBn, block_num: u32) -> bool {
+        let script = &tx_out_bn.tx_out.script;
+        let prev_block_num = tx_out_bn.block_num;
+        if script.is_pkhx_90d_output() {
+            Script::is_pkhx_90d_expired(block_num, prev_block_num)
+        } else if script.is_pkhxr_90d_60d_output() {
+            Script::is_pkhxr_90d_60d_expired(block_num, prev_block_num)
+        } else if script.is_pkhx_1h_output() {
+            Script::is_pkhx_1h_expired(block_num, prev_block_num)
+        } else if script.is_pkhxr_1h_40m_output() {
+            Script::is_pkhxr_1h_40m_expired(block_num, prev_block_num)
+        } else {
+            false
+        }
+    }
+
+    pub fn is_recoverable(tx_out_bn: &TxOutBn, block_num: u32) -> bool {
+        let script = &tx_out_bn.tx_out.script;
+        let prev_block_num = tx_out_bn.block_num;
+        if script.is_pkhxr_90d_60d_output() {
+            Script::is_pkhxr_90d_60d_recoverable(block_num, prev_block_num)
+        } else if script.is_pkhxr_1h_40m_output() {
+            Script::is_pkhxr_1h_40m_recoverable(block_num, prev_block_num)
+        } else {
+            false
+        }
+    }
+
+    pub fn is_prunable(tx_out_bn: &TxOutBn, block_num: u32) -> bool {
+        TxOutBnExpiryIndex::is_expiring(tx_out_bn)
+            && block_num >= tx_out_bn.block_num + HeaderChain::LENGTH_SAFETY_PERIOD
+    }
+
+    pub fn add(&mut self, tx_id: &[u8; 32], tx_out_num: u32, tx_out_bn: &TxOutBn) {
+        if !TxOutBnExpiryIndex::is_expiring(tx_out_bn) {
+            return;
+        }
+        self.by_block_num
+            .entry(tx_out_bn.block_num)
+            .or_default()
+            .insert((*tx_id, tx_out_num));
+    }
+
+    pub fn remove(&mut self, tx_id: &[u8; 32], tx_out_num: u32, block_num: u32) {
+        if let Some(outputs) = self.by_block_num.get_mut(&block_num) {
+            outputs.remove(&(*tx_id, tx_out_num));
+            if outputs.is_empty() {
+                self.by_block_num.remove(&block_num);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_block_num
+            .values()
+            .map(|outputs| outputs.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_block_num.is_empty()
+    }
+
+    // keep the index in step with a block that was connected to the tip. to
+    // disconnect the block, apply the inverted delta.
+    pub fn apply_delta(&mut self, utxo_delta: &UtxoDelta) {
+        for (tx_id, tx_out_num, tx_out_bn) in utxo_delta.spent.entries() {
+            self.remove(&tx_id, tx_out_num, tx_out_bn.block_num);
+        }
+        for (tx_id, tx_out_num, tx_out_bn) in utxo_delta.created.entries() {
+            self.add(&tx_id, tx_out_num, tx_out_bn);
+        }
+    }
+
+    // outputs in the set that can no longer be spent by their owner at
+    // block_num but could at the block before
+    pub fn get_expired_at(
+        &self,
+        tx_out_bn_store: &dyn TxOutBnStore,
+        block_num: u32,
+    ) -> Result<Vec<([u8; 32], u32)>, EbxError> {
+        self.get_changed_at(
+            tx_out_bn_store,
+            block_num,
+            &TxOutBnExpiryIndex::EXPIRY_LOCK_RELS,
+            TxOutBnExpiryIndex::is_expired,
+        )
+    }
+
+    // outputs in the set that can be recovered with the recovery key from
+    // block_num on but not at the block before
+    pub fn get_recoverable_at(
+        &self,
+        tx_out_bn_store: &dyn TxOutBnStore,
+        block_num: u32,
+    ) -> Result<Vec<([u8; 32], u32)>, EbxError> {
+        self.get_changed_at(
+            tx_out_bn_store,
+            block_num,
+            &TxOutBnExpiryIndex::RECOVERY_LOCK_RELS,
+            TxOutBnExpiryIndex::is_recoverable,
+        )
+    }
+
+    fn get_changed_at(
+        &self,
+        tx_out_bn_store: &dyn TxOutBnStore,
+        block_num: u32,
+        lock_rels: &[u32],
+        is_changed: fn(&TxOutBn, u32) -> bool,
+    ) -> Result<Vec<([u8; 32], u32)>, EbxError> {
+        // only outputs created exactly one lock period ago can change state
+        let prev_block_nums: BTreeSet<u32> = lock_rels
+            .iter()
+            .filter_map(|lock_rel| block_num.checked_sub(*lock_rel))
+            .collect();
+        let mut outputs = Vec::new();
+        for prev_block_num in prev_block_nums {
+            let candidates = match self.by_block_num.get(&prev_block_num) {
+                Some(candidates) => candidates,
+                None => continue,
+            };
+            for (tx_id, tx_out_num) in candidates {
+                let tx_out_bn = match tx_out_bn_store.get(tx_id, *tx_out_num)? {
+                    Some(tx_out_bn) => tx_out_bn,
+                    None => continue,
+                };
+                if is_changed(&tx_out_bn, block_num)
+                    && (block_num == 0 || !is_changed(&tx_out_bn, block_num - 1))
+                {
+                    outputs.push((*tx_id, *tx_out_num));
+                }
+            }
+        }
+        Ok(outputs)
+    }
+
+    pub fn get_prunable(&self, block_num: u32) -> Vec<([u8; 32], u32)> {
+        let max_block_num = match block_num.checked_sub(HeaderChain::LENGTH_SAFETY_PERIOD) {
+            Some(max_block_num) => max_block_num,
+            None => return Vec::new(),
+        };
+        self.by_block_num
+            .range(..=max_block_num)
+            .flat_map(|(_, outputs)| outputs.iter().copied())
+            .collect()
+    }
+
+    // remove every output that is past the safety period at block_num from
+    // the utxo set and from the index. the returned delta can be rolled back
+    // to restore them.
+    pub fn prune(
+        &mut self,
+        tx_out_bn_store: &mut dyn TxOutBnStore,
+        block_num: u32,
+    ) -> Result<UtxoDelta, EbxError> {
+        let mut utxo_delta = UtxoDelta::new(block_num);
+        for (tx_id, tx_out_num) in self.get_prunable(block_num) {
+            if let Some(tx_out_bn) = tx_out_bn_store.get(&tx_id, tx_out_num)? {
+                utxo_delta.spend(&tx_id, tx_out_num, &tx_out_bn);
+            }
+        }
+        utxo_delta.apply(tx_out_bn_store)?;
+        self.apply_delta(&utxo_delta);
+        Ok(utxo_delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx_out::TxOut;
+
+    fn setup() -> TxOutBnMap {
+        let pkh = [1; 32];
+        let rpkh = [2; 32];
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        tx_out_bn_map.add(
+            &[0; 32],
+            0,
+            TxOut::new(100, Script::from_pkh_output(&pkh)),
+            0,
+        );
+        tx_out_bn_map.add(
+            &[0; 32],
+            1,
+            TxOut::new(100, Script::from_pkhx_90d_output(&pkh)),
+            0,
+        );
+        tx_out_bn_map.add(
+            &[0; 32],
+            2,
+            TxOut::new(100, Script::from_pkhxr_90d_60d_output(&pkh, &rpkh)),
+            0,
+        );
+        tx_out_bn_map.add(
+            &[0; 32],
+            3,
+            TxOut::new(100, Script::from_pkhx_1h_output(&pkh)),
+            10,
+        );
+        tx_out_bn_map.add(
+            &[0; 32],
+            4,
+            TxOut::new(100, Script::from_pkhxr_1h_40m_output(&pkh, &rpkh)),
+            10,
+        );
+        tx_out_bn_map
+    }
+
+    #[test]
+    fn test_from_map() {
+        let tx_out_bn_map = setup();
+        let index = TxOutBnExpiryIndex::from_map(&tx_out_bn_map);
+        // the plain pkh output is not indexed
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.by_block_num[&0].len(), 2);
+        assert_eq!(index.by_block_num[&10].len(), 2);
+    }
+
+    #[test]
+    fn test_get_expired_at() {
+        let tx_out_bn_map = setup();
+        let index = TxOutBnExpiryIndex::from_map(&tx_out_bn_map);
+        assert!(index
+            .get_expired_at(&tx_out_bn_map, Script::PKHX_90D_LOCK_REL - 1)
+            .unwrap()
+            .is_empty());
+        let mut expired = index
+            .get_expired_at(&tx_out_bn_map, Script::PKHX_90D_LOCK_REL)
+            .unwrap();
+        expired.sort();
+        assert_eq!(expired, vec![([0; 32], 1), ([0; 32], 2)]);
+        assert!(index
+            .get_expired_at(&tx_out_bn_map, Script::PKHX_90D_LOCK_REL + 1)
+            .unwrap()
+            .is_empty());
+
+        let mut expired = index
+            .get_expired_at(&tx_out_bn_map, 10 + Script::PKHX_1H_LOCK_REL)
+            .unwrap();
+        expired.sort();
+        assert_eq!(expired, vec![([0; 32], 3), ([0; 32], 4)]);
+    }
+
+    #[test]
+    fn test_get_recoverable_at() {
+        let tx_out_bn_map = setup();
+        let index = TxOutBnExpiryIndex::from_map(&tx_out_bn_map);
+        let recoverable = index
+            .get_recoverable_at(&tx_out_bn_map, Script::PKHXR_90D_60D_R_LOCK_REL)
+            .unwrap();
+        assert_eq!(recoverable, vec![([0; 32], 2)]);
+        let recoverable = index
+            .get_recoverable_at(&tx_out_bn_map, 10 + Script::PKHXR_1H_40M_R_LOCK_REL)
+            .unwrap();
+        assert_eq!(recoverable, vec![([0; 32], 4)]);
+    }
+
+    #[test]
+    fn test_prune_and_rollback() {
+        let mut tx_out_bn_map = setup();
+        let before = tx_out_bn_map.clone();
+        let mut index = TxOutBnExpiryIndex::from_map(&tx_out_bn_map);
+
+        let utxo_delta = index
+            .prune(&mut tx_out_bn_map, HeaderChain::LENGTH_SAFETY_PERIOD - 1)
+            .unwrap();
+        assert!(utxo_delta.spent.map.is_empty());
+
+        let utxo_delta = index
+            .prune(&mut tx_out_bn_map, HeaderChain::LENGTH_SAFETY_PERIOD)
+            .unwrap();
+        assert_eq!(utxo_delta.spent.map.len(), 2);
+        assert_eq!(tx_out_bn_map.map.len(), 3);
+        assert!(tx_out_bn_map.get(&[0; 32], 0).is_some());
+        assert_eq!(index.len(), 2);
+
+        utxo_delta.rollback(&mut tx_out_bn_map).unwrap();
+        index.apply_delta(&utxo_delta.invert());
+        assert_eq!(tx_out_bn_map.map, before.map);
+        assert_eq!(index.len(), 4);
+    }
+}
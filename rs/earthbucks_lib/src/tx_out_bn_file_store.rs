@@ -0,0 +1,977 @@
+use crate::buf_reader::BufReader;
+use crate::buf_writer::BufWriter;
+use crate::error::EbxError;
+use crate::hash::blake3_hash;
+use crate::tx_out_bn::TxOutBn;
+use crate::tx_out_bn_batch::{TxOutBnBatch, TxOutBnOp};
+use crate::tx_out_bn_store::TxOutBnStore;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// a utxo set on disk, made of an append-only log and an index into it. only
+// the index header is kept in memory, so the set can be much larger than ram.
+//
+// the log is a sequence of records:
+//
+//   payload length (u32) | blake3 hash of payload (32 bytes) | payload
+//
+// where the payload is a record type (u8) followed by a TxOutBnBatch. a batch
+// is written and synced before the index is touched, so it is either fully on
+// disk or not at all. a crash during a write leaves a short or corrupt record
+// at the end of the log, which is dropped the next time the log is opened.
+//
+// the index lives next to the log with a .idx suffix. it is an open
+// addressing hash table of fixed size slots:
+//
+//   state (u8) | tx_id (32 bytes) | tx_out_num (u32) | offset (u64) | len (u32)
+//
+// where offset and len locate the TxOutBn inside a record in the log. the
+// index header records how much of the log the slots cover. it is marked
+// dirty while slots are being written, and a dirty or missing index is built
+// again from the log on open.
+pub struct TxOutBnFileStore {
+    path: PathBuf,
+    log_file: Mutex<File>,
+    log_len: u64,
+    index: TxOutBnIndex,
+    poisoned: bool,
+}
+
+struct TxOutBnIndex {
+    path: PathBuf,
+    file: Mutex<File>,
+    n_slots: u64,
+    n_live: u64,
+    n_used: u64, // live slots plus tombstones
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TxOutBnSlot {
+    state: u8,
+    key: [u8; 36],
+    offset: u64,
+    len: u32,
+}
+
+impl TxOutBnFileStore {
+    const RECORD_BATCH: u8 = 0;
+    const RECORD_HEADER_SIZE: usize = 4 + 32;
+    const COMPACT_BATCH_SIZE: usize = 10_000;
+
+    pub fn key_from_output(tx_id: &[u8; 32], tx_out_num: u32) -> [u8; 36] {
+        let mut key = [0u8; 36];
+        key[..32].copy_from_slice(tx_id);
+        key[32..].copy_from_slice(&tx_out_num.to_be_bytes());
+        key
+    }
+
+    pub fn key_to_output(key: &[u8; 36]) -> ([u8; 32], u32) {
+        let tx_id: [u8; 32] = key[..32].try_into().unwrap();
+        let tx_out_num = u32::from_be_bytes(key[32..].try_into().unwrap());
+        (tx_id, tx_out_num)
+    }
+
+    pub fn open(path: &Path) -> Result<Self, EbxError> {
+        let log_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| EbxError::io("unable to open utxo file", &e))?;
+        let file_len = log_file
+            .metadata()
+            .map_err(|e| EbxError::io("unable to read utxo file", &e))?
+            .len();
+
+        let index_path = TxOutBnFileStore::suffixed_path(path, ".idx");
+        let (index, log_len) = match TxOutBnIndex::open(&index_path)? {
+            Some((index, log_len)) if log_len <= file_len => (index, log_len),
+            // the index is missing, dirty or ahead of the log
+            _ => (TxOutBnIndex::create(&index_path, 0)?, 0),
+        };
+        let mut store = Self {
+            path: path.to_path_buf(),
+            log_file: Mutex::new(log_file),
+            log_len,
+            index,
+            poisoned: false,
+        };
+        store.replay(file_len)?;
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+        let mut path = path.to_path_buf().into_os_string();
+        path.push(suffix);
+        PathBuf::from(path)
+    }
+
+    // bring the index up to date with the records after log_len, then cut
+    // off the torn tail left by a crash, if there is one
+    fn replay(&mut self, file_len: u64) -> Result<(), EbxError> {
+        self.index.write_header(TxOutBnIndex::DIRTY)?;
+        while let Some((batch, record_len)) = self.read_record(self.log_len, file_len)? {
+            self.index_batch(&batch, self.log_len)?;
+            self.log_len += record_len;
+        }
+        if self.log_len < file_len {
+            let file = self.log_file.get_mut().unwrap();
+            file.set_len(self.log_len)
+                .map_err(|e| EbxError::io("unable to truncate utxo file", &e))?;
+            file.sync_all()
+                .map_err(|e| EbxError::io("unable to sync utxo file", &e))?;
+        }
+        self.index.write_header(self.log_len)
+    }
+
+    // the batch in the record at offset and the length of the record, or none
+    // if the record is short or corrupt
+    fn read_record(
+        &mut self,
+        offset: u64,
+        file_len: u64,
+    ) -> Result<Option<(TxOutBnBatch, u64)>, EbxError> {
+        let header_end = offset + TxOutBnFileStore::RECORD_HEADER_SIZE as u64;
+        if file_len < header_end {
+            return Ok(None);
+        }
+        let file = self.log_file.get_mut().unwrap();
+        let mut header = [0u8; TxOutBnFileStore::RECORD_HEADER_SIZE];
+        read_at(file, offset, &mut header)
+            .map_err(|e| EbxError::io("unable to read utxo file", &e))?;
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+        if file_len < header_end + len {
+            return Ok(None);
+        }
+        let mut payload = vec![0u8; len as usize];
+        read_at(file, header_end, &mut payload)
+            .map_err(|e| EbxError::io("unable to read utxo file", &e))?;
+        if blake3_hash(&payload) != header[4..] {
+            return Ok(None);
+        }
+        let mut reader = BufReader::new(payload);
+        if reader.read_u8().ok() != Some(TxOutBnFileStore::RECORD_BATCH) {
+            return Ok(None);
+        }
+        match TxOutBnBatch::from_buf_reader(&mut reader) {
+            Ok(batch) => Ok(Some((batch, header_end - offset + len))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn encode_record(batch: &TxOutBnBatch) -> Vec<u8> {
+        let mut payload = BufWriter::new();
+        payload.write_u8(TxOutBnFileStore::RECORD_BATCH);
+        payload.write(batch.to_buf());
+        let payload = payload.to_buf();
+        let mut writer = BufWriter::new();
+        writer.write_u32_be(payload.len() as u32);
+        writer.write(blake3_hash(&payload).to_vec());
+        writer.write(payload);
+        writer.to_buf()
+    }
+
+    // where each added TxOutBn sits in the record of a batch, counted from
+    // the start of the record. this follows the layout of TxOutBnBatch::to_buf.
+    fn value_locations(batch: &TxOutBnBatch) -> Vec<Option<(u64, u32)>> {
+        let mut var_int = BufWriter::new();
+        var_int.write_var_int(batch.ops.len() as u64);
+        let mut offset = (TxOutBnFileStore::RECORD_HEADER_SIZE + 1 + var_int.to_buf().len()) as u64;
+        let mut locations = Vec::new();
+        for op in &batch.ops {
+            offset += 1 + 32 + 4;
+            match op {
+                TxOutBnOp::Add { tx_out_bn, .. } => {
+                    let len = tx_out_bn.to_buf().len() as u64;
+                    locations.push(Some((offset, len as u32)));
+                    offset += len;
+                }
+                TxOutBnOp::Remove { .. } => locations.push(None),
+            }
+        }
+        locations
+    }
+
+    fn index_batch(&mut self, batch: &TxOutBnBatch, record_offset: u64) -> Result<(), EbxError> {
+        let n_adds = batch
+            .ops
+            .iter()
+            .filter(|op| matches!(op, TxOutBnOp::Add { .. }))
+            .count() as u64;
+        self.index.reserve(n_adds)?;
+        let locations = TxOutBnFileStore::value_locations(batch);
+        for (op, location) in batch.ops.iter().zip(locations) {
+            match (op, location) {
+                (
+                    TxOutBnOp::Add {
+                        tx_id, tx_out_num, ..
+                    },
+                    Some((offset, len)),
+                ) => {
+                    let key = TxOutBnFileStore::key_from_output(tx_id, *tx_out_num);
+                    self.index.insert(&key, record_offset + offset, len)?;
+                }
+                (TxOutBnOp::Remove { tx_id, tx_out_num }, _) => {
+                    let key = TxOutBnFileStore::key_from_output(tx_id, *tx_out_num);
+                    self.index.remove(&key)?;
+                }
+                _ => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_value(&self, slot: &TxOutBnSlot) -> Result<TxOutBn, EbxError> {
+        let mut buf = vec![0u8; slot.len as usize];
+        let mut file = self.log_file.lock().unwrap();
+        read_at(&mut file, slot.offset, &mut buf)
+            .map_err(|e| EbxError::io("unable to read utxo file", &e))?;
+        TxOutBn::from_buf(buf)
+    }
+
+    // a write error leaves part of a record at the end of the log. it is cut
+    // off so that later records are not lost behind it on the next open, and
+    // if that fails too the store refuses any more writes.
+    fn append_record(&mut self, record: &[u8]) -> Result<(), EbxError> {
+        self.check_poisoned()?;
+        let log_len = self.log_len;
+        let file = self.log_file.get_mut().unwrap();
+        let result = write_at(file, log_len, record).and_then(|_| file.sync_data());
+        if let Err(e) = result {
+            if file
+                .set_len(log_len)
+                .and_then(|_| file.sync_data())
+                .is_err()
+            {
+                self.poisoned = true;
+            }
+            return Err(EbxError::io("unable to write utxo file", &e));
+        }
+        self.log_len += record.len() as u64;
+        Ok(())
+    }
+
+    fn check_poisoned(&self) -> Result<(), EbxError> {
+        if self.poisoned {
+            return Err(EbxError::generic(
+                "utxo file failed an earlier write and must be reopened",
+            ));
+        }
+        Ok(())
+    }
+
+    fn try_for_each<F>(&self, mut f: F) -> Result<(), EbxError>
+    where
+        F: FnMut(&[u8; 32], u32, &TxOutBn) -> Result<(), EbxError>,
+    {
+        self.check_poisoned()?;
+        self.index.for_each_live(|slot| {
+            let (tx_id, tx_out_num) = TxOutBnFileStore::key_to_output(&slot.key);
+            f(&tx_id, tx_out_num, &self.read_value(slot)?)
+        })
+    }
+
+    // write a log holding only the outputs that are in the set, one record
+    // per COMPACT_BATCH_SIZE outputs
+    fn write_compacted(&self, path: &Path) -> Result<(), EbxError> {
+        let mut file =
+            File::create(path).map_err(|e| EbxError::io("unable to create utxo file", &e))?;
+        let mut batch = TxOutBnBatch::new();
+        let mut write_batch = |batch: &mut TxOutBnBatch| -> Result<(), EbxError> {
+            file.write_all(&TxOutBnFileStore::encode_record(batch))
+                .map_err(|e| EbxError::io("unable to write utxo file", &e))?;
+            batch.ops.clear();
+            Ok(())
+        };
+        self.try_for_each(|tx_id, tx_out_num, tx_out_bn| {
+            batch.ops.push(TxOutBnOp::Add {
+                tx_id: *tx_id,
+                tx_out_num,
+                tx_out_bn: tx_out_bn.clone(),
+            });
+            if batch.len() < TxOutBnFileStore::COMPACT_BATCH_SIZE {
+                return Ok(());
+            }
+            write_batch(&mut batch)
+        })?;
+        if !batch.is_empty() {
+            write_batch(&mut batch)?;
+        }
+        file.sync_all()
+            .map_err(|e| EbxError::io("unable to sync utxo file", &e))
+    }
+
+    // rename a new log over the current one, so a crash leaves one or the
+    // other intact. the index is marked dirty first and built again
+    // afterwards, so it never points into the wrong log.
+    fn replace_log(&mut self, new_path: &Path) -> Result<(), EbxError> {
+        self.index.write_header(TxOutBnIndex::DIRTY)?;
+        fs::rename(new_path, &self.path)
+            .map_err(|e| EbxError::io("unable to replace utxo file", &e))?;
+        sync_parent_dir(&self.path)?;
+
+        let log_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .map_err(|e| EbxError::io("unable to open utxo file", &e))?;
+        let file_len = log_file
+            .metadata()
+            .map_err(|e| EbxError::io("unable to read utxo file", &e))?
+            .len();
+        self.log_file = Mutex::new(log_file);
+        self.log_len = 0;
+        self.poisoned = false;
+        self.index = TxOutBnIndex::create(&self.index.path, 0)?;
+        self.replay(file_len)
+    }
+
+    // rewrite the log with only the outputs that are in the set
+    pub fn compact(&mut self) -> Result<(), EbxError> {
+        let tmp_path = TxOutBnFileStore::suffixed_path(&self.path, ".tmp");
+        self.write_compacted(&tmp_path)?;
+        self.replace_log(&tmp_path)
+    }
+
+    // write the set as it is now to a file at path, which restore can later
+    // bring back. the set is streamed from disk, not loaded into memory.
+    pub fn snapshot(&self, path: &Path) -> Result<(), EbxError> {
+        self.write_compacted(path)?;
+        sync_parent_dir(path)
+    }
+
+    pub fn restore(&mut self, snapshot_path: &Path) -> Result<(), EbxError> {
+        let tmp_path = TxOutBnFileStore::suffixed_path(&self.path, ".tmp");
+        fs::copy(snapshot_path, &tmp_path)
+            .and_then(|_| File::open(&tmp_path)?.sync_all())
+            .map_err(|e| EbxError::io("unable to copy utxo snapshot", &e))?;
+        self.replace_log(&tmp_path)
+    }
+}
+
+impl TxOutBnStore for TxOutBnFileStore {
+    fn get(&self, tx_id: &[u8; 32], tx_out_num: u32) -> Result<Option<TxOutBn>, EbxError> {
+        self.check_poisoned()?;
+        let key = TxOutBnFileStore::key_from_output(tx_id, tx_out_num);
+        match self.index.find(&key)? {
+            Some(slot) => Ok(Some(self.read_value(&slot)?)),
+            None => Ok(None),
+        }
+    }
+
+    // the outputs are read from disk one at a time
+    fn for_each(&self, f: &mut dyn FnMut(&[u8; 32], u32, &TxOutBn)) -> Result<(), EbxError> {
+        self.try_for_each(|tx_id, tx_out_num, tx_out_bn| {
+            f(tx_id, tx_out_num, tx_out_bn);
+            Ok(())
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.index.n_live as usize
+    }
+
+    fn apply_batch(&mut self, batch: &TxOutBnBatch) -> Result<TxOutBnBatch, EbxError> {
+        // outputs changed earlier in the same batch are looked up here first
+        let mut pending: HashMap<[u8; 36], Option<TxOutBn>> = HashMap::new();
+        let mut undo_ops = Vec::new();
+        for op in &batch.ops {
+            let (tx_id, tx_out_num, next) = match op {
+                TxOutBnOp::Add {
+                    tx_id,
+                    tx_out_num,
+                    tx_out_bn,
+                } => (tx_id, *tx_out_num, Some(tx_out_bn.clone())),
+                TxOutBnOp::Remove { tx_id, tx_out_num } => (tx_id, *tx_out_num, None),
+            };
+            let key = TxOutBnFileStore::key_from_output(tx_id, tx_out_num);
+            let prev = match pending.get(&key) {
+                Some(prev) => prev.clone(),
+                None => self.get(tx_id, tx_out_num)?,
+            };
+            match (prev, next.is_some()) {
+                (Some(prev), _) => undo_ops.push(TxOutBnOp::Add {
+                    tx_id: *tx_id,
+                    tx_out_num,
+                    tx_out_bn: prev,
+                }),
+                (None, true) => undo_ops.push(TxOutBnOp::Remove {
+                    tx_id: *tx_id,
+                    tx_out_num,
+                }),
+                (None, false) => (),
+            }
+            pending.insert(key, next);
+        }
+        undo_ops.reverse();
+
+        let record_offset = self.log_len;
+        self.append_record(&TxOutBnFileStore::encode_record(batch))?;
+        // the batch is now in the log. if the index can not be updated, it is
+        // left dirty and built again from the log when the store is reopened,
+        // and until then the store refuses reads and writes. the record is cut
+        // back off so that an error means the batch was not applied. if even
+        // that fails, the batch is replayed on reopen, so it was applied.
+        let result = self
+            .index
+            .write_header(TxOutBnIndex::DIRTY)
+            .and_then(|_| self.index_batch(batch, record_offset))
+            .and_then(|_| self.index.write_header(self.log_len));
+        if let Err(e) = result {
+            self.poisoned = true;
+            let file = self.log_file.get_mut().unwrap();
+            if file
+                .set_len(record_offset)
+                .and_then(|_| file.sync_data())
+                .is_ok()
+            {
+                self.log_len = record_offset;
+                return Err(e);
+            }
+        }
+        Ok(TxOutBnBatch { ops: undo_ops })
+    }
+}
+
+impl TxOutBnIndex {
+    const MAGIC: [u8; 4] = *b"EBXI";
+    const DIRTY: u64 = u64::MAX;
+    const HEADER_SIZE: u64 = 4 + 8 + 8 + 8 + 8;
+    const SLOT_SIZE: u64 = 1 + 36 + 8 + 4;
+    const MIN_SLOTS: u64 = 64;
+    const SLOT_EMPTY: u8 = 0;
+    const SLOT_LIVE: u8 = 1;
+    const SLOT_TOMBSTONE: u8 = 2;
+
+    // the index and the length of log it covers, or none if it is missing,
+    // dirty or not an index
+    fn open(path: &Path) -> Result<Option<(Self, u64)>, EbxError> {
+        let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(_) => return Ok(None),
+        };
+        let file_len = file
+            .metadata()
+            .map_err(|e| EbxError::io("unable to read utxo index", &e))?
+            .len();
+        if file_len < TxOutBnIndex::HEADER_SIZE {
+            return Ok(None);
+        }
+        let mut header = [0u8; TxOutBnIndex::HEADER_SIZE as usize];
+        read_at(&mut file, 0, &mut header)
+            .map_err(|e| EbxError::io("unable to read utxo index", &e))?;
+        let mut reader = BufReader::new(header.to_vec());
+        let magic = reader.read(4)?;
+        let log_len = reader.read_u64_be()?;
+        let n_slots = reader.read_u64_be()?;
+        let n_live = reader.read_u64_be()?;
+        let n_used = reader.read_u64_be()?;
+        if magic != TxOutBnIndex::MAGIC
+            || log_len == TxOutBnIndex::DIRTY
+            || !n_slots.is_power_of_two()
+            || file_len != TxOutBnIndex::HEADER_SIZE + n_slots * TxOutBnIndex::SLOT_SIZE
+        {
+            return Ok(None);
+        }
+        let index = Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            n_slots,
+            n_live,
+            n_used,
+        };
+        Ok(Some((index, log_len)))
+    }
+
+    // a new index with no outputs, left dirty until the caller writes its
+    // header
+    fn create(path: &Path, n_outputs: u64) -> Result<Self, EbxError> {
+        let n_slots = (n_outputs * 2)
+            .next_power_of_two()
+            .max(TxOutBnIndex::MIN_SLOTS);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| EbxError::io("unable to create utxo index", &e))?;
+        file.set_len(TxOutBnIndex::HEADER_SIZE + n_slots * TxOutBnIndex::SLOT_SIZE)
+            .map_err(|e| EbxError::io("unable to create utxo index", &e))?;
+        let mut index = Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            n_slots,
+            n_live: 0,
+            n_used: 0,
+        };
+        index.write_header(TxOutBnIndex::DIRTY)?;
+        Ok(index)
+    }
+
+    // the slots are synced before a clean header is written, so a clean
+    // header never reaches the disk ahead of the slots it describes
+    fn write_header(&mut self, log_len: u64) -> Result<(), EbxError> {
+        let file = self.file.get_mut().unwrap();
+        if log_len != TxOutBnIndex::DIRTY {
+            file.sync_data()
+                .map_err(|e| EbxError::io("unable to sync utxo index", &e))?;
+        }
+        let mut writer = BufWriter::new();
+        writer.write(TxOutBnIndex::MAGIC.to_vec());
+        writer.write_u64_be(log_len);
+        writer.write_u64_be(self.n_slots);
+        writer.write_u64_be(self.n_live);
+        writer.write_u64_be(self.n_used);
+        write_at(file, 0, &writer.to_buf())
+            .and_then(|_| file.sync_data())
+            .map_err(|e| EbxError::io("unable to write utxo index", &e))
+    }
+
+    fn home_slot(&self, key: &[u8; 36]) -> u64 {
+        let hash = blake3_hash(key);
+        u64::from_be_bytes(hash[..8].try_into().unwrap()) & (self.n_slots - 1)
+    }
+
+    fn slot_offset(n: u64) -> u64 {
+        TxOutBnIndex::HEADER_SIZE + n * TxOutBnIndex::SLOT_SIZE
+    }
+
+    fn read_slot(file: &mut File, n: u64) -> Result<TxOutBnSlot, EbxError> {
+        let mut buf = [0u8; TxOutBnIndex::SLOT_SIZE as usize];
+        read_at(file, TxOutBnIndex::slot_offset(n), &mut buf)
+            .map_err(|e| EbxError::io("unable to read utxo index", &e))?;
+        Ok(TxOutBnSlot::from_bytes(&buf))
+    }
+
+    fn write_slot(&mut self, n: u64, slot: &TxOutBnSlot) -> Result<(), EbxError> {
+        let file = self.file.get_mut().unwrap();
+        write_at(file, TxOutBnIndex::slot_offset(n), &slot.to_bytes())
+            .map_err(|e| EbxError::io("unable to write utxo index", &e))
+    }
+
+    // the live slot for the key and its number, plus the first slot on the
+    // probe path that a new key could go in
+    fn probe(
+        &self,
+        file: &mut File,
+        key: &[u8; 36],
+    ) -> Result<(Option<(u64, TxOutBnSlot)>, u64), EbxError> {
+        let mut n = self.home_slot(key);
+        let mut free = None;
+        loop {
+            let slot = TxOutBnIndex::read_slot(file, n)?;
+            match slot.state {
+                TxOutBnIndex::SLOT_EMPTY => return Ok((None, free.unwrap_or(n))),
+                TxOutBnIndex::SLOT_LIVE if slot.key == *key => {
+                    return Ok((Some((n, slot)), free.unwrap_or(n)))
+                }
+                TxOutBnIndex::SLOT_TOMBSTONE => {
+                    free.get_or_insert(n);
+                }
+                _ => (),
+            }
+            n = (n + 1) & (self.n_slots - 1);
+        }
+    }
+
+    fn find(&self, key: &[u8; 36]) -> Result<Option<TxOutBnSlot>, EbxError> {
+        let mut file = self.file.lock().unwrap();
+        let (live, _) = self.probe(&mut file, key)?;
+        Ok(live.map(|(_, slot)| slot))
+    }
+
+    fn insert(&mut self, key: &[u8; 36], offset: u64, len: u32) -> Result<(), EbxError> {
+        let (live, free) = {
+            let mut file = self.file.lock().unwrap();
+            self.probe(&mut file, key)?
+        };
+        let slot = TxOutBnSlot {
+            state: TxOutBnIndex::SLOT_LIVE,
+            key: *key,
+            offset,
+            len,
+        };
+        match live {
+            Some((n, _)) => self.write_slot(n, &slot),
+            None => {
+                let file = self.file.get_mut().unwrap();
+                if TxOutBnIndex::read_slot(file, free)?.state == TxOutBnIndex::SLOT_EMPTY {
+                    self.n_used += 1;
+                }
+                self.n_live += 1;
+                self.write_slot(free, &slot)
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &[u8; 36]) -> Result<(), EbxError> {
+        let (live, _) = {
+            let mut file = self.file.lock().unwrap();
+            self.probe(&mut file, key)?
+        };
+        if let Some((n, mut slot)) = live {
+            slot.state = TxOutBnIndex::SLOT_TOMBSTONE;
+            self.n_live -= 1;
+            self.write_slot(n, &slot)?;
+        }
+        Ok(())
+    }
+
+    // make room for n_adds more outputs, keeping at least a quarter of the
+    // slots empty so that probes stay short and always end. the table is
+    // copied into a new file, which also clears out tombstones. the new file
+    // is left dirty, as the old one was, until the caller is done.
+    fn reserve(&mut self, n_adds: u64) -> Result<(), EbxError> {
+        if (self.n_used + n_adds) * 4 < self.n_slots * 3 {
+            return Ok(());
+        }
+        let tmp_path = TxOutBnFileStore::suffixed_path(&self.path, ".tmp");
+        let mut grown = TxOutBnIndex::create(&tmp_path, self.n_live + n_adds)?;
+        self.for_each_live(|slot| grown.insert(&slot.key, slot.offset, slot.len))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| EbxError::io("unable to replace utxo index", &e))?;
+        sync_parent_dir(&self.path)?;
+        grown.path = self.path.clone();
+        *self = grown;
+        Ok(())
+    }
+
+    fn for_each_live<F>(&self, mut f: F) -> Result<(), EbxError>
+    where
+        F: FnMut(&TxOutBnSlot) -> Result<(), EbxError>,
+    {
+        const CHUNK_SLOTS: u64 = 1024;
+        let mut buf = Vec::new();
+        let mut n = 0;
+        while n < self.n_slots {
+            let n_chunk = CHUNK_SLOTS.min(self.n_slots - n);
+            buf.resize((n_chunk * TxOutBnIndex::SLOT_SIZE) as usize, 0);
+            {
+                let mut file = self.file.lock().unwrap();
+                read_at(&mut file, TxOutBnIndex::slot_offset(n), &mut buf)
+                    .map_err(|e| EbxError::io("unable to read utxo index", &e))?;
+            }
+            for bytes in buf.chunks(TxOutBnIndex::SLOT_SIZE as usize) {
+                let slot = TxOutBnSlot::from_bytes(bytes);
+                if slot.state == TxOutBnIndex::SLOT_LIVE {
+                    f(&slot)?;
+                }
+            }
+            n += n_chunk;
+        }
+        Ok(())
+    }
+}
+
+impl TxOutBnSlot {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            state: bytes[0],
+            key: bytes[1..37].try_into().unwrap(),
+            offset: u64::from_be_bytes(bytes[37..45].try_into().unwrap()),
+            len: u32::from_be_bytes(bytes[45..49].try_into().unwrap()),
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut writer = BufWriter::new();
+        writer.write_u8(self.state);
+        writer.write(self.key.to_vec());
+        writer.write_u64_be(self.offset);
+        writer.write_u32_be(self.len);
+        writer.to_buf()
+    }
+}
+
+fn read_at(file: &mut File, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+fn write_at(file: &mut File, offset: u64, buf: &[u8]) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buf)
+}
+
+// a rename is only durable once the directory holding it is synced
+fn sync_parent_dir(path: &Path) -> Result<(), EbxError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| EbxError::io("unable to sync utxo directory", &e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buf::EbxBuf;
+    use crate::script::Script;
+    use crate::tx_out::TxOut;
+    use crate::tx_out_bn_map::TxOutBnMap;
+    use rand::Rng;
+
+    fn temp_path() -> PathBuf {
+        let mut rng = rand::thread_rng();
+        let name: [u8; 8] = rng.gen();
+        std::env::temp_dir().join(format!("ebx-utxo-{}.dat", name.to_strict_hex()))
+    }
+
+    fn remove_files(path: &Path) {
+        fs::remove_file(path).unwrap();
+        fs::remove_file(TxOutBnFileStore::suffixed_path(path, ".idx")).unwrap();
+    }
+
+    fn batch_for_block(block_num: u32) -> TxOutBnBatch {
+        let mut batch = TxOutBnBatch::new();
+        let tx_id = [block_num as u8; 32];
+        batch.add(&tx_id, 0, TxOut::new(100, Script::from_empty()), block_num);
+        batch.add(&tx_id, 1, TxOut::new(200, Script::from_empty()), block_num);
+        if block_num > 0 {
+            batch.remove(&[block_num as u8 - 1; 32], 0);
+        }
+        batch
+    }
+
+    fn to_map(store: &TxOutBnFileStore) -> TxOutBnMap {
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        store
+            .for_each(&mut |tx_id, tx_out_num, tx_out_bn| {
+                tx_out_bn_map.add(
+                    tx_id,
+                    tx_out_num,
+                    tx_out_bn.tx_out.clone(),
+                    tx_out_bn.block_num,
+                );
+            })
+            .unwrap();
+        tx_out_bn_map
+    }
+
+    #[test]
+    fn test_key_from_output_and_key_to_output() {
+        let key = TxOutBnFileStore::key_from_output(&[3; 32], 0x01020304);
+        assert_eq!(&key[32..], &[1, 2, 3, 4]);
+        assert_eq!(TxOutBnFileStore::key_to_output(&key), ([3; 32], 0x01020304));
+    }
+
+    #[test]
+    fn test_apply_batch_and_reopen() {
+        let path = temp_path();
+        {
+            let mut store = TxOutBnFileStore::open(&path).unwrap();
+            assert!(store.is_empty());
+            store.apply_batch(&batch_for_block(0)).unwrap();
+            store.apply_batch(&batch_for_block(1)).unwrap();
+            assert_eq!(store.len(), 3);
+        }
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 3);
+        assert!(store.get(&[0; 32], 0).unwrap().is_none());
+        assert_eq!(store.get(&[0; 32], 1).unwrap().unwrap().tx_out.value, 200);
+        assert_eq!(store.get(&[1; 32], 0).unwrap().unwrap().block_num, 1);
+        remove_files(&path);
+    }
+
+    #[test]
+    fn test_undo_batch() {
+        let path = temp_path();
+        let mut store = TxOutBnFileStore::open(&path).unwrap();
+        store.apply_batch(&batch_for_block(0)).unwrap();
+        let before = to_map(&store);
+        // an output added and changed again in the same batch
+        let mut batch = batch_for_block(1);
+        batch.add(&[1; 32], 0, TxOut::new(300, Script::from_empty()), 1);
+        let undo = store.apply_batch(&batch).unwrap();
+        assert_eq!(store.get(&[1; 32], 0).unwrap().unwrap().tx_out.value, 300);
+        store.apply_batch(&undo).unwrap();
+        assert_eq!(to_map(&store).map, before.map);
+
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(to_map(&store).map, before.map);
+        remove_files(&path);
+    }
+
+    #[test]
+    fn test_index_grows() {
+        let path = temp_path();
+        {
+            let mut store = TxOutBnFileStore::open(&path).unwrap();
+            for block_num in 0..10u32 {
+                let mut batch = TxOutBnBatch::new();
+                for tx_out_num in 0..100 {
+                    let tx_out = TxOut::new(tx_out_num as u64, Script::from_empty());
+                    batch.add(&[block_num as u8; 32], tx_out_num, tx_out, block_num);
+                }
+                if block_num > 0 {
+                    for tx_out_num in 0..50 {
+                        batch.remove(&[block_num as u8 - 1; 32], tx_out_num);
+                    }
+                }
+                store.apply_batch(&batch).unwrap();
+            }
+            assert_eq!(store.len(), 1000 - 9 * 50);
+            assert!(store.index.n_slots > TxOutBnIndex::MIN_SLOTS);
+        }
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 1000 - 9 * 50);
+        assert!(store.get(&[8; 32], 49).unwrap().is_none());
+        assert_eq!(store.get(&[8; 32], 50).unwrap().unwrap().tx_out.value, 50);
+        assert_eq!(store.get(&[9; 32], 0).unwrap().unwrap().block_num, 9);
+        remove_files(&path);
+    }
+
+    #[test]
+    fn test_torn_write_is_dropped() {
+        let path = temp_path();
+        {
+            let mut store = TxOutBnFileStore::open(&path).unwrap();
+            store.apply_batch(&batch_for_block(0)).unwrap();
+        }
+        let good_len = fs::metadata(&path).unwrap().len();
+        {
+            // simulate a crash half way through writing the next record
+            let record = TxOutBnFileStore::encode_record(&batch_for_block(1));
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(&record[..record.len() / 2]).unwrap();
+        }
+        {
+            let mut store = TxOutBnFileStore::open(&path).unwrap();
+            assert_eq!(store.len(), 2);
+            assert_eq!(fs::metadata(&path).unwrap().len(), good_len);
+            store.apply_batch(&batch_for_block(1)).unwrap();
+        }
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 3);
+        remove_files(&path);
+    }
+
+    #[test]
+    fn test_missing_index_is_rebuilt() {
+        let path = temp_path();
+        let before = {
+            let mut store = TxOutBnFileStore::open(&path).unwrap();
+            store.apply_batch(&batch_for_block(0)).unwrap();
+            store.apply_batch(&batch_for_block(1)).unwrap();
+            to_map(&store)
+        };
+        fs::remove_file(TxOutBnFileStore::suffixed_path(&path, ".idx")).unwrap();
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(to_map(&store).map, before.map);
+
+        // a corrupt record is dropped along with everything after it
+        let mut buf = fs::read(&path).unwrap();
+        let last = buf.len() - 1;
+        buf[last] ^= 0xff;
+        fs::write(&path, buf).unwrap();
+        fs::remove_file(TxOutBnFileStore::suffixed_path(&path, ".idx")).unwrap();
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        assert!(store.get(&[0; 32], 0).unwrap().is_some());
+        remove_files(&path);
+    }
+
+    #[test]
+    fn test_dirty_index_is_rebuilt() {
+        let path = temp_path();
+        {
+            let mut store = TxOutBnFileStore::open(&path).unwrap();
+            store.apply_batch(&batch_for_block(0)).unwrap();
+            // simulate a crash while slots were being written
+            store.index.write_header(TxOutBnIndex::DIRTY).unwrap();
+            let key = TxOutBnFileStore::key_from_output(&[0; 32], 0);
+            store.index.remove(&key).unwrap();
+        }
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        assert!(store.get(&[0; 32], 0).unwrap().is_some());
+        remove_files(&path);
+    }
+
+    #[test]
+    fn test_failed_write_poisons_store() {
+        let path = temp_path();
+        let mut store = TxOutBnFileStore::open(&path).unwrap();
+        store.apply_batch(&batch_for_block(0)).unwrap();
+        let good_len = fs::metadata(&path).unwrap().len();
+        // a read only handle fails the write and can not truncate either
+        store.log_file = Mutex::new(File::open(&path).unwrap());
+        assert!(store.apply_batch(&batch_for_block(1)).is_err());
+        assert!(store.poisoned);
+        assert!(store.apply_batch(&batch_for_block(1)).is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), good_len);
+
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        remove_files(&path);
+    }
+
+    #[test]
+    fn test_failed_index_write_is_not_applied() {
+        let path = temp_path();
+        let mut store = TxOutBnFileStore::open(&path).unwrap();
+        store.apply_batch(&batch_for_block(0)).unwrap();
+        let good_len = fs::metadata(&path).unwrap().len();
+        // a read only handle fails every write to the index
+        store.index.file = Mutex::new(File::open(&store.index.path).unwrap());
+        assert!(store.apply_batch(&batch_for_block(1)).is_err());
+        assert!(store.poisoned);
+        assert!(store.get(&[0; 32], 0).is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), good_len);
+
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        assert!(store.get(&[1; 32], 0).unwrap().is_none());
+        remove_files(&path);
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let path = temp_path();
+        let snapshot_path = temp_path();
+        let mut store = TxOutBnFileStore::open(&path).unwrap();
+        store.apply_batch(&batch_for_block(0)).unwrap();
+        let before = to_map(&store);
+        store.snapshot(&snapshot_path).unwrap();
+        store.apply_batch(&batch_for_block(1)).unwrap();
+        store.apply_batch(&batch_for_block(2)).unwrap();
+        store.restore(&snapshot_path).unwrap();
+        assert_eq!(to_map(&store).map, before.map);
+
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(to_map(&store).map, before.map);
+        remove_files(&path);
+        fs::remove_file(&snapshot_path).unwrap();
+    }
+
+    #[test]
+    fn test_compact() {
+        let path = temp_path();
+        let mut store = TxOutBnFileStore::open(&path).unwrap();
+        for block_num in 0..10 {
+            store.apply_batch(&batch_for_block(block_num)).unwrap();
+        }
+        let before_len = fs::metadata(&path).unwrap().len();
+        let before = to_map(&store);
+        store.compact().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < before_len);
+        assert_eq!(to_map(&store).map, before.map);
+        store.apply_batch(&batch_for_block(10)).unwrap();
+
+        let store = TxOutBnFileStore::open(&path).unwrap();
+        assert_eq!(store.len(), before.map.len() + 1);
+        assert_eq!(store.get(&[10; 32], 1).unwrap().unwrap().tx_out.value, 200);
+        remove_files(&path);
+    }
+}
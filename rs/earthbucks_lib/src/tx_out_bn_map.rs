@@ -1,7 +1,12 @@
 use crate::buf::EbxBuf;
+use crate::buf_reader::BufReader;
+use crate::buf_writer::BufWriter;
+use crate::error::EbxError;
 use crate::tx::Tx;
 use crate::tx_out::TxOut;
 use crate::tx_out_bn::TxOutBn;
+use crate::tx_out_bn_batch::{TxOutBnBatch, TxOutBnOp};
+use crate::tx_out_bn_store::TxOutBnStore;
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, Default)]
@@ -47,6 +52,17 @@ impl TxOutBnMap {
         self.map.get(&name)
     }
 
+    pub fn entries(&self) -> Vec<([u8; 32], u32, &TxOutBn)> {
+        self.map
+            .iter()
+            .map(|(name, tx_out_bn)| {
+                let tx_id: [u8; 32] = Self::name_to_tx_id(name).try_into().unwrap();
+                let tx_out_num = Self::name_to_tx_out_num(name);
+                (tx_id, tx_out_num, tx_out_bn)
+            })
+            .collect()
+    }
+
     pub fn values(&self) -> Vec<&TxOutBn> {
         self.map.values().collect()
     }
@@ -56,6 +72,93 @@ impl TxOutBnMap {
             self.add(&tx.id(), output_index as u32, output.clone(), block_num);
         }
     }
+
+    // entries are sorted by name so that equal maps have equal buffers
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut names: Vec<&String> = self.map.keys().collect();
+        names.sort();
+        let mut writer = BufWriter::new();
+        writer.write_var_int(names.len() as u64);
+        for name in names {
+            writer.write(Self::name_to_tx_id(name));
+            writer.write_u32_be(Self::name_to_tx_out_num(name));
+            writer.write(self.map[name].to_buf());
+        }
+        writer.to_buf()
+    }
+
+    pub fn from_buf_reader(reader: &mut BufReader) -> Result<Self, EbxError> {
+        let len = reader.read_var_int()? as usize;
+        let mut tx_out_bn_map = Self::new();
+        for _ in 0..len {
+            let tx_id: [u8; 32] = reader.read(32)?.try_into().unwrap();
+            let tx_out_num = reader.read_u32_be()?;
+            let tx_out_bn = TxOutBn::from_buf_reader(reader)?;
+            tx_out_bn_map.add(&tx_id, tx_out_num, tx_out_bn.tx_out, tx_out_bn.block_num);
+        }
+        Ok(tx_out_bn_map)
+    }
+
+    pub fn from_buf(buf: Vec<u8>) -> Result<Self, EbxError> {
+        let mut reader = BufReader::new(buf);
+        Self::from_buf_reader(&mut reader)
+    }
+}
+
+impl TxOutBnStore for TxOutBnMap {
+    fn get(&self, tx_id: &[u8; 32], tx_out_num: u32) -> Result<Option<TxOutBn>, EbxError> {
+        Ok(TxOutBnMap::get(self, tx_id, tx_out_num).cloned())
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&[u8; 32], u32, &TxOutBn)) -> Result<(), EbxError> {
+        for (tx_id, tx_out_num, tx_out_bn) in self.entries() {
+            f(&tx_id, tx_out_num, tx_out_bn);
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn apply_batch(&mut self, batch: &TxOutBnBatch) -> Result<TxOutBnBatch, EbxError> {
+        let mut undo_ops = Vec::new();
+        for op in &batch.ops {
+            match op {
+                TxOutBnOp::Add {
+                    tx_id,
+                    tx_out_num,
+                    tx_out_bn,
+                } => {
+                    let name = Self::name_from_output(tx_id, *tx_out_num);
+                    let prev = self.map.insert(name, tx_out_bn.clone());
+                    undo_ops.push(match prev {
+                        Some(prev) => TxOutBnOp::Add {
+                            tx_id: *tx_id,
+                            tx_out_num: *tx_out_num,
+                            tx_out_bn: prev,
+                        },
+                        None => TxOutBnOp::Remove {
+                            tx_id: *tx_id,
+                            tx_out_num: *tx_out_num,
+                        },
+                    });
+                }
+                TxOutBnOp::Remove { tx_id, tx_out_num } => {
+                    let name = Self::name_from_output(tx_id, *tx_out_num);
+                    if let Some(prev) = self.map.remove(&name) {
+                        undo_ops.push(TxOutBnOp::Add {
+                            tx_id: *tx_id,
+                            tx_out_num: *tx_out_num,
+                            tx_out_bn: prev,
+                        });
+                    }
+                }
+            }
+        }
+        undo_ops.reverse();
+        Ok(TxOutBnBatch { ops: undo_ops })
+    }
 }
 
 #[cfg(test)]
@@ -162,4 +265,46 @@ mod tests {
         assert!(values.contains(&&tx_out_bn1));
         assert!(values.contains(&&tx_out_bn2));
     }
+
+    #[test]
+    fn test_to_buf_and_from_buf() {
+        let mut tx_out_map1 = TxOutBnMap::new();
+        tx_out_map1.add(&[1; 32], 0, TxOut::new(100, Script::from_empty()), 0);
+        tx_out_map1.add(&[1; 32], 1, TxOut::new(200, Script::from_empty()), 0);
+        tx_out_map1.add(&[2; 32], 0, TxOut::new(300, Script::from_empty()), 1);
+        let buf = tx_out_map1.to_buf();
+        let tx_out_map2 = TxOutBnMap::from_buf(buf.clone()).unwrap();
+        assert_eq!(tx_out_map2.map, tx_out_map1.map);
+        assert_eq!(tx_out_map2.to_buf(), buf);
+    }
+
+    #[test]
+    fn test_apply_batch_and_undo() {
+        let mut tx_out_map = TxOutBnMap::new();
+        tx_out_map.add(&[1; 32], 0, TxOut::new(100, Script::from_empty()), 0);
+        let before = tx_out_map.clone();
+
+        let mut batch = TxOutBnBatch::new();
+        batch.remove(&[1; 32], 0);
+        batch.add(&[2; 32], 0, TxOut::new(60, Script::from_empty()), 1);
+        batch.add(&[2; 32], 1, TxOut::new(40, Script::from_empty()), 1);
+        let undo = tx_out_map.apply_batch(&batch).unwrap();
+        assert_eq!(TxOutBnStore::len(&tx_out_map), 2);
+        assert!(tx_out_map.get(&[1; 32], 0).is_none());
+        assert_eq!(tx_out_map.get(&[2; 32], 1).unwrap().tx_out.value, 40);
+
+        tx_out_map.apply_batch(&undo).unwrap();
+        assert_eq!(tx_out_map.map, before.map);
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut tx_out_map = TxOutBnMap::new();
+        tx_out_map.add(&[1; 32], 7, TxOut::new(100, Script::from_empty()), 3);
+        let entries = tx_out_map.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, [1; 32]);
+        assert_eq!(entries[0].1, 7);
+        assert_eq!(entries[0].2.block_num, 3);
+    }
 }
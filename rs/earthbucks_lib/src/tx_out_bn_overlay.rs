@@ -0,0 +1,219 @@
+use crate::error::EbxError;
+use crate::tx::Tx;
+use crate::tx_out::TxOut;
+use crate::tx_out_bn::TxOutBn;
+use crate::tx_out_bn_batch::{TxOutBnBatch, TxOutBnOp};
+use crate::tx_out_bn_map::TxOutBnMap;
+use crate::tx_out_bn_store::TxOutBnStore;
+use std::collections::HashSet;
+
+// pending changes on top of a read-only utxo set. this lets a block be
+// verified tx by tx against the outputs of earlier txs in the same block
+// without touching the underlying store. if the block is valid, to_batch()
+// gives the changes to apply to the store.
+pub struct TxOutBnOverlay<'a> {
+    base: &'a dyn TxOutBnStore,
+    added: TxOutBnMap,
+    removed: HashSet<String>,
+    replaced: HashSet<String>, // added outputs that are also in the base
+}
+
+impl<'a> TxOutBnOverlay<'a> {
+    pub fn new(base: &'a dyn TxOutBnStore) -> Self {
+        Self {
+            base,
+            added: TxOutBnMap::new(),
+            removed: HashSet::new(),
+            replaced: HashSet::new(),
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        tx_id: &[u8; 32],
+        tx_out_num: u32,
+        tx_out: TxOut,
+        block_num: u32,
+    ) -> Result<(), EbxError> {
+        let name = TxOutBnMap::name_from_output(tx_id, tx_out_num);
+        if self.removed.remove(&name) || self.base.get(tx_id, tx_out_num)?.is_some() {
+            self.replaced.insert(name);
+        }
+        self.added.add(tx_id, tx_out_num, tx_out, block_num);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, tx_id: &[u8; 32], tx_out_num: u32) -> Result<(), EbxError> {
+        let name = TxOutBnMap::name_from_output(tx_id, tx_out_num);
+        if self.added.get(tx_id, tx_out_num).is_some() {
+            self.added.remove(tx_id, tx_out_num);
+            if self.replaced.remove(&name) {
+                self.removed.insert(name);
+            }
+        } else if self.base.get(tx_id, tx_out_num)?.is_some() {
+            self.removed.insert(name);
+        }
+        Ok(())
+    }
+
+    pub fn add_tx_outputs(&mut self, tx: &Tx, block_num: u32) -> Result<(), EbxError> {
+        let tx_id = tx.id();
+        for (output_index, output) in tx.outputs.iter().enumerate() {
+            self.add(&tx_id, output_index as u32, output.clone(), block_num)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_batch(&self) -> TxOutBnBatch {
+        let mut removed: Vec<&String> = self.removed.iter().collect();
+        removed.sort();
+        let mut batch = TxOutBnBatch::new();
+        for name in removed {
+            let tx_id: [u8; 32] = TxOutBnMap::name_to_tx_id(name).try_into().unwrap();
+            batch.remove(&tx_id, TxOutBnMap::name_to_tx_out_num(name));
+        }
+        let mut added = self.added.entries();
+        added.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+        for (tx_id, tx_out_num, tx_out_bn) in added {
+            batch.ops.push(TxOutBnOp::Add {
+                tx_id,
+                tx_out_num,
+                tx_out_bn: tx_out_bn.clone(),
+            });
+        }
+        batch
+    }
+}
+
+impl<'a> TxOutBnStore for TxOutBnOverlay<'a> {
+    fn get(&self, tx_id: &[u8; 32], tx_out_num: u32) -> Result<Option<TxOutBn>, EbxError> {
+        if let Some(tx_out_bn) = self.added.get(tx_id, tx_out_num) {
+            return Ok(Some(tx_out_bn.clone()));
+        }
+        if self
+            .removed
+            .contains(&TxOutBnMap::name_from_output(tx_id, tx_out_num))
+        {
+            return Ok(None);
+        }
+        self.base.get(tx_id, tx_out_num)
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&[u8; 32], u32, &TxOutBn)) -> Result<(), EbxError> {
+        self.base.for_each(&mut |tx_id, tx_out_num, tx_out_bn| {
+            let name = TxOutBnMap::name_from_output(tx_id, tx_out_num);
+            if !self.removed.contains(&name) && !self.replaced.contains(&name) {
+                f(tx_id, tx_out_num, tx_out_bn);
+            }
+        })?;
+        for (tx_id, tx_out_num, tx_out_bn) in self.added.entries() {
+            f(&tx_id, tx_out_num, tx_out_bn);
+        }
+        Ok(())
+    }
+
+    // removed only holds outputs of the base, and an added output may replace
+    // one of the base, so only new outputs add to the count
+    fn len(&self) -> usize {
+        self.base.len() - self.removed.len() + self.added.map.len() - self.replaced.len()
+    }
+
+    fn apply_batch(&mut self, batch: &TxOutBnBatch) -> Result<TxOutBnBatch, EbxError> {
+        let mut undo_ops = Vec::new();
+        for op in &batch.ops {
+            match op {
+                TxOutBnOp::Add {
+                    tx_id,
+                    tx_out_num,
+                    tx_out_bn,
+                } => {
+                    undo_ops.push(match self.get(tx_id, *tx_out_num)? {
+                        Some(prev) => TxOutBnOp::Add {
+                            tx_id: *tx_id,
+                            tx_out_num: *tx_out_num,
+                            tx_out_bn: prev,
+                        },
+                        None => TxOutBnOp::Remove {
+                            tx_id: *tx_id,
+                            tx_out_num: *tx_out_num,
+                        },
+                    });
+                    self.add(
+                        tx_id,
+                        *tx_out_num,
+                        tx_out_bn.tx_out.clone(),
+                        tx_out_bn.block_num,
+                    )?;
+                }
+                TxOutBnOp::Remove { tx_id, tx_out_num } => {
+                    if let Some(prev) = self.get(tx_id, *tx_out_num)? {
+                        undo_ops.push(TxOutBnOp::Add {
+                            tx_id: *tx_id,
+                            tx_out_num: *tx_out_num,
+                            tx_out_bn: prev,
+                        });
+                        self.remove(tx_id, *tx_out_num)?;
+                    }
+                }
+            }
+        }
+        undo_ops.reverse();
+        Ok(TxOutBnBatch { ops: undo_ops })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut base = TxOutBnMap::new();
+        base.add(&[1; 32], 0, TxOut::new(100, Script::from_empty()), 0);
+        let mut overlay = TxOutBnOverlay::new(&base);
+        overlay
+            .add(&[2; 32], 0, TxOut::new(100, Script::from_empty()), 1)
+            .unwrap();
+        overlay.remove(&[1; 32], 0).unwrap();
+        assert!(overlay.get(&[1; 32], 0).unwrap().is_none());
+        assert!(overlay.get(&[2; 32], 0).unwrap().is_some());
+        assert_eq!(overlay.len(), 1);
+
+        // an output added and spent within the overlay never reaches the batch
+        overlay
+            .add(&[3; 32], 0, TxOut::new(100, Script::from_empty()), 1)
+            .unwrap();
+        overlay.remove(&[3; 32], 0).unwrap();
+        let batch = overlay.to_batch();
+        assert_eq!(batch.len(), 2);
+
+        base.apply_batch(&batch).unwrap();
+        assert!(base.get(&[1; 32], 0).is_none());
+        assert_eq!(base.get(&[2; 32], 0).unwrap().block_num, 1);
+        assert!(base.get(&[3; 32], 0).is_none());
+    }
+
+    #[test]
+    fn test_len() {
+        let mut base = TxOutBnMap::new();
+        base.add(&[1; 32], 0, TxOut::new(100, Script::from_empty()), 0);
+        base.add(&[1; 32], 1, TxOut::new(100, Script::from_empty()), 0);
+        let mut overlay = TxOutBnOverlay::new(&base);
+        overlay.remove(&[1; 32], 1).unwrap();
+        overlay
+            .add(&[1; 32], 0, TxOut::new(200, Script::from_empty()), 1)
+            .unwrap();
+        overlay
+            .add(&[2; 32], 0, TxOut::new(100, Script::from_empty()), 1)
+            .unwrap();
+        assert_eq!(overlay.len(), 2);
+        assert_eq!(overlay.get(&[1; 32], 0).unwrap().unwrap().tx_out.value, 200);
+
+        // removing an output that replaced one of the base removes both
+        overlay.remove(&[1; 32], 0).unwrap();
+        assert_eq!(overlay.len(), 1);
+        assert!(overlay.get(&[1; 32], 0).unwrap().is_none());
+        assert_eq!(overlay.to_batch().len(), 3);
+    }
+}
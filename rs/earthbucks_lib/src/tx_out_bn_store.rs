@@ -0,0 +1,28 @@
+use crate::error::EbxError;
+use crate::tx_out_bn::TxOutBn;
+use crate::tx_out_bn_batch::TxOutBnBatch;
+
+// the utxo set. TxOutBnMap keeps it in memory, TxOutBnFileStore keeps it on
+// disk. builders, signers and verifiers only need to read it, so they take a
+// &dyn TxOutBnStore rather than cloning a map. get returns an owned TxOutBn so
+// that a backend can read it from disk instead of holding the whole set in
+// memory, and for_each hands out one output at a time for the same reason;
+// snapshotting the whole set is left to each backend. reads fail only if the
+// backend can not read the set, which is not the same as the output being
+// missing.
+pub trait TxOutBnStore {
+    fn get(&self, tx_id: &[u8; 32], tx_out_num: u32) -> Result<Option<TxOutBn>, EbxError>;
+
+    // calls f with every output in the set, in no particular order
+    fn for_each(&self, f: &mut dyn FnMut(&[u8; 32], u32, &TxOutBn)) -> Result<(), EbxError>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // apply every op in the batch or none of them. the returned batch undoes
+    // this one when it is applied.
+    fn apply_batch(&mut self, batch: &TxOutBnBatch) -> Result<TxOutBnBatch, EbxError>;
+}
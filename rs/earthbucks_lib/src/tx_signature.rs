@@ -1,3 +1,4 @@
+#[derive(Clone, Debug, PartialEq)]
 pub struct TxSignature {
     pub hash_type: u8,
     pub sig_buf: [u8; 64],
@@ -8,12 +9,19 @@ impl TxSignature {
     pub const SIGHASH_NONE: u8 = 0x00000002;
     pub const SIGHASH_SINGLE: u8 = 0x00000003;
     pub const SIGHASH_ANYONECANPAY: u8 = 0x00000080;
+    // set to sign with schnorr instead of ecdsa. combine with a sighash type,
+    // e.g. SIGHASH_ALL | SIGHASH_SCHNORR.
+    pub const SIGHASH_SCHNORR: u8 = 0x00000040;
     pub const SIZE: usize = 65; // hashtype (1) plus r (32) plus s (32)
 
     pub fn new(hash_type: u8, sig_buf: [u8; 64]) -> Self {
         Self { hash_type, sig_buf }
     }
 
+    pub fn is_schnorr(&self) -> bool {
+        self.hash_type & TxSignature::SIGHASH_SCHNORR != 0
+    }
+
     pub fn to_buf(&self) -> [u8; TxSignature::SIZE] {
         let mut result = Vec::new();
         result.push(self.hash_type);
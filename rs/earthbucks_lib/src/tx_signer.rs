@@ -2,28 +2,31 @@ use crate::error::EbxError;
 use crate::pkh_key_map::PkhKeyMap;
 use crate::script::Script;
 use crate::tx::Tx;
-use crate::tx_out_bn_map::TxOutBnMap;
+use crate::tx_out_bn_store::TxOutBnStore;
 use crate::tx_signature::TxSignature;
 
-pub struct TxSigner {
+pub struct TxSigner<'a> {
     pub tx: Tx,
     pub pkh_key_map: PkhKeyMap,
-    pub tx_out_bn_map: TxOutBnMap,
+    pub tx_out_bn_map: &'a dyn TxOutBnStore,
     pub working_block_num: u32,
+    // SIGHASH_ALL by default. add SIGHASH_SCHNORR to sign with schnorr.
+    pub hash_type: u8,
 }
 
-impl TxSigner {
+impl<'a> TxSigner<'a> {
     pub fn new(
         tx: Tx,
-        tx_out_bn_map: &TxOutBnMap,
+        tx_out_bn_map: &'a dyn TxOutBnStore,
         pkh_key_map: &PkhKeyMap,
         working_block_num: u32,
     ) -> Self {
         Self {
             tx,
-            tx_out_bn_map: tx_out_bn_map.clone(),
+            tx_out_bn_map,
             pkh_key_map: pkh_key_map.clone(),
             working_block_num,
+            hash_type: TxSignature::SIGHASH_ALL,
         }
     }
 
@@ -33,8 +36,8 @@ impl TxSigner {
         let tx_input = &mut self.tx.inputs[n_in];
         let tx_out_hash: &[u8; 32] = &tx_input.input_tx_id.clone();
         let output_index = tx_input.input_tx_out_num;
-        let tx_out_bn = match self.tx_out_bn_map.get(tx_out_hash, output_index) {
-            Some(tx_out_bn) => tx_out_bn.clone(),
+        let tx_out_bn = match self.tx_out_bn_map.get(tx_out_hash, output_index)? {
+            Some(tx_out_bn) => tx_out_bn,
             None => {
                 return Err(EbxError::GenericError {
                     source: None,
@@ -77,7 +80,7 @@ impl TxSigner {
                 priv_key_buf,
                 output_script_buf.to_vec(),
                 output_amount,
-                TxSignature::SIGHASH_ALL,
+                self.hash_type,
             );
             let sig_buf = sig.to_buf();
 
@@ -127,7 +130,7 @@ impl TxSigner {
                 priv_key_buf,
                 output_script_buf.to_vec(),
                 output_amount,
-                TxSignature::SIGHASH_ALL,
+                self.hash_type,
             );
             let sig_buf = sig.to_buf();
 
@@ -177,7 +180,7 @@ impl TxSigner {
                 private_key_array,
                 output_script_buf.to_vec(),
                 output_amount,
-                TxSignature::SIGHASH_ALL,
+                self.hash_type,
             );
             let sig_buf = sig.to_buf();
 
@@ -254,7 +257,7 @@ impl TxSigner {
                 priv_key_buf,
                 output_script_buf.to_vec(),
                 output_amount,
-                TxSignature::SIGHASH_ALL,
+                self.hash_type,
             );
             let sig_buf = sig.to_buf();
 
@@ -331,7 +334,7 @@ impl TxSigner {
                 priv_key_buf,
                 output_script_buf.to_vec(),
                 output_amount,
-                TxSignature::SIGHASH_ALL,
+                self.hash_type,
             );
             let sig_buf = sig.to_buf();
 
@@ -368,6 +371,7 @@ mod tests {
     use crate::tx_builder::TxBuilder;
     use crate::tx_out::TxOut;
     use crate::tx_out_bn_map::TxOutBnMap;
+    use crate::tx_verifier::TxVerifier;
 
     #[test]
     fn should_sign_a_tx() {
@@ -426,6 +430,34 @@ mod tests {
         assert!(result);
     }
 
+    #[test]
+    fn should_sign_with_schnorr() {
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        let mut pkh_key_map = PkhKeyMap::new();
+        for i in 0..2 {
+            let key = KeyPair::from_random();
+            let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
+            pkh_key_map.add(key.clone(), &pkh.buf.clone());
+            let script = Script::from_pkh_output(&pkh.buf.clone());
+            tx_out_bn_map.add(&[0; 32], i, TxOut::new(100, script), 0);
+        }
+
+        let mut tx_builder = TxBuilder::new(&tx_out_bn_map, Script::from_empty(), 0);
+        tx_builder.add_output(TxOut::new(200, Script::from_empty()));
+        let tx = tx_builder.build().unwrap();
+
+        let mut tx_signer = TxSigner::new(tx, &tx_out_bn_map, &pkh_key_map, 0);
+        tx_signer.hash_type = TxSignature::SIGHASH_ALL | TxSignature::SIGHASH_SCHNORR;
+        let signed_tx = tx_signer.sign().unwrap();
+
+        for tx_in in &signed_tx.inputs {
+            let sig_buf = tx_in.script.chunks[0].buffer.clone().unwrap();
+            assert!(TxSignature::from_buf(sig_buf).unwrap().is_schnorr());
+        }
+        let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, 0);
+        assert!(tx_verifier.verify().is_ok());
+    }
+
     #[test]
     fn should_sign_two_inputs() {
         let mut tx_out_bn_map = TxOutBnMap::new();
@@ -1,152 +1,177 @@
 use crate::script_interpreter::ScriptInterpreter;
+use crate::sig_cache::SigCache;
+use crate::sig_check::SigCheck;
 use crate::tx::{HashCache, Tx};
-use crate::tx_out_bn_map::TxOutBnMap;
+use crate::tx_out_bn::TxOutBn;
+use crate::tx_out_bn_store::TxOutBnStore;
+use crate::verify_error::VerifyError;
+use std::sync::Arc;
 
 pub struct TxVerifier<'a> {
     tx: Tx,
-    tx_out_bn_map: &'a TxOutBnMap,
+    tx_out_bn_map: &'a dyn TxOutBnStore,
     hash_cache: HashCache,
     block_num: u32,
+    deferred_sigs: Option<Vec<SigCheck>>,
 }
 
 impl<'a> TxVerifier<'a> {
-    pub fn new(tx: Tx, tx_out_bn_map: &'a TxOutBnMap, block_num: u32) -> Self {
+    pub fn new(tx: Tx, tx_out_bn_map: &'a dyn TxOutBnStore, block_num: u32) -> Self {
         let hash_cache = HashCache::new();
         Self {
             tx,
             tx_out_bn_map,
             hash_cache,
             block_num,
+            deferred_sigs: None,
         }
     }
 
-    pub fn verify_input_script(&mut self, n_in: usize) -> bool {
-        let tx_input = &self.tx.inputs[n_in];
-        let tx_out_hash: &[u8; 32] = &tx_input.input_tx_id.clone();
-        let output_index = tx_input.input_tx_out_num;
-        let tx_out = self.tx_out_bn_map.get(tx_out_hash, output_index);
-        match tx_out {
-            None => false,
-            Some(tx_out_bn) => {
-                let output_script = &tx_out_bn.tx_out.script;
-                let input_script = &tx_input.script;
-                if !input_script.is_push_only() {
-                    return false;
-                }
-                let stack: Vec<Vec<u8>> = input_script
-                    .chunks
-                    .iter()
-                    .map(|chunk| chunk.get_data().unwrap())
-                    .collect();
-                let mut script_interpreter = ScriptInterpreter::from_output_script_tx(
-                    output_script.clone(),
-                    self.tx.clone(),
-                    n_in,
-                    stack,
-                    tx_out_bn.tx_out.value,
-                    &mut self.hash_cache,
-                );
-                script_interpreter.eval_script()
-            }
-        }
+    // run input scripts with ScriptInterpreter::enable_deferred_sigs. the tx
+    // is only valid if verify passes and every check from take_deferred_sigs
+    // passes too.
+    pub fn enable_deferred_sigs(&mut self) {
+        self.deferred_sigs = Some(Vec::new());
+    }
+
+    pub fn set_sig_cache(&mut self, sig_cache: Arc<SigCache>) {
+        self.hash_cache.sig_cache = Some(sig_cache);
+    }
+
+    pub fn take_deferred_sigs(&mut self) -> Vec<SigCheck> {
+        self.deferred_sigs.take().unwrap_or_default()
     }
 
-    pub fn verify_input_lock_rel(&mut self, n_in: usize) -> bool {
+    fn get_input_tx_out_bn(&self, n_in: usize) -> Result<TxOutBn, VerifyError> {
         let tx_input = &self.tx.inputs[n_in];
-        let tx_id: &[u8; 32] = &tx_input.input_tx_id.clone();
+        let tx_id = tx_input.input_tx_id;
         let tx_out_num = tx_input.input_tx_out_num;
-        let tx_out = self.tx_out_bn_map.get(tx_id, tx_out_num);
-        match tx_out {
-            None => false,
-            Some(tx_out_bn) => {
-                let lock_rel = tx_input.lock_rel;
-                let prev_block_num = tx_out_bn.block_num;
-                self.block_num >= prev_block_num + lock_rel
-            }
+        self.tx_out_bn_map
+            .get(&tx_id, tx_out_num)
+            .map_err(VerifyError::store)?
+            .ok_or(VerifyError::InputNotFound {
+                n_in,
+                tx_id,
+                tx_out_num,
+            })
+    }
+
+    pub fn verify_input_script(&mut self, n_in: usize) -> Result<(), VerifyError> {
+        let tx_out_bn = self.get_input_tx_out_bn(n_in)?;
+        let tx_input = &self.tx.inputs[n_in];
+        let output_script = &tx_out_bn.tx_out.script;
+        let input_script = &tx_input.script;
+        if !input_script.is_push_only() {
+            return Err(VerifyError::InputScriptNotPushOnly { n_in });
+        }
+        let stack: Vec<Vec<u8>> = input_script
+            .chunks
+            .iter()
+            .map(|chunk| chunk.get_data().unwrap())
+            .collect();
+        let mut script_interpreter = ScriptInterpreter::from_output_script_tx(
+            output_script.clone(),
+            self.tx.clone(),
+            n_in,
+            stack,
+            tx_out_bn.tx_out.value,
+            &mut self.hash_cache,
+        );
+        if self.deferred_sigs.is_some() {
+            script_interpreter.enable_deferred_sigs();
+        }
+        if !script_interpreter.eval_script() {
+            return Err(VerifyError::InputScriptFailed {
+                n_in,
+                err: script_interpreter.err,
+            });
+        }
+        if let Some(sig_checks) = script_interpreter.deferred_sigs {
+            self.deferred_sigs.as_mut().unwrap().extend(sig_checks);
+        }
+        Ok(())
+    }
+
+    pub fn verify_input_lock_rel(&mut self, n_in: usize) -> Result<(), VerifyError> {
+        let tx_out_bn = self.get_input_tx_out_bn(n_in)?;
+        let lock_rel = self.tx.inputs[n_in].lock_rel;
+        let prev_block_num = tx_out_bn.block_num;
+        if self.block_num < prev_block_num + lock_rel {
+            return Err(VerifyError::LockRelNotMet {
+                n_in,
+                lock_rel,
+                prev_block_num,
+                block_num: self.block_num,
+            });
         }
+        Ok(())
     }
 
-    pub fn verify_inputs(&mut self) -> bool {
+    pub fn verify_inputs(&mut self) -> Result<(), VerifyError> {
         for i in 0..self.tx.inputs.len() {
-            if !self.verify_input_script(i) {
-                return false;
-            }
-            if !self.verify_input_lock_rel(i) {
-                return false;
-            }
+            self.verify_input_script(i)?;
+            self.verify_input_lock_rel(i)?;
         }
-        true
+        Ok(())
     }
 
-    pub fn verify_no_double_spend(&self) -> bool {
+    pub fn verify_no_double_spend(&self) -> Result<(), VerifyError> {
         let mut spent_outputs = Vec::new();
-        for input in &self.tx.inputs {
-            let tx_out = self
-                .tx_out_bn_map
-                .get(&input.input_tx_id.clone(), input.input_tx_out_num);
-            match tx_out {
-                None => return false,
-                Some(tx_out) => {
-                    if spent_outputs.contains(&tx_out) {
-                        return false;
-                    }
-                    spent_outputs.push(tx_out);
-                }
+        for (n_in, input) in self.tx.inputs.iter().enumerate() {
+            self.get_input_tx_out_bn(n_in)?;
+            let output = (input.input_tx_id, input.input_tx_out_num);
+            if spent_outputs.contains(&output) {
+                return Err(VerifyError::DoubleSpend { n_in });
             }
+            spent_outputs.push(output);
         }
-        true
+        Ok(())
     }
 
-    pub fn verify_output_values(&self) -> bool {
+    pub fn verify_output_values(&self) -> Result<(), VerifyError> {
         let mut total_output_value = 0;
         for output in &self.tx.outputs {
             total_output_value += output.value;
         }
         let mut total_input_value = 0;
-        for input in &self.tx.inputs {
-            let tx_out_bn = self
-                .tx_out_bn_map
-                .get(&input.input_tx_id.clone(), input.input_tx_out_num);
-            match tx_out_bn {
-                None => return false,
-                Some(tx_out_bn) => total_input_value += tx_out_bn.tx_out.value,
-            }
+        for n_in in 0..self.tx.inputs.len() {
+            let tx_out_bn = self.get_input_tx_out_bn(n_in)?;
+            total_input_value += tx_out_bn.tx_out.value;
         }
-        total_input_value == total_output_value
+        if total_input_value != total_output_value {
+            return Err(VerifyError::OutputValuesMismatch {
+                input_value: total_input_value,
+                output_value: total_output_value,
+            });
+        }
+        Ok(())
     }
 
-    pub fn verify_is_not_coinbase(&self) -> bool {
+    pub fn verify_is_not_coinbase(&self) -> Result<(), VerifyError> {
         // TODO: Allow coinbases to have multiple inputs
         if self.tx.inputs.len() == 1 && self.tx.inputs[0].is_coinbase() {
-            return false;
+            return Err(VerifyError::IsCoinbase);
         }
-        true
+        Ok(())
     }
 
-    pub fn verify_lock_abs(&self) -> bool {
+    pub fn verify_lock_abs(&self) -> Result<(), VerifyError> {
         if self.tx.lock_abs > self.block_num {
-            return false;
+            return Err(VerifyError::LockAbsNotMet {
+                lock_abs: self.tx.lock_abs,
+                block_num: self.block_num,
+            });
         }
-        true
+        Ok(())
     }
 
-    pub fn verify(&mut self) -> bool {
-        if !self.verify_lock_abs() {
-            return false;
-        }
-        if !self.verify_is_not_coinbase() {
-            return false;
-        }
-        if !self.verify_no_double_spend() {
-            return false;
-        }
-        if !self.verify_inputs() {
-            return false;
-        }
-        if !self.verify_output_values() {
-            return false;
-        }
-        true
+    pub fn verify(&mut self) -> Result<(), VerifyError> {
+        self.verify_lock_abs()?;
+        self.verify_is_not_coinbase()?;
+        self.verify_no_double_spend()?;
+        self.verify_inputs()?;
+        self.verify_output_values()?;
+        Ok(())
     }
 }
 
@@ -199,19 +224,19 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, 0);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -249,17 +274,23 @@ mod tests {
         assert!(tx_res.is_ok());
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, 0);
-        let verified_input = tx_verifier.verify_input_script(0);
+        let verified_input = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
         let verified = tx_verifier.verify();
-        assert!(!verified);
+        assert_eq!(
+            verified,
+            Err(VerifyError::LockAbsNotMet {
+                lock_abs: 1,
+                block_num: 0
+            })
+        );
     }
 
     #[test]
@@ -299,18 +330,18 @@ mod tests {
         let signed_tx = tx_signer.tx;
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, 0);
-        let verified_input1 = tx_verifier.verify_input_script(0);
+        let verified_input1 = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input1);
-        let verified_input2 = tx_verifier.verify_input_script(1);
+        let verified_input2 = tx_verifier.verify_input_script(1).is_ok();
         assert!(verified_input2);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -349,19 +380,19 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, 0);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -408,19 +439,19 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, working_block_num);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -459,19 +490,19 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, 0);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -518,19 +549,19 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, working_block_num);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -569,19 +600,19 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, 0);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -631,19 +662,19 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, working_block_num);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -693,19 +724,19 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, working_block_num);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -744,19 +775,19 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, 0);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -806,19 +837,19 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, working_block_num);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
 
@@ -868,19 +899,52 @@ mod tests {
 
         let mut tx_verifier = TxVerifier::new(signed_tx, &tx_out_bn_map, working_block_num);
 
-        let verified_input_script = tx_verifier.verify_input_script(0);
+        let verified_input_script = tx_verifier.verify_input_script(0).is_ok();
         assert!(verified_input_script);
 
-        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0);
+        let verified_input_lock_rel = tx_verifier.verify_input_lock_rel(0).is_ok();
         assert!(verified_input_lock_rel);
 
-        let verified_scripts = tx_verifier.verify_inputs();
+        let verified_scripts = tx_verifier.verify_inputs().is_ok();
         assert!(verified_scripts);
 
-        let verified_output_values = tx_verifier.verify_output_values();
+        let verified_output_values = tx_verifier.verify_output_values().is_ok();
         assert!(verified_output_values);
 
-        let verified = tx_verifier.verify();
+        let verified = tx_verifier.verify().is_ok();
         assert!(verified);
     }
+
+    #[test]
+    fn should_not_verify_an_unsigned_tx() {
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        let key = KeyPair::from_random();
+        let pkh = Pkh::from_pub_key_buffer(key.pub_key.buf.to_vec());
+        let script = Script::from_pkh_output(&pkh.buf);
+        tx_out_bn_map.add(&[0; 32], 0, TxOut::new(100, script), 0);
+
+        let mut tx_builder = TxBuilder::new(&tx_out_bn_map, Script::from_empty(), 0);
+        tx_builder.add_output(TxOut::new(100, Script::from_empty()));
+        let tx = tx_builder.build().unwrap();
+
+        let mut tx_verifier = TxVerifier::new(tx.clone(), &tx_out_bn_map, 0);
+        assert!(matches!(
+            tx_verifier.verify(),
+            Err(VerifyError::InputScriptFailed {
+                n_in: 0,
+                err: Some(_)
+            })
+        ));
+
+        let empty_tx_out_bn_map = TxOutBnMap::new();
+        let mut tx_verifier = TxVerifier::new(tx, &empty_tx_out_bn_map, 0);
+        assert_eq!(
+            tx_verifier.verify(),
+            Err(VerifyError::InputNotFound {
+                n_in: 0,
+                tx_id: [0; 32],
+                tx_out_num: 0
+            })
+        );
+    }
 }
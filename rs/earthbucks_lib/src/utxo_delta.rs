@@ -0,0 +1,191 @@
+use crate::buf_reader::BufReader;
+use crate::buf_writer::BufWriter;
+use crate::error::EbxError;
+use crate::tx::Tx;
+use crate::tx_out_bn::TxOutBn;
+use crate::tx_out_bn_batch::TxOutBnBatch;
+use crate::tx_out_bn_map::TxOutBnMap;
+use crate::tx_out_bn_store::TxOutBnStore;
+
+// the change a block makes to the utxo set: the outputs it creates, including
+// the coinbase outputs, and the outputs it spends. spent outputs keep the
+// block_num they were created in so that the delta can be inverted to rewind
+// the tip. outputs created and spent inside the same block appear in neither.
+#[derive(Debug, Clone, Default)]
+pub struct UtxoDelta {
+    pub block_num: u32,
+    pub created: TxOutBnMap,
+    pub spent: TxOutBnMap,
+}
+
+impl UtxoDelta {
+    pub fn new(block_num: u32) -> Self {
+        Self {
+            block_num,
+            created: TxOutBnMap::new(),
+            spent: TxOutBnMap::new(),
+        }
+    }
+
+    pub fn add_tx_outputs(&mut self, tx: &Tx) {
+        self.created.add_tx_outputs(tx, self.block_num);
+    }
+
+    // tx_out_bn is the output being spent, as found in the utxo set or in an
+    // earlier tx of this block
+    pub fn spend(&mut self, tx_id: &[u8; 32], tx_out_num: u32, tx_out_bn: &TxOutBn) {
+        if self.created.get(tx_id, tx_out_num).is_some() {
+            self.created.remove(tx_id, tx_out_num);
+        } else {
+            self.spent.add(
+                tx_id,
+                tx_out_num,
+                tx_out_bn.tx_out.clone(),
+                tx_out_bn.block_num,
+            );
+        }
+    }
+
+    pub fn invert(&self) -> UtxoDelta {
+        Self {
+            block_num: self.block_num,
+            created: self.spent.clone(),
+            spent: self.created.clone(),
+        }
+    }
+
+    pub fn to_batch(&self) -> TxOutBnBatch {
+        let mut batch = TxOutBnBatch::new();
+        let mut spent = self.spent.entries();
+        spent.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+        for (tx_id, tx_out_num, _) in spent {
+            batch.remove(&tx_id, tx_out_num);
+        }
+        let mut created = self.created.entries();
+        created.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+        for (tx_id, tx_out_num, tx_out_bn) in created {
+            batch.add(
+                &tx_id,
+                tx_out_num,
+                tx_out_bn.tx_out.clone(),
+                tx_out_bn.block_num,
+            );
+        }
+        batch
+    }
+
+    // the delta is checked against the whole utxo set before anything is
+    // written, so either every change is made or none is
+    pub fn apply(&self, tx_out_bn_store: &mut dyn TxOutBnStore) -> Result<(), EbxError> {
+        for (tx_id, tx_out_num, tx_out_bn) in self.spent.entries() {
+            if tx_out_bn_store.get(&tx_id, tx_out_num)?.as_ref() != Some(tx_out_bn) {
+                return Err(EbxError::GenericError {
+                    source: None,
+                    message: "spent tx_out not found".to_string(),
+                });
+            }
+        }
+        for (tx_id, tx_out_num, _) in self.created.entries() {
+            if tx_out_bn_store.get(&tx_id, tx_out_num)?.is_some() {
+                return Err(EbxError::GenericError {
+                    source: None,
+                    message: "created tx_out already exists".to_string(),
+                });
+            }
+        }
+        tx_out_bn_store.apply_batch(&self.to_batch())?;
+        Ok(())
+    }
+
+    pub fn rollback(&self, tx_out_bn_store: &mut dyn TxOutBnStore) -> Result<(), EbxError> {
+        self.invert().apply(tx_out_bn_store)
+    }
+
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut writer = BufWriter::new();
+        writer.write_u32_be(self.block_num);
+        writer.write(self.created.to_buf());
+        writer.write(self.spent.to_buf());
+        writer.to_buf()
+    }
+
+    pub fn from_buf_reader(reader: &mut BufReader) -> Result<Self, EbxError> {
+        let block_num = reader.read_u32_be()?;
+        let created = TxOutBnMap::from_buf_reader(reader)?;
+        let spent = TxOutBnMap::from_buf_reader(reader)?;
+        Ok(Self {
+            block_num,
+            created,
+            spent,
+        })
+    }
+
+    pub fn from_buf(buf: Vec<u8>) -> Result<Self, EbxError> {
+        let mut reader = BufReader::new(buf);
+        Self::from_buf_reader(&mut reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+    use crate::tx_out::TxOut;
+
+    fn delta_for_block_1() -> UtxoDelta {
+        let mut delta = UtxoDelta::new(1);
+        let spent = TxOutBn::new(TxOut::new(100, Script::from_empty()), 0);
+        delta.spend(&[1; 32], 0, &spent);
+        delta
+            .created
+            .add(&[2; 32], 0, TxOut::new(100, Script::from_empty()), 1);
+        delta
+    }
+
+    #[test]
+    fn test_apply_and_rollback() {
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        tx_out_bn_map.add(&[1; 32], 0, TxOut::new(100, Script::from_empty()), 0);
+        tx_out_bn_map.add(&[1; 32], 1, TxOut::new(100, Script::from_empty()), 0);
+        let before = tx_out_bn_map.clone();
+
+        let delta = delta_for_block_1();
+        delta.apply(&mut tx_out_bn_map).unwrap();
+        assert!(tx_out_bn_map.get(&[1; 32], 0).is_none());
+        assert_eq!(tx_out_bn_map.get(&[2; 32], 0).unwrap().block_num, 1);
+
+        delta.rollback(&mut tx_out_bn_map).unwrap();
+        assert_eq!(tx_out_bn_map.map, before.map);
+    }
+
+    #[test]
+    fn test_apply_is_atomic() {
+        let mut tx_out_bn_map = TxOutBnMap::new();
+        tx_out_bn_map.add(&[2; 32], 0, TxOut::new(100, Script::from_empty()), 1);
+        let before = tx_out_bn_map.clone();
+
+        // the spent output is missing, so nothing should change
+        let delta = delta_for_block_1();
+        assert!(delta.apply(&mut tx_out_bn_map).is_err());
+        assert_eq!(tx_out_bn_map.map, before.map);
+    }
+
+    #[test]
+    fn test_spend_output_created_in_same_block() {
+        let mut delta = UtxoDelta::new(1);
+        let tx_out = TxOut::new(100, Script::from_empty());
+        delta.created.add(&[2; 32], 0, tx_out.clone(), 1);
+        delta.spend(&[2; 32], 0, &TxOutBn::new(tx_out, 1));
+        assert!(delta.created.map.is_empty());
+        assert!(delta.spent.map.is_empty());
+    }
+
+    #[test]
+    fn test_to_buf_and_from_buf() {
+        let delta1 = delta_for_block_1();
+        let delta2 = UtxoDelta::from_buf(delta1.to_buf()).unwrap();
+        assert_eq!(delta2.block_num, 1);
+        assert_eq!(delta2.created.map, delta1.created.map);
+        assert_eq!(delta2.spent.map, delta1.spent.map);
+    }
+}
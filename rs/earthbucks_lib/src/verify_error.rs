@@ -0,0 +1,178 @@
+use crate::buf::EbxBuf;
+use crate::error::EbxError;
+use crate::script_error::ScriptError;
+use std::fmt;
+
+// why TxVerifier or BlockVerifier rejected a tx or a block. StoreError is the
+// exception: the utxo set could not be read, so nothing is known about the tx
+// or the block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    StoreError {
+        message: String,
+    },
+    LockAbsNotMet {
+        lock_abs: u32,
+        block_num: u32,
+    },
+    IsCoinbase,
+    InputNotFound {
+        n_in: usize,
+        tx_id: [u8; 32],
+        tx_out_num: u32,
+    },
+    DoubleSpend {
+        n_in: usize,
+    },
+    InputScriptNotPushOnly {
+        n_in: usize,
+    },
+    // err is None when the script ran to the end but left a false value
+    InputScriptFailed {
+        n_in: usize,
+        err: Option<ScriptError>,
+    },
+    LockRelNotMet {
+        n_in: usize,
+        lock_rel: u32,
+        prev_block_num: u32,
+        block_num: u32,
+    },
+    OutputValuesMismatch {
+        input_value: u64,
+        output_value: u64,
+    },
+    TimestampInFuture {
+        timestamp: u64,
+        header_timestamp: u64,
+    },
+    InvalidHeader,
+    InvalidMerkleRoot,
+    InvalidCoinbase {
+        rule: &'static str,
+    },
+    InvalidTx {
+        n_tx: usize,
+        tx_id: [u8; 32],
+        err: Box<VerifyError>,
+    },
+}
+
+impl VerifyError {
+    pub fn store(err: EbxError) -> Self {
+        VerifyError::StoreError {
+            message: err.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::StoreError { message } => {
+                write!(f, "unable to read the utxo set: {}", message)
+            }
+            VerifyError::LockAbsNotMet {
+                lock_abs,
+                block_num,
+            } => write!(
+                f,
+                "lock_abs {} is after block number {}",
+                lock_abs, block_num
+            ),
+            VerifyError::IsCoinbase => write!(f, "tx is a coinbase"),
+            VerifyError::InputNotFound {
+                n_in,
+                tx_id,
+                tx_out_num,
+            } => write!(
+                f,
+                "input {} spends unknown output {}:{}",
+                n_in,
+                tx_id.to_strict_hex(),
+                tx_out_num
+            ),
+            VerifyError::DoubleSpend { n_in } => {
+                write!(f, "input {} spends an output already spent", n_in)
+            }
+            VerifyError::InputScriptNotPushOnly { n_in } => {
+                write!(f, "input {} script is not push only", n_in)
+            }
+            VerifyError::InputScriptFailed { n_in, err } => match err {
+                Some(err) => write!(f, "input {} script failed: {}", n_in, err),
+                None => write!(f, "input {} script returned false", n_in),
+            },
+            VerifyError::LockRelNotMet {
+                n_in,
+                lock_rel,
+                prev_block_num,
+                block_num,
+            } => write!(
+                f,
+                "input {} lock_rel {} from block {} not met at block {}",
+                n_in, lock_rel, prev_block_num, block_num
+            ),
+            VerifyError::OutputValuesMismatch {
+                input_value,
+                output_value,
+            } => write!(
+                f,
+                "input value {} does not equal output value {}",
+                input_value, output_value
+            ),
+            VerifyError::TimestampInFuture {
+                timestamp,
+                header_timestamp,
+            } => write!(
+                f,
+                "header timestamp {} is after {}",
+                header_timestamp, timestamp
+            ),
+            VerifyError::InvalidHeader => write!(f, "invalid header"),
+            VerifyError::InvalidMerkleRoot => write!(f, "invalid merkle root"),
+            VerifyError::InvalidCoinbase { rule } => write!(f, "invalid coinbase: {}", rule),
+            VerifyError::InvalidTx { n_tx, tx_id, err } => write!(
+                f,
+                "tx {} ({}) is invalid: {}",
+                n_tx,
+                tx_id.to_strict_hex(),
+                err
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VerifyError::InputScriptFailed { err: Some(err), .. } => Some(err),
+            VerifyError::InvalidTx { err, .. } => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_source_chain() {
+        let err = VerifyError::InvalidTx {
+            n_tx: 1,
+            tx_id: [0; 32],
+            err: Box::new(VerifyError::InputScriptFailed {
+                n_in: 0,
+                err: Some(ScriptError::VerifyFailed),
+            }),
+        };
+        let ebx_error = EbxError::from(err.clone());
+        let source = ebx_error.source().unwrap();
+        assert_eq!(source.to_string(), err.to_string());
+        let source = source.source().unwrap();
+        assert_eq!(source.to_string(), "input 0 script failed: VERIFY failed");
+        assert_eq!(source.source().unwrap().to_string(), "VERIFY failed");
+        assert!(source.source().unwrap().source().is_none());
+    }
+}
@@ -0,0 +1,404 @@
+use crate::buf_reader::BufReader;
+use crate::domain::Domain;
+use crate::error::EbxError;
+use crate::header_chain::HeaderChain;
+use crate::mine_registry::MineRegistry;
+use crate::pkh::Pkh;
+use crate::priv_key::PrivKey;
+use crate::pub_key::PubKey;
+use crate::signed_message::SignedMessage;
+use crate::tx::Tx;
+use std::collections::{HashMap, HashSet};
+
+// votes from recent mines on which blocks and txs are final. a vote is a
+// signed message of
+//
+//   block or tx id (32 bytes) | domain
+//
+// signed by a key whose pkh is paid by a coinbase with that domain. block and
+// tx votes use different key strings, so one can never pass for the other.
+
+#[derive(Debug, Clone)]
+pub struct BlockVote {
+    pub block_id: [u8; 32],
+    pub domain: String,
+    pub signed_message: SignedMessage,
+}
+
+#[derive(Debug, Clone)]
+pub struct TxVote {
+    pub tx_id: [u8; 32],
+    pub domain: String,
+    pub signed_message: SignedMessage,
+}
+
+fn vote_message(id: &[u8; 32], domain: &str) -> Vec<u8> {
+    let mut message = id.to_vec();
+    message.extend_from_slice(domain.as_bytes());
+    message
+}
+
+fn sign_vote(
+    priv_key: &PrivKey,
+    id: &[u8; 32],
+    domain: &str,
+    key_str: &str,
+) -> Result<SignedMessage, EbxError> {
+    if !Domain::is_valid_coinbase_domain(domain) {
+        return Err(EbxError::InvalidEncodingError { source: None });
+    }
+    SignedMessage::from_sign_message(priv_key, vote_message(id, domain), key_str)
+}
+
+fn read_vote(buf: Vec<u8>, key_str: &str) -> Result<([u8; 32], String, SignedMessage), EbxError> {
+    let signed_message = SignedMessage::from_buf(buf, key_str)?;
+    let mut reader = BufReader::new(signed_message.message.clone());
+    let id: [u8; 32] = reader.read(32)?.try_into().unwrap();
+    let domain = String::from_utf8(reader.read_remainder())
+        .map_err(|_| EbxError::InvalidEncodingError { source: None })?;
+    if !Domain::is_valid_coinbase_domain(&domain) {
+        return Err(EbxError::InvalidEncodingError { source: None });
+    }
+    Ok((id, domain, signed_message))
+}
+
+fn vote_is_valid(
+    id: &[u8; 32],
+    domain: &str,
+    signed_message: &SignedMessage,
+    key_str: &str,
+) -> bool {
+    signed_message.message == vote_message(id, domain)
+        && signed_message.is_valid(&PubKey::new(signed_message.pub_key), key_str)
+}
+
+impl BlockVote {
+    pub const KEY_STR: &'static str = "earthbucks block vote";
+
+    pub fn from_sign(
+        priv_key: &PrivKey,
+        block_id: [u8; 32],
+        domain: &str,
+    ) -> Result<Self, EbxError> {
+        let signed_message = sign_vote(priv_key, &block_id, domain, BlockVote::KEY_STR)?;
+        Ok(Self {
+            block_id,
+            domain: domain.to_string(),
+            signed_message,
+        })
+    }
+
+    pub fn from_buf(buf: Vec<u8>) -> Result<Self, EbxError> {
+        let (block_id, domain, signed_message) = read_vote(buf, BlockVote::KEY_STR)?;
+        Ok(Self {
+            block_id,
+            domain,
+            signed_message,
+        })
+    }
+
+    pub fn to_buf(&self) -> Vec<u8> {
+        self.signed_message.to_buf()
+    }
+
+    pub fn pub_key(&self) -> &[u8; PubKey::SIZE] {
+        &self.signed_message.pub_key
+    }
+
+    // the signature is valid. whether the signer is a recent mine for the
+    // domain is up to VoteTally.
+    pub fn is_valid(&self) -> bool {
+        vote_is_valid(
+            &self.block_id,
+            &self.domain,
+            &self.signed_message,
+            BlockVote::KEY_STR,
+        )
+    }
+}
+
+impl TxVote {
+    pub const KEY_STR: &'static str = "earthbucks tx vote";
+
+    pub fn from_sign(priv_key: &PrivKey, tx_id: [u8; 32], domain: &str) -> Result<Self, EbxError> {
+        let signed_message = sign_vote(priv_key, &tx_id, domain, TxVote::KEY_STR)?;
+        Ok(Self {
+            tx_id,
+            domain: domain.to_string(),
+            signed_message,
+        })
+    }
+
+    pub fn from_buf(buf: Vec<u8>) -> Result<Self, EbxError> {
+        let (tx_id, domain, signed_message) = read_vote(buf, TxVote::KEY_STR)?;
+        Ok(Self {
+            tx_id,
+            domain,
+            signed_message,
+        })
+    }
+
+    pub fn to_buf(&self) -> Vec<u8> {
+        self.signed_message.to_buf()
+    }
+
+    pub fn pub_key(&self) -> &[u8; PubKey::SIZE] {
+        &self.signed_message.pub_key
+    }
+
+    pub fn is_valid(&self) -> bool {
+        vote_is_valid(
+            &self.tx_id,
+            &self.domain,
+            &self.signed_message,
+            TxVote::KEY_STR,
+        )
+    }
+}
+
+// a mine's voting weight is the number of recent blocks it made, and a vote
+// carries the weight of those blocks whose coinbase pays the key that signed
+// it, so a key paid by one coinbase that claims a domain can not speak for the
+// rest of that domain's blocks. a block or tx is final once votes with more
+// than half of the total weight have been cast for it. each block counts once
+// per block or tx however many of the keys it pays vote.
+#[derive(Debug, Clone, Default)]
+pub struct VoteTally {
+    registry: MineRegistry,
+    block_votes: HashMap<[u8; 32], Votes>,
+    tx_votes: HashMap<[u8; 32], Votes>,
+}
+
+// the pkhs that voted, by mine domain
+type Votes = HashMap<Domain, HashSet<[u8; 32]>>;
+
+impl VoteTally {
+    pub fn new(registry: MineRegistry) -> Self {
+        Self {
+            registry,
+            block_votes: HashMap::new(),
+            tx_votes: HashMap::new(),
+        }
+    }
+
+    // coinbase_txs are the coinbases of the last coinbase_txs.len() blocks of
+    // lch, oldest first
+    pub fn from_coinbases(lch: &HeaderChain, coinbase_txs: &[Tx]) -> Result<Self, EbxError> {
+        Ok(Self::new(MineRegistry::from_coinbases(lch, coinbase_txs)?))
+    }
+
+    pub fn registry(&self) -> &MineRegistry {
+        &self.registry
+    }
+
+    pub fn total_weight(&self) -> u64 {
+        self.registry.n_blocks()
+    }
+
+    pub fn get_weight(&self, domain: &str) -> u64 {
+        self.registry
+            .get(&Domain::from_strict_str(domain.to_string()))
+            .map_or(0, |mine| mine.n_blocks)
+    }
+
+    // the pkh of the voter, if it is paid by a recent coinbase of the domain
+    fn check_voter(
+        &self,
+        domain: &str,
+        pub_key: &[u8; PubKey::SIZE],
+    ) -> Result<[u8; 32], EbxError> {
+        let invalid = |message: &str| EbxError::GenericError {
+            source: None,
+            message: message.to_string(),
+        };
+        let mine = self
+            .registry
+            .get(&Domain::from_strict_str(domain.to_string()))
+            .ok_or_else(|| invalid("vote is not from a recent mine"))?;
+        let pkh = Pkh::from_pub_key_buffer(pub_key.to_vec());
+        if !mine.pkhs.contains(pkh.to_buf()) {
+            return Err(invalid("vote is not signed by a key of the mine"));
+        }
+        Ok(pkh.buf)
+    }
+
+    pub fn add_block_vote(&mut self, vote: &BlockVote) -> Result<(), EbxError> {
+        if !vote.is_valid() {
+            return Err(EbxError::InvalidKeyError { source: None });
+        }
+        let pkh = self.check_voter(&vote.domain, vote.pub_key())?;
+        let domain = MineRegistry::mine_domain(&Domain::from_strict_str(vote.domain.clone()));
+        self.block_votes
+            .entry(vote.block_id)
+            .or_default()
+            .entry(domain)
+            .or_default()
+            .insert(pkh);
+        Ok(())
+    }
+
+    pub fn add_tx_vote(&mut self, vote: &TxVote) -> Result<(), EbxError> {
+        if !vote.is_valid() {
+            return Err(EbxError::InvalidKeyError { source: None });
+        }
+        let pkh = self.check_voter(&vote.domain, vote.pub_key())?;
+        let domain = MineRegistry::mine_domain(&Domain::from_strict_str(vote.domain.clone()));
+        self.tx_votes
+            .entry(vote.tx_id)
+            .or_default()
+            .entry(domain)
+            .or_default()
+            .insert(pkh);
+        Ok(())
+    }
+
+    fn votes_weight(&self, votes: Option<&Votes>) -> u64 {
+        votes.map_or(0, |votes| {
+            votes
+                .iter()
+                .map(|(domain, pkhs)| self.registry.get_weight(domain, pkhs))
+                .sum()
+        })
+    }
+
+    pub fn get_block_vote_weight(&self, block_id: &[u8; 32]) -> u64 {
+        self.votes_weight(self.block_votes.get(block_id))
+    }
+
+    pub fn get_tx_vote_weight(&self, tx_id: &[u8; 32]) -> u64 {
+        self.votes_weight(self.tx_votes.get(tx_id))
+    }
+
+    pub fn is_block_final(&self, block_id: &[u8; 32]) -> bool {
+        self.get_block_vote_weight(block_id) * 2 > self.total_weight()
+    }
+
+    pub fn is_tx_final(&self, tx_id: &[u8; 32]) -> bool {
+        self.get_tx_vote_weight(tx_id) * 2 > self.total_weight()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_pair::KeyPair;
+    use crate::test_util::{chain, Mine};
+
+    #[test]
+    fn test_vote_to_buf_and_from_buf() {
+        let key = KeyPair::from_random();
+        let vote = BlockVote::from_sign(&key.priv_key, [1; 32], "example.com").unwrap();
+        assert!(vote.is_valid());
+        let vote = BlockVote::from_buf(vote.to_buf()).unwrap();
+        assert!(vote.is_valid());
+        assert_eq!(vote.block_id, [1; 32]);
+        assert_eq!(vote.domain, "example.com");
+        assert_eq!(vote.pub_key(), &key.pub_key.buf);
+
+        let vote = TxVote::from_sign(&key.priv_key, [2; 32], "example.com").unwrap();
+        let vote = TxVote::from_buf(vote.to_buf()).unwrap();
+        assert!(vote.is_valid());
+        assert_eq!(vote.tx_id, [2; 32]);
+
+        assert!(BlockVote::from_sign(&key.priv_key, [1; 32], "not a domain").is_err());
+    }
+
+    #[test]
+    fn test_invalid_votes() {
+        let key = KeyPair::from_random();
+        let vote = BlockVote::from_sign(&key.priv_key, [1; 32], "example.com").unwrap();
+
+        let mut other_block = vote.clone();
+        other_block.block_id = [2; 32];
+        assert!(!other_block.is_valid());
+        let mut other_domain = vote.clone();
+        other_domain.domain = "example.org".to_string();
+        assert!(!other_domain.is_valid());
+
+        // a block vote is not a tx vote
+        assert!(!TxVote::from_buf(vote.to_buf()).unwrap().is_valid());
+    }
+
+    #[test]
+    fn test_tally() {
+        let a = Mine::new("minea.com");
+        let b = Mine::new("mineb.com");
+        let c = Mine::new("minec.com");
+        let (lch, coinbase_txs) = chain(&[&c, &a, &b, &a, &c]);
+        // only the last 4 blocks are recent
+        let mut tally = VoteTally::from_coinbases(&lch, &coinbase_txs[1..]).unwrap();
+        assert_eq!(tally.total_weight(), 4);
+        assert_eq!(tally.get_weight(a.domain), 2);
+        assert_eq!(tally.get_weight(c.domain), 1);
+
+        let block_id = lch.get_tip().unwrap().id();
+        let vote_a = BlockVote::from_sign(&a.key.priv_key, block_id, a.domain).unwrap();
+        tally.add_block_vote(&vote_a).unwrap();
+        tally.add_block_vote(&vote_a).unwrap();
+        assert_eq!(tally.get_block_vote_weight(&block_id), 2);
+        assert!(!tally.is_block_final(&block_id));
+
+        let vote_b = BlockVote::from_sign(&b.key.priv_key, block_id, b.domain).unwrap();
+        tally.add_block_vote(&vote_b).unwrap();
+        assert!(tally.is_block_final(&block_id));
+
+        // tx votes are counted apart from block votes
+        assert!(!tally.is_tx_final(&block_id));
+        let tx_vote = TxVote::from_sign(&c.key.priv_key, [9; 32], c.domain).unwrap();
+        tally.add_tx_vote(&tx_vote).unwrap();
+        assert_eq!(tally.get_tx_vote_weight(&[9; 32]), 1);
+    }
+
+    #[test]
+    fn test_vote_weight_is_blocks_paying_the_key() {
+        let a = Mine::new("minea.com");
+        let b = Mine::new("mineb.com");
+        // another key paid by a single coinbase claiming a's domain
+        let other = Mine::new(a.domain);
+        let (lch, coinbase_txs) = chain(&[&a, &b, &a, &other, &a]);
+        let mut tally = VoteTally::from_coinbases(&lch, &coinbase_txs).unwrap();
+        assert_eq!(tally.total_weight(), 5);
+        assert_eq!(tally.get_weight(a.domain), 4);
+
+        let block_id = lch.get_tip().unwrap().id();
+        let vote = BlockVote::from_sign(&other.key.priv_key, block_id, other.domain).unwrap();
+        tally.add_block_vote(&vote).unwrap();
+        assert_eq!(tally.get_block_vote_weight(&block_id), 1);
+        assert!(!tally.is_block_final(&block_id));
+
+        let vote = BlockVote::from_sign(&a.key.priv_key, block_id, a.domain).unwrap();
+        tally.add_block_vote(&vote).unwrap();
+        assert_eq!(tally.get_block_vote_weight(&block_id), 4);
+        assert!(tally.is_block_final(&block_id));
+    }
+
+    #[test]
+    fn test_tally_rejects_votes_from_others() {
+        let a = Mine::new("minea.com");
+        let b = Mine::new("mineb.com");
+        let (lch, coinbase_txs) = chain(&[&a]);
+        let mut tally = VoteTally::from_coinbases(&lch, &coinbase_txs).unwrap();
+
+        // not a recent mine
+        let vote = BlockVote::from_sign(&b.key.priv_key, [1; 32], b.domain).unwrap();
+        assert!(tally.add_block_vote(&vote).is_err());
+        // a recent domain, but not the key it pays
+        let vote = BlockVote::from_sign(&b.key.priv_key, [1; 32], a.domain).unwrap();
+        assert!(tally.add_block_vote(&vote).is_err());
+        // a bad signature
+        let mut vote = BlockVote::from_sign(&a.key.priv_key, [1; 32], a.domain).unwrap();
+        vote.signed_message.sig[5] ^= 1;
+        assert!(tally.add_block_vote(&vote).is_err());
+        assert_eq!(tally.get_block_vote_weight(&[1; 32]), 0);
+    }
+
+    #[test]
+    fn test_tally_checks_coinbases() {
+        let a = Mine::new("minea.com");
+        let (lch, coinbase_txs) = chain(&[&a, &a]);
+        assert!(VoteTally::from_coinbases(&lch, &coinbase_txs[..1]).is_err());
+        let mut too_many = coinbase_txs.clone();
+        too_many.push(coinbase_txs[1].clone());
+        assert!(VoteTally::from_coinbases(&lch, &too_many).is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use crate::block::Block;
+use crate::buf_reader::BufReader;
+use crate::buf_writer::BufWriter;
+use crate::error::EbxError;
+use crate::header::Header;
+use crate::tx::Tx;
+use std::io::{Read, Write};
+
+// messages exchanged between a node and a mine. on the wire each message is
+//
+//   version (u8) | command (u8) | payload length (u32) | payload
+//
+// a message with a different version is rejected rather than guessed at.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvItem {
+    Block([u8; 32]),
+    Tx([u8; 32]),
+}
+
+#[derive(Debug, Clone)]
+pub enum WireMessage {
+    // locator ids run from the tip back to genesis. the reply holds the
+    // headers after the first id the peer has in its best chain.
+    GetHeaders { locator: Vec<[u8; 32]> },
+    Headers(Vec<Header>),
+    GetBlock { id: [u8; 32] },
+    Block(Block),
+    Inv(Vec<InvItem>),
+    Tx(Tx),
+}
+
+impl InvItem {
+    const KIND_BLOCK: u8 = 0;
+    const KIND_TX: u8 = 1;
+}
+
+impl WireMessage {
+    pub const VERSION: u8 = 1;
+    pub const MAX_PAYLOAD_SIZE: usize = 32 * 1024 * 1024;
+    pub const MAX_HEADERS: usize = 2000;
+    pub const MAX_LOCATOR_IDS: usize = 64;
+    pub const MAX_INV_ITEMS: usize = 50_000;
+    const PREFIX_SIZE: usize = 1 + 1 + 4;
+
+    const CMD_GET_HEADERS: u8 = 0;
+    const CMD_HEADERS: u8 = 1;
+    const CMD_GET_BLOCK: u8 = 2;
+    const CMD_BLOCK: u8 = 3;
+    const CMD_INV: u8 = 4;
+    const CMD_TX: u8 = 5;
+
+    pub fn command(&self) -> u8 {
+        match self {
+            WireMessage::GetHeaders { .. } => WireMessage::CMD_GET_HEADERS,
+            WireMessage::Headers(_) => WireMessage::CMD_HEADERS,
+            WireMessage::GetBlock { .. } => WireMessage::CMD_GET_BLOCK,
+            WireMessage::Block(_) => WireMessage::CMD_BLOCK,
+            WireMessage::Inv(_) => WireMessage::CMD_INV,
+            WireMessage::Tx(_) => WireMessage::CMD_TX,
+        }
+    }
+
+    fn payload_writer(&self) -> BufWriter {
+        let mut bw = BufWriter::new();
+        match self {
+            WireMessage::GetHeaders { locator } => {
+                bw.write_var_int(locator.len() as u64);
+                for id in locator {
+                    bw.write(id.to_vec());
+                }
+            }
+            WireMessage::Headers(headers) => {
+                bw.write_var_int(headers.len() as u64);
+                for header in headers {
+                    bw.write(header.to_buf().to_vec());
+                }
+            }
+            WireMessage::GetBlock { id } => {
+                bw.write(id.to_vec());
+            }
+            WireMessage::Block(block) => {
+                bw.write(block.to_buf());
+            }
+            WireMessage::Inv(items) => {
+                bw.write_var_int(items.len() as u64);
+                for item in items {
+                    let (kind, id) = match item {
+                        InvItem::Block(id) => (InvItem::KIND_BLOCK, id),
+                        InvItem::Tx(id) => (InvItem::KIND_TX, id),
+                    };
+                    bw.write_u8(kind);
+                    bw.write(id.to_vec());
+                }
+            }
+            WireMessage::Tx(tx) => {
+                bw.write(tx.to_buf());
+            }
+        }
+        bw
+    }
+
+    pub fn to_buf(&self) -> Vec<u8> {
+        let payload = self.payload_writer().to_buf();
+        let mut bw = BufWriter::new();
+        bw.write_u8(WireMessage::VERSION);
+        bw.write_u8(self.command());
+        bw.write_u32_be(payload.len() as u32);
+        bw.write(payload);
+        bw.to_buf()
+    }
+
+    fn read_count(br: &mut BufReader, max: usize) -> Result<usize, EbxError> {
+        let count = br.read_var_int()? as usize;
+        if count > max {
+            return Err(EbxError::TooMuchDataError { source: None });
+        }
+        Ok(count)
+    }
+
+    fn read_id(br: &mut BufReader) -> Result<[u8; 32], EbxError> {
+        Ok(br.read(32)?.try_into().unwrap())
+    }
+
+    fn from_payload(command: u8, payload: Vec<u8>) -> Result<Self, EbxError> {
+        let mut br = BufReader::new(payload);
+        let message = match command {
+            WireMessage::CMD_GET_HEADERS => {
+                let count = WireMessage::read_count(&mut br, WireMessage::MAX_LOCATOR_IDS)?;
+                let mut locator = Vec::with_capacity(count);
+                for _ in 0..count {
+                    locator.push(WireMessage::read_id(&mut br)?);
+                }
+                WireMessage::GetHeaders { locator }
+            }
+            WireMessage::CMD_HEADERS => {
+                let count = WireMessage::read_count(&mut br, WireMessage::MAX_HEADERS)?;
+                let mut headers = Vec::with_capacity(count);
+                for _ in 0..count {
+                    headers.push(Header::from_buf_reader(&mut br)?);
+                }
+                WireMessage::Headers(headers)
+            }
+            WireMessage::CMD_GET_BLOCK => WireMessage::GetBlock {
+                id: WireMessage::read_id(&mut br)?,
+            },
+            WireMessage::CMD_BLOCK => WireMessage::Block(Block::from_buf_reader(&mut br)?),
+            WireMessage::CMD_INV => {
+                let count = WireMessage::read_count(&mut br, WireMessage::MAX_INV_ITEMS)?;
+                let mut items = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let kind = br.read_u8()?;
+                    let id = WireMessage::read_id(&mut br)?;
+                    items.push(match kind {
+                        InvItem::KIND_BLOCK => InvItem::Block(id),
+                        InvItem::KIND_TX => InvItem::Tx(id),
+                        _ => return Err(EbxError::InvalidEncodingError { source: None }),
+                    });
+                }
+                WireMessage::Inv(items)
+            }
+            WireMessage::CMD_TX => WireMessage::Tx(Tx::from_buf_reader(&mut br)?),
+            _ => return Err(EbxError::InvalidEncodingError { source: None }),
+        };
+        if !br.eof() {
+            return Err(EbxError::TooMuchDataError { source: None });
+        }
+        Ok(message)
+    }
+
+    pub fn from_buf(buf: Vec<u8>) -> Result<Self, EbxError> {
+        let mut br = BufReader::new(buf);
+        let (command, len) = WireMessage::read_prefix(&br.read(WireMessage::PREFIX_SIZE)?)?;
+        let payload = br.read(len)?;
+        if !br.eof() {
+            return Err(EbxError::TooMuchDataError { source: None });
+        }
+        WireMessage::from_payload(command, payload)
+    }
+
+    fn read_prefix(prefix: &[u8]) -> Result<(u8, usize), EbxError> {
+        let mut br = BufReader::new(prefix.to_vec());
+        let version = br.read_u8()?;
+        if version != WireMessage::VERSION {
+            return Err(EbxError::GenericError {
+                source: None,
+                message: format!("unsupported message version {}", version),
+            });
+        }
+        let command = br.read_u8()?;
+        let len = br.read_u32_be()? as usize;
+        if len > WireMessage::MAX_PAYLOAD_SIZE {
+            return Err(EbxError::TooMuchDataError { source: None });
+        }
+        Ok((command, len))
+    }
+
+    pub fn write_to(&self, writer: &mut dyn Write) -> Result<(), EbxError> {
+        writer
+            .write_all(&self.to_buf())
+            .and_then(|_| writer.flush())
+            .map_err(|e| EbxError::io("unable to write message", &e))
+    }
+
+    pub fn read_from(reader: &mut dyn Read) -> Result<Self, EbxError> {
+        let mut prefix = [0u8; WireMessage::PREFIX_SIZE];
+        reader
+            .read_exact(&mut prefix)
+            .map_err(|e| EbxError::io("unable to read message", &e))?;
+        let (command, len) = WireMessage::read_prefix(&prefix)?;
+        let mut payload = vec![0u8; len];
+        reader
+            .read_exact(&mut payload)
+            .map_err(|e| EbxError::io("unable to read message", &e))?;
+        WireMessage::from_payload(command, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+    use crate::tx_in::TxIn;
+    use crate::tx_out::TxOut;
+
+    fn messages() -> Vec<WireMessage> {
+        let header = Header::from_genesis(1);
+        let tx = Tx::new(
+            1,
+            vec![TxIn::new([1; 32], 0, Script::from_empty(), 0)],
+            vec![TxOut::new(100, Script::from_empty())],
+            0,
+        );
+        vec![
+            WireMessage::GetHeaders {
+                locator: vec![[1; 32], [2; 32]],
+            },
+            WireMessage::Headers(vec![header.clone(), header.clone()]),
+            WireMessage::Headers(vec![]),
+            WireMessage::GetBlock { id: [3; 32] },
+            WireMessage::Block(Block::new(header, vec![tx.clone()])),
+            WireMessage::Inv(vec![InvItem::Block([4; 32]), InvItem::Tx([5; 32])]),
+            WireMessage::Tx(tx),
+        ]
+    }
+
+    #[test]
+    fn test_to_buf_and_from_buf() {
+        for message in messages() {
+            let buf = message.to_buf();
+            assert_eq!(buf[0], WireMessage::VERSION);
+            assert_eq!(buf[1], message.command());
+            let decoded = WireMessage::from_buf(buf.clone()).unwrap();
+            assert_eq!(decoded.to_buf(), buf);
+        }
+    }
+
+    #[test]
+    fn test_read_from_stream() {
+        let mut stream = Vec::new();
+        for message in messages() {
+            message.write_to(&mut stream).unwrap();
+        }
+        let mut reader = &stream[..];
+        for message in messages() {
+            let decoded = WireMessage::read_from(&mut reader).unwrap();
+            assert_eq!(decoded.to_buf(), message.to_buf());
+        }
+        assert!(WireMessage::read_from(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_reject_bad_messages() {
+        let buf = WireMessage::GetBlock { id: [3; 32] }.to_buf();
+
+        let mut bad_version = buf.clone();
+        bad_version[0] = 2;
+        assert!(WireMessage::from_buf(bad_version).is_err());
+
+        let mut bad_command = buf.clone();
+        bad_command[1] = 0xff;
+        assert!(WireMessage::from_buf(bad_command).is_err());
+
+        let mut extra = buf.clone();
+        extra.push(0);
+        assert!(WireMessage::from_buf(extra).is_err());
+
+        // the payload length says one byte more than the payload holds
+        let mut long_payload = buf.clone();
+        long_payload[5] += 1;
+        long_payload.push(0);
+        assert!(WireMessage::from_buf(long_payload).is_err());
+
+        assert!(WireMessage::from_buf(buf[..buf.len() - 1].to_vec()).is_err());
+
+        let mut too_big = buf.clone();
+        too_big[2..6].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(WireMessage::read_from(&mut &too_big[..]).is_err());
+
+        let mut bad_inv = WireMessage::Inv(vec![InvItem::Tx([5; 32])]).to_buf();
+        bad_inv[7] = 9;
+        assert!(WireMessage::from_buf(bad_inv).is_err());
+    }
+}
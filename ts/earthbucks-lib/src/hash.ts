@@ -1,13 +1,15 @@
-import { hash, createKeyed } from "blake3";
+import { hash, createKeyed, createDeriveKey } from "blake3";
 import { SysBuf, FixedBuf } from "./buf.js";
 import { blake3 as blake3browser } from "@noble/hashes/blake3"; // eslint-disable-line
 
 type EbxBufFunction = (input: SysBuf) => FixedBuf<32>;
 type MacFunction = (key: SysBuf, data: SysBuf) => FixedBuf<32>;
+type DeriveKeyFunction = (context: string, data: SysBuf) => FixedBuf<32>;
 
 let blake3Hash: EbxBufFunction;
 let doubleBlake3Hash: EbxBufFunction;
 let blake3Mac: MacFunction;
+let blake3DeriveKey: DeriveKeyFunction;
 
 if (typeof document === "undefined") {
   // running in a server environment
@@ -26,6 +28,16 @@ if (typeof document === "undefined") {
       createKeyed(key).update(data).digest() as SysBuf,
     );
   };
+
+  blake3DeriveKey = function blake3DeriveKey(
+    context: string,
+    data: SysBuf,
+  ): FixedBuf<32> {
+    return FixedBuf.fromBuf(
+      32,
+      createDeriveKey(context).update(data).digest() as SysBuf,
+    );
+  };
 } else {
   // running in a browser environment
 
@@ -50,6 +62,13 @@ if (typeof document === "undefined") {
       ),
     );
   };
+
+  blake3DeriveKey = function blake3DeriveKey(
+    context: string,
+    data: SysBuf,
+  ): FixedBuf<32> {
+    return FixedBuf.fromBuf(32, SysBuf.from(blake3browser(data, { context })));
+  };
 }
 
-export { blake3Hash, doubleBlake3Hash, blake3Mac };
+export { blake3Hash, doubleBlake3Hash, blake3Mac, blake3DeriveKey };
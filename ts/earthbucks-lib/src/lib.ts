@@ -1,4 +1,5 @@
 export * as Hash from "./hash.js";
+export * as Schnorr from "./schnorr.js";
 export * from "./block-builder.js";
 export * from "./block.js";
 export * from "./buf-reader.js";
@@ -6,6 +6,8 @@ export class TxSignature {
   static readonly SIGHASH_NONE = new U8(0x00000002);
   static readonly SIGHASH_SINGLE = new U8(0x00000003);
   static readonly SIGHASH_ANYONECANPAY = new U8(0x00000080);
+  // set alongside one of the above to sign with schnorr instead of ecdsa
+  static readonly SIGHASH_SCHNORR = new U8(0x00000040);
   static readonly SIZE = 65;
 
   hashType: U8;
@@ -16,6 +18,10 @@ export class TxSignature {
     this.sigBuf = sigBuf;
   }
 
+  isSchnorr(): boolean {
+    return (this.hashType.n & TxSignature.SIGHASH_SCHNORR.n) !== 0;
+  }
+
   toBuf(): SysBuf {
     const hashTypeBuf = SysBuf.alloc(1);
     hashTypeBuf.writeUInt8(this.hashType.n);
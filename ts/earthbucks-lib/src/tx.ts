@@ -6,6 +6,7 @@ import { BufWriter } from "./buf-writer.js";
 import * as Hash from "./hash.js";
 import secp256k1 from "secp256k1";
 const { ecdsaSign, ecdsaVerify } = secp256k1;
+import * as Schnorr from "./schnorr.js";
 import { TxSignature } from "./tx-signature.js";
 import { Script } from "./script.js";
 import { SysBuf, FixedBuf } from "./buf.js";
@@ -234,10 +235,7 @@ export class Tx {
     hashType: U8,
   ): TxSignature {
     const hash = this.sighashNoCache(inputIndex, script, amount, hashType);
-    const sigBuf = FixedBuf.fromBuf(
-      64,
-      SysBuf.from(ecdsaSign(hash, privateKey).signature),
-    );
+    const sigBuf = signMessage(hash, privateKey, hashType);
     const sig = new TxSignature(hashType, sigBuf);
     return sig;
   }
@@ -257,10 +255,7 @@ export class Tx {
       hashType,
       hashCache,
     );
-    const sigBuf = FixedBuf.fromBuf(
-      64,
-      SysBuf.from(ecdsaSign(hash, privateKey).signature),
-    );
+    const sigBuf = signMessage(hash, privateKey, hashType);
     const sig = new TxSignature(hashType, sigBuf);
     return sig;
   }
@@ -274,7 +269,7 @@ export class Tx {
   ): boolean {
     const hashType = sig.hashType;
     const hash = this.sighashNoCache(inputIndex, script, amount, hashType);
-    return ecdsaVerify(sig.sigBuf.buf, hash, publicKey);
+    return verifyMessage(hash, publicKey, sig);
   }
 
   verifyWithCache(
@@ -293,6 +288,31 @@ export class Tx {
       hashType,
       hashCache,
     );
-    return ecdsaVerify(sig.sigBuf.buf, hash, publicKey);
+    return verifyMessage(hash, publicKey, sig);
+  }
+}
+
+function signMessage(
+  hash: SysBuf,
+  privateKey: SysBuf,
+  hashType: U8,
+): FixedBuf<64> {
+  if (hashType.n & TxSignature.SIGHASH_SCHNORR.n) {
+    return Schnorr.sign(privateKey, hash);
+  }
+  return FixedBuf.fromBuf(
+    64,
+    SysBuf.from(ecdsaSign(hash, privateKey).signature),
+  );
+}
+
+function verifyMessage(
+  hash: SysBuf,
+  publicKey: SysBuf,
+  sig: TxSignature,
+): boolean {
+  if (sig.isSchnorr()) {
+    return Schnorr.verify(publicKey, hash, sig.sigBuf.buf);
   }
+  return ecdsaVerify(sig.sigBuf.buf, hash, publicKey);
 }
@@ -0,0 +1,93 @@
+import { describe, expect, test } from "vitest";
+import * as Schnorr from "../src/schnorr.js";
+import { KeyPair } from "../src/key-pair.js";
+import { PrivKey } from "../src/priv-key.js";
+import { PubKey } from "../src/pub-key.js";
+import { Tx } from "../src/tx.js";
+import { TxSignature } from "../src/tx-signature.js";
+import { SysBuf } from "../src/buf.js";
+import { U8, U32, U64 } from "../src/numbers.js";
+import fs from "fs";
+import path from "path";
+
+describe("Schnorr", () => {
+  test("sign and verify", () => {
+    const key = KeyPair.fromRandom();
+    const msg = SysBuf.alloc(32, 1);
+    const sig = Schnorr.sign(key.privKey.buf.buf, msg);
+    expect(Schnorr.verify(key.pubKey.buf.buf, msg, sig.buf)).toBe(true);
+    expect(
+      Schnorr.verify(key.pubKey.buf.buf, SysBuf.alloc(32, 2), sig.buf),
+    ).toBe(false);
+    const badSig = SysBuf.from(sig.buf);
+    badSig[63] ^= 1;
+    expect(Schnorr.verify(key.pubKey.buf.buf, msg, badSig)).toBe(false);
+  });
+
+  describe("standard test vectors: schnorr.json", () => {
+    const data = fs.readFileSync(
+      path.resolve(__dirname, "../test-vectors/schnorr.json"),
+      "utf-8",
+    );
+
+    test("schnorr", () => {
+      interface SchnorrJSON {
+        priv_key: string;
+        pub_key: string;
+        message: string;
+        sig: string;
+      }
+      const vectors: SchnorrJSON[] = JSON.parse(data).schnorr;
+
+      for (const vector of vectors) {
+        const privKey = PrivKey.fromStrictStr(vector.priv_key);
+        const pubKey = PubKey.fromStrictStr(vector.pub_key);
+        const msg = SysBuf.from(vector.message, "hex");
+        const sig = Schnorr.sign(privKey.buf.buf, msg);
+        expect(sig.buf.toString("hex")).toBe(vector.sig);
+        expect(Schnorr.verify(pubKey.buf.buf, msg, sig.buf)).toBe(true);
+      }
+    });
+
+    test("tx signatures", () => {
+      interface TxSignatureJSON {
+        priv_key: string;
+        pub_key: string;
+        tx: string;
+        input_index: number;
+        script: string;
+        amount: number;
+        hash_type: number;
+        sig: string;
+      }
+      const vectors: TxSignatureJSON[] = JSON.parse(data).tx_signature;
+
+      for (const vector of vectors) {
+        const privKey = PrivKey.fromStrictStr(vector.priv_key);
+        const pubKey = PubKey.fromStrictStr(vector.pub_key);
+        const tx = Tx.fromStrictHex(vector.tx);
+        const script = SysBuf.from(vector.script, "hex");
+        const sig = tx.signNoCache(
+          new U32(vector.input_index),
+          privKey.buf.buf,
+          script,
+          new U64(vector.amount),
+          new U8(vector.hash_type),
+        );
+        expect(sig.isSchnorr()).toBe(true);
+        expect(sig.toBuf().toString("hex")).toBe(vector.sig);
+
+        const fromBuf = TxSignature.fromBuf(SysBuf.from(vector.sig, "hex"));
+        expect(
+          tx.verifyNoCache(
+            new U32(vector.input_index),
+            pubKey.buf.buf,
+            fromBuf,
+            script,
+            new U64(vector.amount),
+          ),
+        ).toBe(true);
+      }
+    });
+  });
+});
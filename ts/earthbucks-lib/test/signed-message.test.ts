@@ -3,6 +3,8 @@ import { SignedMessage } from "../src/signed-message.js";
 import { PubKey } from "../src/pub-key.js";
 import { PrivKey } from "../src/priv-key.js";
 import { SysBuf } from "../src/buf.js";
+import fs from "fs";
+import path from "path";
 
 describe("SignedMessage", () => {
   test("sign and verify", async () => {
@@ -17,4 +19,45 @@ describe("SignedMessage", () => {
     );
     expect(signedMessage.isValid(pubKey, keyStr)).toBe(true);
   });
+
+  describe("standard test vectors: signed_message.json", () => {
+    const data = fs.readFileSync(
+      path.resolve(__dirname, "../test-vectors/signed_message.json"),
+      "utf-8",
+    );
+
+    test("signed messages", () => {
+      interface SignedMessageJSON {
+        priv_key: string;
+        pub_key: string;
+        key_str: string;
+        message: string;
+        mac: string;
+        sig: string;
+        buf: string;
+      }
+      const signedMessages: SignedMessageJSON[] =
+        JSON.parse(data).signed_message;
+
+      for (const vector of signedMessages) {
+        const privKey = PrivKey.fromStrictStr(vector.priv_key);
+        const pubKey = PubKey.fromStrictStr(vector.pub_key);
+        const message = SysBuf.from(vector.message, "hex");
+        const signedMessage = SignedMessage.fromSignMessage(
+          privKey,
+          message,
+          vector.key_str,
+        );
+        expect(signedMessage.mac.buf.toString("hex")).toBe(vector.mac);
+        expect(signedMessage.sig.buf.toString("hex")).toBe(vector.sig);
+        expect(signedMessage.toBuf().toString("hex")).toBe(vector.buf);
+
+        const fromBuf = SignedMessage.fromBuf(
+          SysBuf.from(vector.buf, "hex"),
+          vector.key_str,
+        );
+        expect(fromBuf.isValid(pubKey, vector.key_str)).toBe(true);
+      }
+    });
+  });
 });